@@ -1,39 +1,51 @@
 use std::{
     collections::HashMap,
-    os::fd::FromRawFd,
+    os::fd::{FromRawFd, IntoRawFd},
     path::{Path, PathBuf},
 };
 
 use super::LoopData;
+use crate::edid::{self, Edid};
 
 use smithay::{
     backend::{
         allocator::{
+            dmabuf::{AsDmabuf, Dmabuf},
             gbm::GbmDevice,
             gbm::{GbmAllocator, GbmBufferFlags},
-            Fourcc,
+            Allocator, Fourcc, Modifier,
         },
         drm::{DrmDevice, DrmDeviceFd, DrmDeviceNotifier, DrmNode, GbmBufferedSurface, NodeType},
         egl::{EGLDevice, EGLDisplay},
         libinput::{LibinputInputBackend, LibinputSessionInterface},
         renderer::{
-            gles::GlesRenderer,
+            gles::{GlesRenderer, GlesTexture},
             multigpu::{gbm::GbmGlesBackend, GpuManager},
+            Frame, ImportDma, Renderer,
         },
         session::{
             libseat::{LibSeatSession, LibSeatSessionNotifier},
             Session,
         },
         udev::{primary_gpu, UdevBackend},
+        winit::{self, WinitEventLoop, WinitGraphicsBackend},
     },
     reexports::{
         calloop::{EventLoop, RegistrationToken},
-        drm::control::{crtc, ModeTypeFlags},
+        drm::{
+            control::{
+                atomic::AtomicModeReq, connector, crtc, property, AtomicCommitFlags,
+                Device as ControlDevice, ModeTypeFlags, ResourceHandle,
+            },
+            Device as BasicDevice, DriverCapability,
+        },
+        gbm::BufferObject,
         input::Libinput,
         nix::fcntl::OFlag,
         wayland_server::Display,
+        winit::window::WindowBuilder,
     },
-    utils::DeviceFd,
+    utils::{DeviceFd, Physical, Point, Rectangle, Size, Transform},
     wayland::dmabuf::DmabufState,
 };
 use smithay_drm_extras::drm_scanner::{DrmScanEvent, DrmScanner};
@@ -52,34 +64,492 @@ const SUPPORTED_FORMATS: &[Fourcc] = &[
 ];
 
 pub struct BackendData {
-    pub session: LibSeatSession,
-    pub device_data: DeviceData,
     // primary_gpu: DrmNode, // I will not use it, it seems useless
     pub gpu_manager: GpuManager<GbmGlesBackend<GlesRenderer>>,
     // Alloctor SEEMS to be needed only for multiple GPU systems
     // allocator: Option<Box<dyn Allocator<Buffer = Dmabuf, Error = AnyError>>>,
+    // Every frame is rendered by the renderer of this node,
+    // created on `gbm` (see the watchdog, it creates it again)
+    pub render_node: DrmNode,
+    pub gbm: GbmDevice<DrmDeviceFd>,
+    pub surface: BackendSurface,
+}
+
+/// Where the frames are shown
+pub enum BackendSurface {
+    /// Directly on the tty, through DRM and libinput
+    Drm {
+        // The devices are opened through the session, it's kept with them
+        session: LibSeatSession,
+        device_data: DeviceData,
+    },
+    /// In a window of another compositor, see `WinitData`
+    Winit(WinitData),
 }
 
 pub struct DeviceData {
     pub drm: DrmDevice,
-    pub gbm: GbmDevice<DrmDeviceFd>,
     // A single surface is handled
     // surfaces: HashMap<crtc::Handle, ?SurfaceData?>,
     pub gbm_surface: GbmBufferedSurface<GbmAllocator<DrmDeviceFd>, ()>,
-    // drm_scanner: DrmScanner, not saved because no real time update is managed
-    pub render_node: DrmNode,
+    // eg. eDP-1 or HDMI-A-1, used to identify the output in the config
+    pub connector_name: String,
+    // None if the monitor has no EDID, it's then identified only by the connector
+    pub edid: Option<Edid>,
+    pub connector: connector::Handle,
+    pub crtc: crtc::Handle,
+    // None if the device has no usable cursor plane
+    pub hardware_cursor: Option<HardwareCursor>,
+    // Remembers the connectors seen so far, a new scan reports only the changes
+    pub drm_scanner: DrmScanner,
     // This is used to save the token related to
     // the callback inserted in the event Loop to manage VBlank events!
     //registration_token: RegistrationToken,
 }
 
-pub struct Notifiers {
-    pub session: LibSeatSessionNotifier,
-    pub libinput: LibinputInputBackend,
-    pub drm: DrmDeviceNotifier,
+/// Cursor drawn by the display controller on the cursor plane, moving it does
+/// not require to render a new frame. The plane has strict limits (size, format)
+/// and when the cursor image does not fit it is rejected, in that case the
+/// caller needs to composite the cursor in the frame
+pub struct HardwareCursor {
+    buffer: BufferObject<()>,
+    size: (u32, u32),
+    // Scale of the image currently in the buffer
+    uploaded: Option<i32>,
+    // Scale of the last image refused by the plane, retried only when it changes
+    rejected: Option<i32>,
+    // (x, y) of the hotspot inside the image
+    hotspot: (i32, i32),
+    visible: bool,
+}
+
+impl HardwareCursor {
+    fn new(
+        gbm: &GbmDevice<DrmDeviceFd>,
+        drm: &DrmDevice,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // 64x64 is what most of the drivers support when they don't say otherwise
+        let width = drm
+            .get_driver_capability(DriverCapability::CursorWidth)
+            .unwrap_or(64) as u32;
+        let height = drm
+            .get_driver_capability(DriverCapability::CursorHeight)
+            .unwrap_or(64) as u32;
+
+        let buffer = gbm.create_buffer_object::<()>(
+            width,
+            height,
+            Fourcc::Argb8888,
+            GbmBufferFlags::CURSOR | GbmBufferFlags::WRITE,
+        )?;
+
+        Ok(Self {
+            buffer,
+            size: (width, height),
+            uploaded: None,
+            rejected: None,
+            hotspot: (0, 0),
+            visible: false,
+        })
+    }
+
+    /// Show the image on the cursor plane, `scale` identifies the image so that
+    /// it is not uploaded again every frame.
+    ///
+    /// Returns false if the plane refused the image
+    pub fn show(
+        &mut self,
+        drm: &DrmDevice,
+        crtc: crtc::Handle,
+        image: &xcursor::parser::Image,
+        scale: i32,
+    ) -> bool {
+        if self.rejected == Some(scale) {
+            return false;
+        }
+
+        if self.uploaded != Some(scale) {
+            if let Err(err) = self.upload(image) {
+                println!("Cursor plane rejected the cursor: {err}");
+                self.rejected = Some(scale);
+                self.hide(drm, crtc);
+                return false;
+            }
+            self.uploaded = Some(scale);
+            self.hotspot = (image.xhot as i32, image.yhot as i32);
+            self.visible = false;
+        }
+
+        if !self.visible {
+            if let Err(err) = drm.set_cursor2(crtc, Some(&self.buffer), self.hotspot) {
+                println!("Cursor plane rejected the cursor: {err}");
+                self.rejected = Some(scale);
+                self.uploaded = None;
+                return false;
+            }
+            self.visible = true;
+        }
+
+        // A new image can be tried again later
+        self.rejected = None;
+        true
+    }
+
+    /// Forget the uploaded image, the next `show` will upload it again
+    pub fn invalidate(&mut self) {
+        self.uploaded = None;
+        self.rejected = None;
+    }
+
+    /// Move the cursor so that its hotspot is in `location`,
+    /// relative to the origin of the output
+    pub fn move_to(&self, drm: &DrmDevice, crtc: crtc::Handle, location: Point<i32, Physical>) {
+        let position = (location.x - self.hotspot.0, location.y - self.hotspot.1);
+        if let Err(err) = drm.move_cursor(crtc, position) {
+            println!("Impossible move the hardware cursor: {err}");
+        }
+    }
+
+    /// The last frame put the cursor on the plane
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn hide(&mut self, drm: &DrmDevice, crtc: crtc::Handle) {
+        if !self.visible {
+            return;
+        }
+        if let Err(err) = drm.set_cursor2(crtc, Option::<&BufferObject<()>>::None, (0, 0)) {
+            println!("Impossible hide the hardware cursor: {err}");
+        }
+        self.visible = false;
+    }
+
+    // Copy the image in the top left corner of the buffer,
+    // the image needs to fit inside the plane
+    fn upload(&mut self, image: &xcursor::parser::Image) -> Result<(), Box<dyn std::error::Error>> {
+        let (width, height) = self.size;
+        if image.width > width || image.height > height {
+            return Err(format!(
+                "image {}x{} bigger than the plane {width}x{height}",
+                image.width, image.height
+            )
+            .into());
+        }
+
+        // xcursor stores the pixels as little endian ARGB,
+        // the same memory layout of the Argb8888 DRM format
+        let mut pixels = vec![0u8; (width * height * 4) as usize];
+        let row_len = (image.width * 4) as usize;
+        for row in 0..image.height as usize {
+            let src = row * row_len;
+            let dst = row * (width * 4) as usize;
+            pixels[dst..dst + row_len].copy_from_slice(&image.pixels_argb[src..src + row_len]);
+        }
+
+        self.buffer.write(&pixels)??;
+        Ok(())
+    }
+}
+
+pub enum Notifiers {
+    Drm {
+        session: LibSeatSessionNotifier,
+        libinput: LibinputInputBackend,
+        drm: DrmDeviceNotifier,
+    },
+    /// The input and the resizes of the window, they are polled
+    Winit(WinitEventLoop),
+}
+
+// Refresh rate reported for the window, the other compositor decides the real one
+pub const WINIT_REFRESH: i32 = 60_000;
+
+/// aigi in a window of another compositor (or of an X server), when it
+/// can't take the seat, eg. started from a terminal of a running desktop.
+/// The frames are rendered like on the tty, by the renderer of the render
+/// node, in a buffer that is then drawn in the window. There are no outputs
+/// to configure, no cursor plane and nothing to turn off
+pub struct WinitData {
+    backend: WinitGraphicsBackend<GlesRenderer>,
+    allocator: GbmAllocator<DrmDeviceFd>,
+    // The buffer the frames are rendered in, and the same buffer imported
+    // by the renderer of the window. Allocated again when the window is resized
+    buffer: Option<(Dmabuf, GlesTexture)>,
+}
+
+impl WinitData {
+    pub fn window_size(&self) -> Size<i32, Physical> {
+        self.backend.window_size().physical_size
+    }
+
+    /// The buffer of the next frame and its age. The same buffer is used for
+    /// every frame, so it has the previous one, a new buffer has no content
+    pub fn next_buffer(&mut self) -> Result<(Dmabuf, u8), Box<dyn std::error::Error>> {
+        let size = self.window_size();
+        if let Some((dmabuf, _)) = self.buffer.as_ref() {
+            if (dmabuf.size().w, dmabuf.size().h) == (size.w, size.h) {
+                return Ok((dmabuf.clone(), 1));
+            }
+        }
+
+        let dmabuf = self
+            .allocator
+            .create_buffer(
+                size.w as u32,
+                size.h as u32,
+                Fourcc::Argb8888,
+                &[Modifier::Invalid],
+            )?
+            .export()?;
+        let texture = self.backend.renderer().import_dmabuf(&dmabuf, None)?;
+        self.buffer = Some((dmabuf.clone(), texture));
+        Ok((dmabuf, 0))
+    }
+
+    /// Draw the last frame in the window, the whole window is redrawn:
+    /// the buffers of the window have their own age
+    pub fn present(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some((_, texture)) = self.buffer.as_ref() else {
+            return Ok(());
+        };
+        let size = self.window_size();
+        let area = Rectangle::from_loc_and_size((0, 0), size);
+        self.backend.bind()?;
+        // The window is upside down for GL
+        let mut frame = self
+            .backend
+            .renderer()
+            .render(size, Transform::Flipped180)?;
+        frame.render_texture_from_to(
+            texture,
+            Rectangle::from_loc_and_size((0.0, 0.0), (size.w as f64, size.h as f64)),
+            area,
+            &[area],
+            Transform::Normal,
+            1.0,
+        )?;
+        frame.finish()?;
+        self.backend.submit(Some(&[area]))?;
+        Ok(())
+    }
+
+    /// The next frame is rendered in a new buffer, from scratch
+    pub fn reset_buffer(&mut self) {
+        self.buffer = None;
+    }
+}
+
+// Values of the DPMS connector property
+const DRM_MODE_DPMS_ON: u64 = 0;
+const DRM_MODE_DPMS_OFF: u64 = 3;
+
+// Property of a DRM object (connector, crtc, plane) found by its name,
+// with its current value
+fn find_property<H: ResourceHandle>(
+    drm: &DrmDevice,
+    object: H,
+    name: &str,
+) -> Result<(property::Handle, u64), Box<dyn std::error::Error>> {
+    let properties = drm.get_properties(object)?;
+    let (handles, values) = properties.as_props_and_values();
+    for (handle, value) in handles.iter().zip(values) {
+        let info = drm.get_property(*handle)?;
+        if info.name().to_str() == Ok(name) {
+            return Ok((*handle, *value));
+        }
+    }
+    Err(format!("No {name} property").into())
+}
+
+/// eg. eDP-1 or HDMI-A-1
+pub fn connector_name(connector: &connector::Info) -> String {
+    format!(
+        "{}-{}",
+        connector.interface().as_str(),
+        connector.interface_id()
+    )
+}
+
+/// Make, model and serial of the monitor plugged in the connector
+pub fn read_edid(drm: &DrmDevice, connector: connector::Handle) -> Option<Edid> {
+    let (_, blob) = find_property(drm, connector, "EDID").ok()?;
+    if blob == 0 {
+        return None;
+    }
+    let bytes = drm.get_property_blob(blob).ok()?;
+    edid::parse(&bytes)
+}
+
+/// Why a connected connector can't be driven: a non-desktop one (eg. a VR
+/// headset, it's for the applications that lease it), one without modes or
+/// one left without a CRTC (they are all taken by other connectors)
+pub fn unusable(
+    drm: &DrmDevice,
+    connector: &connector::Info,
+    crtc: Option<crtc::Handle>,
+) -> Option<&'static str> {
+    let non_desktop =
+        find_property(drm, connector.handle(), "non-desktop").is_ok_and(|(_, value)| value != 0);
+    if non_desktop {
+        return Some("non-desktop, eg. a VR headset");
+    }
+    if connector.modes().is_empty() {
+        return Some("no modes");
+    }
+    if crtc.is_none() {
+        return Some("no free CRTC");
+    }
+    None
+}
+
+/// With atomic modesetting (used by smithay when the driver supports it)
+/// the frames are committed along with the properties of the CRTC and of
+/// the connector, so a mode change is applied with the next frame without
+/// glitches. The properties that smithay doesn't handle are set here,
+/// explicitly, the legacy API is used only by the drivers without atomic
+impl DeviceData {
+    /// Turn the display on or off. With atomic the CRTC is deactivated, and
+    /// activated again with its mode and its connector (a modeset, it's
+    /// the only commit that allows it), otherwise the DPMS property of the
+    /// connector is used
+    pub fn set_display_power(&self, on: bool) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.drm.is_atomic() {
+            return self.set_dpms(on);
+        }
+
+        let mut request = AtomicModeReq::new();
+        let (active, _) = find_property(&self.drm, self.crtc, "ACTIVE")?;
+        request.add_property(self.crtc, active, property::Value::Boolean(on));
+        let mut mode_blob = None;
+        if on {
+            let (mode_id, _) = find_property(&self.drm, self.crtc, "MODE_ID")?;
+            let (crtc_id, _) = find_property(&self.drm, self.connector, "CRTC_ID")?;
+            let mode = self
+                .drm
+                .create_property_blob(&self.gbm_surface.pending_mode())?;
+            if let property::Value::Blob(blob) = mode {
+                mode_blob = Some(blob);
+            }
+            request.add_property(self.crtc, mode_id, mode);
+            request.add_property(
+                self.connector,
+                crtc_id,
+                property::Value::CRTC(Some(self.crtc)),
+            );
+        }
+        let result = self
+            .drm
+            .atomic_commit(AtomicCommitFlags::ALLOW_MODESET, request);
+        // The CRTC keeps its own reference to the mode
+        if let Some(blob) = mode_blob {
+            let _ = self.drm.destroy_property_blob(blob);
+        }
+        Ok(result?)
+    }
+
+    fn set_dpms(&self, on: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let (dpms, _) = find_property(&self.drm, self.connector, "DPMS")?;
+        let value = if on {
+            DRM_MODE_DPMS_ON
+        } else {
+            DRM_MODE_DPMS_OFF
+        };
+        self.drm.set_property(self.connector, dpms, value)?;
+        Ok(())
+    }
+
+    /// Variable refresh rate (adaptive sync), only with atomic and on the
+    /// monitors that support it (the `vrr_capable` property of the connector).
+    /// Nothing is committed if it's already as asked
+    pub fn set_vrr(&self, enabled: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let vrr_enabled = match self.drm.is_atomic() {
+            true => find_property(&self.drm, self.crtc, "VRR_ENABLED").ok(),
+            false => None,
+        };
+        let Some((vrr_enabled, current)) = vrr_enabled else {
+            // There is nothing to turn off
+            return match enabled {
+                true => Err("VRR needs atomic modesetting and a driver supporting it".into()),
+                false => Ok(()),
+            };
+        };
+        if (current != 0) == enabled {
+            return Ok(());
+        }
+        let (_, capable) = find_property(&self.drm, self.connector, "vrr_capable")?;
+        if enabled && capable == 0 {
+            return Err("The monitor doesn't support VRR".into());
+        }
+        let mut request = AtomicModeReq::new();
+        request.add_property(self.crtc, vrr_enabled, property::Value::Boolean(enabled));
+        self.commit_property(request)
+    }
+
+    // A property that doesn't need a modeset, committed on its own: without
+    // ALLOW_MODESET the kernel refuses it instead of blanking the screen if
+    // it would need one. The commit is blocking, so it waits for the page
+    // flip queued by the surface instead of failing with EBUSY. Smithay
+    // doesn't keep these properties in the state of the surface, so its
+    // next commits leave them as they are
+    fn commit_property(&self, request: AtomicModeReq) -> Result<(), Box<dyn std::error::Error>> {
+        self.drm
+            .atomic_commit(AtomicCommitFlags::empty(), request)?;
+        Ok(())
+    }
+
+    /// A gamma ramp left on the CRTC by another program (eg. a night light
+    /// that crashed) is removed, the colors are shown as they are rendered.
+    /// Nothing to do without atomic or without color management
+    pub fn reset_gamma(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.drm.is_atomic() {
+            return Ok(());
+        }
+        let Ok((gamma_lut, current)) = find_property(&self.drm, self.crtc, "GAMMA_LUT") else {
+            return Ok(());
+        };
+        if current == 0 {
+            return Ok(());
+        }
+        let mut request = AtomicModeReq::new();
+        request.add_property(self.crtc, gamma_lut, property::Value::Blob(0));
+        self.commit_property(request)
+    }
 }
 
 impl BackendData {
+    /// Name of the output shown by the backend: only one monitor is driven,
+    /// the first connected one that can be (see `init_device`)
+    pub fn output_name(&self) -> &str {
+        match &self.surface {
+            BackendSurface::Drm { device_data, .. } => &device_data.connector_name,
+            BackendSurface::Winit(_) => "winit",
+        }
+    }
+
+    /// None in the winit backend
+    pub fn device_data(&self) -> Option<&DeviceData> {
+        match &self.surface {
+            BackendSurface::Drm { device_data, .. } => Some(device_data),
+            BackendSurface::Winit(_) => None,
+        }
+    }
+
+    pub fn device_data_mut(&mut self) -> Option<&mut DeviceData> {
+        match &mut self.surface {
+            BackendSurface::Drm { device_data, .. } => Some(device_data),
+            BackendSurface::Winit(_) => None,
+        }
+    }
+
+    /// There is nothing to turn off in a window
+    pub fn set_display_power(&self, on: bool) -> Result<(), Box<dyn std::error::Error>> {
+        match self.device_data() {
+            Some(device_data) => device_data.set_display_power(on),
+            None => Ok(()),
+        }
+    }
+
     // This function should prepare ALL the backend and provide to the caller
     // different notifiers that needs to be inserted in the event_loop
     // + session_notifier (session paused or reactivated)
@@ -97,14 +567,15 @@ impl BackendData {
         let mut libinput_context = Libinput::new_with_udev::<
             LibinputSessionInterface<LibSeatSession>,
         >(session.clone().into());
-        libinput_context.udev_assign_seat(&session.seat()).unwrap();
+        libinput_context
+            .udev_assign_seat(&session.seat())
+            .map_err(|()| "Impossible assign the seat to libinput")?;
         // Handler to be managed by the caller
         let libinput_notifier = LibinputInputBackend::new(libinput_context.clone());
 
         // Search primary GPU and save it in a DrmNode
         // if not found then return Error
-        let (primary_gpu_path, primary_gpu_node) = primary_gpu(&session.seat())
-            .unwrap()
+        let (primary_gpu_path, primary_gpu_node) = primary_gpu(&session.seat())?
             .and_then(|x| {
                 Some((
                     x.clone(),
@@ -121,16 +592,20 @@ impl BackendData {
         // udev_device / gpu is handled (the primary!)
         // (each udev device is a graphics device ?!)
 
-        let (gpu_manager, device_data, drm_notifier) =
+        let (gpu_manager, render_node, gbm, device_data, drm_notifier) =
             Self::init_device(&mut session, primary_gpu_path, primary_gpu_node)?;
 
         Ok((
             BackendData {
-                session,
                 gpu_manager,
-                device_data,
+                render_node,
+                gbm,
+                surface: BackendSurface::Drm {
+                    session,
+                    device_data,
+                },
             },
-            Notifiers {
+            Notifiers::Drm {
                 session: session_notifier,
                 libinput: libinput_notifier,
                 drm: drm_notifier,
@@ -138,6 +613,50 @@ impl BackendData {
         ))
     }
 
+    /// The compositor in a window, see `WinitData`. The renderer of the
+    /// window is only used to draw the frames rendered on its render node
+    pub fn init_winit() -> Result<(Self, Notifiers), Box<dyn std::error::Error>> {
+        let (mut backend, winit_event_loop) =
+            winit::init_from_builder::<GlesRenderer>(WindowBuilder::new().with_title("aigi"))?;
+        // aigi draws its own cursor
+        backend.window().set_cursor_visible(false);
+
+        let render_node =
+            EGLDevice::device_for_display(backend.renderer().egl_context().display())?
+                .try_get_render_node()?
+                .ok_or("The window is not rendered by a GPU with a render node")?;
+        let path = render_node
+            .dev_path()
+            .ok_or("The render node has no device file")?;
+        // A render node doesn't need the seat, anyone can open it
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)?;
+        let fd = DrmDeviceFd::new(unsafe { DeviceFd::from_raw_fd(file.into_raw_fd()) });
+        let gbm = GbmDevice::new(fd)?;
+
+        let mut gpu_manager: GpuManager<GbmGlesBackend<GlesRenderer>> =
+            GpuManager::new(Default::default())?;
+        gpu_manager.as_mut().add_node(render_node, gbm.clone())?;
+        tracing::debug!("WINIT: rendering on {path:?}");
+
+        let winit_data = WinitData {
+            backend,
+            allocator: GbmAllocator::new(gbm.clone(), GbmBufferFlags::RENDERING),
+            buffer: None,
+        };
+        Ok((
+            BackendData {
+                gpu_manager,
+                render_node,
+                gbm,
+                surface: BackendSurface::Winit(winit_data),
+            },
+            Notifiers::Winit(winit_event_loop),
+        ))
+    }
+
     fn init_device(
         session: &mut LibSeatSession,
         path: PathBuf,
@@ -145,6 +664,8 @@ impl BackendData {
     ) -> Result<
         (
             GpuManager<GbmGlesBackend<GlesRenderer>>, // Gpu Manager
+            DrmNode,                                  // render node
+            GbmDevice<DrmDeviceFd>,
             DeviceData, // All the initialized information about the Device that will render stuff on the screen
             DrmDeviceNotifier, // drm_notifier
         ),
@@ -174,7 +695,7 @@ impl BackendData {
         // (On typical desktop it will probably equal the node that DrmDevice was created from,
         // but on some ARM setups this is splited into two separate nodes,
         // one for gpu acceleration and one for handling outputs)
-        let render_node = EGLDevice::device_for_display(&EGLDisplay::new(gbm.clone()).unwrap())
+        let render_node = EGLDevice::device_for_display(&EGLDisplay::new(gbm.clone())?)
             .and_then(|x| x.try_get_render_node())?
             .unwrap_or(node);
 
@@ -199,13 +720,27 @@ impl BackendData {
         };
         */
 
-        let (connector, crtc) = match scan_results.iter().next().unwrap() {
-            DrmScanEvent::Connected {
-                connector,
-                crtc: Some(crtc),
-            } => (connector, crtc),
-            _ => unimplemented!(),
-        };
+        // The first connected monitor that can be driven, the CRTCs are assigned
+        // by the scanner (a connector can be left without one if there are
+        // more connectors than CRTCs)
+        let (connector, crtc) = scan_results
+            .iter()
+            .find_map(|event| match event {
+                DrmScanEvent::Connected { connector, crtc } => {
+                    match unusable(&drm, connector, *crtc) {
+                        Some(reason) => {
+                            tracing::debug!(
+                                "CONNECTOR {} NOT USED: {reason}",
+                                connector_name(connector)
+                            );
+                            None
+                        }
+                        None => Some((connector, (*crtc)?)),
+                    }
+                }
+                _ => None,
+            })
+            .ok_or("No connected monitor that can be driven")?;
 
         // Monitors have diferent modes that can be selected, eg. 1080x1920@90hz
         // let's choose the preferred one
@@ -226,7 +761,8 @@ impl BackendData {
         // in a separed function, here I just what to initialized all the backend stuff
         //
         // maybe the output name should be prepared here
-        // let output_name = format!("{}-{}", connector.interface().as_str(), connector.interface_id());
+        let connector_name = connector_name(connector);
+        let edid = read_edid(&drm, connector.handle());
 
         // I will NOT use the DRM Compositor with different Planes for NOW
         // An update of the project could involve the addition of multiple planes
@@ -246,14 +782,37 @@ impl BackendData {
             render_formats,
         )?;
 
+        // If the cursor plane can't be used the cursor
+        // will be composited in the frame by the renderer
+        let hardware_cursor = HardwareCursor::new(&gbm, &drm)
+            .map_err(|err| println!("Hardware cursor not available: {err}"))
+            .ok();
+
+        tracing::debug!(
+            "DRM {connector_name}: {} modesetting",
+            if drm.is_atomic() { "atomic" } else { "legacy" }
+        );
+        match &edid {
+            Some(edid) => tracing::debug!("MONITOR {connector_name}: {}", edid.identifier()),
+            None => tracing::debug!("MONITOR {connector_name}: no EDID"),
+        }
+
         let device_data = DeviceData {
             drm,
-            gbm,
             gbm_surface,
-            render_node,
+            connector_name,
+            edid,
+            connector: connector.handle(),
+            crtc,
+            hardware_cursor,
+            drm_scanner,
         };
 
-        Ok((gpu_manager, device_data, drm_notifier))
+        if let Err(err) = device_data.reset_gamma() {
+            println!("Gamma not reset: {err}");
+        }
+
+        Ok((gpu_manager, render_node, gbm, device_data, drm_notifier))
     }
 
     // This method should MAYBE render the frame
@@ -0,0 +1,79 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Brightness control exposed by the kernel in sysfs,
+/// eg. /sys/class/backlight/intel_backlight
+///
+/// Writing the brightness file requires the user to have the permission
+/// to do so (usually given by an udev rule for the video group)
+pub struct SysfsBrightness {
+    dir: PathBuf,
+}
+
+impl SysfsBrightness {
+    /// First backlight device of the display
+    pub fn display() -> Option<Self> {
+        Self::first_in("/sys/class/backlight", |_| true)
+    }
+
+    /// Keyboard backlight LED, eg. /sys/class/leds/tpacpi::kbd_backlight
+    pub fn keyboard() -> Option<Self> {
+        Self::first_in("/sys/class/leds", |name| name.contains("kbd_backlight"))
+    }
+
+    fn first_in(class: &str, filter: impl Fn(&str) -> bool) -> Option<Self> {
+        let mut dirs = std::fs::read_dir(class)
+            .ok()?
+            .flatten()
+            .filter(|entry| filter(&entry.file_name().to_string_lossy()))
+            .map(|entry| entry.path())
+            .collect::<Vec<_>>();
+        // The order of read_dir is not defined
+        dirs.sort();
+        dirs.into_iter().next().map(|dir| Self { dir })
+    }
+
+    fn read(&self, file: &str) -> Result<u32> {
+        let path = self.dir.join(file);
+        read_u32(&path)
+    }
+
+    /// Current level, from 0 to 1
+    pub fn level(&self) -> Result<f64> {
+        let max = self.read("max_brightness")?.max(1);
+        Ok(self.read("brightness")? as f64 / max as f64)
+    }
+
+    /// Change the level by `delta` (from -1 to 1) and return the new level
+    pub fn step(&self, delta: f64) -> Result<f64> {
+        let max = self.read("max_brightness")?.max(1);
+        let current = self.read("brightness")? as f64 / max as f64;
+        let new = (current + delta).clamp(0.0, 1.0);
+        self.write((new * max as f64).round() as u32, max)
+    }
+
+    /// Change the raw brightness by `units`, used by devices with
+    /// only a few levels (keyboard backlights usually have 2 or 3)
+    pub fn step_units(&self, units: i32) -> Result<f64> {
+        let max = self.read("max_brightness")?.max(1);
+        let current = self.read("brightness")? as i32;
+        let new = (current + units).clamp(0, max as i32) as u32;
+        self.write(new, max)
+    }
+
+    fn write(&self, brightness: u32, max: u32) -> Result<f64> {
+        let path = self.dir.join("brightness");
+        std::fs::write(&path, brightness.to_string())
+            .with_context(|| format!("Impossible write {path:?}"))?;
+        Ok(brightness as f64 / max as f64)
+    }
+}
+
+fn read_u32(path: &Path) -> Result<u32> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("Impossible read {path:?}"))?;
+    content
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid content of {path:?}"))
+}
@@ -0,0 +1,82 @@
+use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+use tracing_subscriber::filter::LevelFilter;
+
+/// Startup options of the compositor, every option has a default
+#[derive(Parser, Debug)]
+#[command(name = "aigi", version, about = "A tiling Wayland compositor")]
+pub struct Cli {
+    /// Where the compositor is shown
+    #[arg(long, value_enum, default_value_t = BackendKind::Auto)]
+    pub backend: BackendKind,
+    /// Config file used instead of `$XDG_CONFIG_HOME/aigi/config.toml`
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Level of the logs of smithay and of aigi (its traces are at `debug`),
+    /// the errors of aigi are always printed
+    #[arg(long, value_enum, default_value_t = LogLevel::Warn)]
+    pub log_level: LogLevel,
+    /// At least `--log-level debug`, a more verbose level is kept
+    #[arg(long)]
+    pub debug: bool,
+    /// Name of the wayland socket (eg. wayland-1),
+    /// by default the first free one is used
+    #[arg(long)]
+    pub socket: Option<String>,
+    /// Ask the compositor running on the same socket to quit before starting
+    #[arg(long, requires = "socket")]
+    pub replace: bool,
+    /// Start with the default config if the config file is invalid,
+    /// instead of refusing to start
+    #[arg(long)]
+    pub force: bool,
+    /// Start with the default config and without the user script,
+    /// eg. after a crash (see `crash.safe_mode` in the config)
+    #[arg(long)]
+    pub safe_mode: bool,
+    /// Print the wayland events sent to the clients on stderr, only of
+    /// the given interfaces (eg. `wl_surface,xdg_toplevel`) if any
+    #[arg(long, value_delimiter = ',', num_args = 0..)]
+    pub protocol_log: Option<Vec<String>>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum BackendKind {
+    /// The tty if possible, otherwise a window
+    Auto,
+    /// Directly on the tty, through DRM and libinput
+    Udev,
+    /// In a window of the running compositor (or X server)
+    Winit,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Cli {
+    /// Print the logs of smithay and the traces of aigi, they go through `tracing`
+    pub fn init_logs(&self) {
+        let level = match self.log_level {
+            LogLevel::Off => LevelFilter::OFF,
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        };
+        // The more verbose levels are the greater ones
+        let level = if self.debug {
+            level.max(LevelFilter::DEBUG)
+        } else {
+            level
+        };
+        tracing_subscriber::fmt().with_max_level(level).init();
+    }
+}
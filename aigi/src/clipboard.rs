@@ -0,0 +1,88 @@
+use std::os::unix::io::OwnedFd;
+
+use smithay::{
+    input::Seat,
+    reexports::{
+        wayland_protocols::wp::primary_selection::zv1::server::zwp_primary_selection_source_v1::ZwpPrimarySelectionSourceV1,
+        wayland_server::protocol::wl_data_source::WlDataSource,
+    },
+    wayland::{
+        data_device::{
+            request_data_device_client_selection, set_data_device_selection, with_source_metadata,
+        },
+        primary_selection::{
+            request_primary_client_selection, set_primary_selection,
+            with_source_metadata as with_primary_source_metadata,
+        },
+    },
+};
+
+use crate::{config::ClipboardSync, state::AIGIState};
+
+/// Selection that a selection set by the compositor mirrors,
+/// its data is asked to the client that owns that one
+#[derive(Clone, Copy, Debug)]
+pub enum Mirrored {
+    Clipboard,
+    Primary,
+}
+
+/// A client set the clipboard, with `clipboard.sync` the primary selection
+/// offers the same data. A cleared clipboard leaves the primary as it is
+pub fn clipboard_changed(
+    state: &mut AIGIState,
+    source: Option<WlDataSource>,
+    seat: Seat<AIGIState>,
+) {
+    if !matches!(
+        state.config.clipboard.sync,
+        ClipboardSync::ClipboardToPrimary | ClipboardSync::Both
+    ) {
+        return;
+    }
+    let Some(mime_types) = source.and_then(|source| {
+        with_source_metadata(&source, |metadata| metadata.mime_types.clone()).ok()
+    }) else {
+        return;
+    };
+    set_primary_selection(
+        &state.display_handle,
+        &seat,
+        mime_types,
+        Mirrored::Clipboard,
+    );
+}
+
+/// A client set the primary selection, with `clipboard.sync` the clipboard
+/// offers the same data
+pub fn primary_changed(
+    state: &mut AIGIState,
+    source: Option<ZwpPrimarySelectionSourceV1>,
+    seat: Seat<AIGIState>,
+) {
+    if !matches!(
+        state.config.clipboard.sync,
+        ClipboardSync::PrimaryToClipboard | ClipboardSync::Both
+    ) {
+        return;
+    }
+    let Some(mime_types) = source.and_then(|source| {
+        with_primary_source_metadata(&source, |metadata| metadata.mime_types.clone()).ok()
+    }) else {
+        return;
+    };
+    set_data_device_selection(&state.display_handle, &seat, mime_types, Mirrored::Primary);
+}
+
+/// A client pastes a mirrored selection, the data comes
+/// from the client that owns the other one
+pub fn send_mirrored(mirrored: Mirrored, mime_type: String, fd: OwnedFd, seat: &Seat<AIGIState>) {
+    let result = match mirrored {
+        Mirrored::Clipboard => request_data_device_client_selection(seat, mime_type, fd),
+        Mirrored::Primary => request_primary_client_selection(seat, mime_type, fd),
+    };
+    // The mirrored selection could have been cleared in the meantime
+    if let Err(err) = result {
+        println!("Mirrored selection not sent: {err:?}");
+    }
+}
@@ -0,0 +1,1039 @@
+use anyhow::{Context, Result};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use smithay::{
+    input::keyboard::{keysyms, xkb, ModifiersState, XkbConfig},
+    reexports::input,
+};
+use std::path::{Path, PathBuf};
+
+use crate::{osd::OsdKind, permissions::Permission};
+
+// Linux input event codes (see linux/input-event-codes.h)
+pub const BTN_LEFT: u32 = 0x110;
+pub const BTN_RIGHT: u32 = 0x111;
+pub const BTN_MIDDLE: u32 = 0x112;
+
+/// User configuration, read from `$XDG_CONFIG_HOME/aigi/config.toml`.
+///
+/// Every field has a default so the config file can contain
+/// only the things the user wants to change
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub pointer_bindings: Vec<PointerBinding>,
+    pub gesture_bindings: Vec<GestureBinding>,
+    pub media_keys: Vec<MediaKeyBinding>,
+    /// Distance in pixels under which a dragged floating window
+    /// sticks to output and window edges, 0 disables snapping
+    pub snap_threshold: i32,
+    /// App ids of the windows (eg. long running sessions) that are
+    /// closed only once the user confirms it
+    pub confirm_close: Vec<String>,
+    pub pip: PipConfig,
+    pub outputs: Vec<OutputConfig>,
+    pub cursor: CursorConfig,
+    pub power: PowerConfig,
+    pub keyboard: KeyboardConfig,
+    pub decorations: DecorationConfig,
+    pub idle: IdleConfig,
+    pub input_devices: Vec<InputDeviceConfig>,
+    pub focus: FocusConfig,
+    pub tiling: TilingConfig,
+    pub workspaces: WorkspaceConfig,
+    pub crash: CrashConfig,
+    pub notifications: NotificationConfig,
+    pub clipboard: ClipboardConfig,
+    pub permissions: Vec<PermissionConfig>,
+    /// Executables (resolved paths) that can answer `permission_requested`
+    /// through the IPC, nobody else can grant a permission
+    pub permission_helpers: Vec<PathBuf>,
+    /// Rhai script with the hooks called on the compositor events,
+    /// by default `init.rhai` in the directory of the config file
+    pub script: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let logo = Modifiers {
+            logo: true,
+            ..Default::default()
+        };
+        Self {
+            pointer_bindings: vec![
+                PointerBinding {
+                    modifiers: logo,
+                    button: MouseButton::Left,
+                    action: PointerAction::Move,
+                },
+                PointerBinding {
+                    modifiers: logo,
+                    button: MouseButton::Right,
+                    action: PointerAction::Resize,
+                },
+            ],
+            gesture_bindings: vec![
+                GestureBinding {
+                    fingers: 3,
+                    direction: SwipeDirection::Left,
+                    action: GestureAction::WorkspaceNext,
+                },
+                GestureBinding {
+                    fingers: 3,
+                    direction: SwipeDirection::Right,
+                    action: GestureAction::WorkspacePrevious,
+                },
+                GestureBinding {
+                    fingers: 4,
+                    direction: SwipeDirection::Up,
+                    action: GestureAction::ToggleMaximize,
+                },
+                GestureBinding {
+                    fingers: 4,
+                    direction: SwipeDirection::Down,
+                    action: GestureAction::ToggleMaximize,
+                },
+            ],
+            media_keys: MediaKeyBinding::defaults(),
+            snap_threshold: 10,
+            confirm_close: vec![],
+            pip: PipConfig::default(),
+            outputs: vec![],
+            cursor: CursorConfig::default(),
+            power: PowerConfig::default(),
+            keyboard: KeyboardConfig::default(),
+            decorations: DecorationConfig::default(),
+            idle: IdleConfig::default(),
+            input_devices: vec![],
+            focus: FocusConfig::default(),
+            tiling: TilingConfig::default(),
+            workspaces: WorkspaceConfig::default(),
+            crash: CrashConfig::default(),
+            notifications: NotificationConfig::default(),
+            clipboard: ClipboardConfig::default(),
+            permissions: vec![],
+            permission_helpers: vec![],
+            script: None,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file if present, otherwise use the defaults.
+    /// `path` overrides the default location (see `--config`).
+    /// Unknown keys and invalid values are errors, reported
+    /// with the line of the file where they are
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        let Some(path) = path.or_else(Self::path) else {
+            return Ok(Self::default());
+        };
+
+        if !path.exists() {
+            println!("No config found in {path:?}, using defaults");
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Impossible read config file {path:?}"))?;
+        toml::from_str(&content).with_context(|| format!("Invalid config file {path:?}"))
+    }
+
+    /// `config_path` is the one given with `--config`, if any
+    pub fn script_path(&self, config_path: Option<&Path>) -> Option<PathBuf> {
+        if let Some(script) = &self.script {
+            return match (script.strip_prefix("~"), std::env::var_os("HOME")) {
+                (Ok(rest), Some(home)) => Some(PathBuf::from(home).join(rest)),
+                _ => Some(script.clone()),
+            };
+        }
+        let config_path = config_path.map(Path::to_path_buf).or_else(Self::path)?;
+        Some(config_path.parent()?.join("init.rhai"))
+    }
+
+    fn path() -> Option<PathBuf> {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_dir.join("aigi").join("config.toml"))
+    }
+}
+
+/// File kept by the compositor between sessions (eg. the answers of the
+/// user), in `$XDG_STATE_HOME/aigi` (or `~/.local/state/aigi`)
+pub fn state_path(file: &str) -> Option<PathBuf> {
+    let state_dir = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })?;
+    Some(state_dir.join("aigi").join(file))
+}
+
+/// Set of modifiers that needs to be held for a binding to trigger,
+/// in the config file they are written as a list, eg. `["Super", "Shift"]`
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "Vec<String>")]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub logo: bool,
+}
+
+impl Modifiers {
+    /// The binding matches only if exactly the same modifiers are pressed
+    pub fn matches(&self, state: &ModifiersState) -> bool {
+        self.ctrl == state.ctrl
+            && self.alt == state.alt
+            && self.shift == state.shift
+            && self.logo == state.logo
+    }
+}
+
+impl TryFrom<Vec<String>> for Modifiers {
+    type Error = String;
+
+    fn try_from(names: Vec<String>) -> Result<Self, Self::Error> {
+        let mut modifiers = Modifiers::default();
+        for name in names {
+            match name.to_lowercase().as_str() {
+                "ctrl" | "control" => modifiers.ctrl = true,
+                "alt" | "mod1" => modifiers.alt = true,
+                "shift" => modifiers.shift = true,
+                "super" | "logo" | "mod4" => modifiers.logo = true,
+                _ => return Err(format!("unknown modifier `{name}`")),
+            }
+        }
+        Ok(modifiers)
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+impl MouseButton {
+    pub fn code(&self) -> u32 {
+        match self {
+            MouseButton::Left => BTN_LEFT,
+            MouseButton::Right => BTN_RIGHT,
+            MouseButton::Middle => BTN_MIDDLE,
+        }
+    }
+}
+
+/// What a pointer binding does once triggered,
+/// the action is applied to the window under the pointer
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PointerAction {
+    Move,
+    Resize,
+}
+
+/// eg.
+/// ```toml
+/// [[pointer_bindings]]
+/// modifiers = ["Super"]
+/// button = "left"
+/// action = "move"
+/// ```
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PointerBinding {
+    #[serde(default)]
+    pub modifiers: Modifiers,
+    pub button: MouseButton,
+    pub action: PointerAction,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GestureAction {
+    WorkspacePrevious,
+    WorkspaceNext,
+    /// Applied to the focused window
+    ToggleMaximize,
+}
+
+/// Touchpad swipe, eg.
+/// ```toml
+/// [[gesture_bindings]]
+/// fingers = 3
+/// direction = "left"
+/// action = "workspace_next"
+/// ```
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GestureBinding {
+    pub fingers: u32,
+    pub direction: SwipeDirection,
+    pub action: GestureAction,
+}
+
+/// Actions implemented by the compositor itself
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaAction {
+    BrightnessUp,
+    BrightnessDown,
+    KbdBacklightUp,
+    KbdBacklightDown,
+}
+
+/// Media and function keys are handled by the compositor even when
+/// a client has the keyboard focus, eg.
+/// ```toml
+/// [[media_keys]]
+/// key = "XF86AudioRaiseVolume"
+/// exec = "pactl set-sink-volume @DEFAULT_SINK@ +5%"
+/// osd = "volume"
+/// level = "pactl get-sink-volume @DEFAULT_SINK@"
+///
+/// [[media_keys]]
+/// key = "XF86MonBrightnessUp"
+/// action = "brightness_up"
+/// ```
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MediaKeyBinding {
+    /// XKB keysym name
+    #[serde(deserialize_with = "keysym_name")]
+    pub key: String,
+    pub action: Option<MediaAction>,
+    /// Shell command
+    pub exec: Option<String>,
+    /// OSD shown after the command is executed
+    pub osd: Option<OsdKind>,
+    /// Shell command that prints the level shown in the OSD, as a percentage
+    pub level: Option<String>,
+}
+
+impl MediaKeyBinding {
+    fn exec(key: &str, exec: &str) -> Self {
+        Self {
+            key: key.into(),
+            action: None,
+            exec: Some(exec.into()),
+            osd: None,
+            level: None,
+        }
+    }
+
+    fn volume(key: &str, exec: &str) -> Self {
+        Self {
+            osd: Some(OsdKind::Volume),
+            level: Some("pactl get-sink-volume @DEFAULT_SINK@".into()),
+            ..Self::exec(key, exec)
+        }
+    }
+
+    fn action(key: &str, action: MediaAction) -> Self {
+        Self {
+            key: key.into(),
+            action: Some(action),
+            exec: None,
+            osd: None,
+            level: None,
+        }
+    }
+
+    fn defaults() -> Vec<Self> {
+        vec![
+            Self::volume(
+                "XF86AudioRaiseVolume",
+                "pactl set-sink-volume @DEFAULT_SINK@ +5%",
+            ),
+            Self::volume(
+                "XF86AudioLowerVolume",
+                "pactl set-sink-volume @DEFAULT_SINK@ -5%",
+            ),
+            Self::exec("XF86AudioMute", "pactl set-sink-mute @DEFAULT_SINK@ toggle"),
+            Self::exec(
+                "XF86AudioMicMute",
+                "pactl set-source-mute @DEFAULT_SOURCE@ toggle",
+            ),
+            Self::exec("XF86AudioPlay", "playerctl play-pause"),
+            Self::exec("XF86AudioNext", "playerctl next"),
+            Self::exec("XF86AudioPrev", "playerctl previous"),
+            Self::action("XF86MonBrightnessUp", MediaAction::BrightnessUp),
+            Self::action("XF86MonBrightnessDown", MediaAction::BrightnessDown),
+            Self::action("XF86KbdBrightnessUp", MediaAction::KbdBacklightUp),
+            Self::action("XF86KbdBrightnessDown", MediaAction::KbdBacklightDown),
+            // logind acts on these keys too, unless told to ignore them
+            // (HandlePowerKey and HandleSuspendKey in logind.conf)
+            Self::exec("XF86PowerOff", "systemctl poweroff"),
+            Self::exec("XF86Sleep", "systemctl suspend"),
+        ]
+    }
+}
+
+// The names are case sensitive, a name that matches only ignoring
+// the case is refused too, but the right one is suggested
+fn keysym_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    if xkb::keysym_from_name(&name, xkb::KEYSYM_NO_FLAGS) != keysyms::KEY_NoSymbol {
+        return Ok(name);
+    }
+    let message = match xkb::keysym_from_name(&name, xkb::KEYSYM_CASE_INSENSITIVE) {
+        keysyms::KEY_NoSymbol => format!("unknown keysym `{name}`"),
+        keysym => format!(
+            "unknown keysym `{name}`, did you mean `{}`?",
+            xkb::keysym_get_name(keysym)
+        ),
+    };
+    Err(D::Error::custom(message))
+}
+
+/// Rule used to recognize picture-in-picture windows (eg. the video
+/// popup of a browser), matching windows are made floating, always on top
+/// and parked in a corner of the output
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipConfig {
+    pub enabled: bool,
+    /// Windows with exactly this title are considered PiP
+    pub title: String,
+    /// Windows whose maximum size fits inside this size are considered PiP
+    pub max_size: (i32, i32),
+    /// Size given to the PiP window once floating
+    pub size: (i32, i32),
+    pub corner: Corner,
+    /// Distance from the output edges
+    pub margin: i32,
+}
+
+impl Default for PipConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            title: "Picture-in-Picture".to_string(),
+            max_size: (640, 360),
+            size: (480, 270),
+            corner: Corner::BottomRight,
+            margin: 16,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Where an output sits in the global layout, eg.
+/// ```toml
+/// [[outputs]]
+/// name = "DEL DELL U2720Q 1ABC234"
+/// right_of = "eDP-1"
+/// ```
+/// If none of the positioning options is specified (or the reference
+/// output is not connected) the output is placed to the right of the others
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Connector name (eg. eDP-1) or identifier of the monitor from its
+    /// EDID (see `outputs::identifier`), which follows it between ports
+    pub name: String,
+    /// Absolute position of the top left corner
+    pub position: Option<(i32, i32)>,
+    pub left_of: Option<String>,
+    pub right_of: Option<String>,
+    /// Cap of the repaint rate (eg. 60 on a 165Hz panel), 0 means the refresh
+    /// rate of the output. Can be changed at runtime through the IPC
+    #[serde(default)]
+    pub max_fps: u32,
+    /// Overrides `tiling.max_tiles` for this output, eg. more on a wide monitor
+    pub max_tiles: Option<usize>,
+    /// Variable refresh rate, if the monitor supports it
+    #[serde(default)]
+    pub vrr: bool,
+}
+
+/// Cursor theme used by the compositor, if not specified
+/// the XCURSOR_THEME and XCURSOR_SIZE variables are used
+#[derive(Deserialize, Default, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CursorConfig {
+    pub theme: Option<String>,
+    pub size: Option<u32>,
+    /// Hide the cursor when a key is pressed, until the pointer moves
+    pub hide_when_typing: bool,
+    /// Hide the cursor after this many seconds without pointer activity
+    pub hide_after: Option<u64>,
+}
+
+impl CursorConfig {
+    pub fn theme(&self) -> String {
+        self.theme
+            .clone()
+            .or_else(|| std::env::var("XCURSOR_THEME").ok())
+            .unwrap_or("default".into())
+    }
+
+    // The options are 24, 32, 48, 64, with the default normally being 24px.
+    pub fn size(&self) -> u32 {
+        self.size
+            .or_else(|| {
+                std::env::var("XCURSOR_SIZE")
+                    .ok()
+                    .and_then(|s| s.parse::<u32>().ok())
+            })
+            .unwrap_or(24)
+    }
+
+    /// Clients load their own cursors from the XCURSOR_* variables,
+    /// exporting them every spawned client will use the same theme
+    pub fn export_env(&self) {
+        std::env::set_var("XCURSOR_THEME", self.theme());
+        std::env::set_var("XCURSOR_SIZE", self.size().to_string());
+    }
+}
+
+/// Power saving applied only while running on battery
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PowerConfig {
+    /// Cap of the repaint rate, 0 means the refresh rate of the output
+    pub battery_max_fps: u32,
+    /// Workspace slides and fades
+    pub battery_animations: bool,
+    /// Seconds without input and client commits after which the periodic
+    /// timers (watchdog, pings, status line) stop until something happens,
+    /// 0 keeps them always running
+    pub suspend_timers_after: u64,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            battery_max_fps: 30,
+            battery_animations: false,
+            suspend_timers_after: 10,
+        }
+    }
+}
+
+/// libinput options of the devices with the given name (every device
+/// if the name is missing), options not specified keep the device default
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputDeviceConfig {
+    pub name: Option<String>,
+    pub tap: Option<bool>,
+    pub natural_scroll: Option<bool>,
+    pub left_handed: Option<bool>,
+    /// From -1 to 1
+    pub accel_speed: Option<f64>,
+    pub accel_profile: Option<AccelProfile>,
+    /// Touchpads ignore the touches for a while after a key is typed.
+    /// Palm detection is always done by libinput, it can be tuned
+    /// only with its quirks files
+    pub disable_while_typing: Option<bool>,
+}
+
+/// Pointer acceleration, `flat` moves the pointer proportionally
+/// to the device movement (eg. for games)
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccelProfile {
+    Flat,
+    Adaptive,
+}
+
+impl From<AccelProfile> for input::AccelProfile {
+    fn from(profile: AccelProfile) -> Self {
+        match profile {
+            AccelProfile::Flat => input::AccelProfile::Flat,
+            AccelProfile::Adaptive => input::AccelProfile::Adaptive,
+        }
+    }
+}
+
+/// What happens without any input, times are in seconds
+/// from the last input and 0 disables the stage
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdleConfig {
+    pub dim_after: u64,
+    /// Brightness of the dimmed output, from 0 to 1
+    pub dim_level: f32,
+    /// Turn the output off (DPMS)
+    pub off_after: u64,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self {
+            dim_after: 300,
+            dim_level: 0.4,
+            off_after: 330,
+        }
+    }
+}
+
+/// XKB keymap of the keyboard, the empty strings mean the system default
+/// (or the XKB_DEFAULT_* variables), eg.
+/// ```toml
+/// [keyboard]
+/// layout = "us"
+/// variant = "intl" # dead keys
+/// options = "compose:ralt"
+/// compose_file = "~/.XCompose"
+/// ```
+#[derive(Deserialize, Default, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyboardConfig {
+    pub rules: String,
+    pub model: String,
+    pub layout: String,
+    pub variant: String,
+    /// eg. "compose:ralt" to use the right Alt as compose key
+    pub options: Option<String>,
+    /// Compose sequences used by the clients instead of the locale ones
+    pub compose_file: Option<String>,
+}
+
+// Keysyms of the dead keys, from dead_grave to dead_greek
+const DEAD_KEYS: std::ops::RangeInclusive<u32> = 0xfe50..=0xfe8c;
+
+impl KeyboardConfig {
+    pub fn xkb_config(&self) -> XkbConfig<'_> {
+        XkbConfig {
+            rules: &self.rules,
+            model: &self.model,
+            layout: &self.layout,
+            variant: &self.variant,
+            options: self.options.clone(),
+        }
+    }
+
+    /// Compose and dead keys are handled by the clients, the compositor
+    /// only sends the keymap, so the compose file is exported to them
+    pub fn export_env(&self) {
+        match self.compose_file.as_deref() {
+            Some(path) => {
+                let path = match path.strip_prefix("~/") {
+                    Some(rest) => std::env::var("HOME")
+                        .map(|home| format!("{home}/{rest}"))
+                        .unwrap_or(path.to_string()),
+                    None => path.to_string(),
+                };
+                std::env::set_var("XCOMPOSEFILE", path);
+            }
+            None => std::env::remove_var("XCOMPOSEFILE"),
+        }
+    }
+
+    /// Compile the keymap sent to the clients and check that the
+    /// requested compose key and dead keys are really there,
+    /// a typo in the options would otherwise be silently ignored
+    pub fn verify_keymap(&self) {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let Some(keymap) = xkb::Keymap::new_from_names(
+            &context,
+            &self.rules,
+            &self.model,
+            &self.layout,
+            &self.variant,
+            self.options.clone(),
+            xkb::COMPILE_NO_FLAGS,
+        ) else {
+            println!("Invalid keyboard config, the keymap can't be compiled: {self:?}");
+            return;
+        };
+
+        let mut compose = false;
+        let mut dead_keys = false;
+        for key in keymap.min_keycode()..=keymap.max_keycode() {
+            for layout in 0..keymap.num_layouts_for_key(key) {
+                for level in 0..keymap.num_levels_for_key(key, layout) {
+                    for keysym in keymap.key_get_syms_by_level(key, layout, level) {
+                        compose |= *keysym == keysyms::KEY_Multi_key;
+                        dead_keys |= DEAD_KEYS.contains(keysym);
+                    }
+                }
+            }
+        }
+
+        let wants_compose = self
+            .options
+            .as_deref()
+            .is_some_and(|options| options.contains("compose:"));
+        if wants_compose && !compose {
+            println!("The keymap has no compose key, check the keyboard options");
+        }
+        if self.compose_file.is_some() && !compose && !dead_keys {
+            println!("A compose file is set but the keymap has no compose or dead keys");
+        }
+        tracing::debug!("KEYMAP compose key: {compose}, dead keys: {dead_keys}");
+    }
+}
+
+/// RGBA color, written in the config as "#rrggbb" or "#rrggbbaa"
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Color(pub [f32; 4]);
+
+impl Eq for Color {}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(hex: String) -> Result<Self, Self::Error> {
+        let digits = hex
+            .strip_prefix('#')
+            .filter(|digits| matches!(digits.len(), 6 | 8) && digits.is_ascii())
+            .ok_or_else(|| format!("invalid color `{hex}`, expected #rrggbb or #rrggbbaa"))?;
+
+        let mut color = [1.0; 4];
+        for (i, channel) in color.iter_mut().enumerate().take(digits.len() / 2) {
+            let value = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+                .map_err(|_| format!("invalid color `{hex}`"))?;
+            *channel = value as f32 / 255.0;
+        }
+        // Colors are premultiplied by the renderer
+        let alpha = color[3];
+        for channel in color.iter_mut().take(3) {
+            *channel *= alpha;
+        }
+        Ok(Color(color))
+    }
+}
+
+impl Color {
+    fn hex(hex: &str) -> Self {
+        Self::try_from(hex.to_string()).expect("IMP invalid default color")
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DecorationButton {
+    Close,
+    Maximize,
+    Float,
+}
+
+/// Colors of the decorations of a window in a specific state
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DecorationColors {
+    pub titlebar: Color,
+    pub border: Color,
+    pub text: Color,
+}
+
+/// Width of the borders depending on the window state, 0 hides them.
+/// If the tiled ones differ, the clients are resized when the focus changes
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BorderWidths {
+    pub focused: i32,
+    pub unfocused: i32,
+    pub urgent: i32,
+    pub floating: i32,
+}
+
+impl Default for BorderWidths {
+    fn default() -> Self {
+        Self {
+            focused: 2,
+            unfocused: 2,
+            urgent: 2,
+            floating: 2,
+        }
+    }
+}
+
+/// Server side decorations, used by the clients that support
+/// xdg-decoration and accept them, eg.
+/// ```toml
+/// [decorations]
+/// font = "DejaVu Sans"
+/// titlebar_height = 20
+/// buttons = ["close"]
+///
+/// [decorations.focused]
+/// titlebar = "#285577"
+/// border = "#4c7899"
+/// text = "#ffffff"
+/// ```
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DecorationConfig {
+    /// Ask the clients to not draw their own decorations
+    pub server_side: bool,
+    pub titlebar_height: i32,
+    pub border_width: BorderWidths,
+    /// Font family, resolved with fontconfig
+    pub font: String,
+    pub font_size: f32,
+    /// Buttons shown in the titlebar, from left to right
+    pub buttons: Vec<DecorationButton>,
+    pub focused: DecorationColors,
+    pub unfocused: DecorationColors,
+    /// Windows that asked for attention
+    pub urgent: DecorationColors,
+    /// Fade the border color when the focus moves (not on battery,
+    /// unless `power.battery_animations` is set)
+    pub animate_focus: bool,
+    /// Windows that draw their own decorations get the border too,
+    /// so the focused tile can always be told apart
+    pub client_side_borders: bool,
+}
+
+impl Default for DecorationConfig {
+    fn default() -> Self {
+        Self {
+            server_side: true,
+            titlebar_height: 24,
+            border_width: BorderWidths::default(),
+            font: "Sans".into(),
+            font_size: 13.0,
+            buttons: vec![
+                DecorationButton::Float,
+                DecorationButton::Maximize,
+                DecorationButton::Close,
+            ],
+            focused: DecorationColors {
+                titlebar: Color::hex("#285577"),
+                border: Color::hex("#4c7899"),
+                text: Color::hex("#ffffff"),
+            },
+            unfocused: DecorationColors {
+                titlebar: Color::hex("#222222"),
+                border: Color::hex("#333333"),
+                text: Color::hex("#888888"),
+            },
+            urgent: DecorationColors {
+                titlebar: Color::hex("#900000"),
+                border: Color::hex("#2f343a"),
+                text: Color::hex("#ffffff"),
+            },
+            animate_focus: true,
+            client_side_borders: true,
+        }
+    }
+}
+
+/// Keyboard focus, it follows the pointer
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FocusConfig {
+    /// When the focused window closes the pointer moves to the window
+    /// that gets the focus, so that the focus doesn't jump to the window
+    /// under the pointer as soon as it moves
+    pub warp_pointer: bool,
+}
+
+/// How new windows are tiled
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TilingConfig {
+    /// A new window is not tiled right away: the half of a tile it will
+    /// take is highlighted, moved with the arrows and confirmed with Enter
+    pub manual_placement: bool,
+    /// Tiles a workspace can have, the next windows open on the next empty
+    /// workspace instead of splitting the tiles in slivers. 0 means no cap
+    pub max_tiles: usize,
+    /// Space between the tiles, and between them and the edges of the output
+    pub gaps: i32,
+    /// No gaps when a workspace has a single tile
+    pub smart_gaps: bool,
+    /// No border around a single tile
+    pub smart_borders: bool,
+    /// Smallest width and height of a tile, a new window that would split
+    /// a tile below it goes where `refused_split` says. 0 means no minimum
+    pub min_tile_size: (i32, i32),
+    pub refused_split: RefusedSplit,
+    /// The tiles of the active workspace are saved on exit, and the windows
+    /// with the same app ids go back to their tiles when they open again
+    pub restore_layout: bool,
+    /// The split of a new tile alternates with its depth (side by side, one
+    /// above the other and so on) instead of being chosen with Shift+V/O
+    pub spiral: bool,
+}
+
+/// Where a new window goes when splitting a tile would make it too small
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RefusedSplit {
+    /// Tiled on the next empty workspace, floating if there is none
+    #[default]
+    NextWorkspace,
+    /// Floating in the middle of the output
+    Floating,
+    /// A tab next to the tile that would have been split
+    Tabbed,
+}
+
+/// Names of the workspaces shown by the IPC and the status line
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkspaceConfig {
+    /// The name is the number followed by the app ids
+    /// of the windows, eg. "3: firefox nvim"
+    pub auto_rename: bool,
+}
+
+/// What is started after a crash, instead of leaving the tty black
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrashConfig {
+    /// Start aigi again with `--safe-mode`
+    pub safe_mode: bool,
+    /// Shell command executed instead (eg. another compositor)
+    pub exec: Option<String>,
+}
+
+/// Layer surfaces of the notification daemons, hidden by do not disturb
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationConfig {
+    /// Namespaces of their layer surfaces, eg. mako uses "notifications"
+    pub namespaces: Vec<String>,
+    /// Connector name (or EDID identifier) of the output where they are shown,
+    /// instead of the one chosen by the daemon
+    pub output: Option<String>,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            namespaces: vec!["notifications".into()],
+            output: None,
+        }
+    }
+}
+
+/// Copy and paste between the clients
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClipboardConfig {
+    pub sync: ClipboardSync,
+}
+
+/// Which selection is mirrored in the other, so a middle click pastes
+/// what was copied (or the other way around)
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardSync {
+    #[default]
+    None,
+    ClipboardToPrimary,
+    PrimaryToClipboard,
+    Both,
+}
+
+/// Sensitive globals an executable can use, eg.
+/// ```toml
+/// [[permissions]]
+/// executable = "/usr/bin/grim"
+/// allow = ["screencopy"]
+/// ```
+/// Executables without an entry can only be granted them through the IPC
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PermissionConfig {
+    /// Resolved path, as in /proc/<pid>/exe
+    pub executable: PathBuf,
+    pub allow: Vec<Permission>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    // The error as printed, with the line of the file
+    fn error(content: &str) -> String {
+        match toml::from_str::<Config>(content) {
+            Ok(_) => panic!("the config was accepted"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn modifiers_ignore_the_case_and_accept_the_aliases() {
+        let modifiers = Modifiers::try_from(names(&["Super", "shift", "Control", "Mod1"])).unwrap();
+        assert_eq!(
+            modifiers,
+            Modifiers {
+                ctrl: true,
+                alt: true,
+                shift: true,
+                logo: true,
+            }
+        );
+        assert_eq!(Modifiers::try_from(vec![]), Ok(Modifiers::default()));
+    }
+
+    #[test]
+    fn unknown_modifiers_are_refused() {
+        assert_eq!(
+            Modifiers::try_from(names(&["Super", "Hyper"])),
+            Err("unknown modifier `Hyper`".to_string())
+        );
+        let err = error(
+            "[[pointer_bindings]]\nmodifiers = [\"Meta\"]\nbutton = \"left\"\naction = \"move\"\n",
+        );
+        assert!(err.contains("line 2"), "{err}");
+        assert!(err.contains("unknown modifier `Meta`"), "{err}");
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_with_their_line() {
+        let err = error("snap_threshold = 10\n\n[decorations]\ntitlebar_heigth = 20\n");
+        assert!(err.contains("line 4"), "{err}");
+        assert!(err.contains("unknown field `titlebar_heigth`"), "{err}");
+
+        let err = error("snap_treshold = 10\n");
+        assert!(err.contains("line 1"), "{err}");
+        assert!(err.contains("unknown field `snap_treshold`"), "{err}");
+    }
+
+    #[test]
+    fn unknown_keysyms_are_reported_with_their_line() {
+        let err = error("[[media_keys]]\nkey = \"NotAKey\"\nexec = \"true\"\n");
+        assert!(err.contains("line 2"), "{err}");
+        assert!(err.contains("unknown keysym `NotAKey`"), "{err}");
+
+        // Only the case is wrong, the right name is suggested
+        let err = error("[[media_keys]]\nexec = \"true\"\nkey = \"xf86audiomute\"\n");
+        assert!(err.contains("line 3"), "{err}");
+        assert!(
+            err.contains("unknown keysym `xf86audiomute`, did you mean `XF86AudioMute`?"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn a_valid_config_is_accepted() {
+        let config = toml::from_str::<Config>(
+            "snap_threshold = 10\n\n[[media_keys]]\nkey = \"XF86AudioMute\"\nexec = \"true\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.snap_threshold, 10);
+        assert_eq!(config.media_keys[0].key, "XF86AudioMute");
+    }
+}
@@ -0,0 +1,145 @@
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::content_type::v1::server::{
+            wp_content_type_manager_v1::{self, WpContentTypeManagerV1},
+            wp_content_type_v1::{self, Type, WpContentTypeV1},
+        },
+        wayland_server::{
+            protocol::wl_surface::WlSurface, Client, DataInit, Dispatch, DisplayHandle,
+            GlobalDispatch, New, Resource, WEnum,
+        },
+    },
+    wayland::compositor::with_states,
+};
+use std::sync::Mutex;
+
+use crate::state::AIGIState;
+
+const VERSION: u32 = 1;
+
+/// Content type of a surface, double buffered like the rest of its state:
+/// `pending` is applied by the next commit (see `commit`)
+#[derive(Default)]
+struct ContentTypeData {
+    pending: Option<Type>,
+    current: Option<Type>,
+    // A surface has at most one content type object
+    bound: bool,
+}
+
+/// wp-content-type, the clients tell what a surface shows (eg. a video)
+pub fn create_global(display: &DisplayHandle) {
+    display.create_global::<AIGIState, WpContentTypeManagerV1, _>(VERSION, ());
+}
+
+/// Apply the pending content type, called on every commit of the surface
+pub fn commit(surface: &WlSurface) {
+    with_states(surface, |states| {
+        if let Some(data) = states.data_map.get::<Mutex<ContentTypeData>>() {
+            let mut data = data.lock().unwrap();
+            data.current = data.pending;
+        }
+    });
+}
+
+/// True if the client said that the surface shows a video
+pub fn is_video(surface: &WlSurface) -> bool {
+    with_states(surface, |states| {
+        states
+            .data_map
+            .get::<Mutex<ContentTypeData>>()
+            .map_or(false, |data| {
+                data.lock().unwrap().current == Some(Type::Video)
+            })
+    })
+}
+
+impl GlobalDispatch<WpContentTypeManagerV1, ()> for AIGIState {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        manager: New<WpContentTypeManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(manager, ());
+    }
+}
+
+impl Dispatch<WpContentTypeManagerV1, ()> for AIGIState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        manager: &WpContentTypeManagerV1,
+        request: wp_content_type_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_content_type_manager_v1::Request::GetSurfaceContentType { id, surface } => {
+                let already_bound = with_states(&surface, |states| {
+                    states
+                        .data_map
+                        .insert_if_missing_threadsafe(|| Mutex::new(ContentTypeData::default()));
+                    let mut data = states
+                        .data_map
+                        .get::<Mutex<ContentTypeData>>()
+                        .unwrap()
+                        .lock()
+                        .unwrap();
+                    std::mem::replace(&mut data.bound, true)
+                });
+                if already_bound {
+                    manager.post_error(
+                        wp_content_type_manager_v1::Error::AlreadyConstructed,
+                        "the surface already has a content type object",
+                    );
+                    return;
+                }
+                data_init.init(id, surface);
+            }
+            wp_content_type_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WpContentTypeV1, WlSurface> for AIGIState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _object: &WpContentTypeV1,
+        request: wp_content_type_v1::Request,
+        surface: &WlSurface,
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        // The surface could be gone already, then there is nothing to update
+        if !surface.is_alive() {
+            return;
+        }
+        let content_type = match request {
+            wp_content_type_v1::Request::SetContentType {
+                content_type: WEnum::Value(content_type),
+            } => Some(content_type),
+            wp_content_type_v1::Request::SetContentType { .. } => return,
+            // Destroying the object resets the content type on the next commit
+            wp_content_type_v1::Request::Destroy => None,
+            _ => unreachable!(),
+        };
+        with_states(surface, |states| {
+            let mut data = states
+                .data_map
+                .get::<Mutex<ContentTypeData>>()
+                .unwrap()
+                .lock()
+                .unwrap();
+            data.pending = content_type;
+            if content_type.is_none() {
+                data.bound = false;
+            }
+        });
+    }
+}
@@ -0,0 +1,173 @@
+use serde::Serialize;
+use smithay::{backend::drm::DrmDeviceFd, reexports::drm::Device};
+use std::{
+    os::unix::process::CommandExt,
+    panic::PanicInfo,
+    process::Command,
+    sync::{Mutex, OnceLock, TryLockError},
+};
+
+use crate::{
+    config::{self, CrashConfig},
+    decorations,
+    ipc::{self, WindowInfo, WorkspaceInfo},
+    state::AIGIState,
+};
+
+/// What is known when the hook runs: the state is owned by the event
+/// loop, so the interesting parts are copied here when they change
+struct CrashContext {
+    // Its DRM master is released, otherwise the tty stays black.
+    // None in a window, there is no tty
+    drm: Option<DrmDeviceFd>,
+    config: CrashConfig,
+    safe_mode: bool,
+    snapshot: Option<String>,
+}
+
+static CONTEXT: OnceLock<Mutex<CrashContext>> = OnceLock::new();
+
+#[derive(Serialize)]
+struct Snapshot {
+    workspaces: Vec<WorkspaceInfo>,
+    windows: Vec<WindowInfo>,
+    tiles: Vec<TileInfo>,
+}
+
+#[derive(Serialize)]
+struct TileInfo {
+    title: String,
+    geometry: (i32, i32, i32, i32),
+}
+
+#[derive(Serialize)]
+struct CrashReport<'a> {
+    panic: String,
+    snapshot: Option<&'a str>,
+}
+
+/// Install the panic hook, after the default one (which prints the
+/// message) the last snapshot is saved in `$XDG_STATE_HOME/aigi/crash.json`,
+/// the DRM master is released and the fallback, if any, is executed
+pub fn install(drm: Option<DrmDeviceFd>, config: CrashConfig, safe_mode: bool) {
+    let context = CrashContext {
+        drm,
+        config,
+        safe_mode,
+        snapshot: None,
+    };
+    if CONTEXT.set(Mutex::new(context)).is_err() {
+        return;
+    }
+
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        crashed(info);
+    }));
+}
+
+/// The fallback can change reloading the config
+pub fn set_config(config: CrashConfig) {
+    if let Some(context) = CONTEXT.get() {
+        if let Ok(mut context) = context.lock() {
+            context.config = config;
+        }
+    }
+}
+
+/// Copy the workspaces, the windows and the tiles, called when they change
+pub fn update_snapshot(state: &AIGIState) {
+    let Some(context) = CONTEXT.get() else {
+        return;
+    };
+    let tiles = state
+        .tiling_state
+        .placed_tiles()
+        .into_iter()
+        .map(|(window, geometry)| TileInfo {
+            title: decorations::title(&window),
+            geometry: (
+                geometry.loc.x,
+                geometry.loc.y,
+                geometry.size.w,
+                geometry.size.h,
+            ),
+        })
+        .collect();
+    let snapshot = Snapshot {
+        workspaces: ipc::workspaces(state),
+        windows: ipc::windows(state),
+        tiles,
+    };
+    // Serialized before locking, the lock is held only to swap the string:
+    // a panic while it's held would leave the hook waiting for itself
+    let Ok(snapshot) = serde_json::to_string_pretty(&snapshot) else {
+        return;
+    };
+    if let Ok(mut context) = context.lock() {
+        context.snapshot = Some(snapshot);
+    }
+}
+
+// Nothing here can panic again, the errors are only printed
+fn crashed(info: &PanicInfo) {
+    let Some(context) = CONTEXT.get() else {
+        return;
+    };
+    // Never waited for: if the lock is held the panic came while holding it,
+    // or from another thread at the same time
+    let context = match context.try_lock() {
+        Ok(context) => context,
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(TryLockError::WouldBlock) => {
+            eprintln!("Crash context busy, nothing saved");
+            return;
+        }
+    };
+
+    let report = CrashReport {
+        panic: info.to_string(),
+        snapshot: context.snapshot.as_deref(),
+    };
+    match (
+        config::state_path("crash.json"),
+        serde_json::to_string_pretty(&report),
+    ) {
+        (Some(path), Ok(report)) => {
+            let saved = path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| std::fs::write(&path, report));
+            match saved {
+                Ok(()) => eprintln!("State before the crash saved in {path:?}"),
+                Err(err) => eprintln!("Crash report not saved in {path:?}: {err}"),
+            }
+        }
+        _ => eprintln!("Crash report not saved, there is no state directory"),
+    }
+
+    if let Some(Err(err)) = context.drm.as_ref().map(|drm| drm.release_master_lock()) {
+        eprintln!("DRM master not released: {err}");
+    }
+
+    // A crash in safe mode would start it again forever
+    let fallback = match (&context.config.exec, context.config.safe_mode) {
+        (Some(exec), _) => {
+            let mut command = Command::new("sh");
+            command.args(["-c", exec]);
+            Some(command)
+        }
+        (None, true) if !context.safe_mode => std::env::current_exe().ok().map(|executable| {
+            let mut command = Command::new(executable);
+            command.arg("--safe-mode");
+            command
+        }),
+        _ => None,
+    };
+    if let Some(mut command) = fallback {
+        eprintln!("Starting the fallback: {command:?}");
+        let err = command.exec();
+        eprintln!("Fallback not started: {err}");
+    }
+}
@@ -0,0 +1,700 @@
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            element::{
+                solid::{SolidColorBuffer, SolidColorRenderElement},
+                texture::{TextureBuffer, TextureRenderElement},
+                Kind,
+            },
+            multigpu::MultiTexture,
+            ImportAll, ImportMem, Renderer,
+        },
+    },
+    desktop::Window,
+    reexports::{
+        wayland_protocols::xdg::{
+            decoration::zv1::server::zxdg_toplevel_decoration_v1::Mode as DecorationMode,
+            shell::server::xdg_toplevel,
+        },
+        wayland_server::protocol::wl_surface::WlSurface,
+    },
+    utils::{Logical, Point, Rectangle, Scale, Size, Transform},
+    wayland::{compositor::with_states, shell::xdg::XdgToplevelSurfaceData},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::config::{Color, DecorationButton, DecorationColors, DecorationConfig};
+use crate::toplevel_icon::{self, ToplevelIcon};
+
+// Space between the titlebar edges and its content
+const TITLEBAR_PADDING: i32 = 4;
+
+const CLOSE_COLOR: [f32; 4] = [0.88, 0.42, 0.46, 1.0];
+const MAXIMIZE_COLOR: [f32; 4] = [0.6, 0.76, 0.47, 1.0];
+const FLOAT_COLOR: [f32; 4] = [0.9, 0.75, 0.48, 1.0];
+
+const BORDER_FADE_DURATION: Duration = Duration::from_millis(150);
+
+/// Space taken by the decorations around the client area of a window
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Insets {
+    pub top: i32,
+    pub bottom: i32,
+    pub left: i32,
+    pub right: i32,
+}
+
+impl Insets {
+    /// Client area of a window whose decorations fill `outer`
+    pub fn shrink(&self, outer: Rectangle<i32, Logical>) -> Rectangle<i32, Logical> {
+        Rectangle::from_loc_and_size(
+            (outer.loc.x + self.left, outer.loc.y + self.top),
+            (
+                (outer.size.w - self.left - self.right).max(1),
+                (outer.size.h - self.top - self.bottom).max(1),
+            ),
+        )
+    }
+}
+
+/// Decorations are drawn only if the client agreed to it,
+/// the pending state is what the next configure will contain
+pub fn server_side(window: &Window) -> bool {
+    window
+        .toplevel()
+        .with_pending_state(|state| state.decoration_mode == Some(DecorationMode::ServerSide))
+}
+
+/// Width of the border of a window, floating windows have
+/// their own width whatever their state is
+pub fn border_width(config: &DecorationConfig, status: DecorationStatus, floating: bool) -> i32 {
+    let widths = &config.border_width;
+    let width = match status {
+        _ if floating => widths.floating,
+        DecorationStatus::Focused => widths.focused,
+        DecorationStatus::Unfocused => widths.unfocused,
+        DecorationStatus::Urgent => widths.urgent,
+    };
+    width.max(0)
+}
+
+/// Fullscreen windows are never decorated, the ones
+/// with their own decorations get only the border
+pub fn insets(window: &Window, config: &DecorationConfig, border: i32) -> Insets {
+    let fullscreen = window
+        .toplevel()
+        .with_pending_state(|state| state.states.contains(xdg_toplevel::State::Fullscreen));
+    if fullscreen {
+        return Insets::default();
+    }
+    if !server_side(window) {
+        return match config.client_side_borders {
+            true => Insets {
+                top: border,
+                bottom: border,
+                left: border,
+                right: border,
+            },
+            false => Insets::default(),
+        };
+    }
+
+    Insets {
+        top: config.titlebar_height.max(0) + border,
+        bottom: border,
+        left: border,
+        right: border,
+    }
+}
+
+pub fn title(window: &Window) -> String {
+    with_states(window.toplevel().wl_surface(), |states| {
+        states
+            .data_map
+            .get::<XdgToplevelSurfaceData>()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .title
+            .clone()
+            .unwrap_or_default()
+    })
+}
+
+pub fn app_id(window: &Window) -> String {
+    with_states(window.toplevel().wl_surface(), |states| {
+        states
+            .data_map
+            .get::<XdgToplevelSurfaceData>()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .app_id
+            .clone()
+            .unwrap_or_default()
+    })
+}
+
+/// Part of the decorations of a window
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecorationHit {
+    Button(DecorationButton),
+    /// Titlebar outside the buttons, dragging it moves the window
+    Titlebar,
+}
+
+/// What is at `point` (relative to the client area of the window),
+/// the borders are not interactive
+pub fn hit(
+    window: &Window,
+    config: &DecorationConfig,
+    point: Point<f64, Logical>,
+) -> Option<DecorationHit> {
+    if !drawn(window) {
+        return None;
+    }
+
+    let size = window.geometry().size;
+    let titlebar_height = config.titlebar_height.max(0);
+    let titlebar = Rectangle::from_loc_and_size((0, -titlebar_height), (size.w, titlebar_height));
+    if !titlebar.to_f64().contains(point) {
+        return None;
+    }
+
+    let button = button_geometries(config, size.w)
+        .into_iter()
+        .find(|(_, geometry)| geometry.to_f64().contains(point))
+        .map(|(button, _)| button);
+    Some(button.map_or(DecorationHit::Titlebar, DecorationHit::Button))
+}
+
+// Decorations follow the state acked by the client, not the pending one
+fn drawn(window: &Window) -> bool {
+    let current = window.toplevel().current_state();
+    current.decoration_mode == Some(DecorationMode::ServerSide)
+        && !current.states.contains(xdg_toplevel::State::Fullscreen)
+}
+
+// A window that draws its own decorations gets only the border
+fn border_only(window: &Window, config: &DecorationConfig) -> bool {
+    let current = window.toplevel().current_state();
+    config.client_side_borders
+        && current.decoration_mode != Some(DecorationMode::ServerSide)
+        && !current.states.contains(xdg_toplevel::State::Fullscreen)
+}
+
+/// Side of the buttons and of the icon of the client in the titlebar
+pub fn icon_size(config: &DecorationConfig) -> i32 {
+    (config.titlebar_height.max(0) - 2 * TITLEBAR_PADDING).max(0)
+}
+
+// Buttons are aligned to the right edge of the titlebar,
+// geometries are relative to the client area
+fn button_geometries(
+    config: &DecorationConfig,
+    width: i32,
+) -> Vec<(DecorationButton, Rectangle<i32, Logical>)> {
+    let titlebar_height = config.titlebar_height.max(0);
+    let button_size = icon_size(config);
+    let count = config.buttons.len() as i32;
+    config
+        .buttons
+        .iter()
+        .enumerate()
+        .map(|(i, button)| {
+            let from_right = (count - i as i32) * (TITLEBAR_PADDING + button_size);
+            let geometry = Rectangle::from_loc_and_size(
+                (width - from_right, -titlebar_height + TITLEBAR_PADDING),
+                (button_size, button_size),
+            );
+            (*button, geometry)
+        })
+        .collect()
+}
+
+/// Colors used for a window, depending on its state
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecorationStatus {
+    Focused,
+    Unfocused,
+    Urgent,
+}
+
+/// How the decorations of a window are drawn in the current frame
+#[derive(Clone, Copy, Debug)]
+pub struct DecorationStyle {
+    pub status: DecorationStatus,
+    pub border: i32,
+    /// Fade the border color when the status changes
+    pub animate: bool,
+}
+
+// Title already rasterized, recreated only if something changes
+struct Title {
+    text: String,
+    max_width: i32,
+    scale: i32,
+    color: Color,
+    buffer: Option<TextureBuffer<MultiTexture>>,
+}
+
+// Icon of the client already imported, recreated when it's replaced
+struct Icon {
+    icon: Arc<ToplevelIcon>,
+    scale: i32,
+    buffer: Option<TextureBuffer<MultiTexture>>,
+}
+
+// Buffers are kept between frames so the damage tracker can
+// tell if a decoration really changed
+#[derive(Default)]
+struct WindowDecoration {
+    titlebar: SolidColorBuffer,
+    borders: [SolidColorBuffer; 4],
+    buttons: Vec<SolidColorBuffer>,
+    title: Option<Title>,
+    icon: Option<Icon>,
+    // Color of the border for the current status, and the color
+    // the border is fading from (with the start of the fade)
+    border_target: Option<[f32; 4]>,
+    border_fade: Option<([f32; 4], Instant)>,
+}
+
+impl WindowDecoration {
+    // Top, bottom, left and right borders, around the titlebar too
+    fn border_elements<R: Renderer + ImportAll + ImportMem>(
+        &mut self,
+        colors: &DecorationColors,
+        style: DecorationStyle,
+        location: Point<i32, Logical>,
+        size: Size<i32, Logical>,
+        titlebar_height: i32,
+        scale: Scale<f64>,
+    ) -> Vec<DecorationRenderElement<R>> {
+        let border = style.border;
+        let border_color = self.border_color(colors.border.0, style.animate);
+        let outer_width = size.w + 2 * border;
+        let side_height = size.h + titlebar_height;
+        let borders = [
+            ((-border, -titlebar_height - border), (outer_width, border)),
+            ((-border, size.h), (outer_width, border)),
+            ((-border, -titlebar_height), (border, side_height)),
+            ((size.w, -titlebar_height), (border, side_height)),
+        ];
+        self.borders
+            .iter_mut()
+            .zip(borders)
+            .map(|(buffer, (loc, size))| {
+                buffer.update(size, border_color);
+                solid_element(buffer, location + Point::from(loc), scale)
+            })
+            .collect()
+    }
+
+    // Color of the border in this frame, a fade starts from
+    // the color currently shown every time the target changes
+    fn border_color(&mut self, target: [f32; 4], animate: bool) -> [f32; 4] {
+        if self.border_target != Some(target) {
+            let shown = self.border_target.map(|previous| self.faded(previous));
+            self.border_fade = shown
+                .filter(|_| animate)
+                .map(|color| (color, Instant::now()));
+            self.border_target = Some(target);
+        }
+
+        let color = self.faded(target);
+        if self
+            .border_fade
+            .is_some_and(|(_, started)| started.elapsed() >= BORDER_FADE_DURATION)
+        {
+            self.border_fade = None;
+        }
+        color
+    }
+
+    fn faded(&self, target: [f32; 4]) -> [f32; 4] {
+        let Some((from, started)) = self.border_fade else {
+            return target;
+        };
+        let progress =
+            (started.elapsed().as_secs_f32() / BORDER_FADE_DURATION.as_secs_f32()).min(1.0);
+        let mut color = target;
+        for (channel, from) in color.iter_mut().zip(from) {
+            *channel = from + (*channel - from) * progress;
+        }
+        color
+    }
+}
+
+/// Server side decorations: a titlebar (with the title and the buttons)
+/// and a border around the window, everything themable from the config.
+/// Windows with their own decorations get only the border
+#[derive(Default)]
+pub struct DecorationState {
+    windows: HashMap<WlSurface, WindowDecoration>,
+    // Windows that asked for attention, until they get the focus
+    urgent: HashSet<WlSurface>,
+    // Button under the pointer, drawn highlighted
+    hovered: Option<(WlSurface, DecorationButton)>,
+    // Button where the pointer button was pressed, its action
+    // is triggered only if it's released over the same button
+    pressed: Option<(WlSurface, DecorationButton)>,
+    // Loaded on first use, None inside if the font can't be loaded
+    font: Option<Option<fontdue::Font>>,
+}
+
+impl DecorationState {
+    pub fn status(&self, wl_surface: &WlSurface, focus: Option<&WlSurface>) -> DecorationStatus {
+        if focus == Some(wl_surface) {
+            DecorationStatus::Focused
+        } else if self.is_urgent(wl_surface) {
+            DecorationStatus::Urgent
+        } else {
+            DecorationStatus::Unfocused
+        }
+    }
+
+    /// Some border is fading, new frames are needed until it ends
+    pub fn animating(&self) -> bool {
+        self.windows
+            .values()
+            .any(|decoration| decoration.border_fade.is_some())
+    }
+
+    pub fn is_urgent(&self, wl_surface: &WlSurface) -> bool {
+        self.urgent.contains(wl_surface)
+    }
+
+    pub fn set_urgent(&mut self, wl_surface: &WlSurface, urgent: bool) {
+        if urgent {
+            self.urgent.insert(wl_surface.clone());
+        } else {
+            self.urgent.remove(wl_surface);
+        }
+    }
+
+    /// Returns true if the hovered button changed
+    pub fn set_hovered(&mut self, hovered: Option<(WlSurface, DecorationButton)>) -> bool {
+        let changed = self.hovered != hovered;
+        self.hovered = hovered;
+        changed
+    }
+
+    pub fn press(&mut self, wl_surface: &WlSurface, button: DecorationButton) {
+        self.pressed = Some((wl_surface.clone(), button));
+    }
+
+    pub fn take_pressed(&mut self) -> Option<(WlSurface, DecorationButton)> {
+        self.pressed.take()
+    }
+
+    pub fn remove(&mut self, wl_surface: &WlSurface) {
+        self.windows.remove(wl_surface);
+        self.urgent.remove(wl_surface);
+        if self.hovered.as_ref().is_some_and(|(s, _)| s == wl_surface) {
+            self.hovered = None;
+        }
+        if self.pressed.as_ref().is_some_and(|(s, _)| s == wl_surface) {
+            self.pressed = None;
+        }
+    }
+
+    /// Font of the titlebars, also used by the other text drawn by the compositor
+    pub fn font(&mut self, config: &DecorationConfig) -> Option<&fontdue::Font> {
+        self.font.get_or_insert_with(|| load_font(config)).as_ref()
+    }
+
+    /// Forget the font and the rasterized titles (eg. the config changed)
+    pub fn reload(&mut self) {
+        self.font = None;
+        for decoration in self.windows.values_mut() {
+            decoration.title = None;
+        }
+    }
+
+    /// Elements of the decorations of a window whose client area is at
+    /// `location` (relative to the output) and drawn with `size`,
+    /// the first is the topmost one
+    pub fn render_elements<R>(
+        &mut self,
+        renderer: &mut R,
+        config: &DecorationConfig,
+        window: &Window,
+        location: Point<i32, Logical>,
+        size: Size<i32, Logical>,
+        scale: Scale<f64>,
+        style: DecorationStyle,
+    ) -> Vec<DecorationRenderElement<R>>
+    where
+        R: Renderer<TextureId = MultiTexture> + ImportAll + ImportMem,
+    {
+        let wl_surface = window.toplevel().wl_surface();
+        let colors = match style.status {
+            DecorationStatus::Focused => &config.focused,
+            DecorationStatus::Unfocused => &config.unfocused,
+            DecorationStatus::Urgent => &config.urgent,
+        };
+        if !drawn(window) {
+            if !border_only(window, config) {
+                return vec![];
+            }
+            let decoration = self.windows.entry(wl_surface.clone()).or_default();
+            return decoration.border_elements(colors, style, location, size, 0, scale);
+        }
+
+        let title = title(window);
+        let titlebar_height = config.titlebar_height.max(0);
+
+        let decoration = self.windows.entry(wl_surface.clone()).or_default();
+        let mut elements = vec![];
+
+        let buttons = button_geometries(config, size.w);
+        decoration
+            .buttons
+            .resize_with(buttons.len(), SolidColorBuffer::default);
+        for (buffer, (button, geometry)) in decoration.buttons.iter_mut().zip(&buttons) {
+            let color = match button {
+                DecorationButton::Close => CLOSE_COLOR,
+                DecorationButton::Maximize => MAXIMIZE_COLOR,
+                DecorationButton::Float => FLOAT_COLOR,
+            };
+            let hovered = self.hovered.as_ref() == Some(&(wl_surface.clone(), *button));
+            let color = if hovered { highlight(color) } else { color };
+            buffer.update(geometry.size, color);
+            elements.push(solid_element(buffer, location + geometry.loc, scale));
+        }
+
+        // Icon of the client, on the left of the titlebar
+        let int_scale = scale.x.ceil().max(1.0) as i32;
+        let icon_size = icon_size(config);
+        match toplevel_icon::icon(wl_surface).filter(|_| icon_size > 0) {
+            None => decoration.icon = None,
+            Some(icon) => {
+                let needs_update = decoration.icon.as_ref().map_or(true, |current| {
+                    !Arc::ptr_eq(&current.icon, &icon) || current.scale != int_scale
+                });
+                if needs_update {
+                    let buffer = icon.buffer(icon_size * int_scale).and_then(|buffer| {
+                        let texture = renderer
+                            .import_memory(
+                                &buffer.pixels,
+                                Fourcc::Argb8888,
+                                (buffer.size, buffer.size).into(),
+                                false,
+                            )
+                            .map_err(|err| println!("Impossible import icon texture: {err:?}"))
+                            .ok()?;
+                        Some(TextureBuffer::from_texture(
+                            renderer,
+                            texture,
+                            int_scale,
+                            Transform::Normal,
+                            None,
+                        ))
+                    });
+                    decoration.icon = Some(Icon {
+                        icon,
+                        scale: int_scale,
+                        buffer,
+                    });
+                }
+            }
+        }
+        let mut title_x = 2 * TITLEBAR_PADDING;
+        if let Some(buffer) = decoration.icon.as_ref().and_then(|i| i.buffer.as_ref()) {
+            let loc = location + Point::from((title_x, -titlebar_height + TITLEBAR_PADDING));
+            // Drawn at the size of the buttons, whatever the size of the buffer
+            elements.push(DecorationRenderElement::Title(
+                TextureRenderElement::from_texture_buffer(
+                    loc.to_physical_precise_round(scale).to_f64(),
+                    buffer,
+                    None,
+                    None,
+                    Some((icon_size, icon_size).into()),
+                ),
+            ));
+            title_x += icon_size + TITLEBAR_PADDING;
+        }
+
+        // Title, after the icon
+        let buttons_x = buttons
+            .first()
+            .map_or(size.w, |(_, geometry)| geometry.loc.x);
+        let max_width = buttons_x - title_x - TITLEBAR_PADDING;
+        let font = self.font.get_or_insert_with(|| load_font(config)).as_ref();
+        let needs_update = decoration.title.as_ref().map_or(true, |current| {
+            current.text != title
+                || current.max_width != max_width
+                || current.scale != int_scale
+                || current.color != colors.text
+        });
+        if needs_update {
+            let buffer = font.and_then(|font| {
+                let (pixels, w, h) = rasterize(
+                    font,
+                    &title,
+                    config.font_size * int_scale as f32,
+                    max_width * int_scale,
+                    colors.text,
+                )?;
+                let texture = renderer
+                    .import_memory(&pixels, Fourcc::Abgr8888, (w, h).into(), false)
+                    .map_err(|err| println!("Impossible import title texture: {err:?}"))
+                    .ok()?;
+                Some(TextureBuffer::from_texture(
+                    renderer,
+                    texture,
+                    int_scale,
+                    Transform::Normal,
+                    None,
+                ))
+            });
+            decoration.title = Some(Title {
+                text: title,
+                max_width,
+                scale: int_scale,
+                color: colors.text,
+                buffer,
+            });
+        }
+        if let Some(buffer) = decoration.title.as_ref().and_then(|t| t.buffer.as_ref()) {
+            let title_height = buffer.logical_size().h;
+            let loc = location
+                + Point::from((
+                    title_x,
+                    -titlebar_height + (titlebar_height - title_height) / 2,
+                ));
+            elements.push(DecorationRenderElement::Title(
+                TextureRenderElement::from_texture_buffer(
+                    loc.to_physical_precise_round(scale).to_f64(),
+                    buffer,
+                    None,
+                    None,
+                    None,
+                ),
+            ));
+        }
+
+        decoration
+            .titlebar
+            .update((size.w, titlebar_height), colors.titlebar.0);
+        elements.push(solid_element(
+            &decoration.titlebar,
+            location + Point::from((0, -titlebar_height)),
+            scale,
+        ));
+
+        elements.extend(decoration.border_elements(
+            colors,
+            style,
+            location,
+            size,
+            titlebar_height,
+            scale,
+        ));
+        elements
+    }
+}
+
+smithay::backend::renderer::element::render_elements! {
+    pub DecorationRenderElement<R> where R: ImportAll + ImportMem;
+    Solid=SolidColorRenderElement,
+    Title=TextureRenderElement<<R as Renderer>::TextureId>,
+}
+
+// Hovered buttons are drawn closer to white
+fn highlight(color: [f32; 4]) -> [f32; 4] {
+    let mix = |channel: f32| channel + (color[3] - channel) * 0.4;
+    [mix(color[0]), mix(color[1]), mix(color[2]), color[3]]
+}
+
+pub fn solid_element<R: Renderer + ImportAll + ImportMem>(
+    buffer: &SolidColorBuffer,
+    location: Point<i32, Logical>,
+    scale: Scale<f64>,
+) -> DecorationRenderElement<R> {
+    DecorationRenderElement::Solid(SolidColorRenderElement::from_buffer(
+        buffer,
+        location.to_physical_precise_round(scale),
+        scale,
+        1.0,
+        Kind::Unspecified,
+    ))
+}
+
+// The font is choosen by family name through fontconfig, so the
+// config can contain "Sans" or "DejaVu Sans Mono" and not a path
+pub fn load_font(config: &DecorationConfig) -> Option<fontdue::Font> {
+    let output = std::process::Command::new("fc-match")
+        .args(["-f", "%{file}", &config.font])
+        .output()
+        .map_err(|err| println!("Impossible run fc-match: {err}"))
+        .ok()?;
+    let path = String::from_utf8_lossy(&output.stdout).to_string();
+
+    let data = std::fs::read(&path)
+        .map_err(|err| println!("Impossible read font {path:?}: {err}"))
+        .ok()?;
+    fontdue::Font::from_bytes(data, fontdue::FontSettings::default())
+        .map_err(|err| println!("Invalid font {path:?}: {err}"))
+        .ok()
+}
+
+// Draw the text on a single line in a premultiplied RGBA buffer,
+// the text that does not fit in `max_width` is cut.
+// The color is already premultiplied (see `Color`), only the coverage
+// of the glyphs is applied to it
+pub fn rasterize(
+    font: &fontdue::Font,
+    text: &str,
+    px: f32,
+    max_width: i32,
+    color: Color,
+) -> Option<(Vec<u8>, i32, i32)> {
+    let line_metrics = font.horizontal_line_metrics(px)?;
+    let baseline = line_metrics.ascent.ceil() as i32;
+    let height = (line_metrics.ascent - line_metrics.descent).ceil() as i32;
+
+    let mut glyphs = vec![];
+    let mut pen = 0.0;
+    for c in text.chars() {
+        let (metrics, coverage) = font.rasterize(c, px);
+        if (pen + metrics.advance_width).ceil() as i32 > max_width {
+            break;
+        }
+        glyphs.push((pen.round() as i32, metrics, coverage));
+        pen += metrics.advance_width;
+    }
+
+    let width = pen.ceil() as i32;
+    if width <= 0 || height <= 0 {
+        return None;
+    }
+
+    let mut pixels = vec![0u8; (width * height * 4) as usize];
+    for (x, metrics, coverage) in glyphs {
+        let left = x + metrics.xmin;
+        let top = baseline - metrics.height as i32 - metrics.ymin;
+        for row in 0..metrics.height as i32 {
+            for col in 0..metrics.width as i32 {
+                let (px, py) = (left + col, top + row);
+                if px < 0 || py < 0 || px >= width || py >= height {
+                    continue;
+                }
+                let cover = coverage[(row * metrics.width as i32 + col) as usize] as f32 / 255.0;
+                let offset = ((py * width + px) * 4) as usize;
+                let pixel = &mut pixels[offset..offset + 4];
+                for (channel, value) in pixel.iter_mut().zip(color.0) {
+                    *channel = (*channel).max((value * cover * 255.0).round() as u8);
+                }
+            }
+        }
+    }
+
+    Some((pixels, width, height))
+}
@@ -0,0 +1,309 @@
+use serde::{Deserialize, Serialize};
+use smithay::{
+    backend::input::{Device, DeviceCapability, KeyState},
+    input::keyboard::FilterResult,
+    reexports::input,
+    utils::SERIAL_COUNTER,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    config::{self, AccelProfile, InputDeviceConfig},
+    input_handler::ChordState,
+    ipc::{self, Event},
+    state::AIGIState,
+};
+
+/// Input devices currently plugged, as reported by libinput
+#[derive(Default)]
+pub struct InputDevices {
+    devices: Vec<input::Device>,
+    // Acceleration changed at runtime, by device name. They win over the
+    // config and are kept in the state file for the next sessions
+    accel_overrides: HashMap<String, AccelOverride>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, Debug)]
+struct AccelOverride {
+    profile: Option<AccelProfile>,
+    speed: Option<f64>,
+}
+
+impl InputDevices {
+    /// The acceleration chosen in the previous sessions is read from the state file
+    pub fn load() -> Self {
+        let accel_overrides = accel_state_file()
+            .filter(|path| path.exists())
+            .map(|path| {
+                std::fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|content| Ok(serde_json::from_str(&content)?))
+                    .unwrap_or_else(|err| {
+                        println!("Pointer acceleration not loaded from {path:?}: {err:?}");
+                        HashMap::new()
+                    })
+            })
+            .unwrap_or_default();
+        Self {
+            devices: vec![],
+            accel_overrides,
+        }
+    }
+
+    pub fn info(&self) -> Vec<DeviceInfo> {
+        self.devices.iter().map(DeviceInfo::from).collect()
+    }
+
+    fn any_with(&self, capability: DeviceCapability) -> bool {
+        self.devices
+            .iter()
+            .any(|device| device.has_capability(capability))
+    }
+}
+
+/// Keys held on every keyboard. The seat has a single XKB state shared by
+/// all the keyboards (Shift on a keyboard affects the keys of the others),
+/// so for the seat a key is held while it's held on at least one keyboard
+#[derive(Default)]
+pub struct PressedKeys {
+    by_device: HashMap<String, HashSet<u32>>,
+}
+
+impl PressedKeys {
+    /// Returns false if the seat must ignore the event: the key is
+    /// still held on another keyboard, or it was already held
+    pub fn update(&mut self, device: &str, keycode: u32, key_state: KeyState) -> bool {
+        let held_before = self.held(keycode);
+        let keys = self.by_device.entry(device.to_string()).or_default();
+        match key_state {
+            KeyState::Pressed => keys.insert(keycode),
+            KeyState::Released => keys.remove(&keycode),
+        };
+        held_before != self.held(keycode)
+    }
+
+    fn held(&self, keycode: u32) -> bool {
+        self.by_device.values().any(|keys| keys.contains(&keycode))
+    }
+
+    // Keys that are not held anymore once the device is gone
+    fn remove_device(&mut self, device: &str) -> Vec<u32> {
+        let keys = self.by_device.remove(device).unwrap_or_default();
+        keys.into_iter().filter(|key| !self.held(*key)).collect()
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub keyboard: bool,
+    pub pointer: bool,
+    pub touch: bool,
+}
+
+impl From<&input::Device> for DeviceInfo {
+    fn from(device: &input::Device) -> Self {
+        Self {
+            name: Device::name(device),
+            keyboard: device.has_capability(DeviceCapability::Keyboard),
+            pointer: device.has_capability(DeviceCapability::Pointer),
+            touch: device.has_capability(DeviceCapability::Touch),
+        }
+    }
+}
+
+/// libinput reports every device at startup too,
+/// so this is the only place where devices are configured
+pub fn device_added(state: &mut AIGIState, mut device: input::Device) {
+    tracing::debug!("INPUT DEVICE ADDED: {}", Device::name(&device));
+    apply_config(&state.config.input_devices, &mut device);
+    apply_accel_override(&state.input_devices.accel_overrides, &mut device);
+
+    let info = DeviceInfo::from(&device);
+    state.input_devices.devices.push(device);
+    update_capabilities(state);
+    ipc::broadcast(state, Event::DeviceAdded { device: info });
+}
+
+pub fn device_removed(state: &mut AIGIState, device: input::Device) {
+    tracing::debug!("INPUT DEVICE REMOVED: {}", Device::name(&device));
+    state.input_devices.devices.retain(|d| d != &device);
+    release_keys(state, &Device::id(&device));
+    update_capabilities(state);
+    ipc::broadcast(
+        state,
+        Event::DeviceRemoved {
+            device: DeviceInfo::from(&device),
+        },
+    );
+}
+
+/// Apply the config again to every device, eg. after reloading it
+pub fn reconfigure(state: &mut AIGIState) {
+    let input_devices = &mut state.input_devices;
+    for device in input_devices.devices.iter_mut() {
+        apply_config(&state.config.input_devices, device);
+        apply_accel_override(&input_devices.accel_overrides, device);
+    }
+}
+
+/// Change the acceleration of the pointer devices with the given name
+/// (every pointer device without a name), what is not given is kept.
+/// The choice is remembered for the next sessions
+pub fn set_pointer_accel(
+    state: &mut AIGIState,
+    name: Option<&str>,
+    profile: Option<AccelProfile>,
+    speed: Option<f64>,
+) -> Result<(), String> {
+    let input_devices = &mut state.input_devices;
+    let mut changed = 0;
+    for device in input_devices.devices.iter_mut().filter(|device| {
+        device.has_capability(DeviceCapability::Pointer)
+            && name.map_or(true, |name| device.name() == name)
+    }) {
+        let accel = input_devices
+            .accel_overrides
+            .entry(Device::name(device))
+            .or_default();
+        accel.profile = profile.or(accel.profile);
+        accel.speed = speed.or(accel.speed);
+        tracing::debug!("POINTER ACCEL {}: {accel:?}", Device::name(device));
+        apply_accel_override(&input_devices.accel_overrides, device);
+        changed += 1;
+    }
+    if changed == 0 {
+        return Err(match name {
+            Some(name) => format!("No pointer device called {name}"),
+            None => "No pointer device plugged".into(),
+        });
+    }
+
+    let path = accel_state_file().ok_or("No state directory, the choice is not saved")?;
+    save_accel_overrides(&path, &input_devices.accel_overrides)
+        .map_err(|err| format!("Impossible save the pointer acceleration in {path:?}: {err:?}"))
+}
+
+/// Switch every pointer device between the flat and adaptive profiles,
+/// following the first one, eg. when starting or quitting a game
+pub fn toggle_accel_profile(state: &mut AIGIState) {
+    let current = state
+        .input_devices
+        .devices
+        .iter()
+        .filter(|device| device.has_capability(DeviceCapability::Pointer))
+        .find_map(|device| device.config_accel_profile());
+    let profile = match current {
+        Some(input::AccelProfile::Flat) => AccelProfile::Adaptive,
+        _ => AccelProfile::Flat,
+    };
+    if let Err(err) = set_pointer_accel(state, None, Some(profile), None) {
+        println!("Acceleration not changed: {err}");
+    }
+}
+
+fn apply_accel_override(overrides: &HashMap<String, AccelOverride>, device: &mut input::Device) {
+    let Some(accel) = overrides.get(&Device::name(device)) else {
+        return;
+    };
+    if let Some(profile) = accel.profile {
+        let _ = device.config_accel_set_profile(profile.into());
+    }
+    if let Some(speed) = accel.speed {
+        let _ = device.config_accel_set_speed(speed);
+    }
+}
+
+fn accel_state_file() -> Option<PathBuf> {
+    config::state_path("pointer_accel.json")
+}
+
+fn save_accel_overrides(
+    path: &Path,
+    overrides: &HashMap<String, AccelOverride>,
+) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(overrides)?)?;
+    Ok(())
+}
+
+// The keys held on an unplugged keyboard would stay pressed forever
+// (eg. a stuck Shift), so they are released for the seat and the clients.
+// A chord started on that keyboard can't be completed anymore
+fn release_keys(state: &mut AIGIState, device: &str) {
+    let had_keys = state.pressed_keys.by_device.contains_key(device);
+    let released = state.pressed_keys.remove_device(device);
+    if !had_keys {
+        return;
+    }
+
+    state.chord_state = ChordState::Idle;
+    let keyboard = state.seat.get_keyboard().unwrap();
+    let time = Duration::from(state.clock.now()).as_millis() as u32;
+    for keycode in released {
+        keyboard.input::<(), _>(
+            state,
+            keycode,
+            KeyState::Released,
+            SERIAL_COUNTER.next_serial(),
+            time,
+            |_, _, _| FilterResult::Forward,
+        );
+    }
+}
+
+// Clients see the pointer capability only while a pointer device is plugged.
+//
+// The keyboard capability instead is never removed: the keyboard focus
+// is how the compositor knows which window is focused
+fn update_capabilities(state: &mut AIGIState) {
+    let has_pointer = state.input_devices.any_with(DeviceCapability::Pointer);
+    match (has_pointer, state.seat.get_pointer().is_some()) {
+        (true, false) => {
+            state.seat.add_pointer();
+        }
+        (false, true) => state.seat.remove_pointer(),
+        _ => {}
+    }
+}
+
+// Every matching section is applied, in order, so a section
+// without a name can set the defaults for every device
+fn apply_config(configs: &[InputDeviceConfig], device: &mut input::Device) {
+    let name = Device::name(device);
+    for config in configs
+        .iter()
+        .filter(|config| config.name.as_ref().map_or(true, |n| *n == name))
+    {
+        let mut results = vec![];
+        if let Some(tap) = config.tap {
+            results.push(device.config_tap_set_enabled(tap));
+        }
+        if let Some(natural_scroll) = config.natural_scroll {
+            results.push(device.config_scroll_set_natural_scroll_enabled(natural_scroll));
+        }
+        if let Some(left_handed) = config.left_handed {
+            results.push(device.config_left_handed_set(left_handed));
+        }
+        if let Some(accel_speed) = config.accel_speed {
+            results.push(device.config_accel_set_speed(accel_speed));
+        }
+        if let Some(profile) = config.accel_profile {
+            results.push(device.config_accel_set_profile(profile.into()));
+        }
+        if let Some(dwt) = config.disable_while_typing {
+            results.push(device.config_dwt_set_enabled(dwt));
+        }
+
+        // Options not supported by the device (eg. tap on a mouse) are ignored
+        if results.iter().any(Result::is_err) {
+            println!("Some input options are not supported by {name}");
+        }
+    }
+}
@@ -0,0 +1,127 @@
+// Every EDID starts with these bytes
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+// Display descriptors with a text, the monitor name and the serial
+const NAME_DESCRIPTOR: u8 = 0xfc;
+const SERIAL_DESCRIPTOR: u8 = 0xff;
+
+/// Monitor described by the EDID of its connector
+#[derive(Clone, Debug)]
+pub struct Edid {
+    /// PNP id of the manufacturer, eg. DEL
+    pub make: String,
+    /// Name of the monitor, or its product code if it has none
+    pub model: String,
+    pub serial: Option<String>,
+}
+
+impl Edid {
+    /// Identifies the monitor in the config and in the IPC, eg.
+    /// "DEL DELL U2720Q 1ABC234". Unlike the connector name it
+    /// stays the same if the monitor is plugged in another port
+    pub fn identifier(&self) -> String {
+        match &self.serial {
+            Some(serial) => format!("{} {} {serial}", self.make, self.model),
+            None => format!("{} {}", self.make, self.model),
+        }
+    }
+}
+
+/// Only the base block is read, None if it's not an EDID
+pub fn parse(edid: &[u8]) -> Option<Edid> {
+    if edid.len() < 128 || edid[..8] != HEADER {
+        return None;
+    }
+
+    // Three letters of 5 bits, 1 is A
+    let id = u16::from_be_bytes([edid[8], edid[9]]);
+    let make = [10, 5, 0]
+        .iter()
+        .map(|shift| (b'A' - 1 + ((id >> shift) & 0x1f) as u8) as char)
+        .collect::<String>();
+    let product = u16::from_le_bytes([edid[10], edid[11]]);
+    let serial_number = u32::from_le_bytes([edid[12], edid[13], edid[14], edid[15]]);
+
+    let mut name = None;
+    let mut serial = None;
+    for descriptor in edid[54..126].chunks(18) {
+        // The others are detailed timings
+        if descriptor[..3] != [0, 0, 0] {
+            continue;
+        }
+        match descriptor[3] {
+            NAME_DESCRIPTOR => name = descriptor_text(&descriptor[5..]),
+            SERIAL_DESCRIPTOR => serial = descriptor_text(&descriptor[5..]),
+            _ => (),
+        }
+    }
+
+    Some(Edid {
+        make,
+        model: name.unwrap_or_else(|| format!("{product:#06x}")),
+        serial: serial.or_else(|| (serial_number != 0).then(|| serial_number.to_string())),
+    })
+}
+
+// Up to 13 characters, ended by a newline and padded with spaces
+fn descriptor_text(bytes: &[u8]) -> Option<String> {
+    let end = bytes
+        .iter()
+        .position(|b| *b == b'\n')
+        .unwrap_or(bytes.len());
+    let text = String::from_utf8_lossy(&bytes[..end]).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Base block laid out like the one of a DELL U2720Q: a 3840x2160
+    // timing, then the name, the serial and the range limits descriptors
+    #[rustfmt::skip]
+    const U2720Q: [u8; 128] = [
+        0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x10, 0xac, 0xe9, 0xa0, 0x4c, 0x41, 0x53, 0x30,
+        0x1d, 0x1e, 0x01, 0x04, 0xb5, 0x3c, 0x22, 0x78, 0x3b, 0x05, 0x4c, 0xb0, 0xae, 0x50, 0x3e, 0xb4,
+        0x24, 0x0c, 0x50, 0xa5, 0x4b, 0x00, 0xd1, 0xc0, 0xa9, 0x40, 0x81, 0x80, 0x81, 0x00, 0x71, 0x4f,
+        0xe1, 0xc0, 0x01, 0x01, 0x01, 0x01, 0x4d, 0xd0, 0x00, 0xa0, 0xf0, 0x70, 0x3e, 0x80, 0x30, 0x20,
+        0x35, 0x00, 0x54, 0x4f, 0x21, 0x00, 0x00, 0x1a, 0x00, 0x00, 0x00, 0xfc, 0x00, 0x44, 0x45, 0x4c,
+        0x4c, 0x20, 0x55, 0x32, 0x37, 0x32, 0x30, 0x51, 0x0a, 0x20, 0x00, 0x00, 0x00, 0xff, 0x00, 0x31,
+        0x41, 0x42, 0x43, 0x32, 0x33, 0x34, 0x0a, 0x20, 0x20, 0x20, 0x20, 0x20, 0x00, 0x00, 0x00, 0xfd,
+        0x00, 0x31, 0x4c, 0x1e, 0x8c, 0x3c, 0x00, 0x0a, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x01, 0x6f,
+    ];
+
+    #[test]
+    fn parse_reads_the_name_and_serial_descriptors() {
+        let edid = parse(&U2720Q).unwrap();
+        assert_eq!(edid.make, "DEL");
+        assert_eq!(edid.model, "DELL U2720Q");
+        assert_eq!(edid.serial.as_deref(), Some("1ABC234"));
+        assert_eq!(edid.identifier(), "DEL DELL U2720Q 1ABC234");
+    }
+
+    #[test]
+    fn parse_falls_back_to_the_product_code_and_serial_number() {
+        // The name and serial descriptors replaced by dummy ones
+        let mut blob = U2720Q;
+        blob[72..108].fill(0);
+        blob[75] = 0x10;
+        blob[93] = 0x10;
+        let edid = parse(&blob).unwrap();
+        assert_eq!(edid.model, "0xa0e9");
+        assert_eq!(edid.serial.as_deref(), Some("810762572"));
+
+        // Without a serial number there is no serial at all
+        blob[12..16].fill(0);
+        let edid = parse(&blob).unwrap();
+        assert_eq!(edid.serial, None);
+        assert_eq!(edid.identifier(), "DEL 0xa0e9");
+    }
+
+    #[test]
+    fn parse_rejects_what_is_not_an_edid() {
+        assert!(parse(&U2720Q[..127]).is_none());
+        let mut blob = U2720Q;
+        blob[0] = 0xff;
+        assert!(parse(&blob).is_none());
+    }
+}
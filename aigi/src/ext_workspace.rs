@@ -0,0 +1,235 @@
+use smithay::{
+    output::Output,
+    reexports::wayland_server::{
+        protocol::wl_output::WlOutput, Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch,
+        New, Resource,
+    },
+};
+
+use crate::{
+    ipc::{self, WorkspaceInfo},
+    protocols::ext_workspace::{
+        ext_workspace_group_handle_v1::{self, ExtWorkspaceGroupHandleV1, GroupCapabilities},
+        ext_workspace_handle_v1::{self, ExtWorkspaceHandleV1, State, WorkspaceCapabilities},
+        ext_workspace_manager_v1::{self, ExtWorkspaceManagerV1},
+    },
+    state::AIGIState,
+};
+
+const VERSION: u32 = 1;
+
+/// The clients bound to ext-workspace, kept in sync with the workspaces
+#[derive(Default)]
+pub struct ExtWorkspaceState {
+    managers: Vec<Manager>,
+}
+
+// Every client sees a single group, with the outputs in it, holding the
+// fixed workspaces: they can only be activated
+struct Manager {
+    manager: ExtWorkspaceManagerV1,
+    group: ExtWorkspaceGroupHandleV1,
+    workspaces: Vec<ExtWorkspaceHandleV1>,
+    outputs: Vec<WlOutput>,
+    // What the client knows, only the changes are sent
+    sent: Vec<WorkspaceInfo>,
+    // Activated by the next commit
+    pending: Option<usize>,
+}
+
+/// ext-workspace, panels list and switch the workspaces. Created after the
+/// output global: the clients bind the output first, so the group of the
+/// workspaces can be put on it right away
+pub fn create_global(display: &DisplayHandle) {
+    display.create_global::<AIGIState, ExtWorkspaceManagerV1, _>(VERSION, ());
+}
+
+/// Send what changed to every client, called with `ipc::workspaces_changed`
+pub fn refresh(state: &mut AIGIState) {
+    let workspaces = ipc::workspaces(state);
+    let outputs = state.space.outputs().cloned().collect::<Vec<_>>();
+    let managers = &mut state.ext_workspace_state.managers;
+    managers.retain(|manager| manager.manager.is_alive());
+    for manager in managers {
+        let mut changed = manager.enter_outputs(&outputs);
+        for (handle, (info, sent)) in manager
+            .workspaces
+            .iter()
+            .zip(workspaces.iter().zip(manager.sent.iter()))
+        {
+            if info.name != sent.name {
+                handle.name(info.name.clone());
+                changed = true;
+            }
+            if info.active != sent.active {
+                handle.state(workspace_state(info));
+                changed = true;
+            }
+        }
+        if changed {
+            manager.sent = workspaces.clone();
+            manager.manager.done();
+        }
+    }
+}
+
+fn workspace_state(info: &WorkspaceInfo) -> State {
+    if info.active {
+        State::Active
+    } else {
+        State::empty()
+    }
+}
+
+impl Manager {
+    // The outputs of the client that the group is not on yet
+    fn enter_outputs(&mut self, outputs: &[Output]) -> bool {
+        let Some(client) = self.manager.client() else {
+            return false;
+        };
+        let mut entered = false;
+        for wl_output in outputs
+            .iter()
+            .flat_map(|output| output.client_outputs(&client))
+        {
+            if !self.outputs.contains(&wl_output) {
+                self.group.output_enter(&wl_output);
+                self.outputs.push(wl_output);
+                entered = true;
+            }
+        }
+        self.outputs.retain(|wl_output| wl_output.is_alive());
+        entered
+    }
+}
+
+impl GlobalDispatch<ExtWorkspaceManagerV1, ()> for AIGIState {
+    fn bind(
+        state: &mut Self,
+        display: &DisplayHandle,
+        client: &Client,
+        manager: New<ExtWorkspaceManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let manager = data_init.init(manager, ());
+        let Ok(group) =
+            client.create_resource::<ExtWorkspaceGroupHandleV1, _, Self>(display, VERSION, ())
+        else {
+            return;
+        };
+        manager.workspace_group(&group);
+        group.capabilities(GroupCapabilities::empty());
+
+        let sent = ipc::workspaces(state);
+        let mut workspaces = vec![];
+        for info in &sent {
+            let Ok(handle) = client
+                .create_resource::<ExtWorkspaceHandleV1, _, Self>(display, VERSION, info.index)
+            else {
+                return;
+            };
+            manager.workspace(&handle);
+            handle.id(info.index.to_string());
+            handle.name(info.name.clone());
+            handle.coordinates((info.index as u32).to_ne_bytes().to_vec());
+            handle.state(workspace_state(info));
+            handle.capabilities(WorkspaceCapabilities::Activate);
+            group.workspace_enter(&handle);
+            workspaces.push(handle);
+        }
+
+        let mut manager = Manager {
+            manager,
+            group,
+            workspaces,
+            outputs: vec![],
+            sent,
+            pending: None,
+        };
+        let outputs = state.space.outputs().cloned().collect::<Vec<_>>();
+        manager.enter_outputs(&outputs);
+        manager.manager.done();
+        state.ext_workspace_state.managers.push(manager);
+    }
+}
+
+impl Dispatch<ExtWorkspaceManagerV1, ()> for AIGIState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ExtWorkspaceManagerV1,
+        request: ext_workspace_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let managers = &mut state.ext_workspace_state.managers;
+        let Some(index) = managers
+            .iter()
+            .position(|manager| manager.manager == *resource)
+        else {
+            return;
+        };
+        match request {
+            ext_workspace_manager_v1::Request::Commit => {
+                if let Some(workspace) = managers[index].pending.take() {
+                    state.switch_workspace(workspace);
+                }
+            }
+            ext_workspace_manager_v1::Request::Stop => {
+                let manager = managers.remove(index);
+                manager.manager.finished();
+            }
+        }
+    }
+}
+
+impl Dispatch<ExtWorkspaceGroupHandleV1, ()> for AIGIState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _group: &ExtWorkspaceGroupHandleV1,
+        request: ext_workspace_group_handle_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            // Not in the capabilities, the workspaces are fixed
+            ext_workspace_group_handle_v1::Request::CreateWorkspace { .. } => {}
+            ext_workspace_group_handle_v1::Request::Destroy => {}
+        }
+    }
+}
+
+impl Dispatch<ExtWorkspaceHandleV1, usize> for AIGIState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        handle: &ExtWorkspaceHandleV1,
+        request: ext_workspace_handle_v1::Request,
+        index: &usize,
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_workspace_handle_v1::Request::Activate => {
+                let manager = state
+                    .ext_workspace_state
+                    .managers
+                    .iter_mut()
+                    .find(|manager| manager.workspaces.contains(handle));
+                if let Some(manager) = manager {
+                    manager.pending = Some(*index);
+                }
+            }
+            // Not in the capabilities: a workspace is deactivated
+            // only by activating another one, and they are fixed
+            ext_workspace_handle_v1::Request::Deactivate
+            | ext_workspace_handle_v1::Request::Assign { .. }
+            | ext_workspace_handle_v1::Request::Remove
+            | ext_workspace_handle_v1::Request::Destroy => {}
+        }
+    }
+}
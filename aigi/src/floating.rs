@@ -0,0 +1,202 @@
+use smithay::{
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    utils::{Logical, Point, Rectangle, Size},
+};
+use std::collections::{HashMap, HashSet};
+
+use crate::config::Corner;
+
+/// This Struct keeps track of the windows that are not
+/// part of the tiling tree, each one with its own geometry
+#[derive(Default)]
+pub struct FloatingState {
+    geometries: HashMap<WlSurface, Rectangle<i32, Logical>>,
+    // Stacked above every other floating window
+    always_on_top: HashSet<WlSurface>,
+    // Shown on every workspace, see `move_sticky`
+    sticky: HashSet<WlSurface>,
+}
+
+impl FloatingState {
+    pub fn is_floating(&self, wl_surface: &WlSurface) -> bool {
+        self.geometries.contains_key(wl_surface)
+    }
+
+    pub fn geometry(&self, wl_surface: &WlSurface) -> Option<Rectangle<i32, Logical>> {
+        self.geometries.get(wl_surface).copied()
+    }
+
+    /// Insert a new floating window or update the geometry of an existing one
+    pub fn set_geometry(&mut self, wl_surface: &WlSurface, geometry: Rectangle<i32, Logical>) {
+        self.geometries.insert(wl_surface.clone(), geometry);
+    }
+
+    pub fn geometries(&self) -> impl Iterator<Item = (&WlSurface, &Rectangle<i32, Logical>)> {
+        self.geometries.iter()
+    }
+
+    pub fn is_always_on_top(&self, wl_surface: &WlSurface) -> bool {
+        self.always_on_top.contains(wl_surface)
+    }
+
+    pub fn set_always_on_top(&mut self, wl_surface: &WlSurface, on_top: bool) {
+        if on_top {
+            self.always_on_top.insert(wl_surface.clone());
+        } else {
+            self.always_on_top.remove(wl_surface);
+        }
+    }
+
+    pub fn is_sticky(&self, wl_surface: &WlSurface) -> bool {
+        self.sticky.contains(wl_surface)
+    }
+
+    pub fn set_sticky(&mut self, wl_surface: &WlSurface, sticky: bool) {
+        if sticky {
+            self.sticky.insert(wl_surface.clone());
+        } else {
+            self.sticky.remove(wl_surface);
+        }
+    }
+
+    /// Move the sticky windows to the floating state of another workspace,
+    /// with their geometry and if they are always on top
+    pub fn move_sticky(&mut self, to: &mut FloatingState) {
+        for wl_surface in std::mem::take(&mut self.sticky) {
+            let on_top = self.is_always_on_top(&wl_surface);
+            if let Some(geometry) = self.remove(&wl_surface) {
+                to.set_geometry(&wl_surface, geometry);
+                to.set_always_on_top(&wl_surface, on_top);
+                to.set_sticky(&wl_surface, true);
+            }
+        }
+    }
+
+    /// Returns the last known geometry of the removed window
+    pub fn remove(&mut self, wl_surface: &WlSurface) -> Option<Rectangle<i32, Logical>> {
+        self.always_on_top.remove(wl_surface);
+        self.sticky.remove(wl_surface);
+        self.geometries.remove(wl_surface)
+    }
+}
+
+/// Move the location of `geometry` so that it lies inside `area`,
+/// if it's bigger than the area then it will be aligned to the top left corner
+pub fn confine(
+    geometry: Rectangle<i32, Logical>,
+    area: Rectangle<i32, Logical>,
+) -> Point<i32, Logical> {
+    let max_x = area.loc.x + area.size.w - geometry.size.w;
+    let max_y = area.loc.y + area.size.h - geometry.size.h;
+    (
+        geometry.loc.x.min(max_x).max(area.loc.x),
+        geometry.loc.y.min(max_y).max(area.loc.y),
+    )
+        .into()
+}
+
+/// Location of a window of the given size placed in a corner of `area`,
+/// `margin` pixels away from the edges
+pub fn park(
+    size: Size<i32, Logical>,
+    area: Rectangle<i32, Logical>,
+    corner: Corner,
+    margin: i32,
+) -> Point<i32, Logical> {
+    let left = area.loc.x + margin;
+    let right = area.loc.x + area.size.w - size.w - margin;
+    let top = area.loc.y + margin;
+    let bottom = area.loc.y + area.size.h - size.h - margin;
+
+    match corner {
+        Corner::TopLeft => (left, top),
+        Corner::TopRight => (right, top),
+        Corner::BottomLeft => (left, bottom),
+        Corner::BottomRight => (right, bottom),
+    }
+    .into()
+}
+
+/// Move the location of `geometry` so that its edges stick to the edges
+/// of `area` or of the `others` rectangles, if they are closer than `threshold`
+pub fn snap(
+    geometry: Rectangle<i32, Logical>,
+    area: Rectangle<i32, Logical>,
+    others: &[Rectangle<i32, Logical>],
+    threshold: i32,
+) -> Point<i32, Logical> {
+    if threshold <= 0 {
+        return geometry.loc;
+    }
+
+    let mut x_edges = vec![area.loc.x, area.loc.x + area.size.w];
+    let mut y_edges = vec![area.loc.y, area.loc.y + area.size.h];
+    for other in others {
+        // Snapping to a window that is not even next to
+        // the dragged one would be confusing
+        let overlap_x = other.loc.x < geometry.loc.x + geometry.size.w
+            && geometry.loc.x < other.loc.x + other.size.w;
+        let overlap_y = other.loc.y < geometry.loc.y + geometry.size.h
+            && geometry.loc.y < other.loc.y + other.size.h;
+
+        if overlap_y {
+            x_edges.extend([other.loc.x, other.loc.x + other.size.w]);
+        }
+        if overlap_x {
+            y_edges.extend([other.loc.y, other.loc.y + other.size.h]);
+        }
+    }
+
+    (
+        snap_axis(geometry.loc.x, geometry.size.w, &x_edges, threshold),
+        snap_axis(geometry.loc.y, geometry.size.h, &y_edges, threshold),
+    )
+        .into()
+}
+
+// Both the start and the end of the segment can stick to an edge,
+// the closest edge wins
+fn snap_axis(start: i32, length: i32, edges: &[i32], threshold: i32) -> i32 {
+    edges
+        .iter()
+        .flat_map(|&edge| [edge - start, edge - (start + length)])
+        .filter(|offset| offset.abs() <= threshold)
+        .min_by_key(|offset| offset.abs())
+        .map_or(start, |offset| start + offset)
+}
+
+/// Portion of the output a floating window can be snapped to with the keyboard
+#[derive(Clone, Copy, Debug)]
+pub enum SnapPosition {
+    Left,
+    Right,
+    Top,
+    Bottom,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl SnapPosition {
+    pub fn area(&self, output: Rectangle<i32, Logical>) -> Rectangle<i32, Logical> {
+        let half_w = output.size.w / 2;
+        let half_h = output.size.h / 2;
+        let (x, y, w, h) = match self {
+            SnapPosition::Left => (0, 0, half_w, output.size.h),
+            SnapPosition::Right => (half_w, 0, output.size.w - half_w, output.size.h),
+            SnapPosition::Top => (0, 0, output.size.w, half_h),
+            SnapPosition::Bottom => (0, half_h, output.size.w, output.size.h - half_h),
+            SnapPosition::TopLeft => (0, 0, half_w, half_h),
+            SnapPosition::TopRight => (half_w, 0, output.size.w - half_w, half_h),
+            SnapPosition::BottomLeft => (0, half_h, half_w, output.size.h - half_h),
+            SnapPosition::BottomRight => (
+                half_w,
+                half_h,
+                output.size.w - half_w,
+                output.size.h - half_h,
+            ),
+        };
+        Rectangle::from_loc_and_size((output.loc.x + x, output.loc.y + y), (w, h))
+    }
+}
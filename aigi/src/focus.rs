@@ -0,0 +1,42 @@
+use smithay::desktop::Window;
+
+/// Windows of a workspace that had the keyboard focus,
+/// from the most recently focused one
+#[derive(Default)]
+pub struct FocusHistory {
+    windows: Vec<Window>,
+}
+
+impl FocusHistory {
+    /// The window got the focus, it's now the most recent one
+    pub fn focused(&mut self, window: &Window) {
+        self.remove(window);
+        self.windows.insert(0, window.clone());
+    }
+
+    pub fn remove(&mut self, window: &Window) {
+        self.windows.retain(|w| w != window);
+    }
+
+    /// Most recently focused window
+    pub fn last(&self) -> Option<Window> {
+        self.windows.first().cloned()
+    }
+
+    /// The window among `windows` focused most recently,
+    /// None if none of them ever had the focus
+    pub fn most_recent(&self, windows: &[Window]) -> Option<Window> {
+        self.windows
+            .iter()
+            .find(|window| windows.contains(window))
+            .cloned()
+    }
+
+    /// Most recently focused window that is not `current`
+    pub fn previous(&self, current: Option<&Window>) -> Option<Window> {
+        self.windows
+            .iter()
+            .find(|window| Some(*window) != current)
+            .cloned()
+    }
+}
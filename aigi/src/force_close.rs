@@ -0,0 +1,244 @@
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            element::{
+                solid::SolidColorBuffer,
+                texture::{TextureBuffer, TextureRenderElement},
+            },
+            multigpu::MultiTexture,
+            ImportAll, ImportMem, Renderer,
+        },
+    },
+    desktop::Window,
+    reexports::wayland_server::{backend::protocol::ProtocolError, Client, Resource},
+    utils::{Logical, Point, Rectangle, Scale, Size, Transform},
+};
+
+use crate::{
+    config::{Color, DecorationConfig},
+    decorations::{self, DecorationRenderElement},
+    render::schedule_render,
+    state::AIGIState,
+};
+
+const DIALOG_PADDING: i32 = 16;
+const BACKGROUND_COLOR: [f32; 4] = [0.1, 0.1, 0.1, 0.95];
+const TEXT_COLOR: Color = Color([0.95, 0.95, 0.95, 1.0]);
+// Everything behind the dialog is darkened
+const DIM_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.5];
+
+/// Why the dialog is shown
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The client is not answering the pings (see `PingState`)
+    Unresponsive,
+    /// The window is in `confirm_close`
+    Confirm,
+}
+
+/// Shown when the user closes a window whose client is not answering the
+/// pings: Enter kills the client, Escape keeps waiting. The dialog goes away
+/// by itself if the client answers again.
+/// The windows in `confirm_close` get it too: Enter closes the window,
+/// Escape keeps it open
+pub struct ForceCloseDialog {
+    pub window: Window,
+    pub reason: CloseReason,
+    // The text uses the font of the titlebars
+    font: Option<fontdue::Font>,
+    font_size: f32,
+    dim: SolidColorBuffer,
+    background: SolidColorBuffer,
+    // Rasterized on the first frame, for the scale of the output
+    text: Option<(i32, Option<TextureBuffer<MultiTexture>>)>,
+}
+
+impl ForceCloseDialog {
+    /// The text is rasterized again on the next frame (eg. the renderer changed)
+    pub fn forget_texture(&mut self) {
+        self.text = None;
+    }
+
+    fn new(window: Window, reason: CloseReason, config: &DecorationConfig) -> Self {
+        Self {
+            window,
+            reason,
+            font: decorations::load_font(config),
+            font_size: config.font_size,
+            dim: SolidColorBuffer::default(),
+            background: SolidColorBuffer::default(),
+            text: None,
+        }
+    }
+
+    /// Elements of the dialog, centered on an output of the given size,
+    /// the first is the topmost one
+    pub fn render_elements<R>(
+        &mut self,
+        renderer: &mut R,
+        output_size: Size<i32, Logical>,
+        scale: Scale<f64>,
+    ) -> Vec<DecorationRenderElement<R>>
+    where
+        R: Renderer<TextureId = MultiTexture> + ImportAll + ImportMem,
+    {
+        let int_scale = scale.x.ceil().max(1.0) as i32;
+        if self.text.as_ref().map_or(true, |(s, _)| *s != int_scale) {
+            let title = decorations::title(&self.window);
+            let message = match self.reason {
+                CloseReason::Unresponsive => {
+                    format!("{title} is not responding. Enter: force close, Escape: wait")
+                }
+                CloseReason::Confirm => format!("Close {title}? Enter: close, Escape: cancel"),
+            };
+            let buffer = self.font.as_ref().and_then(|font| {
+                let (pixels, w, h) = decorations::rasterize(
+                    font,
+                    &message,
+                    self.font_size * int_scale as f32,
+                    (output_size.w - 4 * DIALOG_PADDING) * int_scale,
+                    TEXT_COLOR,
+                )?;
+                let texture = renderer
+                    .import_memory(&pixels, Fourcc::Abgr8888, (w, h).into(), false)
+                    .map_err(|err| println!("Impossible import dialog texture: {err:?}"))
+                    .ok()?;
+                Some(TextureBuffer::from_texture(
+                    renderer,
+                    texture,
+                    int_scale,
+                    Transform::Normal,
+                    None,
+                ))
+            });
+            self.text = Some((int_scale, buffer));
+        }
+
+        // Without a font the dialog is still there, the keys work the same
+        let text_size = self
+            .text
+            .as_ref()
+            .and_then(|(_, buffer)| buffer.as_ref())
+            .map_or(Size::from((0, 0)), |buffer| buffer.logical_size());
+        let size = Size::from((
+            text_size.w + 2 * DIALOG_PADDING,
+            text_size.h + 2 * DIALOG_PADDING,
+        ));
+        let area = Rectangle::from_loc_and_size(
+            ((output_size.w - size.w) / 2, (output_size.h - size.h) / 2),
+            size,
+        );
+        self.background.update(area.size, BACKGROUND_COLOR);
+        self.dim.update(output_size, DIM_COLOR);
+
+        let mut elements = vec![];
+        if let Some((_, Some(buffer))) = self.text.as_ref() {
+            let loc = area.loc + Point::from((DIALOG_PADDING, DIALOG_PADDING));
+            elements.push(DecorationRenderElement::Title(
+                TextureRenderElement::from_texture_buffer(
+                    loc.to_physical_precise_round(scale).to_f64(),
+                    buffer,
+                    None,
+                    None,
+                    None,
+                ),
+            ));
+        }
+        elements.push(decorations::solid_element(
+            &self.background,
+            area.loc,
+            scale,
+        ));
+        elements.push(decorations::solid_element(&self.dim, (0, 0).into(), scale));
+        elements
+    }
+}
+
+/// Ask the window to close, if its client is unresponsive
+/// the user is asked if it has to be killed, if it's in
+/// `confirm_close` if it has to be closed
+pub fn close_window(state: &mut AIGIState, window: &Window) {
+    let app_id = decorations::app_id(window);
+    if state.config.confirm_close.contains(&app_id) {
+        show(state, window, CloseReason::Confirm);
+    } else {
+        close_confirmed(state, window);
+    }
+}
+
+// The window is closed, unless its client is unresponsive
+fn close_confirmed(state: &mut AIGIState, window: &Window) {
+    if state.ping_state.is_unresponsive(window) {
+        show(state, window, CloseReason::Unresponsive);
+    } else {
+        window.toplevel().send_close();
+    }
+}
+
+fn show(state: &mut AIGIState, window: &Window, reason: CloseReason) {
+    tracing::debug!("CLOSE DIALOG {reason:?}: {}", decorations::title(window));
+    state.force_close = Some(ForceCloseDialog::new(
+        window.clone(),
+        reason,
+        &state.config.decorations,
+    ));
+    schedule_render(state);
+}
+
+/// The user answered the dialog, true kills the unresponsive
+/// client or closes the window that needed the confirmation
+pub fn answer(state: &mut AIGIState, accepted: bool) {
+    let Some(dialog) = state.force_close.take() else {
+        return;
+    };
+    schedule_render(state);
+    if !accepted {
+        return;
+    }
+    match dialog.reason {
+        CloseReason::Unresponsive => kill_client(state, &dialog.window),
+        CloseReason::Confirm => close_confirmed(state, &dialog.window),
+    }
+}
+
+// The pid comes from the credentials of the socket of the client,
+// the window goes away when the client disconnects. A pid that can't be
+// the client's (0 and -1 signal whole process groups, or it's aigi itself)
+// is never signaled, the client is only disconnected
+fn kill_client(state: &AIGIState, window: &Window) {
+    let Some(client) = window.toplevel().wl_surface().client() else {
+        return;
+    };
+    match client.get_credentials(&state.display_handle) {
+        Ok(credentials) if credentials.pid <= 0 || credentials.pid as u32 == std::process::id() => {
+            tracing::debug!("NOT KILLING PID {}, DISCONNECTING", credentials.pid);
+            disconnect(state, &client);
+        }
+        Ok(credentials) => {
+            tracing::debug!("KILL CLIENT: {}", credentials.pid);
+            // SAFETY: kill has no memory safety requirements
+            if unsafe { libc::kill(credentials.pid, libc::SIGKILL) } != 0 {
+                println!("Impossible kill {}", credentials.pid);
+                disconnect(state, &client);
+            }
+        }
+        Err(err) => {
+            println!("No credentials for the client: {err:?}");
+            disconnect(state, &client);
+        }
+    }
+}
+
+// The process keeps running, but it loses its windows
+fn disconnect(state: &AIGIState, client: &Client) {
+    client.kill(
+        &state.display_handle,
+        ProtocolError {
+            code: 0,
+            object_id: 1,
+            object_interface: "wl_display".into(),
+            message: "killed for not responding".into(),
+        },
+    );
+}
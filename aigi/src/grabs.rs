@@ -0,0 +1,427 @@
+use smithay::{
+    desktop::{space::SpaceElement, Window},
+    input::pointer::{
+        AxisFrame, ButtonEvent, GrabStartData as PointerGrabStartData, MotionEvent, PointerGrab,
+        PointerInnerHandle, RelativeMotionEvent,
+    },
+    reexports::{
+        wayland_protocols::xdg::shell::server::xdg_toplevel,
+        wayland_server::protocol::wl_surface::WlSurface,
+    },
+    utils::{Logical, Point, Rectangle, Size},
+};
+
+use crate::{
+    floating::{confine, snap},
+    pointer_warp,
+    render::{schedule_render, set_drop_zone},
+    state::AIGIState,
+    tiling::{Divider, DropZone, Split},
+};
+
+// A window can't be shrunk more than this while resizing
+const MIN_WINDOW_SIZE: i32 = 50;
+
+/// Pointer grab that moves a window following the cursor
+/// until the button that started the grab is released
+pub struct MoveSurfaceGrab {
+    pub start_data: PointerGrabStartData<AIGIState>,
+    pub window: Window,
+    pub initial_window_location: Point<i32, Logical>,
+    pub button: u32,
+    /// A tiled window is dropped next to the tile under the pointer
+    pub drop_target: Option<(Window, DropZone)>,
+}
+
+impl PointerGrab<AIGIState> for MoveSurfaceGrab {
+    fn motion(
+        &mut self,
+        data: &mut AIGIState,
+        handle: &mut PointerInnerHandle<'_, AIGIState>,
+        _focus: Option<(WlSurface, Point<i32, Logical>)>,
+        event: &MotionEvent,
+    ) {
+        // While the grab is active no client should have pointer focus
+        pointer_warp::motion(data, None, event.serial);
+        handle.motion(data, None, event);
+
+        let delta = event.location - self.start_data.location;
+        let mut new_location = (self.initial_window_location.to_f64() + delta).to_i32_round();
+
+        // Floating windows snap to the output edges and to the other
+        // floating windows, but they can't be dragged outside the output
+        let wl_surface = self.window.toplevel().wl_surface();
+        if data.floating_state.is_floating(wl_surface) {
+            if let Some(output_geometry) = data.output_geometry_at(event.location) {
+                let size = self.window.geometry().size;
+                let others = data
+                    .floating_state
+                    .geometries()
+                    .filter(|(surface, _)| *surface != wl_surface)
+                    .map(|(_, geometry)| *geometry)
+                    .collect::<Vec<_>>();
+
+                new_location = snap(
+                    Rectangle::from_loc_and_size(new_location, size),
+                    output_geometry,
+                    &others,
+                    data.config.snap_threshold,
+                );
+                new_location = confine(
+                    Rectangle::from_loc_and_size(new_location, size),
+                    output_geometry,
+                );
+            }
+        } else {
+            self.drop_target = update_drop_target(data, &self.window, event.location);
+        }
+
+        data.space
+            .map_element(self.window.clone(), new_location, true);
+        schedule_render(data);
+    }
+
+    fn relative_motion(
+        &mut self,
+        data: &mut AIGIState,
+        handle: &mut PointerInnerHandle<'_, AIGIState>,
+        focus: Option<(WlSurface, Point<i32, Logical>)>,
+        event: &RelativeMotionEvent,
+    ) {
+        handle.relative_motion(data, focus, event);
+    }
+
+    fn button(
+        &mut self,
+        data: &mut AIGIState,
+        handle: &mut PointerInnerHandle<'_, AIGIState>,
+        event: &ButtonEvent,
+    ) {
+        handle.button(data, event);
+
+        if !handle.current_pressed().contains(&self.button) {
+            handle.unset_grab(data, event.serial, event.time);
+            drop_window(data, &self.window, self.drop_target.take());
+        }
+    }
+
+    fn axis(
+        &mut self,
+        data: &mut AIGIState,
+        handle: &mut PointerInnerHandle<'_, AIGIState>,
+        details: AxisFrame,
+    ) {
+        handle.axis(data, details)
+    }
+
+    fn start_data(&self) -> &PointerGrabStartData<AIGIState> {
+        &self.start_data
+    }
+}
+
+/// Pointer grab that resizes a window dragging the specified edge
+pub struct ResizeSurfaceGrab {
+    pub start_data: PointerGrabStartData<AIGIState>,
+    pub window: Window,
+    pub edges: xdg_toplevel::ResizeEdge,
+    pub initial_window_geometry: Rectangle<i32, Logical>,
+    pub last_window_size: Size<i32, Logical>,
+    pub button: u32,
+}
+
+impl ResizeSurfaceGrab {
+    pub fn new(
+        start_data: PointerGrabStartData<AIGIState>,
+        window: Window,
+        edges: xdg_toplevel::ResizeEdge,
+        initial_window_geometry: Rectangle<i32, Logical>,
+        button: u32,
+    ) -> Self {
+        Self {
+            start_data,
+            window,
+            edges,
+            initial_window_geometry,
+            last_window_size: initial_window_geometry.size,
+            button,
+        }
+    }
+
+    fn has_left(&self) -> bool {
+        use xdg_toplevel::ResizeEdge;
+        matches!(
+            self.edges,
+            ResizeEdge::Left | ResizeEdge::TopLeft | ResizeEdge::BottomLeft
+        )
+    }
+
+    fn has_right(&self) -> bool {
+        use xdg_toplevel::ResizeEdge;
+        matches!(
+            self.edges,
+            ResizeEdge::Right | ResizeEdge::TopRight | ResizeEdge::BottomRight
+        )
+    }
+
+    fn has_top(&self) -> bool {
+        use xdg_toplevel::ResizeEdge;
+        matches!(
+            self.edges,
+            ResizeEdge::Top | ResizeEdge::TopLeft | ResizeEdge::TopRight
+        )
+    }
+
+    fn has_bottom(&self) -> bool {
+        use xdg_toplevel::ResizeEdge;
+        matches!(
+            self.edges,
+            ResizeEdge::Bottom | ResizeEdge::BottomLeft | ResizeEdge::BottomRight
+        )
+    }
+}
+
+impl PointerGrab<AIGIState> for ResizeSurfaceGrab {
+    fn motion(
+        &mut self,
+        data: &mut AIGIState,
+        handle: &mut PointerInnerHandle<'_, AIGIState>,
+        _focus: Option<(WlSurface, Point<i32, Logical>)>,
+        event: &MotionEvent,
+    ) {
+        pointer_warp::motion(data, None, event.serial);
+        handle.motion(data, None, event);
+
+        let delta = event.location - self.start_data.location;
+        let initial_size = self.initial_window_geometry.size;
+        let mut new_size = initial_size;
+
+        if self.has_left() {
+            new_size.w = initial_size.w - delta.x as i32;
+        } else if self.has_right() {
+            new_size.w = initial_size.w + delta.x as i32;
+        }
+
+        if self.has_top() {
+            new_size.h = initial_size.h - delta.y as i32;
+        } else if self.has_bottom() {
+            new_size.h = initial_size.h + delta.y as i32;
+        }
+
+        new_size.w = new_size.w.max(MIN_WINDOW_SIZE);
+        new_size.h = new_size.h.max(MIN_WINDOW_SIZE);
+        self.last_window_size = new_size;
+
+        // Dragging the left or top edge means that also the
+        // window location changes, the opposite edge stays still
+        let mut new_location = self.initial_window_geometry.loc;
+        if self.has_left() {
+            new_location.x += initial_size.w - new_size.w;
+        }
+        if self.has_top() {
+            new_location.y += initial_size.h - new_size.h;
+        }
+        data.space
+            .map_element(self.window.clone(), new_location, true);
+        schedule_render(data);
+
+        self.window.toplevel().with_pending_state(|state| {
+            state.states.set(xdg_toplevel::State::Resizing);
+            state.size = Some(new_size);
+        });
+        self.window.toplevel().send_configure();
+    }
+
+    fn relative_motion(
+        &mut self,
+        data: &mut AIGIState,
+        handle: &mut PointerInnerHandle<'_, AIGIState>,
+        focus: Option<(WlSurface, Point<i32, Logical>)>,
+        event: &RelativeMotionEvent,
+    ) {
+        handle.relative_motion(data, focus, event);
+    }
+
+    fn button(
+        &mut self,
+        data: &mut AIGIState,
+        handle: &mut PointerInnerHandle<'_, AIGIState>,
+        event: &ButtonEvent,
+    ) {
+        handle.button(data, event);
+
+        if !handle.current_pressed().contains(&self.button) {
+            handle.unset_grab(data, event.serial, event.time);
+
+            self.window.toplevel().with_pending_state(|state| {
+                state.states.unset(xdg_toplevel::State::Resizing);
+            });
+            if data
+                .floating_state
+                .is_floating(self.window.toplevel().wl_surface())
+            {
+                self.window.toplevel().send_configure();
+            }
+            finish_grab(data, &self.window, self.last_window_size);
+        }
+    }
+
+    fn axis(
+        &mut self,
+        data: &mut AIGIState,
+        handle: &mut PointerInnerHandle<'_, AIGIState>,
+        details: AxisFrame,
+    ) {
+        handle.axis(data, details)
+    }
+
+    fn start_data(&self) -> &PointerGrabStartData<AIGIState> {
+        &self.start_data
+    }
+}
+
+/// Pointer grab that resizes a tile moving the divisions of the
+/// Structures on the dragged edges: the tiles on both sides follow
+pub struct TileResizeGrab {
+    pub start_data: PointerGrabStartData<AIGIState>,
+    pub window: Window,
+    // At most one division for each axis (eg. for a corner)
+    pub dividers: Vec<Divider>,
+    pub button: u32,
+}
+
+impl PointerGrab<AIGIState> for TileResizeGrab {
+    fn motion(
+        &mut self,
+        data: &mut AIGIState,
+        handle: &mut PointerInnerHandle<'_, AIGIState>,
+        _focus: Option<(WlSurface, Point<i32, Logical>)>,
+        event: &MotionEvent,
+    ) {
+        pointer_warp::motion(data, None, event.serial);
+        handle.motion(data, None, event);
+
+        let delta = event.location - self.start_data.location;
+        for divider in &self.dividers {
+            let (moved, length) = match divider.split {
+                Split::Horizontal => (delta.x, divider.geometry.size.w),
+                Split::Vertical => (delta.y, divider.geometry.size.h),
+            };
+            let ratio = divider.ratio + (moved / length.max(1) as f64) as f32;
+            // The Structure could be gone (eg. a window was closed)
+            match data.tiling_state.set_ratio(divider.node, ratio) {
+                Ok(node) => data.update_tiles(node),
+                Err(err) => println!("Tile not resized: {err}"),
+            }
+        }
+    }
+
+    fn relative_motion(
+        &mut self,
+        data: &mut AIGIState,
+        handle: &mut PointerInnerHandle<'_, AIGIState>,
+        focus: Option<(WlSurface, Point<i32, Logical>)>,
+        event: &RelativeMotionEvent,
+    ) {
+        handle.relative_motion(data, focus, event);
+    }
+
+    fn button(
+        &mut self,
+        data: &mut AIGIState,
+        handle: &mut PointerInnerHandle<'_, AIGIState>,
+        event: &ButtonEvent,
+    ) {
+        handle.button(data, event);
+
+        if !handle.current_pressed().contains(&self.button) {
+            handle.unset_grab(data, event.serial, event.time);
+            self.window.toplevel().with_pending_state(|state| {
+                state.states.unset(xdg_toplevel::State::Resizing);
+            });
+            self.window.toplevel().send_configure();
+        }
+    }
+
+    fn axis(
+        &mut self,
+        data: &mut AIGIState,
+        handle: &mut PointerInnerHandle<'_, AIGIState>,
+        details: AxisFrame,
+    ) {
+        handle.axis(data, details)
+    }
+
+    fn start_data(&self) -> &PointerGrabStartData<AIGIState> {
+        &self.start_data
+    }
+}
+
+/// Choose the edges to resize based on the quadrant of the
+/// window where the pointer is, used when the resize is started
+/// from a binding and not from a client request
+pub fn edges_from_location(
+    window: &Window,
+    window_location: Point<i32, Logical>,
+    pointer_location: Point<f64, Logical>,
+) -> xdg_toplevel::ResizeEdge {
+    let size = window.geometry().size;
+    let relative = pointer_location - window_location.to_f64();
+    let left = relative.x < size.w as f64 / 2.0;
+    let top = relative.y < size.h as f64 / 2.0;
+
+    match (left, top) {
+        (true, true) => xdg_toplevel::ResizeEdge::TopLeft,
+        (true, false) => xdg_toplevel::ResizeEdge::BottomLeft,
+        (false, true) => xdg_toplevel::ResizeEdge::TopRight,
+        (false, false) => xdg_toplevel::ResizeEdge::BottomRight,
+    }
+}
+
+/// The tile next to which a moved tiled window would be dropped,
+/// the half of it that the window would take is shown
+pub fn update_drop_target(
+    data: &mut AIGIState,
+    window: &Window,
+    location: Point<f64, Logical>,
+) -> Option<(Window, DropZone)> {
+    let target = data
+        .tiling_state
+        .drop_target(location.to_i32_round(), window.toplevel().wl_surface());
+    set_drop_zone(data, target.as_ref().map(|(_, _, half)| *half));
+    target.map(|(window, zone, _)| (window, zone))
+}
+
+/// A moved window is released: next to the tile it was dropped on
+/// (see `update_drop_target`), or back where it belongs
+pub fn drop_window(data: &mut AIGIState, window: &Window, target: Option<(Window, DropZone)>) {
+    set_drop_zone(data, None);
+    if let Some((target, zone)) = target {
+        match data.tiling_state.move_tile(
+            window.toplevel().wl_surface(),
+            target.toplevel().wl_surface(),
+            zone,
+        ) {
+            Ok(head) => {
+                data.update_tiles(head);
+                return;
+            }
+            Err(err) => println!("Tile not moved: {err}"),
+        }
+    }
+    finish_grab(data, window, window.geometry().size);
+}
+
+// Once a grab ends the window needs to go back where it belongs:
+// tiled windows can't live outside their tile so the tile layout is restored,
+// while floating windows keep the new geometry
+fn finish_grab(data: &mut AIGIState, window: &Window, size: Size<i32, Logical>) {
+    let wl_surface = window.toplevel().wl_surface();
+    if data.floating_state.is_floating(wl_surface) {
+        if let Some(location) = data.space.element_location(window) {
+            data.floating_state
+                .set_geometry(wl_surface, Rectangle::from_loc_and_size(location, size));
+        }
+    } else if let Some(node) = data.tiling_state.tile_node(wl_surface) {
+        data.update_tiles(node);
+    }
+}
@@ -0,0 +1,179 @@
+use smithay::{
+    backend::renderer::element::{
+        solid::{SolidColorBuffer, SolidColorRenderElement},
+        Kind,
+    },
+    reexports::calloop::timer::{TimeoutAction, Timer},
+    utils::{Logical, Point, Scale, Size},
+};
+use std::time::{Duration, Instant};
+
+use crate::{
+    render::{self, schedule_render},
+    state::AIGIState,
+};
+
+const DIM_FADE: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum IdleStage {
+    Active,
+    // The output is darkened, fading since the instant
+    Dimmed(Instant),
+    // DPMS off, nothing is rendered
+    Off,
+}
+
+/// Two stages of inactivity: after `idle.dim_after` seconds without input
+/// the output is dimmed, after `idle.off_after` it's turned off
+pub struct IdleState {
+    last_input: Instant,
+    stage: IdleStage,
+    timer_armed: bool,
+    // Black layer over the whole output, its alpha makes the dimming
+    dim_buffer: SolidColorBuffer,
+}
+
+impl Default for IdleState {
+    fn default() -> Self {
+        Self {
+            last_input: Instant::now(),
+            stage: IdleStage::Active,
+            timer_armed: false,
+            dim_buffer: SolidColorBuffer::default(),
+        }
+    }
+}
+
+impl IdleState {
+    /// The output is off, rendering would be useless
+    pub fn display_off(&self) -> bool {
+        self.stage == IdleStage::Off
+    }
+
+    /// The dimming is still fading in, new frames are needed
+    pub fn fading(&self) -> bool {
+        matches!(self.stage, IdleStage::Dimmed(since) if since.elapsed() < DIM_FADE)
+    }
+
+    /// Layer drawn above everything while dimmed, `level` is
+    /// the brightness that is reached at the end of the fade
+    pub fn render_element(
+        &mut self,
+        output_size: Size<i32, Logical>,
+        scale: Scale<f64>,
+        level: f32,
+    ) -> Option<SolidColorRenderElement> {
+        let IdleStage::Dimmed(since) = self.stage else {
+            return None;
+        };
+        let progress = (since.elapsed().as_secs_f32() / DIM_FADE.as_secs_f32()).min(1.0);
+        let alpha = (1.0 - level.clamp(0.0, 1.0)) * progress;
+
+        self.dim_buffer.update(output_size, [0.0, 0.0, 0.0, alpha]);
+        Some(SolidColorRenderElement::from_buffer(
+            &self.dim_buffer,
+            Point::<i32, Logical>::from((0, 0)).to_physical_precise_round(scale),
+            scale,
+            1.0,
+            Kind::Unspecified,
+        ))
+    }
+}
+
+/// Any input restores the output right away and restarts the timeouts
+pub fn input_activity(state: &mut AIGIState) {
+    state.idle_state.last_input = Instant::now();
+    render::activity(state);
+
+    match state.idle_state.stage {
+        IdleStage::Active => {}
+        IdleStage::Dimmed(_) => {
+            state.idle_state.stage = IdleStage::Active;
+            schedule_render(state);
+        }
+        IdleStage::Off => {
+            state.idle_state.stage = IdleStage::Active;
+            tracing::debug!("IDLE: output on");
+            if let Err(err) = state.backend_data.set_display_power(true) {
+                println!("Impossible turn the output on: {err}");
+            }
+            schedule_render(state);
+        }
+    }
+
+    arm_timer(state);
+}
+
+/// Start counting the inactivity, eg. after reloading the config
+pub fn arm_timer(state: &mut AIGIState) {
+    if state.idle_state.timer_armed {
+        return;
+    }
+    let Some(delay) = next_stage_in(state) else {
+        return;
+    };
+
+    state.idle_state.timer_armed = true;
+    state
+        .handle
+        .insert_source(Timer::from_duration(delay), |_, _, loop_data| {
+            let state = &mut loop_data.state;
+            advance(state);
+            match next_stage_in(state) {
+                Some(delay) => TimeoutAction::ToDuration(delay),
+                None => {
+                    state.idle_state.timer_armed = false;
+                    TimeoutAction::Drop
+                }
+            }
+        })
+        .expect("failed to schedule idle timer");
+}
+
+// Timeouts are counted from the last input, 0 disables a stage
+fn timeouts(state: &AIGIState) -> (Option<Duration>, Option<Duration>) {
+    let config = &state.config.idle;
+    let timeout = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+    (timeout(config.dim_after), timeout(config.off_after))
+}
+
+// Time left before the next stage, None if there is nothing else to wait for.
+// A game (see `AIGIState::game_mode_window`) is never dimmed
+fn next_stage_in(state: &AIGIState) -> Option<Duration> {
+    let idle = state.idle_state.last_input.elapsed();
+    let (dim_after, off_after) = timeouts(state);
+
+    let dim = dim_after
+        .filter(|_| state.idle_state.stage == IdleStage::Active)
+        .filter(|_| state.game_mode_window().is_none());
+    let off = off_after.filter(|_| state.idle_state.stage != IdleStage::Off);
+    [dim, off]
+        .into_iter()
+        .flatten()
+        .map(|timeout| timeout.saturating_sub(idle))
+        .min()
+}
+
+fn advance(state: &mut AIGIState) {
+    let idle = state.idle_state.last_input.elapsed();
+    let (dim_after, off_after) = timeouts(state);
+
+    if off_after.is_some_and(|off| idle >= off) && state.idle_state.stage != IdleStage::Off {
+        tracing::debug!("IDLE: output off");
+        state.idle_state.stage = IdleStage::Off;
+        if let Err(err) = state.backend_data.set_display_power(false) {
+            println!("Impossible turn the output off: {err}");
+        }
+        return;
+    }
+
+    if dim_after.is_some_and(|dim| idle >= dim)
+        && state.idle_state.stage == IdleStage::Active
+        && state.game_mode_window().is_none()
+    {
+        tracing::debug!("IDLE: output dimmed");
+        state.idle_state.stage = IdleStage::Dimmed(Instant::now());
+        schedule_render(state);
+    }
+}
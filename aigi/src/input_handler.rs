@@ -1,102 +1,285 @@
 use smithay::{
     backend::{
         input::{
-            AbsolutePositionEvent, Event, InputEvent, KeyState, KeyboardKeyEvent,
-            PointerMotionEvent,
+            AbsolutePositionEvent, ButtonState, Device, Event, GestureBeginEvent, GestureEndEvent,
+            GestureSwipeUpdateEvent, InputBackend, InputEvent, KeyState, KeyboardKeyEvent,
+            PointerButtonEvent, PointerMotionEvent, TouchEvent, TouchSlot,
         },
         libinput::LibinputInputBackend,
     },
-    input::keyboard::{keysyms, FilterResult},
-    utils::SERIAL_COUNTER,
+    input::{
+        keyboard::{keysyms, xkb, FilterResult, ModifiersState},
+        pointer::{ButtonEvent, GrabStartData as PointerGrabStartData},
+    },
+    reexports::{
+        calloop::{generic::Generic, Interest, Mode, PostAction},
+        wayland_protocols::xdg::shell::server::xdg_toplevel,
+        wayland_server::protocol::wl_surface::WlSurface,
+    },
+    utils::{Logical, Point, Serial, SERIAL_COUNTER},
     wayland::seat::WaylandFocus,
 };
+use std::{
+    io::{ErrorKind, Read},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
 
-use crate::{state::AIGIState, tiling};
+use crate::{
+    backlight::SysfsBrightness,
+    config::{
+        GestureAction, MediaAction, MediaKeyBinding, PointerAction, SwipeDirection, BTN_LEFT,
+    },
+    decorations::DecorationHit,
+    devices,
+    floating::SnapPosition,
+    force_close, idle, layer_focus, marks, notifications,
+    osd::{self, OsdKind},
+    outputs, overview, placement, pointer_warp, relaunch,
+    render::{cursor_moved, keyboard_activity, pointer_activity, schedule_render},
+    state::AIGIState,
+    tiling, toplevel_drag,
+};
 
 pub enum Action {
     exec_process(&'static str),
     change_split(tiling::Split),
+    toggle_split,
+    toggle_floating,
+    cycle_layout,
+    cycle_container_mode,
+    // true goes to the next tab, false to the previous one
+    cycle_tab(bool),
+    toggle_do_not_disturb,
+    // Part of the container gained by the focused tile, negative to shrink it
+    resize_tile(f32),
+    balance_tiles,
+    toggle_overview,
+    overview_select(tiling::Direction),
+    // true picks the selected window, false goes back to the focused one
+    overview_close(bool),
+    placement_step(tiling::DropZone),
+    // true tiles the window in the chosen half, false where it would go anyway
+    placement_done(bool),
+    snap(SnapPosition),
+    toggle_game_mode,
+    reload_config,
+    relaunch,
+    focus_prev,
+    focus_direction(tiling::Direction),
+    rescan_outputs,
+    toggle_accel_profile,
+    close_window,
+    // Answer of the force close dialog, true kills the client
+    // (or closes the window, if it was asking for a confirmation)
+    force_close(bool),
+    media_key(MediaKeyBinding),
+    // The focused window takes the mark, see `Marks`
+    mark(char),
+    goto_mark(char),
+    // The key has been consumed by the compositor (eg. a chord prefix)
+    // but there is nothing else to do
+    nothing,
 }
 
-// This function based on the input will apply all the required
-// side effects to the AIGIState and return a Action that the AIGIState
-// should take actively
-pub fn handle_input(state: &mut AIGIState, event: InputEvent<LibinputInputBackend>) {
-    match event {
-        InputEvent::Keyboard { event } => {
-            // If we received a keyboard event, get the keyboard from the seat
-            // and process a key input.
-            let serial = SERIAL_COUNTER.next_serial();
-            let time = Event::time_msec(&event);
-            let press_state = event.state();
-            let action = state.seat.get_keyboard().unwrap().input::<Action, _>(
-                state,
-                event.key_code(),
-                press_state,
-                serial,
-                time,
-                |_, _, keysym| {
-                    // If the user pressed the letter T, return the action value of
-                    // 1.
-                    if press_state == KeyState::Pressed && keysym.modified_sym() == keysyms::KEY_W {
-                        println!("WESTON-TERMINAL");
-                        FilterResult::Intercept(Action::exec_process("weston-terminal"))
-                    } else if press_state == KeyState::Pressed
-                        && keysym.modified_sym() == keysyms::KEY_A
-                    {
-                        println!("ALACRITTY");
-                        FilterResult::Intercept(Action::exec_process("alacritty"))
-                    } else if press_state == KeyState::Pressed
-                        && keysym.modified_sym() == keysyms::KEY_V
-                    {
-                        println!("SPLIT VERTICAL");
-                        FilterResult::Intercept(Action::change_split(tiling::Split::Vertical))
-                    } else if press_state == KeyState::Pressed
-                        && keysym.modified_sym() == keysyms::KEY_O
-                    {
-                        println!("SPLIT HORIZONTAL");
-                        FilterResult::Intercept(Action::change_split(tiling::Split::Horizontal))
-                    } else {
-                        println!("Forward: {keysym:?}");
-                        FilterResult::Forward
-                    }
-                },
-            );
+// After a prefix is pressed the second key of the chord
+// needs to arrive before this timeout, otherwise the chord is dropped
+const CHORD_TIMEOUT: Duration = Duration::from_millis(1500);
 
-            match action {
-                Some(Action::exec_process(process_name)) => {
-                    std::process::Command::new(process_name).spawn().unwrap();
-                }
-                Some(Action::change_split(new_split)) => {
-                    match state.seat.get_keyboard().unwrap().current_focus() {
-                        Some(wl_surface) => {
-                            state.tiling_state.set_split(&wl_surface, new_split);
-                        }
-                        None => (),
-                    }
+/// Keeps track of multi-step bindings (Emacs/tmux-style prefixes),
+/// eg. Super+g followed by h or v
+#[derive(Default)]
+pub enum ChordState {
+    #[default]
+    Idle,
+    Pending {
+        prefix: u32,
+        started: Instant,
+    },
+}
+
+impl ChordState {
+    /// Feed a pressed keysym to the state machine.
+    ///
+    /// Returns None if no chord is pending (or it expired) and the key
+    /// must be handled as a normal binding, otherwise the result
+    /// of the chord, keys that are not part of the chord are swallowed
+    fn advance(&mut self, keysym: u32) -> Option<FilterResult<Action>> {
+        let prefix = match self {
+            ChordState::Idle => return None,
+            ChordState::Pending { prefix, started } => {
+                if started.elapsed() > CHORD_TIMEOUT {
+                    tracing::debug!("CHORD TIMED OUT");
+                    *self = ChordState::Idle;
+                    return None;
                 }
-                _ => (),
+                *prefix
             }
+        };
+
+        // Pressing a modifier (eg. releasing Super and pressing Shift)
+        // should not break the chord
+        if (keysyms::KEY_Shift_L..=keysyms::KEY_Hyper_R).contains(&keysym) {
+            return Some(FilterResult::Forward);
         }
+
+        *self = ChordState::Idle;
+        let action = match (prefix, keysym) {
+            (keysyms::KEY_g, keysyms::KEY_h) => Action::change_split(tiling::Split::Horizontal),
+            (keysyms::KEY_g, keysyms::KEY_v) => Action::change_split(tiling::Split::Vertical),
+            (keysyms::KEY_m, _) => match mark_char(keysym) {
+                Some(mark) => Action::mark(mark),
+                None => Action::nothing,
+            },
+            (keysyms::KEY_apostrophe, _) => match mark_char(keysym) {
+                Some(mark) => Action::goto_mark(mark),
+                None => Action::nothing,
+            },
+            _ => {
+                tracing::debug!("CHORD UNBOUND: {keysym:?}");
+                Action::nothing
+            }
+        };
+        Some(FilterResult::Intercept(action))
+    }
+}
+
+// Character typed with the key that follows a mark prefix,
+// None for the keys without one (eg. the arrows)
+fn mark_char(keysym: u32) -> Option<char> {
+    char::from_u32(xkb::keysym_to_utf32(keysym)).filter(|c| !c.is_control())
+}
+
+// A touch that starts this close to the left or right edge
+// of an output can become a workspace swipe
+const SWIPE_EDGE_SIZE: f64 = 24.0;
+// Horizontal distance the finger needs to travel to switch workspace
+const SWIPE_DISTANCE: f64 = 100.0;
+
+/// A touch started on the edge of a touchscreen, if it travels far enough
+/// towards the center of the output the workspace is switched:
+/// swiping from the left edge shows the previous workspace,
+/// from the right edge the next one
+pub struct EdgeSwipe {
+    slot: TouchSlot,
+    start: Point<f64, Logical>,
+    current: Point<f64, Logical>,
+    // -1 previous workspace, +1 next workspace
+    direction: isize,
+}
+
+impl EdgeSwipe {
+    fn start(state: &AIGIState, slot: TouchSlot, location: Point<f64, Logical>) -> Option<Self> {
+        let output_geometry = state.output_geometry_at(location)?.to_f64();
+        let direction = if location.x - output_geometry.loc.x < SWIPE_EDGE_SIZE {
+            -1
+        } else if output_geometry.loc.x + output_geometry.size.w - location.x < SWIPE_EDGE_SIZE {
+            1
+        } else {
+            return None;
+        };
+
+        Some(Self {
+            slot,
+            start: location,
+            current: location,
+            direction,
+        })
+    }
+
+    // The finger moves in the opposite direction of the workspaces:
+    // from the left edge to the right for the previous one
+    fn completed(&self) -> bool {
+        let travelled = self.current.x - self.start.x;
+        -(self.direction as f64) * travelled >= SWIPE_DISTANCE
+    }
+}
+
+// Part of the container a tile gains or loses with Super+l and Super+h
+const RESIZE_STEP: f32 = 0.05;
+
+// Distance, in touchpad units, under which a swipe is ignored
+const GESTURE_THRESHOLD: f64 = 50.0;
+
+/// Touchpad swipe in progress, the action is choosen when
+/// the fingers are lifted, based on where they moved overall
+pub struct SwipeGesture {
+    fingers: u32,
+    delta: Point<f64, Logical>,
+}
+
+impl SwipeGesture {
+    // The axis with the longest movement wins
+    fn direction(&self) -> Option<SwipeDirection> {
+        let (dx, dy) = (self.delta.x, self.delta.y);
+        if dx.abs().max(dy.abs()) < GESTURE_THRESHOLD {
+            return None;
+        }
+
+        Some(if dx.abs() >= dy.abs() {
+            if dx < 0.0 {
+                SwipeDirection::Left
+            } else {
+                SwipeDirection::Right
+            }
+        } else if dy < 0.0 {
+            SwipeDirection::Up
+        } else {
+            SwipeDirection::Down
+        })
+    }
+}
+
+// Touchscreens are mapped on the whole layout, like absolute pointers
+fn touch_location<B: InputBackend>(
+    state: &AIGIState,
+    event: &impl AbsolutePositionEvent<B>,
+) -> Option<Point<f64, Logical>> {
+    let layout_geo = state.layout_geometry()?;
+    Some(event.position_transformed(layout_geo.size) + layout_geo.loc.to_f64())
+}
+
+// This function based on the input will apply all the required
+// side effects to the AIGIState and return a Action that the AIGIState
+// should take actively
+#[profiling::function]
+pub fn handle_input<B: InputBackend>(state: &mut AIGIState, event: InputEvent<B>) {
+    idle::input_activity(state);
+
+    match event {
+        InputEvent::Keyboard { event } => handle_keyboard::<B>(state, event),
         InputEvent::PointerMotionAbsolute { event, .. } => {
-            // Get the first output.
-            let output = state.space.outputs().next().unwrap();
-            let output_geo = state.space.output_geometry(output).unwrap();
-            // Convert the device position to use the output coordinate system.
-            let pointer_location = event.position_transformed(output_geo.size);
+            // Absolute devices cover the whole layout, the bounding box
+            // of all the outputs mapped in the space
+            let Some(layout_geo) = state.layout_geometry() else {
+                return;
+            };
+            // Convert the device position to use the global coordinate system.
+            let pointer_location =
+                event.position_transformed(layout_geo.size) + layout_geo.loc.to_f64();
 
             state.pointer_location = pointer_location;
+            // The cursor needs to be drawn in the new position
+            pointer_activity(state);
+            cursor_moved(state);
+            toplevel_drag::motion(state);
+            // The overview takes the pointer, the clients don't see it
+            if overview::pointer_moved(state) {
+                return;
+            }
 
             println!("Pointer moved, New Location: {pointer_location:?}");
+            let pointer_location = state.client_pointer_location(pointer_location);
 
             let pointer = state.seat.get_pointer().unwrap();
 
             // Get the surface below the pointer if it exists. First get the
             // element under a position, then get the surface under that position.
-            let surface_under_pointer =
+            // The top and overlay layers (eg. a launcher) are above the windows
+            let layer_under = layer_focus::layer_under(state, state.pointer_location);
+            let surface_under_pointer = layer_under.clone().or_else(|| {
                 state
-                    .space
-                    .element_under(pointer_location)
+                    .window_under(pointer_location)
                     .and_then(|(window, location)| {
                         window
                             .surface_under(
@@ -104,20 +287,29 @@ pub fn handle_input(state: &mut AIGIState, event: InputEvent<LibinputInputBacken
                                 smithay::desktop::WindowSurfaceType::ALL,
                             )
                             .map(|(s, p)| (s, p + location))
-                    });
+                    })
+            });
 
+            // Over the decorations the keyboard focus goes to their window
+            let decoration_focus = update_decoration_hover(state);
             let mut serial = SERIAL_COUNTER.next_serial();
-            state.seat.get_keyboard().unwrap().set_focus(
-                state,
-                surface_under_pointer
-                    .as_ref()
-                    .and_then(|s| Some(s.0.clone())),
-                serial,
-            );
+            // A layer gets the keyboard only when clicked, and the
+            // windows can't take it from an exclusive one
+            if layer_under.is_none() && !layer_focus::exclusive(state) {
+                state.seat.get_keyboard().unwrap().set_focus(
+                    state,
+                    surface_under_pointer
+                        .as_ref()
+                        .map(|s| s.0.clone())
+                        .or(decoration_focus),
+                    serial,
+                );
+            }
 
             serial = SERIAL_COUNTER.next_serial();
 
             // Send the motion event to the client.
+            pointer_warp::motion(state, surface_under_pointer.as_ref(), serial);
             pointer.motion(
                 state,
                 surface_under_pointer,
@@ -132,10 +324,20 @@ pub fn handle_input(state: &mut AIGIState, event: InputEvent<LibinputInputBacken
             let mut pointer_location = state.pointer_location;
             pointer_location += event.delta();
 
-            // clamp to screen coords
-            // self.clamp_coords(&mut pointer_location);
+            // clamp to screen coords, the pointer can move freely
+            // between outputs but not outside of them
+            let pointer_location = state.clamp_coords(pointer_location);
 
             state.pointer_location = pointer_location;
+            // The cursor needs to be drawn in the new position
+            pointer_activity(state);
+            cursor_moved(state);
+            toplevel_drag::motion(state);
+            // The overview takes the pointer, the clients don't see it
+            if overview::pointer_moved(state) {
+                return;
+            }
+            let pointer_location = state.client_pointer_location(pointer_location);
 
             let pointer = state
                 .seat
@@ -144,10 +346,11 @@ pub fn handle_input(state: &mut AIGIState, event: InputEvent<LibinputInputBacken
 
             // Get the surface below the pointer if it exists. First get the
             // element under a position, then get the surface under that position.
-            let surface_under_pointer =
+            // The top and overlay layers (eg. a launcher) are above the windows
+            let layer_under = layer_focus::layer_under(state, state.pointer_location);
+            let surface_under_pointer = layer_under.clone().or_else(|| {
                 state
-                    .space
-                    .element_under(state.pointer_location)
+                    .window_under(pointer_location)
                     .and_then(|(window, location)| {
                         Some((
                             window
@@ -155,23 +358,31 @@ pub fn handle_input(state: &mut AIGIState, event: InputEvent<LibinputInputBacken
                                 .expect("Impossible extract wl_surface from window"),
                             location,
                         ))
-                    });
+                    })
+            });
             println!("surface under pointer: {:?}", surface_under_pointer);
 
+            // Over the decorations the keyboard focus goes to their window
+            let decoration_focus = update_decoration_hover(state);
             let mut serial = SERIAL_COUNTER.next_serial();
 
-            // set wl_surface focus
-            state.seat.get_keyboard().unwrap().set_focus(
-                state,
-                surface_under_pointer
-                    .as_ref()
-                    .and_then(|s| Some(s.0.clone())),
-                serial,
-            );
+            // set wl_surface focus, a layer gets it only when clicked
+            // and the windows can't take it from an exclusive one
+            if layer_under.is_none() && !layer_focus::exclusive(state) {
+                state.seat.get_keyboard().unwrap().set_focus(
+                    state,
+                    surface_under_pointer
+                        .as_ref()
+                        .map(|s| s.0.clone())
+                        .or(decoration_focus),
+                    serial,
+                );
+            }
 
             serial = SERIAL_COUNTER.next_serial();
 
             // Send the motion event to the client.
+            pointer_warp::motion(state, surface_under_pointer.as_ref(), serial);
             pointer.motion(
                 state,
                 surface_under_pointer.clone(),
@@ -192,6 +403,656 @@ pub fn handle_input(state: &mut AIGIState, event: InputEvent<LibinputInputBacken
                 },
             )
         }
-        event => println!("Other input to handle: {event:?}"),
+        InputEvent::PointerButton { event, .. } => {
+            let pointer = state.seat.get_pointer().unwrap();
+            let serial = SERIAL_COUNTER.next_serial();
+            let button = event.button_code();
+            let button_state = event.state();
+            pointer_activity(state);
+
+            if button_state == ButtonState::Pressed && overview::pointer_pressed(state) {
+                return;
+            }
+
+            // Pointer bindings are not forwarded to the client,
+            // they start a grab on the window under the pointer
+            if button_state == ButtonState::Pressed
+                && !pointer.is_grabbed()
+                && start_pointer_binding(state, button, serial)
+            {
+                return;
+            }
+
+            if !pointer.is_grabbed() && decoration_click(state, button, button_state, serial) {
+                return;
+            }
+
+            if button_state == ButtonState::Pressed && !pointer.is_grabbed() {
+                layer_focus::layer_clicked(state, state.pointer_location);
+            }
+
+            pointer.button(
+                state,
+                &ButtonEvent {
+                    button,
+                    state: button_state,
+                    serial,
+                    time: event.time_msec(),
+                },
+            );
+        }
+        InputEvent::TouchDown { event } => {
+            // Only one finger at the time can swipe
+            if state.edge_swipe.is_some() {
+                return;
+            }
+            if let Some(location) = touch_location(state, &event) {
+                state.edge_swipe = EdgeSwipe::start(state, event.slot(), location);
+            }
+        }
+        InputEvent::TouchMotion { event } => {
+            let Some(location) = touch_location(state, &event) else {
+                return;
+            };
+            if let Some(swipe) = state.edge_swipe.as_mut() {
+                if swipe.slot == event.slot() {
+                    swipe.current = location;
+                }
+            }
+        }
+        InputEvent::TouchUp { event } => {
+            let Some(swipe) = state.edge_swipe.take() else {
+                return;
+            };
+            // Another finger was lifted, the swipe goes on
+            if swipe.slot != event.slot() {
+                state.edge_swipe = Some(swipe);
+                return;
+            }
+            if swipe.completed() {
+                if let Some(index) = state.workspaces.relative(swipe.direction) {
+                    state.switch_workspace(index);
+                }
+            }
+        }
+        InputEvent::TouchCancel { .. } => state.edge_swipe = None,
+        InputEvent::GestureSwipeBegin { event } => {
+            state.swipe_gesture = Some(SwipeGesture {
+                fingers: event.fingers(),
+                delta: (0.0, 0.0).into(),
+            });
+        }
+        InputEvent::GestureSwipeUpdate { event } => {
+            if let Some(gesture) = state.swipe_gesture.as_mut() {
+                gesture.delta += (event.delta_x(), event.delta_y()).into();
+            }
+        }
+        InputEvent::GestureSwipeEnd { event } => {
+            let Some(gesture) = state.swipe_gesture.take() else {
+                return;
+            };
+            if event.cancelled() {
+                return;
+            }
+            let Some(direction) = gesture.direction() else {
+                return;
+            };
+            let Some(binding) = state
+                .config
+                .gesture_bindings
+                .iter()
+                .find(|b| b.fingers == gesture.fingers && b.direction == direction)
+                .copied()
+            else {
+                return;
+            };
+
+            match binding.action {
+                GestureAction::WorkspacePrevious | GestureAction::WorkspaceNext => {
+                    let offset = match binding.action {
+                        GestureAction::WorkspacePrevious => -1,
+                        _ => 1,
+                    };
+                    if let Some(index) = state.workspaces.relative(offset) {
+                        state.switch_workspace(index);
+                    }
+                }
+                GestureAction::ToggleMaximize => {
+                    if let Some(wl_surface) = state.seat.get_keyboard().unwrap().current_focus() {
+                        state.toggle_maximize(&wl_surface);
+                    }
+                }
+            }
+        }
+        // The devices are configured only on the tty, see `handle_libinput`
+        InputEvent::DeviceAdded { .. } | InputEvent::DeviceRemoved { .. } => {}
+        _ => println!("Other input to handle"),
+    }
+}
+
+/// Input of the tty, the devices are configured as they are plugged
+pub fn handle_libinput(state: &mut AIGIState, event: InputEvent<LibinputInputBackend>) {
+    match event {
+        InputEvent::DeviceAdded { device } => {
+            idle::input_activity(state);
+            devices::device_added(state, device)
+        }
+        InputEvent::DeviceRemoved { device } => {
+            idle::input_activity(state);
+            devices::device_removed(state, device)
+        }
+        event => handle_input(state, event),
+    }
+}
+
+/// Keyboard events of any input backend: the compositor bindings,
+/// then the focused client. Modifiers and keysyms come from the
+/// XKB state of the seat, so every backend behaves the same way
+pub fn handle_keyboard<B: InputBackend>(state: &mut AIGIState, event: B::KeyboardKeyEvent) {
+    // If we received a keyboard event, get the keyboard from the seat
+    // and process a key input.
+    let serial = SERIAL_COUNTER.next_serial();
+    let time = Event::time_msec(&event);
+    let press_state = event.state();
+    if press_state == KeyState::Pressed {
+        keyboard_activity(state);
+    }
+
+    // Every keyboard feeds the same XKB state
+    let device = Device::id(&event.device());
+    if !state
+        .pressed_keys
+        .update(&device, event.key_code(), press_state)
+    {
+        return;
+    }
+
+    let action = state.seat.get_keyboard().unwrap().input::<Action, _>(
+        state,
+        event.key_code(),
+        press_state,
+        serial,
+        time,
+        |state, modifiers, keysym| {
+            if press_state != KeyState::Pressed {
+                return FilterResult::Forward;
+            }
+
+            // The force close dialog takes every key until it's answered
+            if state.force_close.is_some() {
+                return match keysym.modified_sym() {
+                    keysyms::KEY_Return => FilterResult::Intercept(Action::force_close(true)),
+                    keysyms::KEY_Escape => FilterResult::Intercept(Action::force_close(false)),
+                    _ => FilterResult::Intercept(Action::nothing),
+                };
+            }
+
+            // The arrows choose where the new window goes, the other keys
+            // still reach the focused client
+            if state.placement.is_some() {
+                let action = match keysym.modified_sym() {
+                    keysyms::KEY_Left => Some(Action::placement_step(tiling::DropZone::Left)),
+                    keysyms::KEY_Right => Some(Action::placement_step(tiling::DropZone::Right)),
+                    keysyms::KEY_Up => Some(Action::placement_step(tiling::DropZone::Top)),
+                    keysyms::KEY_Down => Some(Action::placement_step(tiling::DropZone::Bottom)),
+                    keysyms::KEY_Return => Some(Action::placement_done(true)),
+                    keysyms::KEY_Escape => Some(Action::placement_done(false)),
+                    _ => None,
+                };
+                if let Some(action) = action {
+                    return FilterResult::Intercept(action);
+                }
+            }
+
+            // The overview takes every key too, the clients have no keyboard focus anyway
+            if state.overview.is_some() {
+                return FilterResult::Intercept(match keysym.modified_sym() {
+                    keysyms::KEY_Left => Action::overview_select(tiling::Direction::Left),
+                    keysyms::KEY_Right => Action::overview_select(tiling::Direction::Right),
+                    keysyms::KEY_Up => Action::overview_select(tiling::Direction::Up),
+                    keysyms::KEY_Down => Action::overview_select(tiling::Direction::Down),
+                    keysyms::KEY_Return => Action::overview_close(true),
+                    keysyms::KEY_Escape => Action::overview_close(false),
+                    keysyms::KEY_e if modifiers.logo => Action::toggle_overview,
+                    _ => Action::nothing,
+                });
+            }
+
+            // Media keys work whatever client has the focus
+            // and whatever modifiers are pressed
+            let name = xkb::keysym_get_name(keysym.modified_sym());
+            if let Some(binding) = state.config.media_keys.iter().find(|b| b.key == name) {
+                return FilterResult::Intercept(Action::media_key(binding.clone()));
+            }
+
+            // A chord prefix was pressed before, this key decides
+            // what the chord will do
+            if let Some(result) = state.chord_state.advance(keysym.modified_sym()) {
+                return result;
+            }
+
+            if modifiers.logo && keysym.modified_sym() == keysyms::KEY_g {
+                tracing::debug!("CHORD STARTED");
+                state.chord_state = ChordState::Pending {
+                    prefix: keysyms::KEY_g,
+                    started: Instant::now(),
+                };
+                FilterResult::Intercept(Action::nothing)
+            } else if modifiers.logo
+                && matches!(
+                    keysym.modified_sym(),
+                    keysyms::KEY_m | keysyms::KEY_apostrophe
+                )
+            {
+                tracing::debug!("MARK CHORD STARTED");
+                state.chord_state = ChordState::Pending {
+                    prefix: keysym.modified_sym(),
+                    started: Instant::now(),
+                };
+                FilterResult::Intercept(Action::nothing)
+            } else if modifiers.logo && modifiers.shift && keysym.modified_sym() == keysyms::KEY_G {
+                FilterResult::Intercept(Action::toggle_game_mode)
+            } else if modifiers.logo && modifiers.shift && keysym.modified_sym() == keysyms::KEY_R {
+                FilterResult::Intercept(Action::reload_config)
+            } else if modifiers.logo
+                && modifiers.shift
+                && keysym.modified_sym() == keysyms::KEY_Escape
+            {
+                FilterResult::Intercept(Action::relaunch)
+            } else if modifiers.logo && modifiers.shift && keysym.modified_sym() == keysyms::KEY_O {
+                FilterResult::Intercept(Action::rescan_outputs)
+            } else if modifiers.logo && modifiers.shift && keysym.modified_sym() == keysyms::KEY_P {
+                FilterResult::Intercept(Action::toggle_accel_profile)
+            } else if modifiers.logo && modifiers.shift && keysym.modified_sym() == keysyms::KEY_S {
+                FilterResult::Intercept(Action::toggle_split)
+            } else if modifiers.logo
+                && modifiers.shift
+                && keysym.modified_sym() == keysyms::KEY_space
+            {
+                FilterResult::Intercept(Action::toggle_floating)
+            } else if modifiers.logo && keysym.modified_sym() == keysyms::KEY_t {
+                FilterResult::Intercept(Action::cycle_container_mode)
+            } else if modifiers.logo && keysym.modified_sym() == keysyms::KEY_period {
+                FilterResult::Intercept(Action::cycle_tab(true))
+            } else if modifiers.logo && keysym.modified_sym() == keysyms::KEY_comma {
+                FilterResult::Intercept(Action::cycle_tab(false))
+            } else if modifiers.logo && keysym.modified_sym() == keysyms::KEY_space {
+                FilterResult::Intercept(Action::cycle_layout)
+            } else if modifiers.logo && modifiers.shift && keysym.modified_sym() == keysyms::KEY_Q {
+                FilterResult::Intercept(Action::close_window)
+            } else if modifiers.logo && modifiers.shift && keysym.modified_sym() == keysyms::KEY_N {
+                FilterResult::Intercept(Action::toggle_do_not_disturb)
+            } else if modifiers.logo && !modifiers.shift && keysym.modified_sym() == keysyms::KEY_l
+            {
+                FilterResult::Intercept(Action::resize_tile(RESIZE_STEP))
+            } else if modifiers.logo && !modifiers.shift && keysym.modified_sym() == keysyms::KEY_h
+            {
+                FilterResult::Intercept(Action::resize_tile(-RESIZE_STEP))
+            } else if modifiers.logo && keysym.modified_sym() == keysyms::KEY_equal {
+                FilterResult::Intercept(Action::balance_tiles)
+            } else if modifiers.logo && keysym.modified_sym() == keysyms::KEY_e {
+                FilterResult::Intercept(Action::toggle_overview)
+            } else if modifiers.logo && keysym.modified_sym() == keysyms::KEY_Tab {
+                FilterResult::Intercept(Action::focus_prev)
+            } else if let Some(direction) = focus_binding(modifiers, keysym.modified_sym()) {
+                FilterResult::Intercept(Action::focus_direction(direction))
+            } else if let Some(position) = snap_binding(modifiers, keysym.modified_sym()) {
+                FilterResult::Intercept(Action::snap(position))
+            } else if keysym.modified_sym() == keysyms::KEY_W {
+                println!("WESTON-TERMINAL");
+                FilterResult::Intercept(Action::exec_process("weston-terminal"))
+            } else if keysym.modified_sym() == keysyms::KEY_A {
+                println!("ALACRITTY");
+                FilterResult::Intercept(Action::exec_process("alacritty"))
+            } else if keysym.modified_sym() == keysyms::KEY_V {
+                println!("SPLIT VERTICAL");
+                FilterResult::Intercept(Action::change_split(tiling::Split::Vertical))
+            } else if keysym.modified_sym() == keysyms::KEY_O {
+                println!("SPLIT HORIZONTAL");
+                FilterResult::Intercept(Action::change_split(tiling::Split::Horizontal))
+            } else {
+                println!("Forward: {keysym:?}");
+                FilterResult::Forward
+            }
+        },
+    );
+
+    match action {
+        Some(Action::exec_process(process_name)) => {
+            std::process::Command::new(process_name).spawn().unwrap();
+        }
+        Some(Action::change_split(new_split)) => {
+            match state.seat.get_keyboard().unwrap().current_focus() {
+                Some(wl_surface) => {
+                    // Floating windows have no tile
+                    if let Err(err) = state.tiling_state.set_split(&wl_surface, new_split) {
+                        println!("Split not changed: {err}");
+                    }
+                }
+                None => (),
+            }
+        }
+        Some(Action::toggle_split) => {
+            if let Some(wl_surface) = state.seat.get_keyboard().unwrap().current_focus() {
+                // Floating windows have no tile
+                match state.tiling_state.toggle_split(&wl_surface) {
+                    Ok(Some(container)) => state.update_tiles(container),
+                    Ok(None) => (),
+                    Err(err) => println!("Split not toggled: {err}"),
+                }
+            }
+        }
+        Some(Action::resize_tile(delta)) => {
+            if let Some(wl_surface) = state.seat.get_keyboard().unwrap().current_focus() {
+                match state.tiling_state.resize_tile(&wl_surface, delta) {
+                    Ok(Some(container)) => state.update_tiles(container),
+                    Ok(None) => (),
+                    Err(err) => println!("Tile not resized: {err}"),
+                }
+            }
+        }
+        Some(Action::balance_tiles) => match state.tiling_state.balance() {
+            Ok(Some(head)) => state.update_tiles(head),
+            Ok(None) => (),
+            Err(err) => println!("Tiles not balanced: {err}"),
+        },
+        Some(Action::placement_step(direction)) => placement::step(state, direction),
+        Some(Action::placement_done(true)) => placement::confirm(state),
+        Some(Action::placement_done(false)) => placement::cancel(state),
+        Some(Action::toggle_overview) => overview::toggle(state),
+        Some(Action::overview_select(direction)) => overview::select(state, direction),
+        Some(Action::overview_close(pick)) => overview::close(state, pick),
+        Some(Action::reload_config) => state.reload_config(),
+        Some(Action::relaunch) => relaunch::request(state),
+        Some(Action::focus_prev) => state.focus_previous(),
+        Some(Action::mark(mark)) => marks::mark(state, mark),
+        Some(Action::goto_mark(mark)) => marks::goto_mark(state, mark),
+        Some(Action::cycle_layout) => state.cycle_layout(),
+        Some(Action::cycle_container_mode) => {
+            if let Some(wl_surface) = state.seat.get_keyboard().unwrap().current_focus() {
+                state.cycle_container_mode(&wl_surface);
+            }
+        }
+        Some(Action::cycle_tab(forward)) => {
+            if let Some(wl_surface) = state.seat.get_keyboard().unwrap().current_focus() {
+                state.cycle_tab(&wl_surface, forward);
+            }
+        }
+        Some(Action::toggle_do_not_disturb) => notifications::set_do_not_disturb(state, None),
+        Some(Action::toggle_floating) => {
+            if let Some(wl_surface) = state.seat.get_keyboard().unwrap().current_focus() {
+                state.toggle_floating(&wl_surface);
+            }
+        }
+        Some(Action::focus_direction(direction)) => state.focus_direction(direction),
+        Some(Action::close_window) => {
+            let focused = state.seat.get_keyboard().unwrap().current_focus();
+            if let Some(window) =
+                focused.and_then(|wl_surface| state.window_for_surface(&wl_surface))
+            {
+                force_close::close_window(state, &window);
+            }
+        }
+        Some(Action::force_close(accepted)) => force_close::answer(state, accepted),
+        Some(Action::rescan_outputs) => {
+            if let Err(err) = outputs::rescan(state) {
+                println!("Outputs not updated: {err}");
+            }
+        }
+        Some(Action::media_key(binding)) => media_key(state, &binding),
+        Some(Action::toggle_accel_profile) => devices::toggle_accel_profile(state),
+        Some(Action::toggle_game_mode) => {
+            state.game_mode = !state.game_mode;
+            tracing::debug!("GAME MODE: {}", state.game_mode);
+        }
+        Some(Action::snap(position)) => {
+            if let Some(wl_surface) = state.seat.get_keyboard().unwrap().current_focus() {
+                state.snap_floating(&wl_surface, position);
+            }
+        }
+        _ => (),
+    }
+}
+
+// Step used by the internal brightness control
+const BRIGHTNESS_STEP: f64 = 0.05;
+
+fn media_key(state: &mut AIGIState, binding: &MediaKeyBinding) {
+    if let Some(action) = binding.action {
+        let (device, kind) = match action {
+            MediaAction::BrightnessUp | MediaAction::BrightnessDown => {
+                (SysfsBrightness::display(), OsdKind::Brightness)
+            }
+            MediaAction::KbdBacklightUp | MediaAction::KbdBacklightDown => {
+                (SysfsBrightness::keyboard(), OsdKind::KeyboardBacklight)
+            }
+        };
+        let Some(device) = device else {
+            println!("No backlight device found for {action:?}");
+            return;
+        };
+
+        let result = match action {
+            MediaAction::BrightnessUp => device.step(BRIGHTNESS_STEP),
+            MediaAction::BrightnessDown => device.step(-BRIGHTNESS_STEP),
+            MediaAction::KbdBacklightUp => device.step_units(1),
+            MediaAction::KbdBacklightDown => device.step_units(-1),
+        };
+        match result {
+            Ok(level) => osd::show(state, kind, level),
+            Err(err) => println!("Brightness not changed: {err:?}"),
+        }
+    }
+
+    let Some(exec) = binding.exec.as_deref() else {
+        return;
+    };
+
+    // Without an OSD nobody cares when the command ends
+    let (Some(kind), Some(level)) = (binding.osd, binding.level.as_deref()) else {
+        if let Err(err) = Command::new("sh").args(["-c", exec]).spawn() {
+            println!("Impossible execute `{exec}`: {err}");
+        }
+        return;
+    };
+
+    // Otherwise the level is printed once the command is done, the two
+    // commands run in the same shell. Its output is read by the event loop
+    // as it comes, a slow command (eg. pactl waiting for the server)
+    // doesn't stop the compositor
+    let script = format!("{{\n{exec}\n}} >/dev/null\n{level}");
+    let mut child = match Command::new("sh")
+        .args(["-c", &script])
+        .stdout(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            println!("Impossible execute `{exec}`: {err}");
+            return;
+        }
+    };
+    let Some(stdout) = child.stdout.take() else {
+        return;
+    };
+
+    let mut output = Vec::new();
+    let command = exec.to_string();
+    let result = state.handle.insert_source(
+        Generic::new(stdout, Interest::READ, Mode::Level),
+        move |_, stdout, loop_data| {
+            // The pipe is readable, so the read doesn't wait.
+            // It returns 0 once the shell is done
+            let mut chunk = [0u8; 256];
+            match stdout.read(&mut chunk) {
+                Ok(0) => {}
+                Ok(n) => {
+                    output.extend_from_slice(&chunk[..n]);
+                    return Ok(PostAction::Continue);
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {
+                    return Ok(PostAction::Continue)
+                }
+                Err(err) => println!("Level of `{command}` not read: {err}"),
+            }
+            // The shell exits right after closing its output
+            let _ = child.wait();
+            if let Some(level) = parse_level(&String::from_utf8_lossy(&output)) {
+                osd::show(&mut loop_data.state, kind, level);
+            }
+            Ok(PostAction::Remove)
+        },
+    );
+    if let Err(err) = result {
+        println!("Level of `{exec}` not read: {err}");
+    }
+}
+
+// The level is the first percentage in the output (eg. pactl prints
+// the volume of every channel both as raw value and as percentage),
+// or the first number if there are no percentages
+fn parse_level(output: &str) -> Option<f64> {
+    let words = output.split_whitespace();
+    let percentage = words
+        .clone()
+        .filter_map(|word| word.strip_suffix('%'))
+        .find_map(|word| word.parse::<f64>().ok())
+        .or_else(|| words.map(|word| word.parse::<f64>()).find_map(Result::ok))?;
+    Some(percentage / 100.0)
+}
+
+// Super+arrows (without other modifiers) move the focus between the tiles
+fn focus_binding(modifiers: &ModifiersState, keysym: u32) -> Option<tiling::Direction> {
+    if !modifiers.logo || modifiers.ctrl || modifiers.shift || modifiers.alt {
+        return None;
+    }
+
+    Some(match keysym {
+        keysyms::KEY_Left => tiling::Direction::Left,
+        keysyms::KEY_Right => tiling::Direction::Right,
+        keysyms::KEY_Up => tiling::Direction::Up,
+        keysyms::KEY_Down => tiling::Direction::Down,
+        _ => return None,
+    })
+}
+
+// Super+Ctrl+arrows snap the focused floating window to an half of the output,
+// Super+Ctrl+1..4 to a quarter (top left, top right, bottom left, bottom right)
+fn snap_binding(modifiers: &ModifiersState, keysym: u32) -> Option<SnapPosition> {
+    if !(modifiers.logo && modifiers.ctrl) {
+        return None;
+    }
+
+    Some(match keysym {
+        keysyms::KEY_Left => SnapPosition::Left,
+        keysyms::KEY_Right => SnapPosition::Right,
+        keysyms::KEY_Up => SnapPosition::Top,
+        keysyms::KEY_Down => SnapPosition::Bottom,
+        keysyms::KEY_1 => SnapPosition::TopLeft,
+        keysyms::KEY_2 => SnapPosition::TopRight,
+        keysyms::KEY_3 => SnapPosition::BottomLeft,
+        keysyms::KEY_4 => SnapPosition::BottomRight,
+        _ => return None,
+    })
+}
+
+// The window whose decorations are under the pointer is returned,
+// the hovered titlebar button is redrawn only when it changes
+fn update_decoration_hover(state: &mut AIGIState) -> Option<WlSurface> {
+    let under = state.decoration_under(state.pointer_location);
+    let hovered = under.as_ref().and_then(|(window, hit)| match hit {
+        DecorationHit::Button(button) => Some((window.toplevel().wl_surface().clone(), *button)),
+        DecorationHit::Titlebar => None,
+    });
+    if state.decoration_state.set_hovered(hovered) {
+        schedule_render(state);
+    }
+    under.map(|(window, _)| window.toplevel().wl_surface().clone())
+}
+
+// A titlebar button triggers its action if the left button is pressed
+// and released over it, while pressing on the rest of the titlebar starts
+// moving the window. Returns true if the decorations consumed the event
+fn decoration_click(
+    state: &mut AIGIState,
+    button: u32,
+    button_state: ButtonState,
+    serial: Serial,
+) -> bool {
+    if button_state == ButtonState::Released {
+        let Some((wl_surface, pressed)) = state.decoration_state.take_pressed() else {
+            return false;
+        };
+        let released_over = state.decoration_under(state.pointer_location);
+        if let Some((window, DecorationHit::Button(released))) = released_over {
+            if window.toplevel().wl_surface() == &wl_surface && released == pressed {
+                state.decoration_button(&wl_surface, pressed);
+            }
+        }
+        return true;
+    }
+
+    if button != BTN_LEFT {
+        return false;
+    }
+    let Some((window, hit)) = state.decoration_under(state.pointer_location) else {
+        return false;
+    };
+
+    match hit {
+        DecorationHit::Button(decoration_button) => state
+            .decoration_state
+            .press(window.toplevel().wl_surface(), decoration_button),
+        DecorationHit::Titlebar => {
+            let start_data = PointerGrabStartData {
+                focus: None,
+                button,
+                location: state.pointer_location,
+            };
+            state.start_move_grab(window, start_data, button, serial);
+        }
+    }
+    true
+}
+
+// Check if the pressed button, together with the current modifiers,
+// matches one of the pointer bindings, if so the related grab is started
+// on the window under the pointer and true is returned
+fn start_pointer_binding(state: &mut AIGIState, button: u32, serial: Serial) -> bool {
+    let modifiers = state.seat.get_keyboard().unwrap().modifier_state();
+    let Some(binding) = state
+        .config
+        .pointer_bindings
+        .iter()
+        .find(|binding| binding.button.code() == button && binding.modifiers.matches(&modifiers))
+        .copied()
+    else {
+        return false;
+    };
+
+    let Some(window) = state
+        .space
+        .element_under(state.pointer_location)
+        .map(|(window, _)| window.clone())
+    else {
+        return false;
+    };
+
+    let start_data = PointerGrabStartData {
+        focus: None,
+        button,
+        location: state.pointer_location,
+    };
+
+    match binding.action {
+        PointerAction::Move => state.start_move_grab(window, start_data, button, serial),
+        PointerAction::Resize => state.start_resize_grab(
+            window,
+            start_data,
+            xdg_toplevel::ResizeEdge::None,
+            button,
+            serial,
+        ),
     }
+    true
 }
@@ -0,0 +1,559 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use smithay::{
+    desktop::Window,
+    reexports::calloop::{generic::Generic, Interest, LoopHandle, Mode, PostAction},
+};
+use std::{
+    cell::RefCell,
+    io::{ErrorKind, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    backlight::SysfsBrightness,
+    config::AccelProfile,
+    crash, decorations,
+    devices::{self, DeviceInfo},
+    ext_workspace, notifications,
+    outputs::{self, OutputChange, OutputInfo},
+    permissions::{self, Permission},
+    relaunch, screencopy,
+    state::AIGIState,
+    status, toplevel_icon,
+    workspaces::WORKSPACE_COUNT,
+    LoopData,
+};
+
+/// Requests accepted on the IPC socket, one JSON object per line, eg.
+/// `{"request": "keyboard_backlight"}`
+#[derive(Deserialize, Debug)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum Request {
+    KeyboardBacklight,
+    Workspaces,
+    ActivateWorkspace {
+        index: usize,
+    },
+    InputDevices,
+    /// Acceleration of the pointer devices called `device` (all if missing),
+    /// the fields that are missing are not changed, eg.
+    /// `{"request": "pointer_accel", "profile": "flat", "speed": -0.2}`
+    PointerAccel {
+        device: Option<String>,
+        profile: Option<AccelProfile>,
+        speed: Option<f64>,
+    },
+    Windows,
+    Outputs,
+    /// Reconfigure an output, eg.
+    /// `{"request": "output", "name": "eDP-1", "change": {"scale": 1.5}}`
+    Output {
+        name: String,
+        change: OutputChange,
+    },
+    /// Layout of the tiles of the active workspace, eg. `tree` or `grid`
+    SetLayout {
+        name: String,
+    },
+    /// Scan the connectors, when the hotplug events of a monitor are not reliable
+    RescanOutputs,
+    /// Stop the compositor, used by `--replace`
+    Quit,
+    /// Execute the compositor again on the same socket, the connected clients
+    /// are closed. Refused without `confirm`, like the binding asks first
+    Relaunch {
+        #[serde(default)]
+        confirm: bool,
+    },
+    /// Allow or deny an executable to use a sensitive global, the answer
+    /// is remembered in the next sessions too. Accepted only from the
+    /// executables in `permission_helpers`
+    AnswerPermission {
+        executable: PathBuf,
+        permission: Permission,
+        allow: bool,
+    },
+    /// Pause or resume the protocol log started with `--protocol-log`,
+    /// `interfaces` (all if empty) are the ones shown
+    ProtocolLog {
+        enabled: bool,
+        #[serde(default)]
+        interfaces: Vec<String>,
+    },
+    /// Hide the notifications, toggled if `enabled` is missing
+    DoNotDisturb {
+        enabled: Option<bool>,
+    },
+    /// Keep the connection open and receive an event on every change
+    Subscribe,
+    /// Keep the connection open and receive the status (workspaces, focused
+    /// title, layout and keyboard layout) in the swaybar protocol
+    StatusLine,
+}
+
+/// Every request gets exactly one response, on a single line
+#[derive(Serialize, Debug)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum Response {
+    /// `level` goes from 0 to 1, it's missing if there is no keyboard backlight
+    KeyboardBacklight {
+        level: Option<f64>,
+    },
+    Workspaces {
+        workspaces: Vec<WorkspaceInfo>,
+    },
+    InputDevices {
+        devices: Vec<DeviceInfo>,
+    },
+    Windows {
+        windows: Vec<WindowInfo>,
+    },
+    Outputs {
+        outputs: Vec<OutputInfo>,
+    },
+    Ok,
+    Error {
+        message: String,
+    },
+}
+
+/// Events sent to the subscribed clients, one JSON object per line
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The active workspace or the windows in a workspace changed
+    Workspaces {
+        workspaces: Vec<WorkspaceInfo>,
+    },
+    /// An input device was plugged (or found at startup)
+    DeviceAdded {
+        device: DeviceInfo,
+    },
+    DeviceRemoved {
+        device: DeviceInfo,
+    },
+    /// A client not known by the permissions wants a sensitive global,
+    /// a helper can ask the user and reply with `answer_permission`
+    PermissionRequested {
+        executable: PathBuf,
+        permission: Permission,
+    },
+    /// The active workspace had already `max_tiles` tiles, or they were
+    /// too small to split, the new window was opened on the `workspace` one
+    WindowOverflowed {
+        title: String,
+        workspace: usize,
+    },
+    /// Do not disturb was turned on or off
+    DoNotDisturb {
+        enabled: bool,
+    },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WorkspaceInfo {
+    pub index: usize,
+    /// The number, with `auto_rename` followed by the app ids of the windows
+    pub name: String,
+    pub active: bool,
+    pub windows: usize,
+    pub layout: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+pub struct WindowInfo {
+    pub title: String,
+    pub workspace: usize,
+    pub focused: bool,
+    /// false if the client did not answer a ping for some seconds
+    pub responsive: bool,
+    /// Name in the icon theme of the icon set by the client (xdg-toplevel-icon)
+    pub icon: Option<String>,
+}
+
+/// Clients that asked to receive the events
+#[derive(Default)]
+pub struct IpcState {
+    subscribers: Vec<Subscriber>,
+    // Sent with the last `workspaces` event, to know when they change
+    names: Vec<String>,
+}
+
+struct Subscriber {
+    stream: UnixStream,
+    // From the credentials of the socket, see `permissions::executable`
+    executable: Option<PathBuf>,
+}
+
+/// Send the event to every subscriber, the ones that
+/// closed the connection are forgotten. The permission requests go only
+/// to the `permission_helpers`: the refused client could be subscribed too
+pub fn broadcast(state: &mut AIGIState, event: Event) {
+    if state.ipc_state.subscribers.is_empty() {
+        return;
+    }
+
+    let helpers_only = matches!(event, Event::PermissionRequested { .. });
+    let mut line = serde_json::to_vec(&event).expect("IMP serialize event");
+    line.push(b'\n');
+    let permissions = &state.permissions;
+    state.ipc_state.subscribers.retain_mut(|subscriber| {
+        let helper = subscriber
+            .executable
+            .as_deref()
+            .is_some_and(|executable| permissions.is_helper(executable));
+        if helpers_only && !helper {
+            return true;
+        }
+        subscriber.stream.write_all(&line).is_ok()
+    });
+}
+
+/// Notify the subscribers that something changed in the workspaces
+pub fn workspaces_changed(state: &mut AIGIState) {
+    let workspaces = workspaces(state);
+    state.ipc_state.names = workspaces.iter().map(|w| w.name.clone()).collect();
+    broadcast(state, Event::Workspaces { workspaces });
+    ext_workspace::refresh(state);
+    status::update(state);
+    crash::update_snapshot(state);
+}
+
+/// The title or the app id of the window changed since the last call, or
+/// this is the first one. Only the window is read, it's called on every commit
+pub fn window_renamed(window: &Window) -> bool {
+    let names = (decorations::title(window), decorations::app_id(window));
+    window
+        .user_data()
+        .insert_if_missing(|| RefCell::new((String::new(), String::new())));
+    let known = window
+        .user_data()
+        .get::<RefCell<(String, String)>>()
+        .expect("IMP inserted above");
+    known.replace(names.clone()) != names
+}
+
+/// With `auto_rename` the app ids of the windows change the names of the
+/// workspaces, which are sent again if they changed (eg. after a rename)
+pub fn names_changed(state: &mut AIGIState) {
+    if !state.config.workspaces.auto_rename {
+        return;
+    }
+    let changed = (0..WORKSPACE_COUNT)
+        .map(|index| workspace_name(state, index))
+        .ne(state.ipc_state.names.iter().cloned());
+    if changed {
+        workspaces_changed(state);
+    }
+}
+
+// The number of the workspace, followed by the app ids of its windows
+// with `auto_rename`, eg. "3: firefox nvim"
+fn workspace_name(state: &AIGIState, index: usize) -> String {
+    let number = format!("{}", index + 1);
+    if !state.config.workspaces.auto_rename {
+        return number;
+    }
+    let windows: Box<dyn Iterator<Item = &Window> + '_> = if index == state.workspaces.active() {
+        Box::new(state.space.elements())
+    } else {
+        Box::new(
+            state
+                .workspaces
+                .windows()
+                .filter(move |(i, _)| *i == index)
+                .map(|(_, window)| window),
+        )
+    };
+    let mut app_ids = Vec::<String>::new();
+    for app_id in windows.map(decorations::app_id) {
+        if !app_id.is_empty() && !app_ids.contains(&app_id) {
+            app_ids.push(app_id);
+        }
+    }
+    match app_ids.is_empty() {
+        true => number,
+        false => format!("{number}: {}", app_ids.join(" ")),
+    }
+}
+
+pub fn workspaces(state: &AIGIState) -> Vec<WorkspaceInfo> {
+    (0..WORKSPACE_COUNT)
+        .map(|index| {
+            let active = index == state.workspaces.active();
+            WorkspaceInfo {
+                index,
+                name: workspace_name(state, index),
+                active,
+                windows: if active {
+                    state.space.elements().count()
+                } else {
+                    state.workspaces.window_count(index)
+                },
+                layout: if active {
+                    state.tiling_state.layout_name()
+                } else {
+                    state.workspaces.layout_name(index)
+                },
+            }
+        })
+        .collect()
+}
+
+// Windows of the active workspace first, then the hidden ones
+pub fn windows(state: &AIGIState) -> Vec<WindowInfo> {
+    let focus = state.seat.get_keyboard().unwrap().current_focus();
+    let active = state.workspaces.active();
+    state
+        .space
+        .elements()
+        .map(|window| (active, window))
+        .chain(state.workspaces.windows())
+        .map(|(workspace, window)| WindowInfo {
+            title: decorations::title(window),
+            workspace,
+            focused: focus.as_ref() == Some(window.toplevel().wl_surface()),
+            responsive: !state.ping_state.is_unresponsive(window),
+            icon: toplevel_icon::icon(window.toplevel().wl_surface())
+                .and_then(|icon| icon.name.clone()),
+        })
+        .collect()
+}
+
+/// Path of the socket, one for every compositor instance
+/// (identified by the wayland socket name)
+fn socket_path(wayland_display: &str) -> Result<PathBuf> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").context("XDG_RUNTIME_DIR is not set")?;
+    Ok(PathBuf::from(runtime_dir).join(format!("aigi.{wayland_display}.sock")))
+}
+
+/// Ask the compositor running on `wayland_display` to quit
+/// and wait for it to exit, it's done once its IPC socket is closed
+pub fn replace(wayland_display: &str) -> Result<()> {
+    let path = socket_path(wayland_display)?;
+    let Ok(mut stream) = UnixStream::connect(&path) else {
+        println!("No compositor running on {wayland_display}");
+        return Ok(());
+    };
+    stream.write_all(b"{\"request\": \"quit\"}\n")?;
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while UnixStream::connect(&path).is_ok() {
+        if Instant::now() > deadline {
+            anyhow::bail!("The compositor running on {wayland_display} did not quit");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Ok(())
+}
+
+/// Start listening for IPC clients, the path of the socket
+/// is exported in AIGI_SOCKET so that spawned clients can find it
+pub fn init(handle: &LoopHandle<'static, LoopData>, wayland_display: &str) -> Result<()> {
+    let path = socket_path(wayland_display)?;
+    // A socket left by a crashed instance
+    let _ = std::fs::remove_file(&path);
+
+    let listener =
+        UnixListener::bind(&path).with_context(|| format!("Impossible bind {path:?}"))?;
+    listener.set_nonblocking(true)?;
+    std::env::set_var("AIGI_SOCKET", &path);
+    tracing::debug!("IPC SOCKET: {path:?}");
+
+    let connection_handle = handle.clone();
+    handle
+        .insert_source(
+            Generic::new(listener, Interest::READ, Mode::Level),
+            move |_, listener, _| {
+                loop {
+                    match listener.accept() {
+                        Ok((stream, _)) => add_client(&connection_handle, stream),
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                        Err(err) => {
+                            println!("IPC accept failed: {err}");
+                            break;
+                        }
+                    }
+                }
+                Ok(PostAction::Continue)
+            },
+        )
+        .map_err(|err| anyhow::anyhow!("Impossible insert IPC socket in the event loop: {err}"))?;
+    Ok(())
+}
+
+// Every client has its own source in the event loop, the requests
+// are handled once a full line is received
+fn add_client(handle: &LoopHandle<'static, LoopData>, stream: UnixStream) {
+    if let Err(err) = stream.set_nonblocking(true) {
+        println!("IPC client refused: {err}");
+        return;
+    }
+    // Who is on the other side, some requests are accepted only from helpers
+    let peer = permissions::executable(&stream);
+
+    let mut buffer = Vec::new();
+    let result = handle.insert_source(
+        Generic::new(stream, Interest::READ, Mode::Level),
+        move |_, stream, loop_data| {
+            let mut chunk = [0u8; 1024];
+            loop {
+                match stream.read(&mut chunk) {
+                    // The client closed the connection
+                    Ok(0) => return Ok(PostAction::Remove),
+                    Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                    Err(_) => return Ok(PostAction::Remove),
+                }
+            }
+
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line = buffer.drain(..=end).collect::<Vec<_>>();
+                let response = match serde_json::from_slice::<Request>(&line) {
+                    Ok(Request::Subscribe) => match stream.try_clone() {
+                        Ok(subscriber) => {
+                            loop_data.state.ipc_state.subscribers.push(Subscriber {
+                                stream: subscriber,
+                                executable: peer.clone(),
+                            });
+                            Response::Ok
+                        }
+                        Err(err) => Response::Error {
+                            message: err.to_string(),
+                        },
+                    },
+                    // The bar gets the header of the protocol instead of a response
+                    Ok(Request::StatusLine) => {
+                        match stream.try_clone() {
+                            Ok(bar) => status::add_bar(&mut loop_data.state, bar),
+                            Err(err) => println!("Status line not sent: {err}"),
+                        }
+                        continue;
+                    }
+                    Ok(request) => handle_request(&mut loop_data.state, request, peer.as_deref()),
+                    Err(err) => Response::Error {
+                        message: err.to_string(),
+                    },
+                };
+
+                let mut reply = serde_json::to_vec(&response).expect("IMP serialize response");
+                reply.push(b'\n');
+                if stream.write_all(&reply).is_err() {
+                    return Ok(PostAction::Remove);
+                }
+            }
+            Ok(PostAction::Continue)
+        },
+    );
+
+    if let Err(err) = result {
+        println!("IPC client refused: {err}");
+    }
+}
+
+// `peer` is the executable of the IPC client, if it's known
+fn handle_request(state: &mut AIGIState, request: Request, peer: Option<&Path>) -> Response {
+    match request {
+        Request::KeyboardBacklight => Response::KeyboardBacklight {
+            level: SysfsBrightness::keyboard().and_then(|kbd| kbd.level().ok()),
+        },
+        Request::Workspaces => Response::Workspaces {
+            workspaces: workspaces(state),
+        },
+        Request::ActivateWorkspace { index } if index < WORKSPACE_COUNT => {
+            state.switch_workspace(index);
+            Response::Ok
+        }
+        Request::InputDevices => Response::InputDevices {
+            devices: state.input_devices.info(),
+        },
+        Request::PointerAccel {
+            device,
+            profile,
+            speed,
+        } => match devices::set_pointer_accel(state, device.as_deref(), profile, speed) {
+            Ok(()) => Response::Ok,
+            Err(message) => Response::Error { message },
+        },
+        Request::ActivateWorkspace { index } => Response::Error {
+            message: format!("There are only {WORKSPACE_COUNT} workspaces, {index} is invalid"),
+        },
+        Request::Windows => Response::Windows {
+            windows: windows(state),
+        },
+        Request::Outputs => Response::Outputs {
+            outputs: outputs::info(state),
+        },
+        Request::Output { name, change } => match outputs::apply(state, &name, change) {
+            Ok(()) => Response::Ok,
+            Err(message) => Response::Error { message },
+        },
+        Request::SetLayout { name } => match state.set_layout(&name) {
+            Ok(()) => Response::Ok,
+            Err(message) => Response::Error { message },
+        },
+        Request::RescanOutputs => match outputs::rescan(state) {
+            Ok(()) => Response::Ok,
+            Err(message) => Response::Error { message },
+        },
+        // The event loop stops after this dispatch, the response is still sent
+        Request::Quit => {
+            tracing::debug!("QUIT REQUESTED");
+            state.running.store(false, Ordering::SeqCst);
+            Response::Ok
+        }
+        Request::Relaunch { confirm: false } => Response::Error {
+            message: "relaunching closes every connected client, send \"confirm\": true".into(),
+        },
+        Request::Relaunch { confirm: true } => {
+            relaunch::relaunch(state);
+            Response::Ok
+        }
+        // Otherwise a client could grant itself the permission it was refused
+        Request::AnswerPermission { .. }
+            if !peer.is_some_and(|peer| state.permissions.is_helper(peer)) =>
+        {
+            println!("Permission answer refused from {peer:?}, it's not in permission_helpers");
+            Response::Error {
+                message: "only the executables in permission_helpers can answer".into(),
+            }
+        }
+        Request::AnswerPermission {
+            executable,
+            permission,
+            allow,
+        } => {
+            let result = state.permissions.answer(executable, permission, allow);
+            // The answer holds even if it was not saved
+            screencopy::permission_answered(state);
+            match result {
+                Ok(()) => Response::Ok,
+                Err(err) => Response::Error {
+                    message: format!("{err:?}"),
+                },
+            }
+        }
+        Request::ProtocolLog {
+            enabled,
+            interfaces,
+        } => match state.protocol_log.as_ref() {
+            Some(log) => {
+                log.set(enabled, interfaces);
+                Response::Ok
+            }
+            None => Response::Error {
+                message: "aigi was not started with --protocol-log".into(),
+            },
+        },
+        Request::DoNotDisturb { enabled } => {
+            notifications::set_do_not_disturb(state, enabled);
+            Response::Ok
+        }
+        // Handled when the request is read, the stream is needed
+        Request::Subscribe | Request::StatusLine => Response::Ok,
+    }
+}
@@ -0,0 +1,175 @@
+use smithay::{
+    desktop::{layer_map_for_output, LayerSurface, WindowSurfaceType},
+    reexports::wayland_server::{protocol::wl_surface::WlSurface, Resource},
+    utils::{Logical, Point, SERIAL_COUNTER},
+    wayland::{
+        compositor::with_states,
+        shell::wlr_layer::{KeyboardInteractivity, Layer, LayerSurfaceCachedState},
+    },
+};
+
+use crate::state::AIGIState;
+
+/// A layer surface with the keyboard focus (eg. a launcher). An exclusive
+/// one keeps it until it goes away, the windows can't take it meanwhile;
+/// an on demand one got it with a click and loses it like a window would.
+/// When it goes away the focus goes back to the window that had it
+pub struct LayerFocus {
+    surface: WlSurface,
+    exclusive: bool,
+    // Focused before the layer, or asked to be focused while
+    // the exclusive layer was there
+    previous: Option<WlSurface>,
+}
+
+// Layer and keyboard interactivity last committed by the surface
+fn interactivity(surface: &WlSurface) -> (Layer, KeyboardInteractivity) {
+    with_states(surface, |states| {
+        let cached = states.cached_state.current::<LayerSurfaceCachedState>();
+        (cached.layer, cached.keyboard_interactivity)
+    })
+}
+
+/// An exclusive layer holds the keyboard, the windows can't get it
+pub fn exclusive(state: &AIGIState) -> bool {
+    state
+        .layer_focus
+        .as_ref()
+        .is_some_and(|focus| focus.exclusive)
+}
+
+/// The window is focused when the exclusive layer goes away, returns
+/// false if there is no exclusive layer and the window can get the focus now
+pub fn defer(state: &mut AIGIState, wl_surface: Option<&WlSurface>) -> bool {
+    match state.layer_focus.as_mut() {
+        Some(focus) if focus.exclusive => {
+            focus.previous = wl_surface.cloned();
+            true
+        }
+        _ => false,
+    }
+}
+
+// The layer gets the keyboard, the window focused until now is remembered
+fn focus(state: &mut AIGIState, surface: &WlSurface, exclusive: bool) {
+    let keyboard = state.seat.get_keyboard().unwrap();
+    let current = keyboard.current_focus();
+    if current.as_ref() == Some(surface) {
+        if let Some(focus) = state.layer_focus.as_mut() {
+            focus.exclusive = exclusive;
+        }
+        return;
+    }
+    // Another layer had it, the window focused before it is the one to go back to
+    let previous = match state.layer_focus.take() {
+        Some(focus) => focus.previous,
+        None => current,
+    };
+
+    tracing::debug!("LAYER FOCUS, exclusive: {exclusive}");
+    state.layer_focus = Some(LayerFocus {
+        surface: surface.clone(),
+        exclusive,
+        previous,
+    });
+    keyboard.set_focus(state, Some(surface.clone()), SERIAL_COUNTER.next_serial());
+}
+
+// The focus goes back to the window that had it before the layer
+fn restore(state: &mut AIGIState) {
+    let Some(focus) = state.layer_focus.take() else {
+        return;
+    };
+    tracing::debug!("LAYER FOCUS RELEASED");
+    // The window could have been closed or hidden in the meantime
+    let window = focus
+        .previous
+        .filter(|wl_surface| wl_surface.is_alive())
+        .and_then(|wl_surface| state.window_for_surface(&wl_surface))
+        .filter(|window| state.space.elements().any(|w| w == window))
+        .or_else(|| state.focus_history.last());
+    state.set_keyboard_focus(window.as_ref());
+}
+
+/// An exclusive surface on the top or overlay layer takes the keyboard
+/// as soon as it's configured, and gives it back when it stops asking for it
+pub fn layer_committed(state: &mut AIGIState, surface: &WlSurface) {
+    let (layer, keyboard_interactivity) = interactivity(surface);
+    let holding = state
+        .layer_focus
+        .as_ref()
+        .is_some_and(|focus| &focus.surface == surface);
+    match keyboard_interactivity {
+        KeyboardInteractivity::Exclusive if matches!(layer, Layer::Top | Layer::Overlay) => {
+            focus(state, surface, true)
+        }
+        KeyboardInteractivity::None if holding => restore(state),
+        _ => (),
+    }
+}
+
+/// A click on a layer that accepts the keyboard gives it the focus
+pub fn layer_clicked(state: &mut AIGIState, point: Point<f64, Logical>) {
+    let Some((layer, _)) = layer_at(state, point) else {
+        return;
+    };
+    let surface = layer.wl_surface().clone();
+    if let (_, KeyboardInteractivity::OnDemand) = interactivity(&surface) {
+        focus(state, &surface, false);
+    }
+}
+
+pub fn layer_destroyed(state: &mut AIGIState, surface: &WlSurface) {
+    let holding = state
+        .layer_focus
+        .as_ref()
+        .is_some_and(|focus| &focus.surface == surface);
+    if holding {
+        restore(state);
+    }
+}
+
+/// The keyboard went somewhere else, an on demand layer forgets about it
+pub fn focus_changed(state: &mut AIGIState, focused: Option<&WlSurface>) {
+    let lost = state
+        .layer_focus
+        .as_ref()
+        .is_some_and(|focus| !focus.exclusive && Some(&focus.surface) != focused);
+    if lost {
+        state.layer_focus = None;
+    }
+}
+
+// Layer surface of the top or overlay layers under the point,
+// with its location in the layout
+fn layer_at(
+    state: &AIGIState,
+    point: Point<f64, Logical>,
+) -> Option<(LayerSurface, Point<i32, Logical>)> {
+    let output = state.space.output_under(point).next()?;
+    let output_location = state.space.output_geometry(output)?.loc;
+    let relative = point - output_location.to_f64();
+    let layer_map = layer_map_for_output(output);
+    // A fullscreen window is above the top layer of its output
+    let layers = match state.fullscreen_on(output) {
+        true => [Layer::Overlay].as_slice(),
+        false => [Layer::Overlay, Layer::Top].as_slice(),
+    };
+    let layer = layers
+        .iter()
+        .find_map(|layer| layer_map.layer_under(*layer, relative))?;
+    let location = layer_map.layer_geometry(layer)?.loc + output_location;
+    Some((layer.clone(), location))
+}
+
+/// Surface of the top or overlay layers under the point, with its location.
+/// These layers are above the windows, so they get the pointer first
+pub fn layer_under(
+    state: &AIGIState,
+    point: Point<f64, Logical>,
+) -> Option<(WlSurface, Point<i32, Logical>)> {
+    let (layer, location) = layer_at(state, point)?;
+    layer
+        .surface_under(point - location.to_f64(), WindowSurfaceType::ALL)
+        .map(|(wl_surface, loc)| (wl_surface, loc + location))
+}
@@ -0,0 +1,154 @@
+use smithay::{
+    desktop::Window,
+    utils::{Logical, Rectangle},
+};
+
+/// Places the tiles of a workspace all at once. The tiling tree still
+/// decides which windows are tiled and their order (from the left, or
+/// top, tile of the tree), the engine only gives them their geometry
+pub trait LayoutEngine {
+    /// Used to choose the engine, eg. in the IPC
+    fn name(&self) -> &'static str;
+
+    /// Geometry of each window inside `area`, in the same order of `windows`
+    fn arrange(
+        &self,
+        windows: &[Window],
+        area: Rectangle<i32, Logical>,
+    ) -> Vec<Rectangle<i32, Logical>>;
+}
+
+/// Name of the binary tree of `TilingState`, used when no engine is set
+pub const TREE_LAYOUT: &str = "tree";
+
+// Part of the width taken by the master window of `MasterStack`
+const MASTER_RATIO: f32 = 0.6;
+
+/// Engines that can be chosen by name, every workspace has its own
+pub struct LayoutRegistry {
+    engines: Vec<(&'static str, fn() -> Box<dyn LayoutEngine>)>,
+}
+
+impl Default for LayoutRegistry {
+    fn default() -> Self {
+        let mut registry = Self { engines: vec![] };
+        registry.register("grid", || Box::new(Grid));
+        registry.register("master_stack", || Box::new(MasterStack));
+        registry
+    }
+}
+
+impl LayoutRegistry {
+    /// Engines implemented outside of this module are added here,
+    /// a name already taken is replaced
+    pub fn register(&mut self, name: &'static str, create: fn() -> Box<dyn LayoutEngine>) {
+        self.engines.retain(|(engine, _)| *engine != name);
+        self.engines.push((name, create));
+    }
+
+    /// None is the binary tree
+    pub fn create(&self, name: &str) -> Result<Option<Box<dyn LayoutEngine>>, String> {
+        if name == TREE_LAYOUT {
+            return Ok(None);
+        }
+        self.engines
+            .iter()
+            .find(|(engine, _)| *engine == name)
+            .map(|(_, create)| Some(create()))
+            .ok_or_else(|| {
+                let names = self
+                    .engines
+                    .iter()
+                    .map(|(engine, _)| *engine)
+                    .collect::<Vec<_>>();
+                format!(
+                    "unknown layout `{name}`, available: {TREE_LAYOUT}, {}",
+                    names.join(", ")
+                )
+            })
+    }
+
+    /// The layout after `name`, the tree first and then the engines
+    /// in the order they were registered, back to the tree after the last
+    pub fn next(&self, name: &str) -> &'static str {
+        let names = std::iter::once(TREE_LAYOUT)
+            .chain(self.engines.iter().map(|(engine, _)| *engine))
+            .collect::<Vec<_>>();
+        let index = names.iter().position(|layout| *layout == name);
+        names[index.map_or(0, |index| (index + 1) % names.len())]
+    }
+}
+
+/// Rows of the same height, the last one can have less windows
+/// which share its width
+pub struct Grid;
+
+impl LayoutEngine for Grid {
+    fn name(&self) -> &'static str {
+        "grid"
+    }
+
+    fn arrange(
+        &self,
+        windows: &[Window],
+        area: Rectangle<i32, Logical>,
+    ) -> Vec<Rectangle<i32, Logical>> {
+        let count = windows.len() as i32;
+        if count == 0 {
+            return vec![];
+        }
+        let columns = (count as f64).sqrt().ceil() as i32;
+        let rows = (count + columns - 1) / columns;
+
+        (0..count)
+            .map(|index| {
+                let row = index / columns;
+                let in_row = if row == rows - 1 {
+                    count - row * columns
+                } else {
+                    columns
+                };
+                let column = index % columns;
+                // The last row and column take the pixels left by the divisions
+                let x = area.loc.x + area.size.w * column / in_row;
+                let y = area.loc.y + area.size.h * row / rows;
+                let right = area.loc.x + area.size.w * (column + 1) / in_row;
+                let bottom = area.loc.y + area.size.h * (row + 1) / rows;
+                Rectangle::from_loc_and_size((x, y), (right - x, bottom - y))
+            })
+            .collect()
+    }
+}
+
+/// The first window (the master) takes the left part of the area,
+/// the others are stacked on the right with the same height
+pub struct MasterStack;
+
+impl LayoutEngine for MasterStack {
+    fn name(&self) -> &'static str {
+        "master_stack"
+    }
+
+    fn arrange(
+        &self,
+        windows: &[Window],
+        area: Rectangle<i32, Logical>,
+    ) -> Vec<Rectangle<i32, Logical>> {
+        let stacked = windows.len() as i32 - 1;
+        if stacked <= 0 {
+            return vec![area; windows.len()];
+        }
+        let master_width = (area.size.w as f32 * MASTER_RATIO) as i32;
+        let master = Rectangle::from_loc_and_size(area.loc, (master_width, area.size.h));
+
+        let x = area.loc.x + master_width;
+        let width = area.size.w - master_width;
+        let stack = (0..stacked).map(|index| {
+            // The last window takes the pixels left by the divisions
+            let y = area.loc.y + area.size.h * index / stacked;
+            let bottom = area.loc.y + area.size.h * (index + 1) / stacked;
+            Rectangle::from_loc_and_size((x, y), (width, bottom - y))
+        });
+        std::iter::once(master).chain(stack).collect()
+    }
+}
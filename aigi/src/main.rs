@@ -1,27 +1,74 @@
 mod backend;
+mod backlight;
+mod cli;
+mod clipboard;
+mod config;
+mod content_type;
+mod crash;
+mod decorations;
+mod devices;
+mod edid;
+mod ext_workspace;
+mod floating;
+mod focus;
+mod force_close;
+mod grabs;
+mod idle;
 mod input_handler;
+mod ipc;
+mod layer_focus;
+mod layout;
+mod marks;
+mod notifications;
+mod osd;
+mod outputs;
+mod overview;
+mod permissions;
+mod ping;
+mod placement;
 mod pointer;
+mod pointer_warp;
+mod power;
+mod protocol_log;
+mod protocols;
+mod relaunch;
 mod render;
+mod saved_layout;
+mod screencopy;
+mod scripting;
 mod state;
+mod status;
+mod tabs;
+#[cfg(test)]
+mod test_windows;
 mod tiling;
+mod toplevel_drag;
+mod toplevel_icon;
+mod watchdog;
+mod workspaces;
 
-use backend::BackendData;
-use input_handler::{handle_input, Action};
+use backend::{BackendData, BackendSurface, Notifiers};
+use clap::Parser;
+use cli::{BackendKind, Cli};
+use config::Config;
+use input_handler::{handle_input, handle_libinput, Action};
 use pointer::{PointerElement, PointerRenderElement};
+use protocol_log::ProtocolLog;
 use state::{AIGIState, ClientState};
 
 use anyhow::{Error, Result};
 use smithay::{
     backend::{
-        drm::DrmEvent,
+        drm::{DrmDeviceNotifier, DrmEvent, DrmEventTime},
         input::{AbsolutePositionEvent, Event, InputEvent, KeyState, KeyboardKeyEvent},
+        libinput::LibinputInputBackend,
         renderer::{
             damage::OutputDamageTracker,
             element::{surface::WaylandSurfaceRenderElement, AsRenderElements},
             gles::{GlesRenderer, GlesTexture},
             Bind,
         },
-        winit::{self, WinitEvent},
+        winit::{self, WinitEvent, WinitEventLoop},
     },
     delegate_compositor, delegate_data_device, delegate_output, delegate_seat, delegate_shm,
     delegate_xdg_shell,
@@ -36,7 +83,7 @@ use smithay::{
         calloop::{
             generic::Generic,
             timer::{TimeoutAction, Timer},
-            EventLoop, Interest, Mode, PostAction,
+            EventLoop, Interest, LoopHandle, Mode, PostAction,
         },
         wayland_protocols::xdg::shell::server::xdg_toplevel,
         wayland_server::{
@@ -58,11 +105,12 @@ use smithay::{
             XdgToplevelSurfaceData,
         },
         shm::{ShmHandler, ShmState},
-        socket::ListeningSocketSource,
     },
 };
 use std::{
+    cell::RefCell,
     os::fd::AsRawFd,
+    rc::Rc,
     sync::{atomic::Ordering, Arc},
     time::Duration,
 };
@@ -73,6 +121,13 @@ pub struct LoopData {
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    cli.init_logs();
+
+    // Tracy connects to the running client, the spans are sent from now on
+    #[cfg(feature = "profile-with-tracy")]
+    tracy_client::Client::start();
+
     // Setting up everyghin for the Wayland Compositor
 
     // Create the EventLoop
@@ -86,27 +141,92 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // + and some Metadata (BOH)
     let mut event_loop: EventLoop<LoopData> = EventLoop::try_new()?;
 
+    // Read the user configuration before touching the backend,
+    // an invalid config should not leave the tty in a weird state
+    // In safe mode the config file is not even read, it could be the problem
+    let loaded = match cli.safe_mode {
+        true => {
+            println!("SAFE MODE: using the default config");
+            Ok(Config::default())
+        }
+        false => Config::load(cli.config.clone()),
+    };
+    let config = match loaded {
+        Ok(config) => config,
+        Err(err) if cli.force => {
+            println!("Using the default config (--force): {err:?}");
+            Config::default()
+        }
+        Err(err) => return Err(err.into()),
+    };
+    // Spawned clients inherit the cursor theme of the compositor
+    config.cursor.export_env();
+    // and the compose sequences
+    config.keyboard.export_env();
+    config.keyboard.verify_keymap();
+
     // Initialize the Backend and get all the important notifiers
     // that needs to be inserted in the event Loop
     //
     // Each notifier has a different functionality but before
     // insert those in the event_loop let's create the state and
     // then see how the notifiers interact with the State of the Compositor
-    let (backend_data, notifiers) = BackendData::init()?;
+    let (backend_data, notifiers) = init_backend(cli.backend)?;
+
+    let protocol_log = cli
+        .protocol_log
+        .clone()
+        .map(ProtocolLog::start)
+        .transpose()?;
 
     // Creation of the Wayand Display  (main objecet of the protocol)
     let mut display: Display<AIGIState> = Display::new()?;
+    // The clients spawned later must not log their own messages
+    std::env::remove_var("WAYLAND_DEBUG");
 
     // Initialize the State of the compositor
-    let mut aigi_state = AIGIState::init(event_loop.handle(), &mut display, backend_data)?;
+    let mut aigi_state = AIGIState::init(
+        event_loop.handle(),
+        &mut display,
+        backend_data,
+        config,
+        cli.config,
+    )?;
+    aigi_state.protocol_log = protocol_log;
+    // The script can't do anything before the state exists
+    if !cli.safe_mode {
+        scripting::reload(&mut aigi_state);
+    }
 
-    // Configure the server Socket
-    let socket_notifier = ListeningSocketSource::new_auto()?;
-    let socket_name = socket_notifier.socket_name().to_os_string();
+    // From now on a panic must give the tty back
+    crash::install(
+        aigi_state
+            .backend_data
+            .device_data()
+            .map(|device_data| device_data.drm.device_fd().clone()),
+        aigi_state.config.crash.clone(),
+        cli.safe_mode,
+    );
+
+    // The running compositor has to release the socket before it's taken
+    if let (true, Some(socket)) = (cli.replace, cli.socket.as_deref()) {
+        ipc::replace(socket)?;
+    }
+
+    // Configure the server Socket, after a relaunch the one used before.
+    // It's shared with its source to hand it over to a relaunch
+    let socket = Rc::new(relaunch::WaylandSocket::bind(cli.socket.as_deref())?);
+    let socket_name = socket.name().to_os_string();
     // Set the enviroment variable that Wayland clients can use.
     // They get the socket and connect to it.
     std::env::set_var("WAYLAND_DISPLAY", &socket_name);
 
+    // External tools (bars, scripts) can query the compositor,
+    // the compositor works fine even without it
+    if let Err(err) = ipc::init(&event_loop.handle(), &socket_name.to_string_lossy()) {
+        println!("IPC not available: {err:?}");
+    }
+
     // Add the Display itself into the event loop to dispatch all the request
     let display_notifier = Generic::new(
         display.backend().poll_fd().as_raw_fd(),
@@ -114,10 +234,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Mode::Level,
     );
 
-    // Let's create the Output Global
-    let drm_surface = aigi_state.backend_data.device_data.gbm_surface.surface();
-    let mode = drm_surface.current_mode();
-    let wl_mode = output::Mode::from(mode);
+    // Let's create the Output Global, in a window it has the size of the window
+    let name = aigi_state.backend_data.output_name().to_string();
+    let (wl_mode, edid) = match &aigi_state.backend_data.surface {
+        BackendSurface::Drm { device_data, .. } => (
+            output::Mode::from(device_data.gbm_surface.surface().current_mode()),
+            device_data.edid.clone(),
+        ),
+        BackendSurface::Winit(winit_data) => (
+            output::Mode {
+                size: winit_data.window_size(),
+                refresh: backend::WINIT_REFRESH,
+            },
+            None,
+        ),
+    };
 
     // Tells the client what the physical properties of the output are.
     // Create a new output which is an area in the compositor space
@@ -127,23 +258,31 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // TODO: understan why here is insered 0,0 and only then modified
     // why I can't diretly create it in the correct way?
     let output = output::Output::new(
-        "monitor1".to_string(), // random name
+        name,
         output::PhysicalProperties {
             size: (0, 0).into(),
             subpixel: Subpixel::Unknown,
-            make: "Smithay".into(),
-            model: "Winit".into(),
+            make: edid
+                .as_ref()
+                .map_or("Smithay".into(), |edid| edid.make.clone()),
+            model: edid
+                .as_ref()
+                .map_or("Winit".into(), |edid| edid.model.clone()),
         },
     );
+    // The config and the IPC can refer to the output by its EDID identifier,
+    // another monitor can be plugged in the same connector (see outputs::rescan)
+    output.user_data().insert_if_missing(|| RefCell::new(edid));
     // Clients can access the global objects to get the physical properties and output state.
     let _global = output.create_global::<AIGIState>(&display.handle());
+    ext_workspace::create_global(&display.handle());
 
-    // last argoment (0,0) because it is mapped at the top right of the space
-    output.change_current_state(Some(wl_mode), None, None, Some((0, 0).into()));
+    output.change_current_state(Some(wl_mode), None, None, None);
     output.set_preferred(wl_mode);
 
-    // Set the output of a space with coordinates for the upper left corner of the surface.
-    aigi_state.space.map_output(&output, (0, 0));
+    // Set the output of a space with coordinates for the upper left corner of the surface,
+    // the position depends on the config and on the other outputs
+    aigi_state.map_output(&output);
 
     // Let's create the Dmabuf Global
     let _global = aigi_state
@@ -156,17 +295,26 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Set up notifiers:
 
     // Add Wayland socket to event loop
-    event_loop
-        .handle()
-        .insert_source(socket_notifier, |stream, _, state| {
-            // Insert a new client into Display with data associated with that client.
-            // This starts the management of the client, the communication is over the UnixStream.
-            state
-                .display
-                .handle()
-                .insert_client(stream, Arc::new(ClientState::default()))
-                .unwrap();
-        })?;
+    let listening = socket.clone();
+    event_loop.handle().insert_source(
+        Generic::new(socket.as_raw_fd(), Interest::READ, Mode::Level),
+        move |_, _, state| {
+            while let Some(stream) = listening.accept()? {
+                // Insert a new client into Display with data associated with that client.
+                // This starts the management of the client, the communication is over the UnixStream.
+                let client_state = ClientState {
+                    executable: permissions::executable(&stream),
+                    ..Default::default()
+                };
+                state
+                    .display
+                    .handle()
+                    .insert_client(stream, Arc::new(client_state))
+                    .unwrap();
+            }
+            Ok(PostAction::Continue)
+        },
+    )?;
 
     // Add the Display Notifier to manage all the Requests from the clients
     event_loop
@@ -175,50 +323,52 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             // Dispatch requests received from clients to callbacks for clients. The callbacks will
             // probably need to access the current compositor state, so that is passed along.
             state.display.dispatch_clients(&mut state.state).unwrap();
+            permissions::report_requests(&mut state.state);
             // we must return a PostAction::Continue to tell the event loop to continue listening for events.
             Ok(PostAction::Continue)
         })?;
 
     // Add remaining notifiers
+    match notifiers {
+        Notifiers::Drm { libinput, drm, .. } => {
+            insert_drm_notifiers(&event_loop.handle(), libinput, drm)?
+        }
+        Notifiers::Winit(winit_event_loop) => {
+            insert_winit_notifier(&event_loop.handle(), winit_event_loop)?
+        }
+    }
 
-    // Session nofifier is NOT managed for now
-    // event_loop.state
-    event_loop
-        .handle()
-        .insert_source(notifiers.drm, |event, _, loop_data| match event {
-            DrmEvent::VBlank(_crtc) => {
-                render::frame_showed(&mut loop_data.state)
-                    .expect("Something wrong happened during the rendering phase");
-            }
-            DrmEvent::Error(err) => {
-                println!("An error occur in the DRM: {err}");
-            }
-        })?;
+    // Follow the power supply to save energy on battery
+    power::arm_timer(&mut aigi_state);
 
-    // LibInput notifier, used to get Seat input and apply those input to the State
-    event_loop
-        .handle()
-        .insert_source(notifiers.libinput, |event, _, loop_data| {
-            handle_input(&mut loop_data.state, event);
-        })?;
+    // Dim and then turn off the output when there is no input
+    idle::arm_timer(&mut aigi_state);
 
-    // Insert timer in the loop
-    event_loop.handle().insert_source(
-        Timer::from_duration(Duration::from_secs(30)),
-        |_, _, _| {
-            panic!("Aborted");
-        },
-    )?;
+    // Find out which clients stopped answering
+    ping::arm_timer(&mut aigi_state);
+
+    // Recover the output if it stops showing frames
+    watchdog::arm_timer(&mut aigi_state);
+
+    // Bars connected with `status_line` are updated periodically
+    status::arm_timer(&mut aigi_state);
 
-    // initial rendering
-    render::render_frame(&mut aigi_state)?;
+    // The first frame would wait for the drivers to prepare the shaders
+    if let Err(err) = render::warm_up(&mut aigi_state.backend_data) {
+        println!("Renderer not warmed up: {err}");
+    }
+
+    // initial rendering, then new frames are rendered only
+    // when something changes (see render::schedule_render)
+    render::schedule_render(&mut aigi_state);
 
     while aigi_state.running.load(Ordering::SeqCst) {
         let mut loop_data = LoopData {
             state: aigi_state,
             display,
         };
-        let result = event_loop.dispatch(Some(Duration::from_millis(16)), &mut loop_data);
+        // No timeout: there is no need to wake up if nothing happens
+        let result = event_loop.dispatch(None, &mut loop_data);
         LoopData {
             state: aigi_state,
             display,
@@ -232,5 +382,106 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             display.flush_clients().unwrap();
         }
     }
+
+    // Put back when the windows open again, also after a relaunch
+    saved_layout::save(&aigi_state);
+
+    // The new process needs the DRM device, so the state is dropped before
+    // executing it. The socket instead is kept and handed over
+    if aigi_state.relaunch {
+        drop(aigi_state);
+        drop(display);
+        drop(event_loop);
+        return Err(relaunch::exec(&socket).into());
+    }
+    Ok(())
+}
+
+/// The backend asked on the command line. By default the tty is tried
+/// first, without a seat or a GPU to drive (eg. started from ssh, or
+/// inside another compositor) aigi opens in a window instead
+fn init_backend(kind: BackendKind) -> Result<(BackendData, Notifiers), Box<dyn std::error::Error>> {
+    match kind {
+        BackendKind::Udev => BackendData::init().map_err(|err| {
+            format!("The DRM backend is not available, aigi must be started from a tty: {err}")
+                .into()
+        }),
+        BackendKind::Winit => BackendData::init_winit()
+            .map_err(|err| format!("The winit backend is not available: {err}").into()),
+        BackendKind::Auto => {
+            let init = || {
+                BackendData::init().or_else(|err| {
+                    println!("DRM backend not available ({err}), starting in a window");
+                    BackendData::init_winit()
+                })
+            };
+            init().or_else(|err| {
+                // Eg. simpledrm or a virtual GPU without 3D: mesa renders GLES on the cpu
+                // (llvmpipe), the clients started by aigi inherit it as well
+                println!("GLES not available ({err}), retrying with the software renderer");
+                std::env::set_var("LIBGL_ALWAYS_SOFTWARE", "1");
+                init().map_err(|err| {
+                    format!("Neither the DRM nor the winit backend are available: {err}").into()
+                })
+            })
+        }
+    }
+}
+
+fn insert_drm_notifiers(
+    handle: &LoopHandle<'static, LoopData>,
+    libinput: LibinputInputBackend,
+    drm: DrmDeviceNotifier,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Session nofifier is NOT managed for now
+    handle.insert_source(drm, |event, metadata, loop_data| match event {
+        DrmEvent::VBlank(_crtc) => {
+            let presented = metadata.as_ref().and_then(|metadata| match metadata.time {
+                DrmEventTime::Monotonic(time) => Some(time),
+                DrmEventTime::Realtime(_) => None,
+            });
+            if let Err(err) = render::frame_showed(&mut loop_data.state, presented) {
+                watchdog::recover(
+                    &mut loop_data.state,
+                    &format!("the shown frame was not submitted: {err}"),
+                );
+            }
+        }
+        DrmEvent::Error(err) => {
+            println!("An error occur in the DRM: {err}");
+        }
+    })?;
+
+    // LibInput notifier, used to get Seat input and apply those input to the State
+    handle.insert_source(libinput, |event, _, loop_data| {
+        handle_libinput(&mut loop_data.state, event);
+    })?;
+    Ok(())
+}
+
+// How often the events of the window are read, winit has no fd to wait on
+const WINIT_POLL: Duration = Duration::from_millis(5);
+
+// The input goes through the same handling as libinput,
+// the window closed by the other compositor stops aigi
+fn insert_winit_notifier(
+    handle: &LoopHandle<'static, LoopData>,
+    mut winit_event_loop: WinitEventLoop,
+) -> Result<(), Box<dyn std::error::Error>> {
+    handle.insert_source(Timer::immediate(), move |_, _, loop_data| {
+        let state = &mut loop_data.state;
+        let result = winit_event_loop.dispatch_new_events(|event| match event {
+            WinitEvent::Resized { size, .. } => outputs::window_resized(state, size),
+            WinitEvent::Input(event) => handle_input(state, event),
+            WinitEvent::Refresh => render::schedule_render(state),
+            WinitEvent::Focus(_) => {}
+        });
+        if let Err(err) = result {
+            println!("WINIT: {err}");
+            state.running.store(false, Ordering::SeqCst);
+            return TimeoutAction::Drop;
+        }
+        TimeoutAction::ToDuration(WINIT_POLL)
+    })?;
     Ok(())
 }
@@ -0,0 +1,50 @@
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use std::collections::HashMap;
+
+use crate::state::AIGIState;
+
+/// Windows marked with a character, like the marks of vim: Super+m and the
+/// character marks the focused window, Super+apostrophe and the same
+/// character focuses it again, on whatever workspace it is
+#[derive(Default)]
+pub struct Marks {
+    // A character marks a single window, a window can have more of them
+    windows: HashMap<char, WlSurface>,
+}
+
+/// The focused window takes the mark, from the window that had it
+pub fn mark(state: &mut AIGIState, mark: char) {
+    let Some(wl_surface) = state.seat.get_keyboard().unwrap().current_focus() else {
+        return;
+    };
+    // Only windows, not a layer surface with the keyboard
+    if state.window_for_surface(&wl_surface).is_none() {
+        return;
+    }
+    state.marks.windows.insert(mark, wl_surface);
+}
+
+/// The marked window is focused, its workspace is shown first
+pub fn goto_mark(state: &mut AIGIState, mark: char) {
+    let Some(wl_surface) = state.marks.windows.get(&mark).cloned() else {
+        tracing::debug!("NO WINDOW MARKED {mark}");
+        return;
+    };
+    let hidden_on = state
+        .workspaces
+        .windows()
+        .find(|(_, window)| window.toplevel().wl_surface() == &wl_surface)
+        .map(|(index, _)| index);
+    if let Some(index) = hidden_on {
+        state.switch_workspace(index);
+    }
+    let Some(window) = state.window_for_surface(&wl_surface) else {
+        return;
+    };
+    state.set_keyboard_focus(Some(&window));
+}
+
+/// The marks of a closed window are free again
+pub fn window_closed(state: &mut AIGIState, wl_surface: &WlSurface) {
+    state.marks.windows.retain(|_, marked| marked != wl_surface);
+}
@@ -0,0 +1,119 @@
+use smithay::{
+    desktop::{layer_map_for_output, LayerSurface},
+    output::Output,
+    wayland::shell::wlr_layer::LayerSurface as WlrLayerSurface,
+};
+
+use crate::{
+    ipc::{self, Event},
+    outputs,
+    render::schedule_render,
+    state::AIGIState,
+};
+
+/// Layer surfaces of the notification daemons, recognized by their
+/// namespace (see `[notifications]` in the config). With do not disturb
+/// they are taken off their output, and put back when it's turned off
+#[derive(Default)]
+pub struct NotificationState {
+    do_not_disturb: bool,
+    // With the output they go back to
+    hidden: Vec<(LayerSurface, Output)>,
+}
+
+pub fn is_notification(state: &AIGIState, namespace: &str) -> bool {
+    state
+        .config
+        .notifications
+        .namespaces
+        .iter()
+        .any(|n| n == namespace)
+}
+
+/// Output chosen in the config for the notifications, None if the
+/// layer is not a notification or the output is not connected
+pub fn output(state: &AIGIState, namespace: &str) -> Option<Output> {
+    if !is_notification(state, namespace) {
+        return None;
+    }
+    let name = state.config.notifications.output.as_ref()?;
+    state
+        .space
+        .outputs()
+        .find(|output| outputs::is_named(output, name))
+        .cloned()
+}
+
+/// A new notification while do not disturb is on is kept aside,
+/// returns false if it can be mapped
+pub fn suppress(state: &mut AIGIState, layer: &LayerSurface, output: &Output) -> bool {
+    if !state.notification_state.do_not_disturb || !is_notification(state, layer.namespace()) {
+        return false;
+    }
+    tracing::debug!("NOTIFICATION SUPPRESSED");
+    state
+        .notification_state
+        .hidden
+        .push((layer.clone(), output.clone()));
+    true
+}
+
+pub fn layer_destroyed(state: &mut AIGIState, surface: &WlrLayerSurface) {
+    state
+        .notification_state
+        .hidden
+        .retain(|(layer, _)| layer.layer_surface() != surface);
+}
+
+/// None toggles it. The subscribed IPC clients are told about it
+pub fn set_do_not_disturb(state: &mut AIGIState, enabled: Option<bool>) {
+    let enabled = enabled.unwrap_or(!state.notification_state.do_not_disturb);
+    if enabled == state.notification_state.do_not_disturb {
+        return;
+    }
+    state.notification_state.do_not_disturb = enabled;
+
+    if enabled {
+        let outputs = state.space.outputs().cloned().collect::<Vec<_>>();
+        for output in outputs {
+            let mut map = layer_map_for_output(&output);
+            let notifications = map
+                .layers()
+                .filter(|layer| is_notification(state, layer.namespace()))
+                .cloned()
+                .collect::<Vec<_>>();
+            for layer in notifications {
+                map.unmap_layer(&layer);
+                state
+                    .notification_state
+                    .hidden
+                    .push((layer, output.clone()));
+            }
+            map.arrange();
+        }
+    } else {
+        let first = state.space.outputs().next().cloned();
+        for (layer, output) in std::mem::take(&mut state.notification_state.hidden) {
+            // The output could have been unplugged in the meantime
+            let output = match state.space.outputs().any(|o| o == &output) {
+                true => output,
+                false => match first.clone() {
+                    Some(first) => first,
+                    None => continue,
+                },
+            };
+            let mut map = layer_map_for_output(&output);
+            if let Err(err) = map.map_layer(&layer) {
+                println!("Notification not shown again: {err}");
+                continue;
+            }
+            map.arrange();
+            // A notification suppressed as soon as it was created
+            // is still waiting for its first configure
+            layer.layer_surface().send_configure();
+        }
+    }
+
+    ipc::broadcast(state, Event::DoNotDisturb { enabled });
+    schedule_render(state);
+}
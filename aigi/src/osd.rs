@@ -0,0 +1,377 @@
+use serde::Deserialize;
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            element::{
+                solid::{SolidColorBuffer, SolidColorRenderElement},
+                texture::{TextureBuffer, TextureRenderElement},
+                Kind,
+            },
+            multigpu::MultiTexture,
+            ImportAll, ImportMem, Renderer,
+        },
+    },
+    reexports::calloop::timer::{TimeoutAction, Timer},
+    utils::{Logical, Point, Rectangle, Scale, Size, Transform},
+};
+use std::time::{Duration, Instant};
+
+use crate::{
+    config::Color,
+    decorations::{self, DecorationRenderElement},
+    render::schedule_render,
+    state::AIGIState,
+};
+
+// The OSD is fully visible for this time and then fades out
+const OSD_VISIBLE: Duration = Duration::from_millis(800);
+const OSD_FADE: Duration = Duration::from_millis(200);
+
+// Geometry of the OSD, in logical pixels
+const OSD_SIZE: (i32, i32) = (280, 56);
+const OSD_BOTTOM_MARGIN: i32 = 96;
+const OSD_PADDING: i32 = 16;
+const ICON_SIZE: i32 = 24;
+const BAR_HEIGHT: i32 = 8;
+
+const BACKGROUND_COLOR: [f32; 4] = [0.1, 0.1, 0.1, 0.85];
+const TRACK_COLOR: [f32; 4] = [0.35, 0.35, 0.35, 1.0];
+const FOREGROUND_COLOR: [f32; 4] = [0.95, 0.95, 0.95, 1.0];
+
+// The workspace OSD is sized on its text, in the middle of the output
+const WORKSPACE_PADDING: i32 = 24;
+// Relative to the font size of the titlebars
+const WORKSPACE_TEXT_SCALE: f32 = 2.0;
+
+/// What the OSD is showing, it decides the icon
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OsdKind {
+    Volume,
+    Brightness,
+    KeyboardBacklight,
+}
+
+impl OsdKind {
+    // Icons are made of rectangles inside a ICON_SIZE square
+    fn icon(&self) -> &'static [(i32, i32, i32, i32)] {
+        match self {
+            // speaker: the body and the cone
+            OsdKind::Volume => &[(2, 8, 6, 8), (8, 4, 6, 16), (17, 10, 2, 4), (20, 7, 2, 10)],
+            // sun: the center and the rays
+            OsdKind::Brightness => &[
+                (8, 8, 8, 8),
+                (11, 0, 2, 5),
+                (11, 19, 2, 5),
+                (0, 11, 5, 2),
+                (19, 11, 5, 2),
+            ],
+            // keyboard: three rows of keys and the space bar
+            OsdKind::KeyboardBacklight => &[
+                (0, 4, 6, 4),
+                (9, 4, 6, 4),
+                (18, 4, 6, 4),
+                (0, 10, 6, 4),
+                (9, 10, 6, 4),
+                (18, 10, 6, 4),
+                (4, 16, 16, 4),
+            ],
+        }
+    }
+}
+
+/// Transient overlay shown on the output when a volume or brightness
+/// key is handled by the compositor, a bar shows the new level
+pub struct Osd {
+    kind: OsdKind,
+    // from 0 to 1
+    level: f64,
+    shown_at: Instant,
+    // Without animations the OSD disappears at once
+    animated: bool,
+    // Buffers are kept between frames so the damage tracker
+    // knows when they really changed
+    background: SolidColorBuffer,
+    track: SolidColorBuffer,
+    fill: SolidColorBuffer,
+    icon: Vec<SolidColorBuffer>,
+}
+
+impl Osd {
+    fn new(kind: OsdKind, level: f64, animated: bool) -> Self {
+        Self {
+            kind,
+            level,
+            shown_at: Instant::now(),
+            animated,
+            background: SolidColorBuffer::default(),
+            track: SolidColorBuffer::default(),
+            fill: SolidColorBuffer::default(),
+            icon: kind
+                .icon()
+                .iter()
+                .map(|_| SolidColorBuffer::default())
+                .collect(),
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        self.shown_at.elapsed() >= OSD_VISIBLE + OSD_FADE
+    }
+
+    /// While fading every frame is different
+    pub fn fading(&self) -> bool {
+        if !self.animated {
+            return false;
+        }
+        let elapsed = self.shown_at.elapsed();
+        elapsed >= OSD_VISIBLE && elapsed < OSD_VISIBLE + OSD_FADE
+    }
+
+    /// Elements of the OSD, centered at the bottom of an output of the given size
+    pub fn render_elements(
+        &mut self,
+        output_size: Size<i32, Logical>,
+        scale: Scale<f64>,
+    ) -> Vec<SolidColorRenderElement> {
+        let opacity = opacity(self.shown_at, OSD_VISIBLE, self.animated);
+        let fade = |color: [f32; 4]| {
+            let alpha = color[3] * opacity;
+            // colors are premultiplied
+            [color[0] * alpha, color[1] * alpha, color[2] * alpha, alpha]
+        };
+
+        let area = Rectangle::from_loc_and_size(
+            (
+                (output_size.w - OSD_SIZE.0) / 2,
+                output_size.h - OSD_SIZE.1 - OSD_BOTTOM_MARGIN,
+            ),
+            OSD_SIZE,
+        );
+        let icon_loc = area.loc + Point::from((OSD_PADDING, (OSD_SIZE.1 - ICON_SIZE) / 2));
+        let bar_loc = Point::from((
+            icon_loc.x + ICON_SIZE + OSD_PADDING,
+            area.loc.y + (OSD_SIZE.1 - BAR_HEIGHT) / 2,
+        ));
+        let bar_width = area.loc.x + area.size.w - OSD_PADDING - bar_loc.x;
+        let fill_width = (bar_width as f64 * self.level.clamp(0.0, 1.0)).round() as i32;
+
+        self.background.update(area.size, fade(BACKGROUND_COLOR));
+        self.track
+            .update((bar_width, BAR_HEIGHT), fade(TRACK_COLOR));
+        self.fill
+            .update((fill_width, BAR_HEIGHT), fade(FOREGROUND_COLOR));
+
+        // The first element is the topmost one
+        let mut elements = vec![];
+        for (buffer, &(x, y, w, h)) in self.icon.iter_mut().zip(self.kind.icon()) {
+            buffer.update((w, h), fade(FOREGROUND_COLOR));
+            let loc = icon_loc + Point::from((x, y));
+            elements.push(element(buffer, loc, scale));
+        }
+        if fill_width > 0 {
+            elements.push(element(&self.fill, bar_loc, scale));
+        }
+        elements.push(element(&self.track, bar_loc, scale));
+        elements.push(element(&self.background, area.loc, scale));
+        elements
+    }
+}
+
+// Opacity of an OSD shown at `shown_at`, it fades out after `visible`
+fn opacity(shown_at: Instant, visible: Duration, animated: bool) -> f32 {
+    let elapsed = shown_at.elapsed();
+    if !animated || elapsed < visible {
+        return 1.0;
+    }
+    let fade = (elapsed - visible).as_secs_f32() / OSD_FADE.as_secs_f32();
+    (1.0 - fade).clamp(0.0, 1.0)
+}
+
+/// Shown after a workspace switch: the number of the workspace and how
+/// many windows it has. Or a message, eg. to confirm a relaunch.
+/// The text uses the font of the titlebars
+pub struct TextOsd {
+    text: String,
+    shown_at: Instant,
+    // Fully visible for this time, then it fades out
+    visible: Duration,
+    animated: bool,
+    background: SolidColorBuffer,
+    // Rasterized on the first frame, for the scale of the output
+    texture: Option<(i32, Option<TextureBuffer<MultiTexture>>)>,
+}
+
+impl TextOsd {
+    pub fn expired(&self) -> bool {
+        self.shown_at.elapsed() >= self.visible + OSD_FADE
+    }
+
+    /// While fading every frame is different
+    pub fn fading(&self) -> bool {
+        let elapsed = self.shown_at.elapsed();
+        self.animated && elapsed >= self.visible && elapsed < self.visible + OSD_FADE
+    }
+
+    /// The text is rasterized again on the next frame (eg. the renderer changed)
+    pub fn forget_texture(&mut self) {
+        self.texture = None;
+    }
+
+    /// Elements of the OSD, centered on an output of the given size,
+    /// the first is the topmost one. Without a font only the background is drawn
+    pub fn render_elements<R>(
+        &mut self,
+        renderer: &mut R,
+        font: Option<&fontdue::Font>,
+        font_size: f32,
+        output_size: Size<i32, Logical>,
+        scale: Scale<f64>,
+    ) -> Vec<DecorationRenderElement<R>>
+    where
+        R: Renderer<TextureId = MultiTexture> + ImportAll + ImportMem,
+    {
+        let int_scale = scale.x.ceil().max(1.0) as i32;
+        if self.texture.as_ref().map_or(true, |(s, _)| *s != int_scale) {
+            let buffer = font.and_then(|font| {
+                let (pixels, w, h) = decorations::rasterize(
+                    font,
+                    &self.text,
+                    font_size * WORKSPACE_TEXT_SCALE * int_scale as f32,
+                    (output_size.w - 2 * WORKSPACE_PADDING) * int_scale,
+                    Color(FOREGROUND_COLOR),
+                )?;
+                let texture = renderer
+                    .import_memory(&pixels, Fourcc::Abgr8888, (w, h).into(), false)
+                    .map_err(|err| println!("Impossible import OSD texture: {err:?}"))
+                    .ok()?;
+                Some(TextureBuffer::from_texture(
+                    renderer,
+                    texture,
+                    int_scale,
+                    Transform::Normal,
+                    None,
+                ))
+            });
+            self.texture = Some((int_scale, buffer));
+        }
+
+        let opacity = opacity(self.shown_at, self.visible, self.animated);
+        let text = self
+            .texture
+            .as_ref()
+            .and_then(|(_, buffer)| buffer.as_ref());
+        let text_size = text.map_or(Size::from((0, 0)), |buffer| buffer.logical_size());
+        let size = Size::from((
+            text_size.w + 2 * WORKSPACE_PADDING,
+            text_size.h + 2 * WORKSPACE_PADDING,
+        ));
+        let area = Rectangle::from_loc_and_size(
+            ((output_size.w - size.w) / 2, (output_size.h - size.h) / 2),
+            size,
+        );
+        let alpha = BACKGROUND_COLOR[3] * opacity;
+        self.background.update(
+            area.size,
+            [
+                BACKGROUND_COLOR[0] * alpha,
+                BACKGROUND_COLOR[1] * alpha,
+                BACKGROUND_COLOR[2] * alpha,
+                alpha,
+            ],
+        );
+
+        let mut elements = vec![];
+        if let Some(buffer) = text {
+            let loc = area.loc + Point::from((WORKSPACE_PADDING, WORKSPACE_PADDING));
+            elements.push(DecorationRenderElement::Title(
+                TextureRenderElement::from_texture_buffer(
+                    loc.to_physical_precise_round(scale).to_f64(),
+                    buffer,
+                    Some(opacity),
+                    None,
+                    None,
+                ),
+            ));
+        }
+        elements.push(decorations::solid_element(
+            &self.background,
+            area.loc,
+            scale,
+        ));
+        elements
+    }
+}
+
+fn element(
+    buffer: &SolidColorBuffer,
+    location: Point<i32, Logical>,
+    scale: Scale<f64>,
+) -> SolidColorRenderElement {
+    SolidColorRenderElement::from_buffer(
+        buffer,
+        location.to_physical_precise_round(scale),
+        scale,
+        1.0,
+        Kind::Unspecified,
+    )
+}
+
+/// Show the OSD with the new level, replacing the one already shown
+pub fn show(state: &mut AIGIState, kind: OsdKind, level: f64) {
+    let animated = state.animations_enabled();
+    match state.osd.as_mut() {
+        // Pressing the key again keeps the OSD on screen with the new level
+        Some(osd) if osd.kind == kind => {
+            osd.level = level;
+            osd.shown_at = Instant::now();
+            osd.animated = animated;
+        }
+        _ => state.osd = Some(Osd::new(kind, level, animated)),
+    }
+    schedule_render(state);
+    arm_timer(state, OSD_VISIBLE, animated);
+}
+
+// Nothing changes until the fade out starts (or until the OSD
+// disappears), so there is no need to render frames until then
+fn arm_timer(state: &mut AIGIState, visible: Duration, animated: bool) {
+    let timeout = if animated {
+        visible
+    } else {
+        visible + OSD_FADE
+    };
+    state
+        .handle
+        .insert_source(Timer::from_duration(timeout), |_, _, loop_data| {
+            schedule_render(&mut loop_data.state);
+            TimeoutAction::Drop
+        })
+        .expect("failed to schedule OSD timer");
+}
+
+/// Show the number of the workspace that became active, with its windows
+pub fn show_workspace(state: &mut AIGIState) {
+    let windows = state.space.elements().count();
+    let text = match windows {
+        1 => format!("Workspace {}: 1 window", state.workspaces.active() + 1),
+        n => format!("Workspace {}: {n} windows", state.workspaces.active() + 1),
+    };
+    show_text(state, text, OSD_VISIBLE);
+}
+
+/// Show a message, fully visible for `visible`
+pub fn show_text(state: &mut AIGIState, text: String, visible: Duration) {
+    let animated = state.animations_enabled();
+    state.text_osd = Some(TextOsd {
+        text,
+        shown_at: Instant::now(),
+        visible,
+        animated,
+        background: SolidColorBuffer::default(),
+        texture: None,
+    });
+    schedule_render(state);
+    arm_timer(state, visible, animated);
+}
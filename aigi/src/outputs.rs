@@ -0,0 +1,396 @@
+use serde::{Deserialize, Serialize};
+use smithay::{
+    desktop::layer_map_for_output,
+    output::{self, Output},
+    reexports::drm::control::Device as ControlDevice,
+    utils::{Logical, Physical, Rectangle, Size, Transform},
+};
+use smithay_drm_extras::drm_scanner::DrmScanEvent;
+use std::cell::RefCell;
+
+use crate::{
+    backend::{self, connector_name},
+    config::OutputConfig,
+    edid::Edid,
+    render::{schedule_render, FrameClock},
+    scripting,
+    state::AIGIState,
+};
+
+/// Change of an output applied at runtime, eg. through the IPC.
+///
+/// Every runtime reconfiguration goes through `apply`,
+/// an output management protocol would use the same path
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputChange {
+    /// `refresh` is in Hz, without it the highest refresh rate is picked
+    Mode {
+        width: u16,
+        height: u16,
+        refresh: Option<u32>,
+    },
+    Pos {
+        x: i32,
+        y: i32,
+    },
+    Scale(f64),
+    /// normal, 90, 180, 270, flipped, flipped-90, flipped-180 or flipped-270
+    Transform(String),
+    /// 0 removes the cap, see `OutputConfig::max_fps`
+    MaxFps(u32),
+    /// Variable refresh rate, see `OutputConfig::vrr`
+    Vrr(bool),
+    Enable,
+    Disable,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OutputInfo {
+    pub name: String,
+    /// From the EDID, see `identifier`
+    pub identifier: Option<String>,
+    pub enabled: bool,
+    /// (width, height, refresh in mHz)
+    pub mode: Option<(i32, i32, i32)>,
+    pub position: (i32, i32),
+    pub scale: f64,
+    pub transform: &'static str,
+    pub max_fps: Option<u32>,
+}
+
+impl From<(&Output, bool)> for OutputInfo {
+    fn from((output, enabled): (&Output, bool)) -> Self {
+        let position = output.current_location();
+        Self {
+            name: output.name(),
+            identifier: identifier(output),
+            enabled,
+            mode: output
+                .current_mode()
+                .map(|mode| (mode.size.w, mode.size.h, mode.refresh)),
+            position: (position.x, position.y),
+            scale: output.current_scale().fractional_scale(),
+            transform: transform_name(output.current_transform()),
+            max_fps: FrameClock::of(output).borrow().max_fps(),
+        }
+    }
+}
+
+/// Make, model and serial of the monitor, eg. "DEL DELL U2720Q 1ABC234".
+/// Unlike the connector name it doesn't change between ports
+pub fn identifier(output: &Output) -> Option<String> {
+    output
+        .user_data()
+        .get::<RefCell<Option<Edid>>>()?
+        .borrow()
+        .as_ref()
+        .map(Edid::identifier)
+}
+
+/// Outputs are named by their connector or by their EDID identifier
+pub fn is_named(output: &Output, name: &str) -> bool {
+    output.name() == name || identifier(output).as_deref() == Some(name)
+}
+
+/// Config of the output, the one with its EDID identifier wins
+/// over the one with its connector name
+pub fn config<'a>(configs: &'a [OutputConfig], output: &Output) -> Option<&'a OutputConfig> {
+    let identifier = identifier(output);
+    configs
+        .iter()
+        .find(|config| identifier.as_ref() == Some(&config.name))
+        .or_else(|| configs.iter().find(|config| config.name == output.name()))
+}
+
+/// Every output, enabled or not
+pub fn info(state: &AIGIState) -> Vec<OutputInfo> {
+    let enabled = state.space.outputs().map(|output| (output, true));
+    let disabled = state.disabled_outputs.iter().map(|output| (output, false));
+    enabled.chain(disabled).map(OutputInfo::from).collect()
+}
+
+/// Area where the tiles are laid out: the active output (see
+/// `AIGIState::active_output`), without the space reserved by panels and bars
+pub fn tiling_area(state: &AIGIState) -> Option<Rectangle<i32, Logical>> {
+    let output = state.active_output()?;
+    let geometry = state.space.output_geometry(&output)?;
+    let zone = layer_map_for_output(&output).non_exclusive_zone();
+    Some(Rectangle::from_loc_and_size(
+        geometry.loc + zone.loc,
+        zone.size,
+    ))
+}
+
+pub fn apply(state: &mut AIGIState, name: &str, change: OutputChange) -> Result<(), String> {
+    let output = state
+        .space
+        .outputs()
+        .chain(state.disabled_outputs.iter())
+        .find(|output| is_named(output, name))
+        .cloned()
+        .ok_or_else(|| format!("There is no output named {name}"))?;
+    let enabled = state.space.outputs().any(|o| o == &output);
+    tracing::debug!("OUTPUT {name}: {change:?}");
+
+    match change {
+        OutputChange::Enable if enabled => return Ok(()),
+        OutputChange::Enable => {
+            set_power(state, &output, true)?;
+            state.disabled_outputs.retain(|o| o != &output);
+            state.map_output(&output);
+        }
+        // The new cap is used from the next frame, the layout is untouched
+        OutputChange::MaxFps(fps) => {
+            FrameClock::of(&output).borrow_mut().set_max_fps(fps);
+            schedule_render(state);
+            return Ok(());
+        }
+        OutputChange::Vrr(enabled) => return set_vrr(state, &output, enabled),
+        OutputChange::Disable if !enabled => return Ok(()),
+        OutputChange::Disable => {
+            // Windows and the pointer need an output to live on
+            if state.space.outputs().count() == 1 {
+                return Err(format!("{name} is the only enabled output"));
+            }
+            set_power(state, &output, false)?;
+            state.space.unmap_output(&output);
+            state.disabled_outputs.push(output.clone());
+        }
+        _ if !enabled => return Err(format!("{name} is disabled")),
+        OutputChange::Mode {
+            width,
+            height,
+            refresh,
+        } => set_mode(state, &output, (width, height), refresh)?,
+        OutputChange::Pos { x, y } => {
+            output.change_current_state(None, None, None, Some((x, y).into()));
+            state.space.map_output(&output, (x, y));
+        }
+        OutputChange::Scale(scale) => {
+            if !(0.25..=10.0).contains(&scale) {
+                return Err(format!("Invalid scale {scale}"));
+            }
+            output.change_current_state(None, None, Some(output::Scale::Fractional(scale)), None);
+        }
+        OutputChange::Transform(transform) => {
+            let transform = transform_from_name(&transform)
+                .ok_or_else(|| format!("Invalid transform {transform}"))?;
+            output.change_current_state(None, Some(transform), None, None);
+        }
+    }
+
+    output_changed(state, &output);
+    Ok(())
+}
+
+/// Scan the connectors again, for monitors and docks whose hotplug events
+/// are not reliable. Only the connector chosen at startup is driven: its
+/// output is enabled when it comes back and disabled when it goes away,
+/// the other connectors are only reported
+pub fn rescan(state: &mut AIGIState) -> Result<(), String> {
+    let device_data = state
+        .backend_data
+        .device_data_mut()
+        .ok_or("There are no connectors in a window")?;
+    let scan = device_data.drm_scanner.scan_connectors(&device_data.drm);
+    let driven = device_data.connector;
+    let name = device_data.connector_name.clone();
+
+    let mut change = None;
+    let mut edid = None;
+    for event in scan.iter() {
+        match event {
+            // It could be another monitor
+            DrmScanEvent::Connected { connector, .. } if connector.handle() == driven => {
+                edid = Some(backend::read_edid(&device_data.drm, driven));
+                change = Some(OutputChange::Enable);
+            }
+            DrmScanEvent::Disconnected { connector, .. } if connector.handle() == driven => {
+                change = Some(OutputChange::Disable);
+            }
+            DrmScanEvent::Connected { connector, crtc } => {
+                let connected = connector_name(connector);
+                match backend::unusable(&device_data.drm, connector, *crtc) {
+                    Some(reason) => tracing::debug!("CONNECTED {connected}: not usable, {reason}"),
+                    None => tracing::debug!("CONNECTED {connected}: only {name} is driven"),
+                }
+            }
+            DrmScanEvent::Disconnected { connector, .. } => {
+                tracing::debug!("DISCONNECTED {}", connector_name(connector));
+            }
+        }
+    }
+
+    if let Some(edid) = edid {
+        match &edid {
+            Some(edid) => tracing::debug!("MONITOR {name}: {}", edid.identifier()),
+            None => tracing::debug!("MONITOR {name}: no EDID"),
+        }
+        if let Some(device_data) = state.backend_data.device_data_mut() {
+            device_data.edid = edid.clone();
+        }
+        // The config of the new monitor is used once it's enabled
+        let output = state
+            .space
+            .outputs()
+            .chain(state.disabled_outputs.iter())
+            .find(|output| output.name() == name);
+        if let Some(known) =
+            output.and_then(|output| output.user_data().get::<RefCell<Option<Edid>>>())
+        {
+            *known.borrow_mut() = edid;
+        }
+    }
+
+    match change {
+        Some(change) => apply(state, &name, change),
+        None => Ok(()),
+    }
+}
+
+// Only the connector driven by the DRM device can change mode,
+// the closest mode among the ones advertised by the monitor is used
+fn set_mode(
+    state: &mut AIGIState,
+    output: &Output,
+    (width, height): (u16, u16),
+    refresh: Option<u32>,
+) -> Result<(), String> {
+    let device_data = state
+        .backend_data
+        .device_data_mut()
+        .filter(|device_data| output.name() == device_data.connector_name)
+        .ok_or_else(|| format!("{} has no DRM connector", output.name()))?;
+
+    let connector = device_data
+        .drm
+        .get_connector(device_data.connector, false)
+        .map_err(|err| err.to_string())?;
+    let drm_mode = connector
+        .modes()
+        .iter()
+        .filter(|mode| mode.size() == (width, height))
+        .filter(|mode| refresh.map_or(true, |refresh| mode.vrefresh() == refresh))
+        .max_by_key(|mode| mode.vrefresh())
+        .copied()
+        .ok_or_else(|| format!("{} does not support {width}x{height}", output.name()))?;
+
+    device_data
+        .gbm_surface
+        .use_mode(drm_mode)
+        .map_err(|err| err.to_string())?;
+    output.change_current_state(Some(output::Mode::from(drm_mode)), None, None, None);
+    Ok(())
+}
+
+/// Only the connector driven by the DRM device has VRR
+pub fn set_vrr(state: &AIGIState, output: &Output, enabled: bool) -> Result<(), String> {
+    let Some(device_data) = state
+        .backend_data
+        .device_data()
+        .filter(|device_data| output.name() == device_data.connector_name)
+    else {
+        return match enabled {
+            true => Err(format!("{} has no DRM connector", output.name())),
+            false => Ok(()),
+        };
+    };
+    device_data.set_vrr(enabled).map_err(|err| err.to_string())
+}
+
+fn set_power(state: &AIGIState, output: &Output, on: bool) -> Result<(), String> {
+    let Some(device_data) = state
+        .backend_data
+        .device_data()
+        .filter(|device_data| output.name() == device_data.connector_name)
+    else {
+        return Ok(());
+    };
+    device_data
+        .set_display_power(on)
+        .map_err(|err| err.to_string())
+}
+
+/// The settings of the config that don't move the output, applied when it's
+/// mapped and when the config is reloaded. What the previous config
+/// enabled is turned off
+pub fn apply_settings(state: &AIGIState, output: &Output) {
+    let config = config(&state.config.outputs, output);
+    FrameClock::of(output)
+        .borrow_mut()
+        .set_max_fps(config.map_or(0, |config| config.max_fps));
+    let vrr = config.is_some_and(|config| config.vrr);
+    if let Err(err) = set_vrr(state, output, vrr) {
+        println!("VRR not set on {}: {err}", output.name());
+    }
+}
+
+/// The window of the winit backend was resized, its output takes the new size
+pub fn window_resized(state: &mut AIGIState, size: Size<i32, Physical>) {
+    let Some(output) = state.space.outputs().next().cloned() else {
+        return;
+    };
+    let mode = output::Mode {
+        size,
+        refresh: backend::WINIT_REFRESH,
+    };
+    output.change_current_state(Some(mode), None, None, None);
+    output.set_preferred(mode);
+    output_changed(state, &output);
+}
+
+/// The size or the position of an output, or the space taken by its panels,
+/// changed: layer surfaces are arranged again and the tiles of every
+/// workspace (and the maximized window) fill the new area
+pub fn output_changed(state: &mut AIGIState, output: &Output) {
+    layer_map_for_output(output).arrange();
+
+    if let Some(area) = tiling_area(state) {
+        if let Err(err) = state.tiling_state.set_area(area) {
+            println!("Tiles not resized: {err}");
+        }
+        state.workspaces.set_tiling_area(area);
+        if let Some(head) = state.tiling_state.tile_tree_head {
+            state.update_tiles(head);
+        }
+    }
+    // update_tiles already takes care of it when there are tiles
+    if state.tiling_state.tile_tree_head.is_none() {
+        if let Some(window) = state.maximized.clone() {
+            state.apply_maximize(&window);
+        }
+    }
+
+    // The pointer could be outside of every output now
+    state.pointer_location = state.clamp_coords(state.pointer_location);
+    schedule_render(state);
+    scripting::output_changed(state, output.name());
+}
+
+fn transform_from_name(name: &str) -> Option<Transform> {
+    Some(match name {
+        "normal" => Transform::Normal,
+        "90" => Transform::_90,
+        "180" => Transform::_180,
+        "270" => Transform::_270,
+        "flipped" => Transform::Flipped,
+        "flipped-90" => Transform::Flipped90,
+        "flipped-180" => Transform::Flipped180,
+        "flipped-270" => Transform::Flipped270,
+        _ => return None,
+    })
+}
+
+fn transform_name(transform: Transform) -> &'static str {
+    match transform {
+        Transform::Normal => "normal",
+        Transform::_90 => "90",
+        Transform::_180 => "180",
+        Transform::_270 => "270",
+        Transform::Flipped => "flipped",
+        Transform::Flipped90 => "flipped-90",
+        Transform::Flipped180 => "flipped-180",
+        Transform::Flipped270 => "flipped-270",
+    }
+}
@@ -0,0 +1,266 @@
+use smithay::{
+    backend::renderer::element::{
+        solid::{SolidColorBuffer, SolidColorRenderElement},
+        Kind,
+    },
+    desktop::{Space, Window},
+    utils::{Logical, Point, Rectangle, Scale, Size},
+};
+use std::time::{Duration, Instant};
+
+use crate::{
+    layout::{Grid, LayoutEngine},
+    render::{schedule_render, Rescaled},
+    state::AIGIState,
+    tiling::Direction,
+};
+
+const OVERVIEW_DURATION: Duration = Duration::from_millis(200);
+
+// Space around the grid and between the windows, in logical pixels
+const OVERVIEW_MARGIN: i32 = 32;
+// Border drawn around the selected window
+const HIGHLIGHT_WIDTH: i32 = 4;
+const HIGHLIGHT_COLOR: [f32; 4] = [0.3, 0.55, 0.9, 1.0];
+
+/// Every window of the workspace is scaled down and placed in a grid
+/// covering the output, the windows don't know about it: they keep their
+/// geometry and are only drawn somewhere else. One of them is picked
+/// with the pointer or the arrows and gets the focus
+pub struct Overview {
+    // Where each window ends up once the animation is over
+    slots: Vec<(Window, Rescaled)>,
+    selected: usize,
+    started: Instant,
+    // Without animations the windows jump in the grid at once
+    animated: bool,
+    // Focused when the overview was opened, it gets the focus back
+    // if nothing is picked
+    previous_focus: Option<Window>,
+    highlight: SolidColorBuffer,
+}
+
+impl Overview {
+    // Progress from 0 to 1, eased out like the workspace transitions
+    fn progress(&self) -> f64 {
+        if !self.animated {
+            return 1.0;
+        }
+        let linear =
+            (self.started.elapsed().as_secs_f64() / OVERVIEW_DURATION.as_secs_f64()).min(1.0);
+        1.0 - (1.0 - linear).powi(3)
+    }
+
+    /// The windows are still moving to their slots
+    pub fn animating(&self) -> bool {
+        self.animated && self.started.elapsed() < OVERVIEW_DURATION
+    }
+
+    /// Where the window is drawn now, None if it was opened after the
+    /// overview and it's drawn as usual
+    pub fn rescaled(&self, space: &Space<Window>, window: &Window) -> Option<Rescaled> {
+        let (_, slot) = self.slots.iter().find(|(w, _)| w == window)?;
+        let from = space.element_location(window)?.to_f64();
+        let progress = self.progress();
+        let moved = slot.location - from;
+        Some(Rescaled {
+            location: from + Point::from((moved.x * progress, moved.y * progress)),
+            scale: 1.0 + (slot.scale - 1.0) * progress,
+        })
+    }
+
+    /// Border around the selected window, drawn below it. `origin` and
+    /// `size` are where the window is drawn, relative to the output
+    pub fn highlight_element(
+        &mut self,
+        window: &Window,
+        origin: Point<f64, Logical>,
+        size: Size<i32, Logical>,
+        scale: Scale<f64>,
+    ) -> Option<SolidColorRenderElement> {
+        if self.slots.get(self.selected).map(|(w, _)| w) != Some(window) {
+            return None;
+        }
+        self.highlight.update(
+            (size.w + 2 * HIGHLIGHT_WIDTH, size.h + 2 * HIGHLIGHT_WIDTH),
+            HIGHLIGHT_COLOR,
+        );
+        let location = origin - Point::from((HIGHLIGHT_WIDTH, HIGHLIGHT_WIDTH)).to_f64();
+        Some(SolidColorRenderElement::from_buffer(
+            &self.highlight,
+            location.to_physical_precise_round(scale),
+            scale,
+            1.0,
+            Kind::Unspecified,
+        ))
+    }
+
+    // The slot drawn under the point, once the animation is over
+    fn slot_at(&self, point: Point<f64, Logical>) -> Option<usize> {
+        self.slots.iter().position(|(window, slot)| {
+            Rectangle::from_loc_and_size(slot.location, slot.size(window).to_f64()).contains(point)
+        })
+    }
+
+    // The nearest slot in the direction, from the center of the selected one
+    fn neighbor(&self, direction: Direction) -> Option<usize> {
+        let center = |(window, slot): &(Window, Rescaled)| {
+            let size = slot.size(window).to_f64();
+            slot.location + Point::from((size.w / 2.0, size.h / 2.0))
+        };
+        let from = center(self.slots.get(self.selected)?);
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let delta = center(slot) - from;
+                let ahead = match direction {
+                    Direction::Left => -delta.x,
+                    Direction::Right => delta.x,
+                    Direction::Up => -delta.y,
+                    Direction::Down => delta.y,
+                };
+                (ahead > 0.0).then(|| (index, delta.x.hypot(delta.y)))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(index, _)| index)
+    }
+}
+
+/// Open the overview of the active workspace, or pick the selected
+/// window if it's already open
+pub fn toggle(state: &mut AIGIState) {
+    if state.overview.is_some() {
+        close(state, true);
+        return;
+    }
+
+    let Some(output) = state.space.outputs().next() else {
+        return;
+    };
+    let Some(output_geometry) = state.space.output_geometry(output) else {
+        return;
+    };
+    let windows = state.space.elements().cloned().collect::<Vec<_>>();
+    if windows.is_empty() {
+        return;
+    }
+
+    // Each window is scaled to fit its cell of the grid (never enlarged),
+    // centered in it
+    let area = Rectangle::from_loc_and_size(
+        output_geometry.loc + Point::from((OVERVIEW_MARGIN, OVERVIEW_MARGIN)),
+        (
+            output_geometry.size.w - 2 * OVERVIEW_MARGIN,
+            output_geometry.size.h - 2 * OVERVIEW_MARGIN,
+        ),
+    );
+    let cells = Grid.arrange(&windows, area);
+    let slots = windows
+        .into_iter()
+        .zip(cells)
+        .map(|(window, cell)| {
+            let size = window.geometry().size.to_f64();
+            let cell_w = (cell.size.w - OVERVIEW_MARGIN).max(1) as f64;
+            let cell_h = (cell.size.h - OVERVIEW_MARGIN).max(1) as f64;
+            let scale = (cell_w / size.w.max(1.0))
+                .min(cell_h / size.h.max(1.0))
+                .min(1.0);
+            let location = cell.loc.to_f64()
+                + Point::from((
+                    (cell.size.w as f64 - size.w * scale) / 2.0,
+                    (cell.size.h as f64 - size.h * scale) / 2.0,
+                ));
+            (window, Rescaled { location, scale })
+        })
+        .collect::<Vec<_>>();
+
+    let previous_focus = state
+        .seat
+        .get_keyboard()
+        .and_then(|keyboard| keyboard.current_focus())
+        .and_then(|wl_surface| state.window_for_surface(&wl_surface));
+    let selected = previous_focus
+        .as_ref()
+        .and_then(|focus| slots.iter().position(|(window, _)| window == focus))
+        .unwrap_or(0);
+
+    state.overview = Some(Overview {
+        slots,
+        selected,
+        started: Instant::now(),
+        animated: state.animations_enabled(),
+        previous_focus,
+        highlight: SolidColorBuffer::default(),
+    });
+    // No client gets the keyboard while the overview is open
+    state.set_keyboard_focus(None);
+    schedule_render(state);
+}
+
+/// Leave the overview, the selected window gets the focus if it's picked,
+/// otherwise the focus goes back to where it was
+pub fn close(state: &mut AIGIState, pick: bool) {
+    let Some(overview) = state.overview.take() else {
+        return;
+    };
+    let picked = match pick {
+        true => overview
+            .slots
+            .get(overview.selected)
+            .map(|(window, _)| window.clone()),
+        false => overview.previous_focus,
+    };
+    // The window could have been closed in the meantime
+    let picked = picked.filter(|window| state.space.elements().any(|w| w == window));
+
+    match picked {
+        // The focus follows the pointer, it goes where the window is
+        Some(window) => {
+            state.set_keyboard_focus(Some(&window));
+            state.warp_pointer_to(&window);
+        }
+        None => schedule_render(state),
+    }
+}
+
+/// Move the selection with the arrows
+pub fn select(state: &mut AIGIState, direction: Direction) {
+    let Some(overview) = state.overview.as_mut() else {
+        return;
+    };
+    if let Some(index) = overview.neighbor(direction) {
+        overview.selected = index;
+        schedule_render(state);
+    }
+}
+
+/// The window under the pointer is selected, returns false
+/// if the overview is not open and the motion is for the clients
+pub fn pointer_moved(state: &mut AIGIState) -> bool {
+    let point = state.pointer_location;
+    let Some(overview) = state.overview.as_mut() else {
+        return false;
+    };
+    if let Some(index) = overview.slot_at(point) {
+        if index != overview.selected {
+            overview.selected = index;
+            schedule_render(state);
+        }
+    }
+    true
+}
+
+/// A click picks the window under the pointer, outside of the
+/// windows nothing happens. Returns false if the overview is not open
+pub fn pointer_pressed(state: &mut AIGIState) -> bool {
+    let point = state.pointer_location;
+    let Some(overview) = state.overview.as_mut() else {
+        return false;
+    };
+    if let Some(index) = overview.slot_at(point) {
+        overview.selected = index;
+        close(state, true);
+    }
+    true
+}
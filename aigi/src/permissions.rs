@@ -0,0 +1,214 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use smithay::reexports::{
+    nix::sys::socket::{getsockopt, sockopt::PeerCredentials},
+    wayland_server::Client,
+};
+use std::{
+    collections::HashSet,
+    os::{fd::AsRawFd, unix::net::UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    config::{self, PermissionConfig},
+    ipc::{self, Event},
+    state::{AIGIState, ClientState},
+};
+
+/// Globals that let a client see or control the other clients
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Screencopy,
+    VirtualKeyboard,
+}
+
+// A client was refused a global
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct PermissionRequest {
+    executable: PathBuf,
+    permission: Permission,
+}
+
+/// Answer of the user to a `permission_requested` event,
+/// remembered in the state file
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct Decision {
+    executable: PathBuf,
+    permission: Permission,
+    allow: bool,
+}
+
+/// Executables allowed to use the sensitive globals, from the config or
+/// answered through the IPC. It's shared with the filters of the globals,
+/// which are called by the wayland backend without the state
+#[derive(Clone, Default)]
+pub struct Permissions(Arc<Mutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    config: Vec<PermissionConfig>,
+    // The only executables that can answer through the IPC
+    helpers: Vec<PathBuf>,
+    decisions: Vec<Decision>,
+    // Asked to the helpers and not answered yet, asked only once
+    asked: HashSet<(PathBuf, Permission)>,
+    // Refused clients not reported to the IPC yet
+    requests: Vec<PermissionRequest>,
+}
+
+impl Inner {
+    // None if the user has to be asked
+    fn allows(&self, executable: &Path, permission: Permission) -> Option<bool> {
+        let configured = self
+            .config
+            .iter()
+            .any(|config| config.executable == executable && config.allow.contains(&permission));
+        if configured {
+            return Some(true);
+        }
+        self.decisions
+            .iter()
+            .find(|decision| decision.executable == executable && decision.permission == permission)
+            .map(|decision| decision.allow)
+    }
+}
+
+impl Permissions {
+    /// The answers given in the previous sessions are read from the state file
+    pub fn new(config: &[PermissionConfig], helpers: &[PathBuf]) -> Self {
+        let permissions = Self::default();
+        permissions.set_config(config, helpers);
+        if let Some(path) = state_file() {
+            match load_decisions(&path) {
+                Ok(decisions) => permissions.0.lock().unwrap().decisions = decisions,
+                Err(err) => println!("Permissions not loaded from {path:?}: {err:?}"),
+            }
+        }
+        permissions
+    }
+
+    pub fn set_config(&self, config: &[PermissionConfig], helpers: &[PathBuf]) {
+        let mut inner = self.0.lock().unwrap();
+        inner.config = config.to_vec();
+        inner.helpers = helpers.to_vec();
+    }
+
+    /// The executable can answer the permission requests
+    pub fn is_helper(&self, executable: &Path) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .helpers
+            .iter()
+            .any(|helper| helper == executable)
+    }
+
+    /// Filter of a sensitive global: it's advertised and can be bound only
+    /// by clients whose executable is allowed
+    pub fn filter(&self, permission: Permission) -> impl Fn(&Client) -> bool + Send + Sync {
+        let permissions = self.clone();
+        move |client| permissions.decide(client, permission).unwrap_or(false)
+    }
+
+    /// None if the user has not answered yet, they are asked through the
+    /// IPC (see `report_requests`) the first time
+    pub fn decide(&self, client: &Client, permission: Permission) -> Option<bool> {
+        // Without an executable there is nothing to match
+        let Some(executable) = client
+            .get_data::<ClientState>()
+            .and_then(|data| data.executable.clone())
+        else {
+            return Some(false);
+        };
+
+        let mut inner = self.0.lock().unwrap();
+        if let Some(allow) = inner.allows(&executable, permission) {
+            return Some(allow);
+        }
+        if inner.asked.insert((executable.clone(), permission)) {
+            inner.requests.push(PermissionRequest {
+                executable,
+                permission,
+            });
+        }
+        None
+    }
+
+    /// The client sees the global (or not) the next time it gets the
+    /// registry, the answer is saved for the next sessions too
+    pub fn answer(
+        &self,
+        executable: PathBuf,
+        permission: Permission,
+        allow: bool,
+    ) -> anyhow::Result<()> {
+        tracing::debug!("PERMISSION {permission:?} to {executable:?}: allow {allow}");
+        let mut inner = self.0.lock().unwrap();
+        inner.asked.remove(&(executable.clone(), permission));
+        inner.decisions.retain(|decision| {
+            decision.executable != executable || decision.permission != permission
+        });
+        inner.decisions.push(Decision {
+            executable,
+            permission,
+            allow,
+        });
+
+        let path = state_file().context("No state directory, the answer is not saved")?;
+        save_decisions(&path, &inner.decisions)
+            .with_context(|| format!("Impossible save the permissions in {path:?}"))
+    }
+
+    fn take_requests(&self) -> Vec<PermissionRequest> {
+        std::mem::take(&mut self.0.lock().unwrap().requests)
+    }
+}
+
+fn state_file() -> Option<PathBuf> {
+    config::state_path("permissions.json")
+}
+
+fn load_decisions(path: &Path) -> anyhow::Result<Vec<Decision>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let content = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+fn save_decisions(path: &Path, decisions: &[Decision]) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(decisions)?)?;
+    Ok(())
+}
+
+/// Executable of the process on the other side of a new wayland
+/// (or IPC) connection, from the credentials of the socket
+pub fn executable(stream: &UnixStream) -> Option<PathBuf> {
+    let credentials = getsockopt(stream.as_raw_fd(), PeerCredentials).ok()?;
+    std::fs::read_link(format!("/proc/{}/exe", credentials.pid())).ok()
+}
+
+/// Ask the IPC helpers about the clients refused since the last time,
+/// called after the requests of the clients are dispatched. Until the
+/// answer the global is hidden from the client
+pub fn report_requests(state: &mut AIGIState) {
+    for request in state.permissions.take_requests() {
+        println!(
+            "PERMISSION DENIED: {:?} to {:?}",
+            request.permission, request.executable
+        );
+        ipc::broadcast(
+            state,
+            Event::PermissionRequested {
+                executable: request.executable,
+                permission: request.permission,
+            },
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use smithay::{
+    desktop::Window,
+    reexports::calloop::timer::{TimeoutAction, Timer},
+    utils::SERIAL_COUNTER,
+    wayland::shell::xdg::ShellClient,
+};
+use std::time::{Duration, Instant};
+
+use crate::{decorations, render, state::AIGIState};
+
+// Clients with a mapped toplevel are pinged this often
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+// A client that didn't answer a ping within this time is unresponsive
+pub const PING_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Pings sent through xdg_wm_base that did not get the pong yet
+#[derive(Default)]
+pub struct PingState {
+    pending: Vec<Ping>,
+    timer_armed: bool,
+}
+
+struct Ping {
+    client: ShellClient,
+    sent: Instant,
+}
+
+impl PingState {
+    /// The client of the window was pinged and it did
+    /// not answer for more than `PING_TIMEOUT`
+    pub fn is_unresponsive(&self, window: &Window) -> bool {
+        self.is_client_unresponsive(&window.toplevel().client())
+    }
+
+    /// The client answered, returns true if it was unresponsive
+    pub fn pong(&mut self, client: &ShellClient) -> bool {
+        let unresponsive = self.is_client_unresponsive(client);
+        self.pending.retain(|ping| &ping.client != client);
+        unresponsive
+    }
+
+    fn is_client_unresponsive(&self, client: &ShellClient) -> bool {
+        self.pending
+            .iter()
+            .any(|ping| &ping.client == client && ping.sent.elapsed() > PING_TIMEOUT)
+    }
+}
+
+pub fn arm_timer(state: &mut AIGIState) {
+    if state.ping_state.timer_armed {
+        return;
+    }
+    state.ping_state.timer_armed = true;
+    state
+        .handle
+        .insert_source(Timer::from_duration(PING_INTERVAL), |_, _, loop_data| {
+            let state = &mut loop_data.state;
+            // The pending pings are checked again when the timers resume
+            if render::suspend_timer(state) {
+                state.ping_state.timer_armed = false;
+                return TimeoutAction::Drop;
+            }
+            ping_clients(state);
+            TimeoutAction::ToDuration(PING_INTERVAL)
+        })
+        .expect("failed to schedule ping timer");
+}
+
+// Windows of the hidden workspaces are pinged too, so an unresponsive
+// client is known also before switching to its workspace.
+// A client still waiting for the previous ping is not pinged again
+fn ping_clients(state: &mut AIGIState) {
+    state.ping_state.pending.retain(|ping| ping.client.alive());
+
+    let windows = state
+        .space
+        .elements()
+        .cloned()
+        .chain(state.workspaces.windows().map(|(_, window)| window.clone()))
+        .collect::<Vec<_>>();
+    for window in windows {
+        let client = window.toplevel().client();
+        if state.ping_state.is_client_unresponsive(&client) {
+            tracing::debug!("UNRESPONSIVE CLIENT: {}", decorations::title(&window));
+        }
+        if state
+            .ping_state
+            .pending
+            .iter()
+            .any(|ping| ping.client == client)
+        {
+            continue;
+        }
+
+        match client.send_ping(SERIAL_COUNTER.next_serial()) {
+            Ok(()) => state.ping_state.pending.push(Ping {
+                client,
+                sent: Instant::now(),
+            }),
+            Err(err) => println!("Ping not sent: {err:?}"),
+        }
+    }
+}
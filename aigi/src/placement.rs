@@ -0,0 +1,133 @@
+use smithay::{
+    desktop::Window, reexports::wayland_server::protocol::wl_surface::WlSurface, utils::Point,
+};
+
+use crate::{ipc, render::set_drop_zone, scripting, state::AIGIState, tiling::DropZone};
+
+/// A new window waiting for the user to choose where it's tiled (see
+/// `manual_placement` in the config): it takes the `zone` half of the
+/// `target` tile, highlighted until Enter is pressed
+pub struct Placement {
+    window: Window,
+    target: Window,
+    zone: DropZone,
+}
+
+/// The window waits to be placed, returns false if it must be tiled
+/// as usual (eg. it's the first one and takes the whole area)
+pub fn start(state: &mut AIGIState, window: &Window) -> bool {
+    if !state.config.tiling.manual_placement {
+        return false;
+    }
+    // A window already waiting is tiled where it was going
+    confirm(state);
+    let Some(target) = state.split_target() else {
+        return false;
+    };
+
+    state.placement = Some(Placement {
+        window: window.clone(),
+        target,
+        zone: DropZone::Right,
+    });
+    show(state);
+    true
+}
+
+// The half that the window would take is highlighted like a drop zone
+fn show(state: &mut AIGIState) {
+    let zone = state.placement.as_ref().and_then(|placement| {
+        let geometry = state
+            .tiling_state
+            .tile_geometry(placement.target.toplevel().wl_surface())?;
+        Some(placement.zone.half(geometry))
+    });
+    set_drop_zone(state, zone);
+}
+
+/// An arrow selects that half of the tile, if it's already
+/// selected the tile on that side is selected
+pub fn step(state: &mut AIGIState, direction: DropZone) {
+    let Some(placement) = state.placement.as_mut() else {
+        return;
+    };
+    if placement.zone != direction {
+        placement.zone = direction;
+        show(state);
+        return;
+    }
+
+    let Some(geometry) = state
+        .tiling_state
+        .tile_geometry(placement.target.toplevel().wl_surface())
+    else {
+        return;
+    };
+    // A point just outside the edge of the tile, the tile there
+    // gets the half next to the edge
+    let center = geometry.loc + Point::from((geometry.size.w / 2, geometry.size.h / 2));
+    let beyond = match direction {
+        DropZone::Left => Point::from((geometry.loc.x - 1, center.y)),
+        DropZone::Right => Point::from((geometry.loc.x + geometry.size.w, center.y)),
+        DropZone::Top => Point::from((center.x, geometry.loc.y - 1)),
+        DropZone::Bottom => Point::from((center.x, geometry.loc.y + geometry.size.h)),
+    };
+    if let Some((target, zone, _)) = state
+        .tiling_state
+        .drop_target(beyond, placement.window.toplevel().wl_surface())
+    {
+        placement.target = target;
+        placement.zone = zone;
+        show(state);
+    }
+}
+
+/// The window is tiled in the highlighted half
+pub fn confirm(state: &mut AIGIState) {
+    let Some(placement) = state.placement.take() else {
+        return;
+    };
+    set_drop_zone(state, None);
+    let window = placement.window;
+    match state.tiling_state.split_in_zone(
+        placement.target.toplevel().wl_surface(),
+        window.clone(),
+        placement.zone,
+    ) {
+        Ok(node) => {
+            state.update_tiles(node);
+            ipc::workspaces_changed(state);
+        }
+        // The target was closed in the meantime
+        Err(err) => {
+            println!("Window not placed: {err}");
+            state.tile_window(window.clone());
+        }
+    }
+    scripting::window_opened(state, &window);
+}
+
+/// The window is tiled as if there was no manual placement
+pub fn cancel(state: &mut AIGIState) {
+    let Some(placement) = state.placement.take() else {
+        return;
+    };
+    set_drop_zone(state, None);
+    state.tile_window(placement.window.clone());
+    scripting::window_opened(state, &placement.window);
+}
+
+/// The waiting window was closed before being placed,
+/// returns false if it's not the waiting one
+pub fn forget(state: &mut AIGIState, wl_surface: &WlSurface) -> bool {
+    let waiting = state
+        .placement
+        .as_ref()
+        .is_some_and(|placement| placement.window.toplevel().wl_surface() == wl_surface);
+    if !waiting {
+        return false;
+    }
+    state.placement = None;
+    set_drop_zone(state, None);
+    true
+}
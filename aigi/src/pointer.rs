@@ -10,44 +10,62 @@ use smithay::{
             ImportAll, ImportMem, Renderer, Texture,
         },
     },
-    input::pointer::CursorImageStatus,
+    input::pointer::{CursorImageAttributes, CursorImageStatus},
     render_elements,
-    utils::{Clock, Monotonic, Physical, Point, Scale, Transform},
+    utils::{Clock, Logical, Monotonic, Physical, Point, Scale, Transform},
+    wayland::compositor::with_states,
 };
-use std::{collections::BTreeMap, env::var, fs::File, io::Read, ops::Bound, time::Duration};
-use xcursor::{parser::parse_xcursor, CursorTheme};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::Read,
+    ops::Bound,
+    sync::Mutex,
+    time::Duration,
+};
+use xcursor::{
+    parser::{parse_xcursor, Image},
+    CursorTheme,
+};
+
+struct CursorTexture<T: Texture> {
+    buffer: TextureBuffer<T>,
+    hotspot: Point<f64, Logical>,
+    // Index of the image in the theme used to create the texture
+    image: usize,
+}
 
 pub struct PointerElement<T: Texture> {
-    pub texture: Option<TextureBuffer<T>>,
+    // Every size available in the theme for the default cursor
+    images: Vec<Image>,
+    // Nominal size of the cursor, in logical pixels
+    size: u32,
+    // Textures already imported, one for each scale
+    textures: HashMap<i32, CursorTexture<T>>,
+    current_scale: i32,
+    // Hidden by the compositor (eg. while typing), whatever the status is
+    hidden: bool,
     pub status: CursorImageStatus,
 }
 
 impl<T: Texture> Default for PointerElement<T> {
     fn default() -> Self {
         Self {
-            texture: Default::default(),
+            images: vec![],
+            size: 24,
+            textures: HashMap::new(),
+            current_scale: 1,
+            hidden: false,
             status: CursorImageStatus::Default,
         }
     }
 }
 
 impl<T: Texture> PointerElement<T> {
-    pub fn new<R>(renderer: &mut R) -> Self
-    where
-        R: Renderer<TextureId = T> + ImportMem,
-    {
-        // Get the xcursor theme. For example there might be a light and dark theme of cursors. let theme = var("XCURSOR_THEME").ok().unwrap_or("default".into());
-        let theme = var("XCURSOR_THEME").ok().unwrap_or("default".into());
-
-        // Get the xcursor size. The options are 24, 32, 48, 64, with the default normally being
-        // 24px.
-        let size = var("XCURSOR_SIZE")
-            .ok()
-            .and_then(|s| s.parse::<i32>().ok())
-            .unwrap_or(24);
-
+    /// Load the default cursor of the theme, `size` is the nominal size of the cursor
+    pub fn new(theme: &str, size: u32) -> Self {
         // Load the theme and get the default cursor of that theme.
-        let cursor_theme = CursorTheme::load(&theme);
+        let cursor_theme = CursorTheme::load(theme);
         let cursor_path = cursor_theme.load_icon("default").unwrap();
 
         // Open the xcursor file and read the data.
@@ -55,45 +73,98 @@ impl<T: Texture> PointerElement<T> {
         let mut cursor_data = vec![];
         cursor_file.read_to_end(&mut cursor_data).unwrap();
 
-        // Parse the data into xcursor::parser::Image structs.
-        let cursor_images = parse_xcursor(&cursor_data)
-            .unwrap()
-            .into_iter()
-            .filter(move |image| image.width == size as u32 && image.height == size as u32);
-
-        // xcursor can contain an animation of a cursor (for example a cursor with a spinner).
-        // Each image can contain a delay, the time period until showing the next image of the
-        // cursor animation, the total delay from the start is used as the key.
-        //
-        // Get only the first texture
-        let image = cursor_images.into_iter().next().unwrap();
-        let texture = renderer
-            .import_memory(
-                image.pixels_rgba.as_slice(),
-                Fourcc::Xrgb8888,
-                (size, size).into(),
-                false,
-            )
-            .unwrap();
+        // Parse the data into xcursor::parser::Image structs,
+        // all the sizes are kept, the right one depends on the output scale
+        let images = parse_xcursor(&cursor_data).unwrap();
+
+        Self {
+            images,
+            size,
+            ..Default::default()
+        }
+    }
+
+    /// Select the texture used for an output with the given scale,
+    /// importing it if it's the first time the scale is used.
+    ///
+    /// The image is choosen from the theme with the size closest
+    /// to the nominal size multiplied by the scale, so the cursor
+    /// has always the same logical size and it is sharp on HiDPI outputs
+    pub fn set_scale<R>(&mut self, renderer: &mut R, scale: i32)
+    where
+        R: Renderer<TextureId = T> + ImportMem,
+    {
+        let scale = scale.max(1);
+        self.current_scale = scale;
+        if self.textures.contains_key(&scale) {
+            return;
+        }
+
+        let target_size = self.size * scale as u32;
+        // xcursor can contain an animation of a cursor (for example a cursor with a spinner),
+        // only the first image of the choosen size is used
+        let Some((image_index, image)) = self
+            .images
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, image)| (image.size.abs_diff(target_size), u32::MAX - image.size))
+        else {
+            return;
+        };
+
+        let texture = match renderer.import_memory(
+            image.pixels_rgba.as_slice(),
+            Fourcc::Abgr8888,
+            (image.width as i32, image.height as i32).into(),
+            false,
+        ) {
+            Ok(texture) => texture,
+            Err(err) => {
+                println!("Impossible import cursor texture: {err:?}");
+                return;
+            }
+        };
+
+        // The buffer has the same scale of the output, so the
+        // image is drawn pixel per pixel without being stretched
+        let buffer_scale = (image.size as f64 / self.size as f64).max(1.0).round() as i32;
 
         // A buffer that represents the texture and can be turned into a TextureRenderElement
         // which provides damage tracking. It can then be rendered as an element and stacked
         // on the output.
         let texture_buffer =
-            TextureBuffer::from_texture(renderer, texture, 1, Transform::Normal, None);
+            TextureBuffer::from_texture(renderer, texture, buffer_scale, Transform::Normal, None);
+        let hotspot = Point::from((
+            image.xhot as f64 / buffer_scale as f64,
+            image.yhot as f64 / buffer_scale as f64,
+        ));
+        self.textures.insert(
+            scale,
+            CursorTexture {
+                buffer: texture_buffer,
+                hotspot,
+                image: image_index,
+            },
+        );
+    }
 
-        Self {
-            texture: Some(texture_buffer),
-            status: CursorImageStatus::Default,
-        }
+    /// The xcursor image used for the current scale, together with the scale,
+    /// useful to draw the cursor without the renderer (eg. on the cursor plane)
+    pub fn current_image(&self) -> Option<(&Image, i32)> {
+        let texture = self.textures.get(&self.current_scale)?;
+        Some((self.images.get(texture.image)?, self.current_scale))
     }
 
     pub fn set_status(&mut self, status: CursorImageStatus) {
         self.status = status;
     }
 
-    pub fn set_texture(&mut self, texture: TextureBuffer<T>) {
-        self.texture = Some(texture);
+    pub fn set_hidden(&mut self, hidden: bool) {
+        self.hidden = hidden;
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
     }
 }
 
@@ -123,14 +194,20 @@ where
     where
         E: From<PointerRenderElement<R>>,
     {
+        if self.hidden {
+            return vec![];
+        }
+
         match &self.status {
             CursorImageStatus::Hidden => vec![],
             CursorImageStatus::Default => {
-                if let Some(texture) = self.texture.as_ref() {
+                if let Some(texture) = self.textures.get(&self.current_scale) {
+                    // The location is where the hotspot of the cursor must be
+                    let location = location.to_f64() - texture.hotspot.to_physical(scale);
                     vec![PointerRenderElement::<R>::from(
                         TextureRenderElement::from_texture_buffer(
-                            location.to_f64(),
-                            texture,
+                            location,
+                            &texture.buffer,
                             None,
                             None,
                             None,
@@ -142,10 +219,18 @@ where
                 }
             }
             CursorImageStatus::Surface(surface) => {
+                // The hotspot is in logical coordinates, whatever the buffer
+                // scale of the client is, like the size of the surface
+                let hotspot = with_states(surface, |states| {
+                    states
+                        .data_map
+                        .get::<Mutex<CursorImageAttributes>>()
+                        .map(|attributes| attributes.lock().unwrap().hotspot)
+                        .unwrap_or_default()
+                });
+                let location = location - hotspot.to_physical_precise_round(scale);
                 let elements: Vec<PointerRenderElement<R>> =
-                    smithay::backend::renderer::element::surface::render_elements_from_surface_tree(
-                        renderer, surface, location, scale, alpha,
-                    );
+                    render_elements_from_surface_tree(renderer, surface, location, scale, alpha);
                 elements.into_iter().map(E::from).collect()
             }
         }
@@ -0,0 +1,109 @@
+use smithay::{
+    backend::renderer::utils::with_renderer_surface_state,
+    reexports::wayland_server::{
+        protocol::wl_surface::WlSurface, Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch,
+        New,
+    },
+    utils::{Logical, Point, Serial},
+};
+
+use crate::{
+    protocols::pointer_warp::wp_pointer_warp_v1::{self, WpPointerWarpV1},
+    state::AIGIState,
+};
+
+const VERSION: u32 = 1;
+
+/// The surface entered by the pointer, the warps are checked against it
+pub struct PointerEnter {
+    surface: WlSurface,
+    // Where the surface is, in the coordinates seen by the clients
+    location: Point<i32, Logical>,
+    serial: Serial,
+}
+
+/// wp-pointer-warp, a client (eg. a game or a remote desktop viewer)
+/// moves the pointer inside the surface it's over
+pub fn create_global(display: &DisplayHandle) {
+    display.create_global::<AIGIState, WpPointerWarpV1, _>(VERSION, ());
+}
+
+/// Remember the surface under the pointer, called with every motion sent
+/// to the clients: `serial` is the one of the enter when the focus changes
+pub fn motion(
+    state: &mut AIGIState,
+    focus: Option<&(WlSurface, Point<i32, Logical>)>,
+    serial: Serial,
+) {
+    state.pointer_enter = match (focus, state.pointer_enter.take()) {
+        (Some((surface, location)), Some(enter)) if enter.surface == *surface => {
+            Some(PointerEnter {
+                location: *location,
+                ..enter
+            })
+        }
+        (Some((surface, location)), _) => Some(PointerEnter {
+            surface: surface.clone(),
+            location: *location,
+            serial,
+        }),
+        (None, _) => None,
+    };
+}
+
+// Only the surface under the pointer can move it, to a point inside itself
+fn warp(state: &mut AIGIState, surface: &WlSurface, point: Point<f64, Logical>, serial: Serial) {
+    let Some(enter) = state.pointer_enter.as_ref() else {
+        return;
+    };
+    if enter.surface != *surface || enter.serial != serial {
+        tracing::debug!("POINTER WARP REFUSED: the surface doesn't have the pointer");
+        return;
+    }
+    let size = with_renderer_surface_state(surface, |surface_state| surface_state.surface_size())
+        .unwrap_or_default();
+    let inside = (0.0..size.w as f64).contains(&point.x) && (0.0..size.h as f64).contains(&point.y);
+    if !inside {
+        tracing::debug!("POINTER WARP REFUSED: {point:?} is outside of the surface");
+        return;
+    }
+    let location = enter.location.to_f64() + point;
+    let location = state.screen_pointer_location(location);
+    state.warp_pointer(location);
+}
+
+impl GlobalDispatch<WpPointerWarpV1, ()> for AIGIState {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        manager: New<WpPointerWarpV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(manager, ());
+    }
+}
+
+impl Dispatch<WpPointerWarpV1, ()> for AIGIState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _manager: &WpPointerWarpV1,
+        request: wp_pointer_warp_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_pointer_warp_v1::Request::WarpPointer {
+                surface,
+                pointer: _,
+                x,
+                y,
+                serial,
+            } => warp(state, &surface, (x, y).into(), serial.into()),
+            wp_pointer_warp_v1::Request::Destroy => {}
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use smithay::reexports::calloop::timer::{TimeoutAction, Timer};
+use std::{path::Path, time::Duration};
+
+use crate::{
+    render::{self, schedule_render},
+    state::AIGIState,
+};
+
+// The power supply is polled, plugging the charger is not
+// something that needs an immediate reaction
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The system runs on battery if there is a mains power supply and it's
+/// offline, machines without one (eg. desktops) are always on AC
+pub fn on_battery() -> bool {
+    let Ok(supplies) = std::fs::read_dir("/sys/class/power_supply") else {
+        return false;
+    };
+
+    let mains = supplies
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| read_trimmed(&path.join("type")).as_deref() == Some("Mains"))
+        .collect::<Vec<_>>();
+
+    !mains.is_empty()
+        && mains
+            .iter()
+            .all(|path| read_trimmed(&path.join("online")).as_deref() == Some("0"))
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|content| content.trim().to_string())
+}
+
+/// Keep `AIGIState::on_battery` up to date. The poll is suspended with
+/// the other periodic timers on static content, the power supply is read
+/// again as soon as the timer is armed on the next activity
+pub fn arm_timer(state: &mut AIGIState) {
+    if state.power_timer_armed {
+        return;
+    }
+    state.power_timer_armed = true;
+    state
+        .handle
+        .insert_source(Timer::immediate(), |_, _, loop_data| {
+            let state = &mut loop_data.state;
+            let on_battery = on_battery();
+            if on_battery != state.on_battery {
+                tracing::debug!("ON BATTERY: {on_battery}");
+                state.on_battery = on_battery;
+                // The frame rate cap could have changed
+                schedule_render(state);
+            }
+            if render::suspend_timer(state) {
+                state.power_timer_armed = false;
+                return TimeoutAction::Drop;
+            }
+            TimeoutAction::ToDuration(POLL_INTERVAL)
+        })
+        .expect("failed to schedule power supply polling");
+}
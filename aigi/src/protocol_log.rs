@@ -0,0 +1,98 @@
+use anyhow::Result;
+use smithay::reexports::nix::unistd::{close, dup, dup2, pipe};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    os::fd::FromRawFd,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Events sent to every client, printed by the wayland backend like
+/// WAYLAND_DEBUG does for the clients (the server side of the backend
+/// doesn't print the requests). The backend writes them on stderr, which
+/// goes through a pipe so the lines can be filtered by interface.
+/// The lines don't say which client received them, the backend doesn't
+/// print it
+pub struct ProtocolLog {
+    filter: Arc<Mutex<Filter>>,
+}
+
+struct Filter {
+    enabled: bool,
+    // Empty means every interface
+    interfaces: Vec<String>,
+}
+
+impl Filter {
+    // Anything else written on stderr (eg. a panic) is always shown
+    fn shows(&self, line: &str) -> bool {
+        match interface(line) {
+            Some(interface) => {
+                self.enabled
+                    && (self.interfaces.is_empty()
+                        || self.interfaces.iter().any(|name| name == interface))
+            }
+            None => true,
+        }
+    }
+}
+
+// Protocol lines look like `[1234567.890] -> wl_surface@12.frame(new id wl_callback@30)`
+fn interface(line: &str) -> Option<&str> {
+    if !line.starts_with('[') {
+        return None;
+    }
+    line.split_whitespace()
+        .find_map(|word| word.split_once('@'))
+        .map(|(interface, _)| interface)
+}
+
+impl ProtocolLog {
+    /// Must be called before creating the Display, the backend
+    /// reads WAYLAND_DEBUG only when it's created
+    pub fn start(interfaces: Vec<String>) -> Result<Self> {
+        std::env::set_var("WAYLAND_DEBUG", "server");
+
+        let (read, write) = pipe()?;
+        let stderr = dup(2)?;
+        let terminal = dup(2)?;
+        dup2(write, 2)?;
+        close(write)?;
+        // A panic is printed straight on the real stderr, the thread that
+        // empties the pipe could be stopped before showing it
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let _ = dup2(terminal, 2);
+            default_hook(info);
+        }));
+        let lines = BufReader::new(unsafe { File::from_raw_fd(read) }).lines();
+        let mut stderr = unsafe { File::from_raw_fd(stderr) };
+
+        let filter = Arc::new(Mutex::new(Filter {
+            enabled: true,
+            interfaces,
+        }));
+        // The pipe is emptied by another thread, a full pipe
+        // would block the event loop while it's writing
+        let thread_filter = filter.clone();
+        thread::Builder::new()
+            .name("protocol-log".into())
+            .spawn(move || {
+                for line in lines.map_while(|line| line.ok()) {
+                    if thread_filter.lock().unwrap().shows(&line) {
+                        let _ = writeln!(stderr, "{line}");
+                    }
+                }
+            })?;
+
+        Ok(Self { filter })
+    }
+
+    /// Pause or resume the log, and change the interfaces shown
+    pub fn set(&self, enabled: bool, interfaces: Vec<String>) {
+        let mut filter = self.filter.lock().unwrap();
+        filter.enabled = enabled;
+        filter.interfaces = interfaces;
+    }
+}
@@ -0,0 +1,42 @@
+//! Server side of the protocols missing from the wayland-protocols version
+//! used by smithay, generated from the XML files in `protocols/`
+//! like wayland-protocols does for its own
+
+macro_rules! server_protocol {
+    ($name:ident, $path:literal, [$($imports:path),*]) => {
+        #[allow(dead_code, non_camel_case_types, unused_unsafe, unused_variables)]
+        #[allow(non_upper_case_globals, non_snake_case, unused_imports)]
+        #[allow(missing_docs, clippy::all)]
+        pub mod $name {
+            use smithay::reexports::wayland_server;
+            use smithay::reexports::wayland_server::protocol::*;
+            $(use $imports::{*};)*
+
+            pub mod __interfaces {
+                use smithay::reexports::wayland_server::protocol::__interfaces::*;
+                $(use $imports::{__interfaces::*};)*
+                wayland_scanner::generate_interfaces!($path);
+            }
+            use self::__interfaces::*;
+
+            wayland_scanner::generate_server_code!($path);
+        }
+    };
+}
+
+// wp-pointer-warp, staging
+server_protocol!(pointer_warp, "protocols/pointer-warp-v1.xml", []);
+// ext-workspace, staging
+server_protocol!(ext_workspace, "protocols/ext-workspace-v1.xml", []);
+// xdg-toplevel-icon, staging
+server_protocol!(
+    toplevel_icon,
+    "protocols/xdg-toplevel-icon-v1.xml",
+    [smithay::reexports::wayland_protocols::xdg::shell::server]
+);
+// xdg-toplevel-drag, staging
+server_protocol!(
+    toplevel_drag,
+    "protocols/xdg-toplevel-drag-v1.xml",
+    [smithay::reexports::wayland_protocols::xdg::shell::server]
+);
@@ -0,0 +1,193 @@
+use anyhow::{anyhow, Error};
+use smithay::reexports::{
+    nix::fcntl::{fcntl, flock, FcntlArg, FdFlag, FlockArg},
+    wayland_server::ListeningSocket,
+};
+use std::{
+    ffi::{OsStr, OsString},
+    fs::File,
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd, RawFd},
+        unix::{
+            net::{UnixListener, UnixStream},
+            process::CommandExt,
+        },
+    },
+    path::PathBuf,
+    process::Command,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use crate::{osd, state::AIGIState};
+
+// Tell the new process which wayland socket to take again, and its fd
+const RELAUNCH_SOCKET: &str = "AIGI_RELAUNCH_SOCKET";
+const RELAUNCH_SOCKET_FD: &str = "AIGI_RELAUNCH_SOCKET_FD";
+// The binding must be pressed again within this time
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Super+Shift+Escape asks for a confirmation first, pressing it
+/// again within a few seconds relaunches the compositor
+pub fn request(state: &mut AIGIState) {
+    let confirmed = state
+        .relaunch_asked
+        .is_some_and(|asked| asked.elapsed() < CONFIRM_TIMEOUT);
+    if confirmed {
+        relaunch(state);
+        return;
+    }
+    state.relaunch_asked = Some(Instant::now());
+    osd::show_text(
+        state,
+        "Relaunch aigi? Every window is closed. Press again to confirm".to_string(),
+        CONFIRM_TIMEOUT,
+    );
+}
+
+/// Stop the event loop, then the compositor is executed again (eg. after
+/// an upgrade). The listening wayland socket is handed over to the new
+/// process, the clients that connect meanwhile wait in its backlog.
+///
+/// The clients already connected are closed: their surfaces, buffers and
+/// every other object live in the memory of this process, a new one
+/// couldn't go on with their connections even if it got their fds.
+/// The DRM fd isn't handed over either: the DRM master belongs to the
+/// seat session, which ends with this process, so the new one opens the
+/// device again through its own session
+pub fn relaunch(state: &mut AIGIState) {
+    tracing::debug!("RELAUNCH");
+    state.relaunch = true;
+    state.running.store(false, Ordering::SeqCst);
+}
+
+/// The listening wayland socket, either bound by this process or
+/// received from the one that relaunched
+pub enum WaylandSocket {
+    Bound(ListeningSocket),
+    Inherited(InheritedSocket),
+}
+
+pub struct InheritedSocket {
+    listener: UnixListener,
+    name: OsString,
+    path: PathBuf,
+    // Nobody else can take the name meanwhile, like in `ListeningSocket`
+    _lock: File,
+}
+
+impl WaylandSocket {
+    /// The socket of the process that relaunched if any, otherwise a new
+    /// one named `name` (`--socket`) or the first free one
+    pub fn bind(name: Option<&str>) -> Result<Self, Error> {
+        if let Some(socket) = InheritedSocket::take()? {
+            return Ok(WaylandSocket::Inherited(socket));
+        }
+        let socket = match name {
+            Some(name) => ListeningSocket::bind(name)?,
+            None => ListeningSocket::bind_auto("wayland", 1..33)?,
+        };
+        Ok(WaylandSocket::Bound(socket))
+    }
+
+    pub fn name(&self) -> &OsStr {
+        match self {
+            WaylandSocket::Bound(socket) => socket.socket_name().unwrap_or_default(),
+            WaylandSocket::Inherited(socket) => &socket.name,
+        }
+    }
+
+    /// A client waiting to connect, if any
+    pub fn accept(&self) -> io::Result<Option<UnixStream>> {
+        match self {
+            WaylandSocket::Bound(socket) => socket.accept(),
+            WaylandSocket::Inherited(socket) => match socket.listener.accept() {
+                Ok((stream, _)) => Ok(Some(stream)),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+                Err(err) => Err(err),
+            },
+        }
+    }
+}
+
+impl AsRawFd for WaylandSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            WaylandSocket::Bound(socket) => socket.as_raw_fd(),
+            WaylandSocket::Inherited(socket) => socket.listener.as_raw_fd(),
+        }
+    }
+}
+
+impl InheritedSocket {
+    fn take() -> Result<Option<Self>, Error> {
+        let (Some(name), Some(fd)) = (
+            std::env::var_os(RELAUNCH_SOCKET),
+            std::env::var_os(RELAUNCH_SOCKET_FD),
+        ) else {
+            return Ok(None);
+        };
+        // The clients started by this process must not see them
+        std::env::remove_var(RELAUNCH_SOCKET);
+        std::env::remove_var(RELAUNCH_SOCKET_FD);
+        let fd: RawFd = fd
+            .to_str()
+            .and_then(|fd| fd.parse().ok())
+            .ok_or_else(|| anyhow!("Invalid {RELAUNCH_SOCKET_FD}: {fd:?}"))?;
+        // Nor inherit the fd
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        // SAFETY: the fd was left open for this process by `exec`
+        let listener = unsafe { UnixListener::from_raw_fd(fd) };
+        listener.set_nonblocking(true)?;
+
+        let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .ok_or_else(|| anyhow!("XDG_RUNTIME_DIR is not set"))?;
+        let path = runtime_dir.join(&name);
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock = File::options().create(true).write(true).open(&lock_path)?;
+        flock(lock.as_raw_fd(), FlockArg::LockExclusiveNonblock)
+            .map_err(|err| anyhow!("The socket {name:?} was taken meanwhile: {err}"))?;
+        tracing::debug!("RELAUNCHED ON {name:?}");
+        Ok(Some(InheritedSocket {
+            listener,
+            name,
+            path,
+            _lock: lock,
+        }))
+    }
+}
+
+impl Drop for InheritedSocket {
+    // Like `ListeningSocket`, the next compositor finds the name free
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+        let mut lock_path = self.path.clone().into_os_string();
+        lock_path.push(".lock");
+        let _ = std::fs::remove_file(lock_path);
+    }
+}
+
+/// Replace this process with a new one of the same executable and
+/// arguments, everything but the socket must be dropped before (the DRM
+/// master, the session). The socket is left open across the exec, and not
+/// dropped, its file must stay. Returns only if the exec failed
+pub fn exec(socket: &WaylandSocket) -> Error {
+    let executable = match std::env::current_exe() {
+        Ok(executable) => executable,
+        Err(err) => return anyhow!("Executable of the compositor not found: {err}"),
+    };
+    let fd = socket.as_raw_fd();
+    if let Err(err) = fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty())) {
+        return anyhow!("Impossible keep the socket open across the relaunch: {err}");
+    }
+    tracing::debug!("RELAUNCHING {executable:?}");
+    let err = Command::new(&executable)
+        .args(std::env::args_os().skip(1))
+        .env(RELAUNCH_SOCKET, socket.name())
+        .env(RELAUNCH_SOCKET_FD, fd.to_string())
+        .exec();
+    anyhow!("Impossible relaunch {executable:?}: {err}")
+}
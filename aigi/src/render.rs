@@ -1,66 +1,712 @@
-use std::time::Duration;
+use std::{
+    cell::RefCell,
+    time::{Duration, Instant},
+};
 
 use smithay::{
     backend::{
-        allocator::gbm::GbmAllocator,
+        allocator::{gbm::GbmAllocator, Fourcc},
         drm::{DrmDeviceFd, GbmBufferedSurface},
         renderer::{
             damage::OutputDamageTracker,
-            element::AsRenderElements,
+            element::{
+                solid::{SolidColorBuffer, SolidColorRenderElement},
+                surface::WaylandSurfaceRenderElement,
+                texture::{TextureBuffer, TextureRenderElement},
+                utils::RescaleRenderElement,
+                AsRenderElements, Kind,
+            },
             gles::{GlesRenderer, GlesTexture},
             multigpu::{gbm::GbmGlesBackend, MultiRenderer, MultiTexture},
-            Bind, ImportAll, ImportMem,
+            utils::import_surface_tree,
+            Bind, ImportAll, ImportMem, Renderer,
         },
     },
-    desktop::{space::SpaceRenderElements, Space, Window},
+    desktop::{layer_map_for_output, space::SpaceElement, Space, Window},
     input::{pointer::CursorImageStatus, SeatHandler},
-    output::Output,
+    output::{Mode, Output},
     reexports::calloop::timer::{TimeoutAction, Timer},
-    utils::{Logical, Point, Scale},
+    reexports::wayland_protocols::xdg::shell::server::xdg_toplevel,
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    utils::{Logical, Point, Rectangle, Scale, Size, Transform},
+    wayland::shell::wlr_layer::Layer,
 };
 
 use crate::{
+    backend::{BackendData, BackendSurface, DeviceData, WinitData},
+    decorations::{self, DecorationRenderElement, DecorationStyle},
+    ping,
     pointer::{PointerElement, PointerRenderElement},
+    power,
+    screencopy::{self, Screencopy},
     state::AIGIState,
+    status,
+    tiling::TilingState,
+    watchdog::{self, Watchdog},
 };
 
 type UdevRenderer<'a, 'b> =
     MultiRenderer<'a, 'a, 'b, GbmGlesBackend<GlesRenderer>, GbmGlesBackend<GlesRenderer>>; // size = 112 (0x70), align = 0x8
 
 smithay::backend::renderer::element::render_elements! {
-    pub OutputRenderElements<R, E> where R: ImportAll + ImportMem;
-    Space=SpaceRenderElements<R, E>,
+    pub OutputRenderElements<R> where R: ImportAll + ImportMem;
+    Window=WaylandSurfaceRenderElement<R>,
+    Letterboxed=RescaleRenderElement<WaylandSurfaceRenderElement<R>>,
+    Decoration=DecorationRenderElement<R>,
+    Custom=CustomRenderElements<R>,
+}
+
+// Elements drawn by the compositor above the windows
+smithay::backend::renderer::element::render_elements! {
+    pub CustomRenderElements<R> where R: ImportAll + ImportMem;
     Pointer=PointerRenderElement<R>,
+    Solid=SolidColorRenderElement,
 }
 
-pub fn frame_showed(state: &mut AIGIState) -> Result<(), Box<dyn std::error::Error>> {
-    // Define the previous frame as correctly submitted
+/// Rendering is event driven: a frame is rendered only when something
+/// (a commit, the pointer, the layout) could have changed the output content
+/// and at most one frame at the time is waiting to be shown
+#[derive(Default)]
+pub struct RenderState {
+    // Kept between frames, this is what let us know if the new frame
+    // is different from the previous one
+    damage_tracker: Option<OutputDamageTracker>,
+    pointer_element: Option<PointerElement<MultiTexture>>,
+    // A frame has been queued and the VBlank is not arrived yet
+    frame_pending: bool,
+    // Something changed since the last rendered frame
+    redraw_needed: bool,
+    // A render is already scheduled in the event loop
+    render_scheduled: bool,
+    // Used to hide the cursor after some time without pointer activity
+    last_pointer_activity: Option<Instant>,
+    inactivity_timer_armed: bool,
+    // Used to cap the frame rate, see `AIGIState::max_fps`
+    last_render: Option<Instant>,
+    workspace_transition: Option<WorkspaceTransition>,
+    // Black bars around letterboxed windows
+    letterbox_buffer: SolidColorBuffer,
+    // Where a dragged tile would be dropped, in global coordinates
+    drop_zone: Option<Rectangle<i32, Logical>>,
+    drop_zone_buffer: SolidColorBuffer,
+    pub watchdog: Watchdog,
+    // Last input or client commit, the periodic timers are suspended
+    // when nothing happened for `power.suspend_timers_after`
+    last_activity: Option<Instant>,
+    timers_suspended: bool,
+    // Waiting for the next frame, see `screencopy::copy_frames`
+    pub screencopies: Vec<Screencopy>,
+    // Waiting for the user to answer the permission request
+    pub screencopies_asked: Vec<Screencopy>,
+}
 
-    state
+/// A window drawn scaled instead of at the size of its buffer,
+/// see `letterbox` and `shrink_to_tile`
+pub struct Rescaled {
+    /// Where the window geometry is drawn, in global coordinates
+    pub location: Point<f64, Logical>,
+    pub scale: f64,
+}
+
+impl Rescaled {
+    /// Size of the window once drawn
+    pub fn size(&self, window: &Window) -> Size<i32, Logical> {
+        let size = window.geometry().size;
+        Size::from((
+            (size.w as f64 * self.scale).round() as i32,
+            (size.h as f64 * self.scale).round() as i32,
+        ))
+    }
+
+    /// Location in the window (in global coordinates, as if it was not scaled)
+    /// of a point drawn on the screen, None if the point is outside the window
+    pub fn unscale(
+        &self,
+        window: &Window,
+        space: &Space<Window>,
+        point: Point<f64, Logical>,
+    ) -> Option<Point<f64, Logical>> {
+        let location = space.element_location(window)?.to_f64();
+        let size = window.geometry().size.to_f64();
+        let relative = point - self.location;
+        let (x, y) = (relative.x / self.scale, relative.y / self.scale);
+        ((0.0..size.w).contains(&x) && (0.0..size.h).contains(&y))
+            .then(|| location + Point::from((x, y)))
+    }
+
+    /// Where a point of the window (in global coordinates, as if it was not
+    /// scaled) is drawn on the screen, None if the point is outside the window
+    pub fn rescale(
+        &self,
+        window: &Window,
+        space: &Space<Window>,
+        point: Point<f64, Logical>,
+    ) -> Option<Point<f64, Logical>> {
+        let location = space.element_location(window)?.to_f64();
+        let size = window.geometry().size.to_f64();
+        let relative = point - location;
+        ((0.0..size.w).contains(&relative.x) && (0.0..size.h).contains(&relative.y)).then(|| {
+            self.location + Point::from((relative.x * self.scale, relative.y * self.scale))
+        })
+    }
+}
+
+/// A fullscreen window whose size doesn't match its output (eg. a 4:3 game
+/// on a 16:9 output) is scaled to fit the output keeping its aspect ratio,
+/// centered, with black bars around it
+pub fn letterbox(space: &Space<Window>, window: &Window) -> Option<Rescaled> {
+    let fullscreen = window
+        .toplevel()
+        .current_state()
+        .states
+        .contains(xdg_toplevel::State::Fullscreen);
+    if !fullscreen {
+        return None;
+    }
+
+    let location = space.element_location(window)?;
+    let output = space.output_under(location.to_f64()).next()?;
+    let output_geometry = space.output_geometry(output)?;
+    let size = window.geometry().size;
+    if size == output_geometry.size || size.w <= 0 || size.h <= 0 {
+        return None;
+    }
+
+    let (output_w, output_h) = (output_geometry.size.w as f64, output_geometry.size.h as f64);
+    let scale = (output_w / size.w as f64).min(output_h / size.h as f64);
+    let bars = Point::from((
+        (output_w - size.w as f64 * scale) / 2.0,
+        (output_h - size.h as f64 * scale) / 2.0,
+    ));
+    Some(Rescaled {
+        location: output_geometry.loc.to_f64() + bars,
+        scale,
+    })
+}
+
+/// A tiled client that committed a buffer bigger than its tile (eg. it
+/// ignored the configure) is scaled down into the tile, instead of covering
+/// its neighbors, until it commits the size it was asked for
+pub fn shrink_to_tile(
+    space: &Space<Window>,
+    tiling_state: &TilingState,
+    window: &Window,
+) -> Option<Rescaled> {
+    tiling_state.tile_node(window.toplevel().wl_surface())?;
+    let tile = window.toplevel().with_pending_state(|state| state.size)?;
+    let size = window.geometry().size;
+    if (size.w <= tile.w && size.h <= tile.h) || tile.w <= 0 || tile.h <= 0 {
+        return None;
+    }
+
+    let scale = (tile.w as f64 / size.w as f64).min(tile.h as f64 / size.h as f64);
+    Some(Rescaled {
+        location: space.element_location(window)?.to_f64(),
+        scale,
+    })
+}
+
+const WORKSPACE_TRANSITION_DURATION: Duration = Duration::from_millis(250);
+
+// While switching workspace the old windows slide out of the output
+// and the new ones slide in from the opposite side
+struct WorkspaceTransition {
+    // Windows of the workspace that is being left, already unmapped from the space
+    leaving: Vec<(Window, Point<i32, Logical>)>,
+    // 1 if the new workspace comes from the right, -1 from the left
+    direction: i32,
+    started: Instant,
+}
+
+impl WorkspaceTransition {
+    // Progress from 0 to 1, eased out so that the slide slows down at the end
+    fn progress(&self) -> f64 {
+        let linear = (self.started.elapsed().as_secs_f64()
+            / WORKSPACE_TRANSITION_DURATION.as_secs_f64())
+        .min(1.0);
+        1.0 - (1.0 - linear).powi(3)
+    }
+
+    fn finished(&self) -> bool {
+        self.started.elapsed() >= WORKSPACE_TRANSITION_DURATION
+    }
+}
+
+/// Animate the switch from the `leaving` windows to the ones now mapped
+/// in the space, `direction` is where the new workspace comes from
+pub fn start_workspace_transition(
+    state: &mut AIGIState,
+    leaving: Vec<(Window, Point<i32, Logical>)>,
+    direction: i32,
+) {
+    if !state.animations_enabled() {
+        schedule_render(state);
+        return;
+    }
+
+    state.render_state.workspace_transition = Some(WorkspaceTransition {
+        leaving,
+        direction,
+        started: Instant::now(),
+    });
+    schedule_render(state);
+}
+
+// Premultiplied, the windows below are still visible
+const DROP_ZONE_COLOR: [f32; 4] = [0.12, 0.22, 0.36, 0.4];
+
+/// Highlight where a dragged tile would be dropped, None hides it
+pub fn set_drop_zone(state: &mut AIGIState, zone: Option<Rectangle<i32, Logical>>) {
+    if state.render_state.drop_zone != zone {
+        state.render_state.drop_zone = zone;
+        schedule_render(state);
+    }
+}
+
+/// Drop every cached cursor image, the theme will be loaded
+/// again on the next frame (eg. because the cursor theme changed)
+pub fn reload_cursor_theme(state: &mut AIGIState) {
+    state.render_state.pointer_element = None;
+    if let Some(hardware_cursor) = state
         .backend_data
-        .device_data
-        .gbm_surface
-        .frame_submitted()?;
-
-    // Here should be created a time to let the clients render their frames
-    let timer = match state.get_output()?.current_mode() {
-        Some(mode) => Timer::from_duration(Duration::from_millis(
-            ((1_000_000f32 / mode.refresh as f32) * 0.6f32) as u64,
-        )),
-        None => return Err("Mode not setted in the output".into()),
+        .device_data_mut()
+        .and_then(|device_data| device_data.hardware_cursor.as_mut())
+    {
+        hardware_cursor.invalidate();
+    }
+    schedule_render(state);
+}
+
+/// The pointer moved or a button was pressed: the cursor is shown again
+/// and the inactivity timeout restarts
+pub fn pointer_activity(state: &mut AIGIState) {
+    state.render_state.last_pointer_activity = Some(Instant::now());
+    set_cursor_hidden(state, false);
+    arm_inactivity_timer(state);
+}
+
+/// The pointer moved: if the cursor is on the cursor plane and nothing else
+/// changed, the plane is just moved and no frame is rendered. A composited
+/// cursor needs a frame, but the damage tracker repaints only the area where
+/// it was and the one where it is now
+pub fn cursor_moved(state: &mut AIGIState) {
+    if !state.render_state.redraw_needed && move_cursor_plane(state) {
+        return;
+    }
+    schedule_render(state);
+}
+
+// Returns false if the cursor is not on the cursor plane,
+// eg. it's hidden or a client set its own
+fn move_cursor_plane(state: &AIGIState) -> bool {
+    let hidden = state
+        .render_state
+        .pointer_element
+        .as_ref()
+        .map_or(true, |pointer_element| pointer_element.is_hidden());
+    if hidden || !matches!(state.cursor_status, CursorImageStatus::Default) {
+        return false;
+    }
+    let Some(output) = state.space.output_under(state.pointer_location).next() else {
+        return false;
+    };
+    let Some(output_geometry) = state.space.output_geometry(output) else {
+        return false;
+    };
+    let scale = Scale::from(output.current_scale().fractional_scale());
+    let cursor_location = (state.pointer_location - output_geometry.loc.to_f64())
+        .to_physical(scale)
+        .to_i32_round();
+
+    let Some(device_data) = state.backend_data.device_data() else {
+        return false;
     };
+    match device_data.hardware_cursor.as_ref() {
+        Some(hardware_cursor) if hardware_cursor.is_visible() => {
+            hardware_cursor.move_to(&device_data.drm, device_data.crtc, cursor_location);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// A key was pressed, the cursor is hidden if the user asked so
+pub fn keyboard_activity(state: &mut AIGIState) {
+    if state.config.cursor.hide_when_typing {
+        set_cursor_hidden(state, true);
+    }
+}
 
+// Once the cursor is hidden the damage tracker takes care of
+// damaging the area where the cursor was drawn in the previous frame
+fn set_cursor_hidden(state: &mut AIGIState, hidden: bool) {
+    let Some(pointer_element) = state.render_state.pointer_element.as_mut() else {
+        return;
+    };
+    if pointer_element.is_hidden() != hidden {
+        pointer_element.set_hidden(hidden);
+        schedule_render(state);
+    }
+}
+
+// A single timer is used, when it expires it checks if there was
+// some pointer activity in the meantime and eventually waits again
+fn arm_inactivity_timer(state: &mut AIGIState) {
+    if state.render_state.inactivity_timer_armed {
+        return;
+    }
+    let Some(timeout) = state.config.cursor.hide_after.map(Duration::from_secs) else {
+        return;
+    };
+    state.render_state.inactivity_timer_armed = true;
     state
         .handle
-        .insert_source(timer, |_, _, loop_data| {
-            render_frame(&mut loop_data.state).unwrap();
-            TimeoutAction::Drop
+        .insert_source(Timer::from_duration(timeout), |_, _, loop_data| {
+            let state = &mut loop_data.state;
+            let timeout = state.config.cursor.hide_after.map(Duration::from_secs);
+            let idle = state
+                .render_state
+                .last_pointer_activity
+                .map(|last| last.elapsed())
+                .unwrap_or_default();
+
+            match timeout {
+                Some(timeout) if idle < timeout => TimeoutAction::ToDuration(timeout - idle),
+                // the option could have been removed reloading the config
+                None => {
+                    state.render_state.inactivity_timer_armed = false;
+                    TimeoutAction::Drop
+                }
+                Some(_) => {
+                    set_cursor_hidden(state, true);
+                    state.render_state.inactivity_timer_armed = false;
+                    TimeoutAction::Drop
+                }
+            }
         })
-        .expect("failed to schedule frame timer");
+        .expect("failed to schedule cursor inactivity timer");
+}
+
+/// Input or a client commit: the periodic timers suspended
+/// while the content was static are started again
+pub fn activity(state: &mut AIGIState) {
+    state.render_state.last_activity = Some(Instant::now());
+    if !std::mem::take(&mut state.render_state.timers_suspended) {
+        return;
+    }
+    tracing::debug!("TIMERS RESUMED");
+    watchdog::arm_timer(state);
+    ping::arm_timer(state);
+    status::arm_timer(state);
+    power::arm_timer(state);
+    // The bars didn't get anything while suspended
+    status::update(state);
+}
+
+/// Nothing happened for `power.suspend_timers_after` and no frame is
+/// on its way: a periodic timer can stop, only the events wake the
+/// compositor up until the next activity (see `activity`)
+pub fn suspend_timer(state: &mut AIGIState) -> bool {
+    let render_state = &mut state.render_state;
+    let after = Duration::from_secs(state.config.power.suspend_timers_after);
+    let quiescent = !after.is_zero()
+        && !render_state.frame_pending
+        && !render_state.redraw_needed
+        && render_state
+            .last_activity
+            .is_some_and(|last| last.elapsed() >= after);
+    if quiescent && !render_state.timers_suspended {
+        tracing::debug!("TIMERS SUSPENDED: static content");
+        render_state.timers_suspended = true;
+    }
+    quiescent
+}
+
+/// Ask for a new frame, the rendering will happen as soon as the
+/// event loop is idle, or after the VBlank if a frame is already pending
+pub fn schedule_render(state: &mut AIGIState) {
+    let render_state = &mut state.render_state;
+    render_state.redraw_needed = true;
+
+    if render_state.frame_pending || render_state.render_scheduled {
+        return;
+    }
+
+    render_state.render_scheduled = true;
+    let last_render = render_state.last_render;
+
+    // With a frame rate cap the render waits until enough time
+    // has passed since the previous frame, the cap of the rendered output
+    let delay = state.max_fps().zip(last_render).and_then(|(fps, last)| {
+        let interval = Duration::from_secs(1) / fps.max(1);
+        interval.checked_sub(last.elapsed())
+    });
+
+    match delay {
+        Some(delay) => {
+            state
+                .handle
+                .insert_source(Timer::from_duration(delay), |_, _, loop_data| {
+                    loop_data.state.render_state.render_scheduled = false;
+                    render_if_needed(&mut loop_data.state);
+                    TimeoutAction::Drop
+                })
+                .expect("failed to schedule render");
+        }
+        None => {
+            state.handle.insert_idle(|loop_data| {
+                loop_data.state.render_state.render_scheduled = false;
+                render_if_needed(&mut loop_data.state);
+            });
+        }
+    }
+}
+
+// Used only if the output has no mode
+const FALLBACK_REFRESH_INTERVAL: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// Frame timing of an output, derived from the real refresh rate of its
+/// current mode, so that high refresh rate monitors are not treated as 60Hz ones
+pub struct FrameClock {
+    refresh_interval: Duration,
+    last_presentation: Option<Duration>,
+    max_fps: Option<u32>,
+}
+
+impl FrameClock {
+    /// Every output has its own clock, stored in the output user data
+    pub fn of(output: &Output) -> &RefCell<FrameClock> {
+        output.user_data().insert_if_missing(|| {
+            RefCell::new(FrameClock {
+                refresh_interval: FALLBACK_REFRESH_INTERVAL,
+                last_presentation: None,
+                max_fps: None,
+            })
+        });
+        output.user_data().get::<RefCell<FrameClock>>().unwrap()
+    }
+
+    /// The refresh of a mode is expressed in mHz
+    pub fn update_mode(&mut self, mode: Option<Mode>) {
+        self.refresh_interval = mode
+            .filter(|mode| mode.refresh > 0)
+            .map(|mode| Duration::from_nanos(1_000_000_000_000 / mode.refresh as u64))
+            .unwrap_or(FALLBACK_REFRESH_INTERVAL);
+    }
+
+    /// Repaint rate cap of the output, 0 removes it
+    pub fn set_max_fps(&mut self, fps: u32) {
+        self.max_fps = (fps > 0).then_some(fps);
+    }
+
+    pub fn max_fps(&self) -> Option<u32> {
+        self.max_fps
+    }
+
+    pub fn refresh_interval(&self) -> Duration {
+        self.refresh_interval
+    }
+
+    pub fn presented(&mut self, time: Duration) {
+        self.last_presentation = Some(time);
+    }
+
+    /// Estimation of when the next frame will hit the screen,
+    /// the presentations happen every refresh interval starting from the last one
+    pub fn next_presentation(&self, now: Duration) -> Duration {
+        let Some(last) = self.last_presentation else {
+            return now;
+        };
+
+        if now <= last {
+            return last + self.refresh_interval;
+        }
 
+        let elapsed_frames = (now - last).as_nanos() / self.refresh_interval.as_nanos();
+        last + self.refresh_interval * (elapsed_frames as u32 + 1)
+    }
+}
+
+/// The queued frame is on screen since `presented`, the time of the page flip
+/// taken by the kernel (CLOCK_MONOTONIC, like `state.clock`). Without it the
+/// time the event is handled is used, a bit later than the real one
+pub fn frame_showed(
+    state: &mut AIGIState,
+    presented: Option<Duration>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Define the previous frame as correctly submitted
+    if let Some(device_data) = state.backend_data.device_data_mut() {
+        device_data.gbm_surface.frame_submitted()?;
+    }
+    state.render_state.frame_pending = false;
+    state.render_state.watchdog.frame_shown();
+
+    let presented = presented.unwrap_or_else(|| Duration::from(state.clock.now()));
+    let output = state
+        .rendered_output()
+        .ok_or("The rendered output is not mapped")?;
+    FrameClock::of(&output).borrow_mut().presented(presented);
+
+    // Nothing waits for a timer, if something changed while the previous
+    // frame was on its way then the new one is rendered right now
+    render_if_needed(state);
+
+    Ok(())
+}
+
+/// Forget what was shown on the output and render it again from scratch,
+/// used by the watchdog after the surface or the renderer was reset
+pub fn reset(state: &mut AIGIState) {
+    state.render_state.damage_tracker = None;
+    state.render_state.pointer_element = None;
+    state.render_state.frame_pending = false;
+    schedule_render(state);
+}
+
+/// The committed buffers are imported right away in the textures the surfaces
+/// already have (they are kept in the surface state), and only the damaged
+/// regions are copied (see `ImportMemWl`). The software rendered clients
+/// (eg. the terminals) don't upload their whole buffer every frame
+pub fn import_surface(state: &mut AIGIState, surface: &WlSurface) {
+    let render_node = state.backend_data.render_node;
+    let mut renderer = match state.backend_data.gpu_manager.single_renderer(&render_node) {
+        Ok(renderer) => renderer,
+        Err(err) => {
+            println!("Surface not imported: {err:?}");
+            return;
+        }
+    };
+    if let Err(err) = import_surface_tree(&mut renderer, surface) {
+        println!("Surface not imported: {err:?}");
+    }
+}
+
+// Side of the solid color and of the texture drawn by `warm_up`
+const WARM_UP_SIZE: i32 = 16;
+
+/// The programs of the renderer (textures, solid colors) are compiled when
+/// it's created, but the drivers finish the job, and fill their shader cache
+/// on disk (eg. Mesa), only the first time something is drawn with them.
+/// A solid color and a blended texture are drawn in the next buffer of the
+/// surface, so the first real frame doesn't stutter. The buffer is not
+/// queued, the first frame is drawn from scratch over it. In a window the
+/// other compositor has already done the job
+pub fn warm_up(backend_data: &mut BackendData) -> Result<(), Box<dyn std::error::Error>> {
+    let BackendSurface::Drm { device_data, .. } = &mut backend_data.surface else {
+        return Ok(());
+    };
+    let (width, height) = device_data.gbm_surface.pending_mode().size();
+    let mut renderer = backend_data
+        .gpu_manager
+        .single_renderer(&backend_data.render_node)?;
+    let (dmabuf, _) = device_data.gbm_surface.next_buffer()?;
+    renderer.bind(dmabuf)?;
+
+    let mut solid = SolidColorBuffer::default();
+    solid.update((WARM_UP_SIZE, WARM_UP_SIZE), [0.0, 0.0, 0.0, 1.0]);
+    // Transparent, like the titles, so it's blended
+    let pixels = vec![0; (WARM_UP_SIZE * WARM_UP_SIZE * 4) as usize];
+    let texture = renderer.import_memory(
+        &pixels,
+        Fourcc::Abgr8888,
+        (WARM_UP_SIZE, WARM_UP_SIZE).into(),
+        false,
+    )?;
+    let texture = TextureBuffer::from_texture(&renderer, texture, 1, Transform::Normal, None);
+    let elements = [
+        DecorationRenderElement::Title(TextureRenderElement::from_texture_buffer(
+            (0.0, 0.0),
+            &texture,
+            None,
+            None,
+            None,
+        )),
+        decorations::solid_element(&solid, Point::default(), Scale::from(1.0)),
+    ];
+
+    let mut damage_tracker =
+        OutputDamageTracker::new((width as i32, height as i32), 1.0, Transform::Normal);
+    damage_tracker
+        .render_output(&mut renderer, 0, &elements, [0.0, 0.0, 0.0, 1.0])
+        .map_err(|_| "Impossible render the warm up frame")?;
     Ok(())
 }
 
+fn render_if_needed(state: &mut AIGIState) {
+    // While the output is off the redraw waits until it's turned on
+    if !state.render_state.redraw_needed
+        || state.render_state.frame_pending
+        || state.idle_state.display_off()
+    {
+        return;
+    }
+    state.render_state.redraw_needed = false;
+    state.render_state.last_render = Some(Instant::now());
+
+    match render_frame(state) {
+        Ok(queued) => {
+            state.render_state.frame_pending = queued;
+            if queued {
+                state.render_state.watchdog.frame_queued();
+            }
+        }
+        Err(err) => {
+            println!("An error occur during the rendering: {err}");
+            if state.render_state.watchdog.render_failed() {
+                watchdog::recover(state, &format!("rendering failed: {err}"));
+                return;
+            }
+        }
+    }
+
+    // The animation goes on with the next VBlank
+    let osd_fading = state.osd.as_ref().is_some_and(|osd| osd.fading())
+        || state.text_osd.as_ref().is_some_and(|osd| osd.fading());
+    let overview_animating = state.overview.as_ref().is_some_and(|o| o.animating());
+    if state.render_state.workspace_transition.is_some()
+        || overview_animating
+        || osd_fading
+        || state.decoration_state.animating()
+        || state.idle_state.fading()
+    {
+        schedule_render(state);
+    }
+}
+
+// Elements of the layer surfaces of an output, the first is the topmost one
+fn layer_elements<R>(
+    renderer: &mut R,
+    output: &Output,
+    layers: &[Layer],
+    scale: Scale<f64>,
+) -> Vec<WaylandSurfaceRenderElement<R>>
+where
+    R: Renderer + ImportAll,
+    <R as Renderer>::TextureId: 'static,
+{
+    let layer_map = layer_map_for_output(output);
+    layers
+        .iter()
+        .flat_map(|layer| layer_map.layers_on(*layer).rev())
+        .flat_map(|surface| {
+            let location = layer_map
+                .layer_geometry(surface)
+                .unwrap_or_default()
+                .loc
+                .to_physical_precise_round(scale);
+            surface.render_elements(renderer, location, scale, 1.0)
+        })
+        .collect()
+}
+
+// Where `render_frame` renders, borrowed from `BackendSurface`
+// along with the cursor plane
+enum FrameTarget<'a> {
+    Drm(&'a mut GbmBufferedSurface<GbmAllocator<DrmDeviceFd>, ()>),
+    Winit(&'a mut WinitData),
+}
+
+#[profiling::function]
 pub fn render_frame<'state, 'a, 'b>(
     state: &'state mut AIGIState,
     // gbm_surface: &mut GbmBufferedSurface<GbmAllocator<DrmDeviceFd>, ()>,
@@ -69,25 +715,71 @@ pub fn render_frame<'state, 'a, 'b>(
     // cursor_status: CursorImageStatus,
     // pointer_location: Point<f64, Logical>,
     // space: &Space<Window>,
-) -> Result<(), Box<dyn std::error::Error>>
+) -> Result<bool, Box<dyn std::error::Error>>
 where
     'state: 'a + 'b,
     'b: 'a,
 {
-    let gbm_surface = &mut state.backend_data.device_data.gbm_surface;
-    //let output = state.get_output()?;
-    let output = state.space.outputs().next().unwrap();
+    // Must be evaluated before borrowing pieces of the state
+    let output = state
+        .rendered_output()
+        .ok_or("The rendered output is not mapped")?;
+    let game_mode_window = state.game_mode_window();
+    let fullscreen = state.fullscreen_on(&output);
+    let focus = state
+        .seat
+        .get_keyboard()
+        .and_then(|keyboard| keyboard.current_focus());
+    let animate_borders = state.config.decorations.animate_focus && state.animations_enabled();
+
+    if state.osd.as_ref().is_some_and(|osd| osd.expired()) {
+        state.osd = None;
+    }
+    if state.text_osd.as_ref().is_some_and(|osd| osd.expired()) {
+        state.text_osd = None;
+    }
+
+    // The last frame of a transition is rendered as a normal frame
+    if let Some(transition) = state.render_state.workspace_transition.as_ref() {
+        if transition.finished() {
+            state.render_state.workspace_transition = None;
+        }
+    }
+
+    // The cursor plane exists only on the tty
+    let (mut target, mut cursor_plane) = match &mut state.backend_data.surface {
+        BackendSurface::Drm { device_data, .. } => {
+            let DeviceData {
+                drm,
+                crtc,
+                hardware_cursor,
+                gbm_surface,
+                ..
+            } = device_data;
+            (
+                FrameTarget::Drm(gbm_surface),
+                hardware_cursor
+                    .as_mut()
+                    .map(|hardware_cursor| (&*drm, *crtc, hardware_cursor)),
+            )
+        }
+        BackendSurface::Winit(winit_data) => (FrameTarget::Winit(winit_data), None),
+    };
+    let output = &output;
     let mut renderer = state
         .backend_data
         .gpu_manager
-        .single_renderer(&state.backend_data.device_data.render_node)
+        .single_renderer(&state.backend_data.render_node)
         .map_err(|_| "Impossible extract Renderer from State")?;
 
     // NOW LET'S PREPARE ALL THE ELEMENTS
     // only two sets for now, the cursor image and the one present in the Space
 
-    // An element that renders the pointer when rendering the output to display.
-    let mut pointer_element = PointerElement::<MultiTexture>::new(&mut renderer);
+    // An element that renders the pointer when rendering the output to display,
+    // the theme is loaded only once and the textures are cached
+    let pointer_element = state.render_state.pointer_element.get_or_insert_with(|| {
+        PointerElement::new(&state.config.cursor.theme(), state.config.cursor.size())
+    });
 
     // Update the pointer element with the clock to determine which xcursor image to show,
     // and the cursor status. The status can be set to a surface by a window to show a
@@ -95,52 +787,509 @@ where
     //pointer_element.set_current_delay(&state.clock);
     pointer_element.set_status(state.cursor_status.clone());
 
-    // Get the cursor position if the output is fractionally scaled.
+    // Every location and size (windows, tiles, layers, cursor) is logical,
+    // it becomes physical only here with the scale of the output.
+    // Surfaces with a buffer scale are sized by smithay in logical
+    // coordinates too, so a client drawing at the scale of the output
+    // is shown pixel for pixel
     let scale = Scale::from(output.current_scale().fractional_scale());
+    // The cursor image is picked for the scale of the output
+    pointer_element.set_scale(&mut renderer, output.current_scale().integer_scale());
     //let cursor_pos = pointer_location;
     //let cursor_pos_scaled = cursor_pos.to_physical(scale).to_i32_round();
 
-    // Get the rendered elements from the pointer element.
-    let custom_elements = pointer_element
-        .render_elements::<PointerRenderElement<UdevRenderer<'a, 'b>>>(
-            &mut renderer,
-            //cursor_pos_scaled,
-            state.pointer_location.to_physical(1.0).to_i32_round(),
-            scale,
-            1.0,
+    // The pointer location is in global layout coordinates,
+    // the cursor is drawn only on the output it is over,
+    // relative to the origin of that output
+    let output_geometry = state
+        .space
+        .output_geometry(output)
+        .ok_or("Output not mapped in the Space")?;
+
+    let cursor_visible =
+        !pointer_element.is_hidden() && output_geometry.to_f64().contains(state.pointer_location);
+    let mut custom_elements: Vec<CustomRenderElements<UdevRenderer<'a, 'b>>> = if cursor_visible {
+        let cursor_location = (state.pointer_location - output_geometry.loc.to_f64())
+            .to_physical(scale)
+            .to_i32_round();
+
+        // The theme cursor is put on the cursor plane when possible, client
+        // cursors and images refused by the plane are composited in the frame
+        let on_cursor_plane = match (
+            &state.cursor_status,
+            pointer_element.current_image(),
+            cursor_plane.as_mut(),
+        ) {
+            (
+                CursorImageStatus::Default,
+                Some((image, image_scale)),
+                Some((drm, crtc, hardware_cursor)),
+            ) => {
+                let shown = hardware_cursor.show(drm, *crtc, image, image_scale);
+                if shown {
+                    hardware_cursor.move_to(drm, *crtc, cursor_location);
+                }
+                shown
+            }
+            _ => false,
+        };
+
+        if on_cursor_plane {
+            vec![]
+        } else {
+            if let Some((drm, crtc, hardware_cursor)) = cursor_plane.as_mut() {
+                hardware_cursor.hide(drm, *crtc);
+            }
+
+            // Get the rendered elements from the pointer element.
+            pointer_element
+                .render_elements::<PointerRenderElement<UdevRenderer<'a, 'b>>>(
+                    &mut renderer,
+                    cursor_location,
+                    scale,
+                    1.0,
+                )
+                .into_iter()
+                .map(CustomRenderElements::from)
+                .collect()
+        }
+    } else {
+        if let Some((drm, crtc, hardware_cursor)) = cursor_plane.as_mut() {
+            hardware_cursor.hide(drm, *crtc);
+        }
+        vec![]
+    };
+
+    // The OSD stays below the cursor, but it's not an effect worth
+    // showing over a game
+    if let (Some(osd), None) = (state.osd.as_mut(), game_mode_window.as_ref()) {
+        custom_elements.extend(
+            osd.render_elements(output_geometry.size, scale)
+                .into_iter()
+                .map(CustomRenderElements::from),
         );
+    }
 
-    let (dmabuf, age) = gbm_surface.next_buffer()?;
+    // Above the windows, the dragged one too
+    if let Some(zone) = state.render_state.drop_zone {
+        let buffer = &mut state.render_state.drop_zone_buffer;
+        buffer.update(zone.size, DROP_ZONE_COLOR);
+        custom_elements.push(CustomRenderElements::from(
+            SolidColorRenderElement::from_buffer(
+                buffer,
+                (zone.loc - output_geometry.loc).to_physical_precise_round(scale),
+                scale,
+                1.0,
+                Kind::Unspecified,
+            ),
+        ));
+    }
+
+    // The dimming covers everything, the cursor too, but never a game
+    let dim_level = state.config.idle.dim_level;
+    if let (None, Some(dim)) = (
+        game_mode_window.as_ref(),
+        state
+            .idle_state
+            .render_element(output_geometry.size, scale, dim_level),
+    ) {
+        custom_elements.insert(0, CustomRenderElements::from(dim));
+    }
+
+    let (dmabuf, age) = match &mut target {
+        FrameTarget::Drm(gbm_surface) => gbm_surface.next_buffer()?,
+        FrameTarget::Winit(winit_data) => winit_data.next_buffer()?,
+    };
     renderer.bind(dmabuf)?;
+    // The screencopy clients need the whole frame, not only what changed
+    let screencopies = std::mem::take(&mut state.render_state.screencopies);
+    let age = match screencopies.is_empty() {
+        true => age,
+        false => 0,
+    };
+
+    // The same damage tracker is used for every frame, otherwise
+    // it would not be possible to know what changed since the last one
+    let damage_tracker = state
+        .render_state
+        .damage_tracker
+        .get_or_insert_with(|| OutputDamageTracker::from_output(output));
+
+    let game_mode = game_mode_window.is_some();
+    // Windows to draw from the bottom to the top, with their location relative
+    // to the output, and if the layer surfaces are drawn around them
+    let (windows, with_layers, clear_color) = match (
+        game_mode_window,
+        state.render_state.workspace_transition.as_ref(),
+    ) {
+        // Game mode: the focused fullscreen window covers everything,
+        // so only it and the cursor are processed
+        (Some(window), _) => {
+            let location = state.space.element_location(&window).unwrap_or_default();
+            (
+                vec![(window, location - output_geometry.loc)],
+                false,
+                [0.0, 0.0, 0.0, 1.0],
+            )
+        }
+        // Both workspaces are drawn, shifted by the progress of the slide,
+        // the layer surfaces are skipped until the transition ends
+        (None, Some(transition)) => {
+            let width = output_geometry.size.w as f64;
+            let progress = transition.progress();
+            let direction = transition.direction as f64;
+            let leaving_offset = (-direction * progress * width).round() as i32;
+            let entering_offset = (direction * (1.0 - progress) * width).round() as i32;
+
+            // The sticky windows don't slide, they stay above both workspaces
+            let space = &state.space;
+            let (sticky, entering): (Vec<_>, Vec<_>) = space
+                .elements()
+                .partition(|w| state.floating_state.is_sticky(w.toplevel().wl_surface()));
+            let located = |windows: Vec<&Window>, offset: i32| {
+                windows.into_iter().map(move |w| {
+                    let location = space.element_location(w).unwrap_or_default();
+                    (w.clone(), location, offset)
+                })
+            };
+            let leaving = transition
+                .leaving
+                .iter()
+                .map(|(window, location)| (window.clone(), *location, leaving_offset));
+            let windows = located(entering, entering_offset)
+                .chain(leaving)
+                .chain(located(sticky, 0))
+                .map(|(window, location, offset)| {
+                    (window, location - output_geometry.loc + (offset, 0).into())
+                })
+                .collect::<Vec<_>>();
+            (windows, false, [0.1, 0.1, 0.1, 1.0])
+        }
+        // The layer surfaces (eg. the bars) are hidden by the overview
+        (None, None) => {
+            let windows = state
+                .space
+                .elements()
+                .map(|w| {
+                    let location = state.space.element_location(w).unwrap_or_default();
+                    (w.clone(), location - output_geometry.loc)
+                })
+                .collect::<Vec<_>>();
+            (windows, state.overview.is_none(), [0.1, 0.1, 0.1, 1.0])
+        }
+    };
+
+    // Everything that will be drawn, from the topmost element
+    let elements = {
+        profiling::scope!("collect_elements");
+        let mut elements: Vec<OutputRenderElements<UdevRenderer<'a, 'b>>> = custom_elements
+            .into_iter()
+            .map(OutputRenderElements::from)
+            .collect();
+
+        // The dialog is modal, it covers everything but the cursor
+        if let Some(dialog) = state.force_close.as_mut() {
+            elements.extend(
+                dialog
+                    .render_elements(&mut renderer, output_geometry.size, scale)
+                    .into_iter()
+                    .map(OutputRenderElements::from),
+            );
+        }
+
+        // Above the windows and the bars, not over a game
+        if let (Some(osd), false) = (state.text_osd.as_mut(), game_mode) {
+            let font = state.decoration_state.font(&state.config.decorations);
+            elements.extend(
+                osd.render_elements(
+                    &mut renderer,
+                    font,
+                    state.config.decorations.font_size,
+                    output_geometry.size,
+                    scale,
+                )
+                .into_iter()
+                .map(OutputRenderElements::from),
+            );
+        }
+
+        // A fullscreen window covers the panels, not the overlays (eg. a lock screen)
+        if with_layers {
+            let layers = match fullscreen {
+                true => [Layer::Overlay].as_slice(),
+                false => [Layer::Overlay, Layer::Top].as_slice(),
+            };
+            elements.extend(layer_elements(&mut renderer, output, layers, scale));
+        }
+
+        // The first render element is the topmost one, every
+        // window is drawn above its own decorations
+        for (window, location) in windows.iter().rev() {
+            let in_overview = state
+                .overview
+                .as_ref()
+                .and_then(|overview| overview.rescaled(&state.space, window));
+            let is_in_overview = in_overview.is_some();
+            let letterboxed = letterbox(&state.space, window).filter(|_| !is_in_overview);
+            let is_letterboxed = letterboxed.is_some();
+            let rescaled = in_overview
+                .or(letterboxed)
+                .or_else(|| shrink_to_tile(&state.space, &state.tiling_state, window));
+
+            // Size of the client area once drawn, the decorations go around it
+            let mut size = window.geometry().size;
+            if let Some(rescaled) = rescaled {
+                // Moved like the window during the workspace transitions
+                let space_location = state.space.element_location(window).unwrap_or_default();
+                let offset = *location - (space_location - output_geometry.loc);
+                let origin = rescaled.location - output_geometry.loc.to_f64() + offset.to_f64();
+                size = rescaled.size(window);
 
-    // insered just because I can't do without
-    let mut damage_tracker = OutputDamageTracker::from_output(&output);
+                // The window is drawn at its size and then scaled around
+                // the top left corner of its geometry
+                let surface_location =
+                    (origin - window.geometry().loc.to_f64()).to_physical_precise_round(scale);
+                elements.extend(
+                    window
+                        .render_elements::<WaylandSurfaceRenderElement<_>>(
+                            &mut renderer,
+                            surface_location,
+                            scale,
+                            1.0,
+                        )
+                        .into_iter()
+                        .map(|element| {
+                            RescaleRenderElement::from_element(
+                                element,
+                                origin.to_physical_precise_round(scale),
+                                rescaled.scale,
+                            )
+                        })
+                        .map(OutputRenderElements::from),
+                );
 
-    smithay::desktop::space::render_output::<_, PointerRenderElement<UdevRenderer<'a, 'b>>, _, _>(
-        &output,
-        &mut renderer,
-        1.0,
-        0,
-        [&state.space],
-        custom_elements.as_slice(),
-        &mut damage_tracker,
-        [0.1, 0.1, 0.1, 1.0],
-    )
-    .map_err(|_| "Impossible render Space")?;
+                // The bars cover the output, below the window
+                if is_letterboxed {
+                    state
+                        .render_state
+                        .letterbox_buffer
+                        .update(output_geometry.size, [0.0, 0.0, 0.0, 1.0]);
+                    elements.push(OutputRenderElements::from(CustomRenderElements::from(
+                        SolidColorRenderElement::from_buffer(
+                            &state.render_state.letterbox_buffer,
+                            offset.to_physical_precise_round(scale),
+                            scale,
+                            1.0,
+                            Kind::Unspecified,
+                        ),
+                    )));
+                    continue;
+                }
 
-    gbm_surface.queue_buffer(None, None, ()).unwrap();
+                // Thumbnails have no decorations, the selected one is highlighted
+                if is_in_overview {
+                    let overview = state.overview.as_mut().expect("IMP checked above");
+                    if let Some(highlight) = overview.highlight_element(window, origin, size, scale)
+                    {
+                        elements.push(OutputRenderElements::from(CustomRenderElements::from(
+                            highlight,
+                        )));
+                    }
+                    continue;
+                }
+            } else {
+                // The window geometry does not include the client side shadows
+                let surface_location =
+                    (*location - window.geometry().loc).to_physical_precise_round(scale);
+                elements.extend(window.render_elements(
+                    &mut renderer,
+                    surface_location,
+                    scale,
+                    1.0,
+                ));
+            }
+
+            let wl_surface = window.toplevel().wl_surface();
+            let status = state.decoration_state.status(wl_surface, focus.as_ref());
+            let style = DecorationStyle {
+                status,
+                border: state.border_width(window, status),
+                animate: animate_borders,
+            };
+            elements.extend(
+                state
+                    .decoration_state
+                    .render_elements(
+                        &mut renderer,
+                        &state.config.decorations,
+                        window,
+                        *location,
+                        size,
+                        scale,
+                        style,
+                    )
+                    .into_iter()
+                    .map(OutputRenderElements::from),
+            );
+        }
+
+        // Above their tiles, so below every window. They don't follow
+        // the windows in the overview and while switching workspace
+        let tabs_shown = !game_mode
+            && state.overview.is_none()
+            && state.render_state.workspace_transition.is_none();
+        if tabs_shown {
+            let strips = state.tiling_state.tab_strips();
+            let font = state.decoration_state.font(&state.config.decorations);
+            elements.extend(
+                state
+                    .tab_state
+                    .render_elements(
+                        &mut renderer,
+                        &state.config.decorations,
+                        font,
+                        &strips,
+                        output_geometry.loc,
+                        scale,
+                    )
+                    .into_iter()
+                    .map(OutputRenderElements::from),
+            );
+        }
+
+        if with_layers {
+            elements.extend(layer_elements(
+                &mut renderer,
+                output,
+                &[Layer::Bottom, Layer::Background],
+                scale,
+            ));
+        }
+        elements
+    };
+
+    let damage = {
+        profiling::scope!("render_output");
+        damage_tracker
+            .render_output(&mut renderer, age.into(), &elements, clear_color)
+            .map_err(|_| "Impossible render output")?
+            .0
+    };
+    if !screencopies.is_empty() {
+        profiling::scope!("screencopy");
+        let time = Duration::from(state.clock.now());
+        screencopy::copy_frames(&mut renderer, screencopies, time);
+    }
+
+    // Nothing changed on screen, there is no reason to queue the
+    // buffer and wait for a VBlank.
+    //
+    // The damage goes along with the buffer (FB_DAMAGE_CLIPS), panels with
+    // PSR can then update only the changed region. It's the damage of the
+    // buffer since it was last used (see its age), so it covers everything
+    // that changed since the previous frame too
+    //
+    // In a window there is no VBlank, the frame is drawn in the window right
+    // away and it's considered shown
+    let queued = match (damage, &mut target) {
+        (Some(damage), FrameTarget::Drm(gbm_surface)) => {
+            profiling::scope!("queue_buffer");
+            gbm_surface.queue_buffer(None, Some(damage), ())?;
+            true
+        }
+        (Some(_), FrameTarget::Winit(winit_data)) => {
+            winit_data.present()?;
+            FrameClock::of(output)
+                .borrow_mut()
+                .presented(Duration::from(state.clock.now()));
+            false
+        }
+        (None, _) => false,
+    };
+    profiling::finish_frame!();
 
     // TODO: is this important?
     // For each of the windows send the frame callbacks to windows telling them to draw
     // the new frame.
+    //
+    // Clients get the estimated presentation time of the frame and
+    // windows not visible are throttled to the refresh rate of the output
+    let (presentation_time, refresh_interval) = {
+        let mut frame_clock = FrameClock::of(output).borrow_mut();
+        frame_clock.update_mode(output.current_mode());
+        (
+            frame_clock.next_presentation(Duration::from(state.clock.now())),
+            frame_clock.refresh_interval(),
+        )
+    };
     state.space.elements().for_each(|window| {
         window.send_frame(
             &output,
-            state.clock.now(),
-            Some(core::time::Duration::ZERO),
+            presentation_time,
+            Some(refresh_interval),
             |_, _| Some(output.clone()),
         )
     });
+    for layer in layer_map_for_output(output).layers() {
+        layer.send_frame(output, presentation_time, Some(refresh_interval), |_, _| {
+            Some(output.clone())
+        });
+    }
 
-    Ok(())
+    Ok(queued)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    // A 100 Hz output that showed a frame at `last`
+    fn clock(last: Option<Duration>) -> FrameClock {
+        FrameClock {
+            refresh_interval: ms(10),
+            last_presentation: last,
+            max_fps: None,
+        }
+    }
+
+    #[test]
+    fn next_presentation_is_now_before_the_first_frame() {
+        assert_eq!(clock(None).next_presentation(ms(123)), ms(123));
+    }
+
+    #[test]
+    fn next_presentation_follows_the_last_one_if_now_is_not_after_it() {
+        let clock = clock(Some(ms(100)));
+        assert_eq!(clock.next_presentation(ms(100)), ms(110));
+        assert_eq!(clock.next_presentation(ms(95)), ms(110));
+    }
+
+    #[test]
+    fn next_presentation_skips_the_elapsed_frames() {
+        let clock = clock(Some(ms(100)));
+        assert_eq!(clock.next_presentation(ms(101)), ms(110));
+        assert_eq!(clock.next_presentation(ms(125)), ms(130));
+        // On a refresh the frame is already gone, the next one is targeted
+        assert_eq!(clock.next_presentation(ms(130)), ms(140));
+        assert_eq!(clock.next_presentation(ms(1_005)), ms(1_010));
+    }
+
+    #[test]
+    fn refresh_interval_comes_from_the_mode() {
+        let mut clock = clock(None);
+        clock.update_mode(Some(Mode {
+            size: (1920, 1080).into(),
+            refresh: 144_000,
+        }));
+        assert_eq!(clock.refresh_interval(), Duration::from_nanos(6_944_444));
+        clock.update_mode(Some(Mode {
+            size: (1920, 1080).into(),
+            refresh: 0,
+        }));
+        assert_eq!(clock.refresh_interval(), FALLBACK_REFRESH_INTERVAL);
+    }
 }
@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+use smithay::{desktop::Window, reexports::wayland_server::protocol::wl_surface::WlSurface};
+use std::{
+    ops::Range,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{self, TilingConfig},
+    decorations,
+    state::AIGIState,
+    tiling::{Node, Split, TilingError},
+};
+
+const LAYOUT_FILE: &str = "layout.json";
+// Windows opened later are tiled as usual, even if their app id is in the tree
+const RESTORE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Tiling tree of the active workspace, saved on exit with the app ids
+/// of the windows in place of the windows
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SavedNode {
+    Tile {
+        app_id: String,
+    },
+    Structure {
+        split: Split,
+        ratio: f32,
+        left: Box<SavedNode>,
+        right: Box<SavedNode>,
+    },
+}
+
+impl SavedNode {
+    // App ids of the tiles, from the left (or top) one
+    fn app_ids(&self) -> Vec<&str> {
+        match self {
+            SavedNode::Tile { app_id } => vec![app_id],
+            SavedNode::Structure { left, right, .. } => {
+                let mut app_ids = left.app_ids();
+                app_ids.extend(right.app_ids());
+                app_ids
+            }
+        }
+    }
+
+    fn tile_count(&self) -> usize {
+        match self {
+            SavedNode::Tile { .. } => 1,
+            SavedNode::Structure { left, right, .. } => left.tile_count() + right.tile_count(),
+        }
+    }
+}
+
+/// The tree saved in the previous session, filled as the windows with
+/// its app ids appear. Best effort: the windows are put back next to the
+/// ones already there, in the same order, with the saved split and ratio
+pub struct LayoutRestore {
+    tree: SavedNode,
+    // Window put in each tile of the tree, in the order of `app_ids`
+    placed: Vec<Option<WlSurface>>,
+    started: Instant,
+}
+
+/// The tree saved in the previous session, if `tiling.restore_layout`
+pub fn load(config: &TilingConfig) -> Option<LayoutRestore> {
+    if !config.restore_layout {
+        return None;
+    }
+    let file = std::fs::read_to_string(config::state_path(LAYOUT_FILE)?).ok()?;
+    match serde_json::from_str::<SavedNode>(&file) {
+        Ok(tree) => {
+            tracing::debug!("RESTORING THE LAYOUT: {:?}", tree.app_ids());
+            Some(LayoutRestore {
+                placed: vec![None; tree.tile_count()],
+                tree,
+                started: Instant::now(),
+            })
+        }
+        Err(err) => {
+            println!("Saved layout not restored: {err}");
+            None
+        }
+    }
+}
+
+/// Save the tiles of the active workspace, without tiles
+/// there is nothing to restore and the old file is removed
+pub fn save(state: &AIGIState) {
+    if !state.config.tiling.restore_layout {
+        return;
+    }
+    let Some(path) = config::state_path(LAYOUT_FILE) else {
+        return;
+    };
+    let result = match state.tiling_state.save() {
+        Some(tree) => write(&path, &tree),
+        None => {
+            let _ = std::fs::remove_file(&path);
+            Ok(())
+        }
+    };
+    if let Err(err) = result {
+        println!("Layout not saved: {err}");
+    }
+}
+
+fn write(path: &Path, tree: &SavedNode) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(tree)?)?;
+    Ok(())
+}
+
+// Structures above the tile `index`, from the closest one: their split,
+// their ratio, if the tile is on their left (or top) and the tiles
+// on the other side
+fn ancestors(tree: &SavedNode, index: usize) -> Vec<(Split, f32, bool, Range<usize>)> {
+    let mut ancestors = vec![];
+    let mut node = tree;
+    let mut offset = 0;
+    while let SavedNode::Structure {
+        split,
+        ratio,
+        left,
+        right,
+    } = node
+    {
+        let middle = offset + left.tile_count();
+        if index < middle {
+            ancestors.push((*split, *ratio, true, middle..middle + right.tile_count()));
+            node = left;
+        } else {
+            ancestors.push((*split, *ratio, false, offset..middle));
+            offset = middle;
+            node = right;
+        }
+    }
+    ancestors.reverse();
+    ancestors
+}
+
+/// A window with an app id of the saved tree splits the subtree of the
+/// windows already placed on the other side of its closest Structure.
+/// Returns the node to be laid out, None if the window is tiled as usual
+/// (eg. it's the first one, or its app id is not in the tree)
+pub fn place(state: &mut AIGIState, window: &Window) -> Option<Result<Node, TilingError>> {
+    let restore = state.layout_restore.as_mut()?;
+    if restore.started.elapsed() > RESTORE_TIMEOUT {
+        state.layout_restore = None;
+        return None;
+    }
+
+    let app_id = decorations::app_id(window);
+    let index = restore
+        .tree
+        .app_ids()
+        .iter()
+        .zip(&restore.placed)
+        .position(|(saved, placed)| *saved == app_id && placed.is_none())?;
+    restore.placed[index] = Some(window.toplevel().wl_surface().clone());
+
+    // The windows could have been closed or moved in the meantime
+    let mut result = None;
+    for (split, ratio, first, others) in ancestors(&restore.tree, index) {
+        let others = &restore.placed[others];
+        let inside = |wl_surface: &WlSurface| others.iter().flatten().any(|s| s == wl_surface);
+        let Some(anchor) = others
+            .iter()
+            .flatten()
+            .find(|wl_surface| state.tiling_state.tile_node(wl_surface).is_some())
+        else {
+            continue;
+        };
+        let Some(node) = state
+            .tiling_state
+            .enclosing(anchor, |w| inside(w.toplevel().wl_surface()))
+        else {
+            continue;
+        };
+        tracing::debug!("WINDOW {app_id} BACK IN ITS SAVED TILE");
+        result = Some(
+            state
+                .tiling_state
+                .split_node(node, window.clone(), split, first, ratio),
+        );
+        break;
+    }
+
+    if restore.placed.iter().all(Option::is_some) {
+        tracing::debug!("LAYOUT RESTORED");
+        state.layout_restore = None;
+    }
+    result
+}
@@ -0,0 +1,278 @@
+use smithay::{
+    backend::{allocator::Fourcc, renderer::ExportMem},
+    output::Output,
+    reexports::{
+        wayland_protocols_wlr::screencopy::v1::server::{
+            zwlr_screencopy_frame_v1::{self, Flags, ZwlrScreencopyFrameV1},
+            zwlr_screencopy_manager_v1::{self, ZwlrScreencopyManagerV1},
+        },
+        wayland_server::{
+            protocol::{wl_buffer::WlBuffer, wl_shm},
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+        },
+    },
+    utils::{Buffer, Logical, Physical, Rectangle},
+    wayland::shm,
+};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    permissions::{Permission, Permissions},
+    render::schedule_render,
+    state::AIGIState,
+};
+
+const VERSION: u32 = 3;
+
+/// Advertised only to the clients allowed by the permissions
+pub struct ScreencopyGlobalData {
+    filter: Box<dyn Fn(&Client) -> bool + Send + Sync>,
+}
+
+pub struct FrameData {
+    // The part of the output it captures, in pixels of the output. None
+    // if the output or the region are not valid, the copy then fails
+    region: Option<Rectangle<i32, Physical>>,
+    // A frame is copied only once
+    copied: AtomicBool,
+}
+
+/// A copy waiting for the next frame of the output, see `copy_frames`
+pub struct Screencopy {
+    frame: ZwlrScreencopyFrameV1,
+    buffer: WlBuffer,
+    region: Rectangle<i32, Physical>,
+    with_damage: bool,
+}
+
+/// wlr-screencopy, used by the screenshot and recording tools (eg. grim).
+/// Only shm buffers are supported, the frame is copied from the buffer
+/// just rendered for the output. The cursor is in the copy only when it's
+/// composited in the frame, not when it's on the cursor plane
+pub fn create_global(display: &DisplayHandle, permissions: &Permissions) {
+    display.create_global::<AIGIState, ZwlrScreencopyManagerV1, _>(
+        VERSION,
+        ScreencopyGlobalData {
+            filter: Box::new(permissions.filter(Permission::Screencopy)),
+        },
+    );
+}
+
+impl GlobalDispatch<ZwlrScreencopyManagerV1, ScreencopyGlobalData> for AIGIState {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        manager: New<ZwlrScreencopyManagerV1>,
+        _global_data: &ScreencopyGlobalData,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(manager, ());
+    }
+
+    fn can_view(client: Client, global_data: &ScreencopyGlobalData) -> bool {
+        (global_data.filter)(&client)
+    }
+}
+
+impl Dispatch<ZwlrScreencopyManagerV1, ()> for AIGIState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _manager: &ZwlrScreencopyManagerV1,
+        request: zwlr_screencopy_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let (frame, output, region) = match request {
+            zwlr_screencopy_manager_v1::Request::CaptureOutput { frame, output, .. } => {
+                (frame, output, None)
+            }
+            zwlr_screencopy_manager_v1::Request::CaptureOutputRegion {
+                frame,
+                output,
+                x,
+                y,
+                width,
+                height,
+                ..
+            } => (
+                frame,
+                output,
+                Some(Rectangle::from_loc_and_size((x, y), (width, height))),
+            ),
+            _ => return,
+        };
+
+        let region =
+            Output::from_resource(&output).and_then(|output| capture_region(&output, region));
+        let frame = data_init.init(
+            frame,
+            FrameData {
+                region,
+                copied: AtomicBool::new(false),
+            },
+        );
+        let Some(region) = region else {
+            frame.failed();
+            return;
+        };
+        let (width, height) = (region.size.w as u32, region.size.h as u32);
+        frame.buffer(wl_shm::Format::Argb8888, width, height, width * 4);
+        if frame.version() >= 3 {
+            frame.buffer_done();
+        }
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, FrameData> for AIGIState {
+    fn request(
+        state: &mut Self,
+        client: &Client,
+        frame: &ZwlrScreencopyFrameV1,
+        request: zwlr_screencopy_frame_v1::Request,
+        data: &FrameData,
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let (buffer, with_damage) = match request {
+            zwlr_screencopy_frame_v1::Request::Copy { buffer } => (buffer, false),
+            zwlr_screencopy_frame_v1::Request::CopyWithDamage { buffer } => (buffer, true),
+            _ => return,
+        };
+        if data.copied.swap(true, Ordering::Relaxed) {
+            frame.post_error(
+                zwlr_screencopy_frame_v1::Error::AlreadyUsed,
+                "the frame was already copied",
+            );
+            return;
+        }
+        let Some(region) = data.region else {
+            frame.failed();
+            return;
+        };
+
+        // Only a buffer like the one advertised
+        let fits = shm::with_buffer_contents(&buffer, |_, _, info| {
+            info.format == wl_shm::Format::Argb8888
+                && info.width == region.size.w
+                && info.height == region.size.h
+                && info.stride == region.size.w * 4
+        })
+        .unwrap_or(false);
+        if !fits {
+            frame.post_error(
+                zwlr_screencopy_frame_v1::Error::InvalidBuffer,
+                "the buffer is not the one advertised",
+            );
+            return;
+        }
+
+        let copy = Screencopy {
+            frame: frame.clone(),
+            buffer,
+            region,
+            with_damage,
+        };
+        // The global was advertised, but the permission could have been
+        // revoked (or removed from the config) since then
+        match state.permissions.decide(client, Permission::Screencopy) {
+            Some(true) => queue(state, copy),
+            Some(false) => copy.frame.failed(),
+            None => state.render_state.screencopies_asked.push(copy),
+        }
+    }
+}
+
+// Copied from the next frame, even if nothing changed on the output
+fn queue(state: &mut AIGIState, copy: Screencopy) {
+    state.render_state.screencopies.push(copy);
+    schedule_render(state);
+}
+
+/// The user answered a permission request, the copies waiting
+/// for it are done or fail
+pub fn permission_answered(state: &mut AIGIState) {
+    for copy in std::mem::take(&mut state.render_state.screencopies_asked) {
+        let Some(client) = copy.frame.client() else {
+            continue;
+        };
+        match state.permissions.decide(&client, Permission::Screencopy) {
+            Some(true) => queue(state, copy),
+            Some(false) => copy.frame.failed(),
+            None => state.render_state.screencopies_asked.push(copy),
+        }
+    }
+}
+
+// The whole output without a region, the region is clamped to the output
+fn capture_region(
+    output: &Output,
+    region: Option<Rectangle<i32, Logical>>,
+) -> Option<Rectangle<i32, Physical>> {
+    let mode = output.current_mode()?;
+    let size = output.current_transform().transform_size(mode.size);
+    let whole = Rectangle::from_loc_and_size((0, 0), size);
+    match region {
+        Some(region) => region
+            .to_physical_precise_round(output.current_scale().fractional_scale())
+            .intersection(whole),
+        None => Some(whole),
+    }
+}
+
+/// The frame just rendered, still bound to the renderer, is copied in the
+/// buffers of the waiting clients. `time` is the monotonic time of the frame
+pub fn copy_frames<R: ExportMem>(renderer: &mut R, copies: Vec<Screencopy>, time: Duration) {
+    for copy in copies {
+        if !copy.frame.is_alive() {
+            continue;
+        }
+        let region = Rectangle::<i32, Buffer>::from_loc_and_size(
+            (copy.region.loc.x, copy.region.loc.y),
+            (copy.region.size.w, copy.region.size.h),
+        );
+        let pixels = match renderer
+            .copy_framebuffer(region, Fourcc::Argb8888)
+            .and_then(|mapping| renderer.map_texture(&mapping).map(<[u8]>::to_vec))
+        {
+            Ok(pixels) => pixels,
+            Err(err) => {
+                println!("Screencopy failed: {err:?}");
+                copy.frame.failed();
+                continue;
+            }
+        };
+
+        let row = copy.region.size.w as usize * 4;
+        let written = shm::with_buffer_contents_mut(&copy.buffer, |ptr, len, info| {
+            // SAFETY: the pool is mapped for `len` bytes while the closure runs
+            let contents = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
+            for (y, line) in pixels.chunks_exact(row).enumerate() {
+                let start = info.offset as usize + y * info.stride as usize;
+                let Some(target) = contents.get_mut(start..start + row) else {
+                    break;
+                };
+                target.copy_from_slice(line);
+            }
+        });
+        if let Err(err) = written {
+            println!("Screencopy not written: {err:?}");
+            copy.frame.failed();
+            continue;
+        }
+
+        copy.frame.flags(Flags::empty());
+        if copy.with_damage {
+            copy.frame
+                .damage(0, 0, copy.region.size.w as u32, copy.region.size.h as u32);
+        }
+        let seconds = time.as_secs();
+        copy.frame
+            .ready((seconds >> 32) as u32, seconds as u32, time.subsec_nanos());
+    }
+}
@@ -0,0 +1,221 @@
+use rhai::{Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST};
+use smithay::desktop::Window;
+use std::{cell::RefCell, collections::HashSet, path::Path, rc::Rc};
+
+use crate::{decorations, state::AIGIState, workspaces::WORKSPACE_COUNT};
+
+// The hooks run on the event loop, a script looping (or recursing) forever
+// would freeze the compositor. They are stopped at these limits, in the
+// order of a few milliseconds of work
+const MAX_OPERATIONS: u64 = 100_000;
+const MAX_CALL_LEVELS: usize = 32;
+
+/// What a script asks to the compositor, applied once the hook returns.
+/// The window actions apply to the window of the event (eg. the one just
+/// opened) or to the focused window for the other events
+#[derive(Debug)]
+enum ScriptAction {
+    Spawn(String),
+    SwitchWorkspace(usize),
+    MoveToWorkspace(usize),
+    ToggleFloating,
+    ToggleMaximize,
+}
+
+/// User script (`init.rhai` next to the config file) whose functions are
+/// called on the compositor events, eg.
+/// ```rhai
+/// fn on_window_opened(window) {
+///     if window.app_id == "Spotify" {
+///         move_to_workspace(8);
+///     }
+/// }
+/// ```
+pub struct Scripting {
+    engine: Engine,
+    ast: AST,
+    // Variables set by the top level statements, visible to the hooks
+    scope: Scope<'static>,
+    actions: Rc<RefCell<Vec<ScriptAction>>>,
+    // Hooks stopped at the limits, not called again until the script is reloaded
+    disabled: HashSet<String>,
+}
+
+impl Scripting {
+    /// The top level statements are run right away
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let actions = Rc::new(RefCell::new(vec![]));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+
+        let queue = actions.clone();
+        engine.register_fn("spawn", move |command: &str| {
+            queue.borrow_mut().push(ScriptAction::Spawn(command.into()))
+        });
+        let queue = actions.clone();
+        engine.register_fn("switch_workspace", move |index: i64| {
+            queue
+                .borrow_mut()
+                .push(ScriptAction::SwitchWorkspace(index.max(0) as usize))
+        });
+        let queue = actions.clone();
+        engine.register_fn("move_to_workspace", move |index: i64| {
+            queue
+                .borrow_mut()
+                .push(ScriptAction::MoveToWorkspace(index.max(0) as usize))
+        });
+        let queue = actions.clone();
+        engine.register_fn("toggle_floating", move || {
+            queue.borrow_mut().push(ScriptAction::ToggleFloating)
+        });
+        let queue = actions.clone();
+        engine.register_fn("toggle_maximize", move || {
+            queue.borrow_mut().push(ScriptAction::ToggleMaximize)
+        });
+        engine.on_print(|text| println!("SCRIPT: {text}"));
+
+        let ast = engine
+            .compile_file(path.into())
+            .map_err(|err| err.to_string())?;
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|err| err.to_string())?;
+
+        Ok(Self {
+            engine,
+            ast,
+            scope,
+            actions,
+            disabled: HashSet::new(),
+        })
+    }
+
+    // Hooks are optional, a script defines only the ones it needs
+    fn call(&mut self, hook: &str, args: impl FuncArgs) -> Vec<ScriptAction> {
+        let defined = self
+            .ast
+            .iter_functions()
+            .any(|function| function.name == hook);
+        if defined && !self.disabled.contains(hook) {
+            if let Err(err) = self
+                .engine
+                .call_fn::<Dynamic>(&mut self.scope, &self.ast, hook, args)
+            {
+                println!("SCRIPT {hook} failed: {err}");
+                if hit_limits(&err) {
+                    println!("SCRIPT {hook} disabled, reload the config to enable it again");
+                    self.disabled.insert(hook.to_string());
+                    // Nothing is applied from a hook that was stopped
+                    self.actions.take();
+                }
+            }
+        }
+        self.actions.take()
+    }
+}
+
+// The error could come from a function called by the hook
+fn hit_limits(err: &EvalAltResult) -> bool {
+    match err {
+        EvalAltResult::ErrorTooManyOperations(_) | EvalAltResult::ErrorStackOverflow(_) => true,
+        EvalAltResult::ErrorInFunctionCall(_, _, inner, _) => hit_limits(inner),
+        _ => false,
+    }
+}
+
+/// Load the script again, eg. after a config reload.
+/// Without the file there are no hooks
+pub fn reload(state: &mut AIGIState) {
+    state.scripting = None;
+    let Some(path) = state.config.script_path(state.config_path.as_deref()) else {
+        return;
+    };
+    if !path.exists() {
+        return;
+    }
+    match Scripting::load(&path) {
+        Ok(scripting) => {
+            tracing::debug!("SCRIPT loaded from {path:?}");
+            state.scripting = Some(scripting);
+        }
+        Err(err) => println!("Script {path:?} not loaded: {err}"),
+    }
+}
+
+pub fn window_opened(state: &mut AIGIState, window: &Window) {
+    let info = window_info(window);
+    run_hook(state, "on_window_opened", (info,), Some(window));
+}
+
+pub fn window_closed(state: &mut AIGIState, window: &Window) {
+    let info = window_info(window);
+    run_hook(state, "on_window_closed", (info,), None);
+}
+
+pub fn workspace_changed(state: &mut AIGIState, index: usize) {
+    run_hook(state, "on_workspace_changed", (index as i64,), None);
+}
+
+pub fn output_changed(state: &mut AIGIState, name: String) {
+    run_hook(state, "on_output_changed", (name,), None);
+}
+
+// The events raised by the actions of a hook don't call other hooks,
+// so a script can't end up calling itself forever
+fn run_hook(state: &mut AIGIState, hook: &str, args: impl FuncArgs, window: Option<&Window>) {
+    let Some(mut scripting) = state.scripting.take() else {
+        return;
+    };
+    let actions = scripting.call(hook, args);
+
+    let window = window.cloned().or_else(|| {
+        state
+            .seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.current_focus())
+            .and_then(|wl_surface| state.window_for_surface(&wl_surface))
+    });
+    for action in actions {
+        apply(state, action, window.as_ref());
+    }
+    state.scripting = Some(scripting);
+}
+
+fn apply(state: &mut AIGIState, action: ScriptAction, window: Option<&Window>) {
+    match (action, window) {
+        (ScriptAction::Spawn(command), _) => {
+            if let Err(err) = std::process::Command::new("sh")
+                .args(["-c", &command])
+                .spawn()
+            {
+                println!("Script command {command:?} failed: {err}");
+            }
+        }
+        (ScriptAction::SwitchWorkspace(index), _) if index < WORKSPACE_COUNT => {
+            state.switch_workspace(index)
+        }
+        (ScriptAction::SwitchWorkspace(index), _) => {
+            println!("Script asked for workspace {index}, there are only {WORKSPACE_COUNT}")
+        }
+        (ScriptAction::MoveToWorkspace(index), Some(window)) => {
+            state.move_to_workspace(window, index)
+        }
+        (ScriptAction::ToggleFloating, Some(window)) => {
+            state.toggle_floating(window.toplevel().wl_surface())
+        }
+        (ScriptAction::ToggleMaximize, Some(window)) => {
+            state.toggle_maximize(window.toplevel().wl_surface())
+        }
+        (action, None) => println!("Script action {action:?} without a window"),
+    }
+}
+
+// `title` and `app_id` of the window, as a script object
+fn window_info(window: &Window) -> Map {
+    let mut info = Map::new();
+    info.insert("title".into(), decorations::title(window).into());
+    info.insert("app_id".into(), decorations::app_id(window).into());
+    info
+}
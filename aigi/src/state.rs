@@ -1,10 +1,51 @@
 use crate::backend::BackendData;
+use crate::clipboard::{self, Mirrored};
+use crate::config::Config;
+use crate::config::DecorationButton;
+use crate::config::RefusedSplit;
+use crate::content_type;
+use crate::crash;
+use crate::decorations::{self, DecorationHit, DecorationState, DecorationStatus, Insets};
+use crate::devices::{self, InputDevices, PressedKeys};
+use crate::ext_workspace::ExtWorkspaceState;
+use crate::floating::{park, FloatingState, SnapPosition};
+use crate::focus::FocusHistory;
+use crate::force_close::{self, CloseReason, ForceCloseDialog};
+use crate::grabs::{edges_from_location, MoveSurfaceGrab, ResizeSurfaceGrab, TileResizeGrab};
+use crate::idle::{self, IdleState};
+use crate::input_handler::{ChordState, EdgeSwipe, SwipeGesture};
+use crate::ipc::{self, IpcState};
+use crate::layer_focus::{self, LayerFocus};
+use crate::layout::LayoutRegistry;
+use crate::marks::{self, Marks};
+use crate::notifications::{self, NotificationState};
+use crate::osd::{self, Osd, TextOsd};
+use crate::outputs;
+use crate::overview::Overview;
+use crate::permissions::{Permission, Permissions};
+use crate::ping::PingState;
+use crate::placement::{self, Placement};
+use crate::pointer_warp::{self, PointerEnter};
+use crate::protocol_log::ProtocolLog;
+use crate::render::{
+    self, letterbox, reload_cursor_theme, schedule_render, shrink_to_tile,
+    start_workspace_transition, FrameClock, RenderState,
+};
+use crate::saved_layout::{self, LayoutRestore};
+use crate::screencopy;
+use crate::scripting::{self, Scripting};
+use crate::status::StatusState;
+use crate::tabs::TabState;
+use crate::toplevel_drag::{self, ToplevelDragState};
+use crate::toplevel_icon;
+use crate::workspaces::{Workspace, Workspaces, WORKSPACE_COUNT};
 
-use super::tiling::{Split, TilingState};
+use super::tiling::{Direction, Node, Split, TilingState};
 use super::LoopData;
 
 use anyhow::{Error, Result};
 use smithay::backend::allocator::dmabuf::Dmabuf;
+use smithay::backend::renderer::utils::with_renderer_surface_state;
 use smithay::backend::renderer::{ImportDma, ImportMemWl};
 use smithay::delegate_dmabuf;
 use smithay::output::Output;
@@ -13,49 +54,79 @@ use smithay::utils::{Clock, Monotonic};
 use smithay::wayland::dmabuf::{
     DmabufFeedback, DmabufFeedbackBuilder, DmabufGlobal, DmabufHandler, DmabufState, ImportError,
 };
-use smithay::wayland::shell::wlr_layer::WlrLayerShellState;
+use smithay::wayland::shell::wlr_layer::{
+    Layer, LayerSurface as WlrLayerSurface, LayerSurfaceData, WlrLayerShellHandler,
+    WlrLayerShellState,
+};
 use smithay::{
     backend::renderer::utils::on_commit_buffer_handler,
-    delegate_compositor, delegate_data_device, delegate_output, delegate_seat, delegate_shm,
-    delegate_xdg_shell,
-    desktop::{layer_map_for_output, space::SpaceElement, Space, Window},
+    delegate_compositor, delegate_data_device, delegate_layer_shell, delegate_output,
+    delegate_primary_selection, delegate_seat, delegate_shm, delegate_virtual_keyboard_manager,
+    delegate_xdg_activation, delegate_xdg_decoration, delegate_xdg_shell,
+    desktop::{
+        layer_map_for_output, space::SpaceElement, LayerSurface, Space, Window, WindowSurfaceType,
+    },
     input::{
-        keyboard::{keysyms, FilterResult},
-        pointer::CursorImageStatus,
+        keyboard::{keysyms, FilterResult, XkbConfig},
+        pointer::{CursorImageStatus, Focus, GrabStartData as PointerGrabStartData, MotionEvent},
         Seat, SeatHandler, SeatState,
     },
     reexports::{
         calloop::{generic::Generic, EventLoop, Interest, Mode, PostAction},
-        wayland_protocols::xdg::shell::server::xdg_toplevel,
+        wayland_protocols::{
+            wp::primary_selection::zv1::server::zwp_primary_selection_source_v1::ZwpPrimarySelectionSourceV1,
+            xdg::{
+                decoration::zv1::server::zxdg_toplevel_decoration_v1::Mode as DecorationMode,
+                shell::server::xdg_toplevel,
+            },
+        },
         wayland_server::{
             backend::ClientData,
-            protocol::{wl_buffer, wl_seat, wl_surface::WlSurface},
-            Client, Display, DisplayHandle,
+            protocol::{
+                wl_buffer, wl_data_source::WlDataSource, wl_output::WlOutput, wl_seat,
+                wl_surface::WlSurface,
+            },
+            Client, Display, DisplayHandle, Resource,
         },
     },
-    utils::{Logical, Point, Rectangle, Serial},
+    utils::{Logical, Point, Rectangle, Serial, Size, SERIAL_COUNTER},
     wayland::{
         buffer::BufferHandler,
         compositor::{with_states, CompositorClientState, CompositorHandler, CompositorState},
         data_device::{
-            ClientDndGrabHandler, DataDeviceHandler, DataDeviceState, ServerDndGrabHandler,
+            set_data_device_focus, ClientDndGrabHandler, DataDeviceHandler, DataDeviceState,
+            ServerDndGrabHandler,
         },
         output::OutputManagerState,
+        primary_selection::{set_primary_focus, PrimarySelectionHandler, PrimarySelectionState},
         shell::xdg::{
-            PopupSurface, PositionerState, ToplevelSurface, XdgShellHandler, XdgShellState,
-            XdgToplevelSurfaceData,
+            decoration::{XdgDecorationHandler, XdgDecorationState},
+            PopupSurface, PositionerState, ShellClient, SurfaceCachedState, ToplevelSurface,
+            XdgShellHandler, XdgShellState, XdgToplevelSurfaceData,
         },
         shm::{ShmHandler, ShmState},
         socket::ListeningSocketSource,
+        virtual_keyboard::VirtualKeyboardManagerState,
+        xdg_activation::{
+            XdgActivationHandler, XdgActivationState, XdgActivationToken, XdgActivationTokenData,
+        },
     },
 };
 
 use std::sync::atomic::AtomicBool;
-use std::{collections::HashMap, os::unix::prelude::AsRawFd, sync::Arc};
+use std::{
+    collections::HashMap,
+    os::unix::prelude::{AsRawFd, OwnedFd},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 #[derive(Default)]
 pub struct ClientState {
     pub compositor_state: CompositorClientState, // not sure about this
+    // Matched against the permissions, None if it can't be read
+    pub executable: Option<PathBuf>,
 }
 
 impl ClientData for ClientState {}
@@ -64,6 +135,9 @@ pub struct AIGIState {
     // everythin related with the backend
     pub backend_data: BackendData,
 
+    // user configuration
+    pub config: Config,
+
     // main wayland object
     pub display_handle: DisplayHandle,
 
@@ -72,26 +146,113 @@ pub struct AIGIState {
 
     // Atomic bool to keeps track of the running compositor
     pub running: AtomicBool,
+    // executed again once the event loop stops, see `relaunch::relaunch`
+    pub relaunch: bool,
+    // When the relaunch binding was pressed, it waits for a second press
+    pub relaunch_asked: Option<Instant>,
+    // set with --config, reloading the config reads the same file
+    pub config_path: Option<PathBuf>,
 
     // desktop stuff
     pub space: Space<Window>,
+    // outputs turned off at runtime, not mapped in the space
+    pub disabled_outputs: Vec<Output>,
 
     // Smithay State
     pub compositor_state: CompositorState,
     pub output_manager_state: OutputManagerState,
     pub seat_state: SeatState<Self>,
+    pub data_device_state: DataDeviceState,
+    pub primary_selection_state: PrimarySelectionState,
     pub shm_state: ShmState,
     pub xdg_shell_state: XdgShellState,
+    pub xdg_decoration_state: XdgDecorationState,
+    pub xdg_activation_state: XdgActivationState,
+    pub layer_shell_state: WlrLayerShellState,
     pub dmabuf_state: DmabufState,
     pub dmabuf_default_feedback: DmabufFeedback,
 
     // input things
     pub seat: Seat<Self>,
     pub pointer_location: Point<f64, Logical>,
+    // The surface entered by the pointer, see `pointer_warp::motion`
+    pub pointer_enter: Option<PointerEnter>,
     pub cursor_status: CursorImageStatus,
+    pub chord_state: ChordState,
+    pub edge_swipe: Option<EdgeSwipe>,
+    pub swipe_gesture: Option<SwipeGesture>,
+    pub input_devices: InputDevices,
+    pub pressed_keys: PressedKeys,
+
+    // Less work for fullscreen clients, see `game_mode_window`
+    pub game_mode: bool,
+    // Updated by the power module, used to save energy
+    pub on_battery: bool,
+    pub power_timer_armed: bool,
+    // dimming and DPMS after some time without input
+    pub idle_state: IdleState,
 
+    // frame scheduling
+    pub render_state: RenderState,
+    // volume/brightness overlay, see `osd::show`
+    pub osd: Option<Osd>,
+    // number of the workspace, see `osd::show_workspace`
+    pub text_osd: Option<TextOsd>,
+    // titlebars and borders drawn by the compositor
+    pub decoration_state: DecorationState,
+    // titles of the tabbed containers
+    pub tab_state: TabState,
+    // layer surfaces of the notification daemons, and do not disturb
+    pub notification_state: NotificationState,
+    // windows marked to be focused again with a key
+    pub marks: Marks,
+
+    // toplevels without a buffer yet, they are mapped
+    // (tiled or floating) on their first commit with a buffer
+    pub unmapped: Vec<Window>,
     // tiling state
     pub tiling_state: TilingState,
+    // windows outside the tiling tree
+    pub floating_state: FloatingState,
+    // windows of the active workspace, from the last focused one
+    pub focus_history: FocusHistory,
+    // window covering the whole output of the active workspace,
+    // tiled or floating, it keeps its place to go back to
+    pub maximized: Option<Window>,
+    // window covering its whole output, the panels too,
+    // it keeps its place to go back to like a maximized one
+    pub fullscreen: Option<Window>,
+    // the workspaces not shown, the active one is made by
+    // the space, the tiling_state and the floating_state
+    pub workspaces: Workspaces,
+    // clients connected to the IPC socket that want events
+    pub ipc_state: IpcState,
+    // panels bound to ext-workspace
+    pub ext_workspace_state: ExtWorkspaceState,
+    // the window following the pointer in a drag and drop, see `toplevel_drag`
+    pub toplevel_drag_state: ToplevelDragState,
+    // xdg_wm_base pings waiting for the pong
+    pub ping_state: PingState,
+    // asks if an unresponsive client has to be killed
+    pub force_close: Option<ForceCloseDialog>,
+    // the windows of the workspace in a grid, one is picked
+    pub overview: Option<Overview>,
+    // a new window waiting to be tiled where the user wants
+    pub placement: Option<Placement>,
+    // a layer surface with the keyboard (eg. a launcher)
+    pub layer_focus: Option<LayerFocus>,
+    // started with --protocol-log, filtered through the IPC
+    pub protocol_log: Option<ProtocolLog>,
+    // executables allowed to use the sensitive globals
+    pub permissions: Permissions,
+    // hooks of the user script, see `scripting::reload`
+    pub scripting: Option<Scripting>,
+    // layouts that a workspace can use instead of the tiling tree
+    pub layouts: LayoutRegistry,
+    // bars that receive the status line
+    pub status_state: StatusState,
+    // tree of the previous session, filled as its windows open
+    pub layout_restore: Option<LayoutRestore>,
     pub clock: Clock<Monotonic>,
 }
 
@@ -113,10 +274,14 @@ impl CompositorHandler for AIGIState {
     // the synchronized apply effectively the current stata only when the parent commit it
     // (it works recursively), while if the surface is not syncronized it is directly applied
     fn commit(&mut self, surface: &WlSurface) {
+        render::activity(self);
         // Let Smithay take the surface buffer so that desktop helpers get the new surface state.
         on_commit_buffer_handler::<Self>(surface);
 
-        // Should be done something on the gpu_managed called `early_import`
+        // The new buffer is uploaded now, only where it's damaged
+        render::import_surface(self, surface);
+        content_type::commit(surface);
+        toplevel_icon::commit(surface);
 
         // Now we should AVOID update the state of a surface if it is
         // sync (see anvil impmentation of this method) but the first version
@@ -132,29 +297,48 @@ impl CompositorHandler for AIGIState {
         {
             // Refresh the window state.
             window.on_commit();
+            // The names are computed again only when the app id could have changed
+            if ipc::window_renamed(&window) {
+                ipc::names_changed(self);
+            }
+        } else if let Some(index) = self
+            .unmapped
+            .iter()
+            .position(|w| w.toplevel().wl_surface() == surface)
+        {
+            let window = self.unmapped[index].clone();
+            window.on_commit();
 
             // Ensure Initial Configuration
-            // Find if the window has been configured yet.
-            let initial_configure_sent = with_states(surface, |states| {
-                states
-                    .data_map
-                    .get::<XdgToplevelSurfaceData>()
-                    .unwrap()
-                    .lock()
-                    .unwrap()
-                    .initial_configure_sent
-            });
+            // The first commit has no buffer, the client waits for the
+            // configure before drawing anything
+            if !initial_configure_sent(window.toplevel()) {
+                // The title is known only after the first commit,
+                // so this is the first moment the rule can be checked
+                if self.is_pip(&window) {
+                    let size = Size::from(self.config.pip.size);
+                    window.toplevel().with_pending_state(|state| {
+                        state.size = Some(size);
+                    });
+                }
 
-            if !initial_configure_sent {
                 // Configure window size/attributes.
                 window.toplevel().send_configure();
+            } else if with_renderer_surface_state(surface, |state| state.buffer().is_some()) {
+                // The window has something to show only now
+                self.unmapped.remove(index);
+                // A window waiting to be placed is not opened yet
+                if self.map_toplevel(window.clone()) {
+                    scripting::window_opened(self, &window);
+                }
             }
-
-            //
-            // Should be also managed some Initial cofiguration on the layer_map
-            // (see ensure_initial_configuration in anvil/src/shell/mod)
+        } else {
+            self.layer_commit(surface);
         }
 
+        // The new state of the surface needs to be shown
+        schedule_render(self);
+
         // commit of the popup should now be managed
     }
 }
@@ -177,13 +361,92 @@ impl SeatHandler for AIGIState {
     ) {
         // Change the cursor image to respect what defined by the client
         self.cursor_status = new_image;
+        schedule_render(self);
     }
 
-    // do nothing for now, here should be inserted all the side effects
-    // of changing the focus
-    fn focus_changed(&mut self, _: &smithay::input::Seat<Self>, _: Option<&WlSurface>) {}
+    // The decorations show which window has the focus,
+    // and a window that gets the focus is not urgent anymore.
+    // The focused client is the one that can paste
+    fn focus_changed(&mut self, seat: &smithay::input::Seat<Self>, focused: Option<&WlSurface>) {
+        let client = focused.and_then(|wl_surface| wl_surface.client());
+        set_data_device_focus(&self.display_handle, seat, client.clone());
+        set_primary_focus(&self.display_handle, seat, client);
+        if let Some(wl_surface) = focused {
+            self.decoration_state.set_urgent(wl_surface, false);
+            if let Some(window) = self.window_for_surface(wl_surface) {
+                self.focus_history.focused(&window);
+            }
+        }
+        layer_focus::focus_changed(self, focused);
+        self.decoration_status_changed();
+    }
 }
 delegate_seat!(AIGIState);
+// Keyboards of the on-screen keyboards and of the remote desktop servers
+delegate_virtual_keyboard_manager!(AIGIState);
+
+// Copy and paste, see `clipboard` for the selections mirrored by the compositor
+impl DataDeviceHandler for AIGIState {
+    type SelectionUserData = Mirrored;
+
+    fn data_device_state(&self) -> &DataDeviceState {
+        &self.data_device_state
+    }
+
+    fn new_selection(&mut self, source: Option<WlDataSource>, seat: Seat<Self>) {
+        clipboard::clipboard_changed(self, source, seat);
+    }
+
+    fn send_selection(
+        &mut self,
+        mime_type: String,
+        fd: OwnedFd,
+        seat: Seat<Self>,
+        mirrored: &Mirrored,
+    ) {
+        clipboard::send_mirrored(*mirrored, mime_type, fd, &seat);
+    }
+}
+// A drag and drop can move a window with it, see `toplevel_drag`
+impl ClientDndGrabHandler for AIGIState {
+    fn started(
+        &mut self,
+        source: Option<WlDataSource>,
+        _icon: Option<WlSurface>,
+        _seat: Seat<Self>,
+    ) {
+        toplevel_drag::started(self, source);
+    }
+
+    fn dropped(&mut self, _seat: Seat<Self>) {
+        toplevel_drag::dropped(self);
+    }
+}
+impl ServerDndGrabHandler for AIGIState {}
+delegate_data_device!(AIGIState);
+
+impl PrimarySelectionHandler for AIGIState {
+    type SelectionUserData = Mirrored;
+
+    fn primary_selection_state(&self) -> &PrimarySelectionState {
+        &self.primary_selection_state
+    }
+
+    fn new_selection(&mut self, source: Option<ZwpPrimarySelectionSourceV1>, seat: Seat<Self>) {
+        clipboard::primary_changed(self, source, seat);
+    }
+
+    fn send_selection(
+        &mut self,
+        mime_type: String,
+        fd: OwnedFd,
+        seat: Seat<Self>,
+        mirrored: &Mirrored,
+    ) {
+        clipboard::send_mirrored(*mirrored, mime_type, fd, &seat);
+    }
+}
+delegate_primary_selection!(AIGIState);
 
 // Even inside Anvil is not implemented
 // not sure if we will ever need to update things when a buffer is destroyed
@@ -203,92 +466,280 @@ impl XdgShellHandler for AIGIState {
         &mut self.xdg_shell_state
     }
 
+    // The window is not mapped yet: until the client commits a buffer
+    // it does not take any space in the layout, see `map_toplevel`
     fn new_toplevel(&mut self, surface: ToplevelSurface) {
-        let window = Window::new(surface);
+        self.unmapped.push(Window::new(surface));
+    }
 
-        // get the window underfocus
-        let focus_window: Option<Window> = self
-            .seat
-            .get_keyboard()
-            .unwrap()
-            .current_focus()
-            .and_then(|wl_surface| {
-                Some(
-                    self.space
-                        .elements()
-                        .find(|w| w.toplevel().wl_surface() == &wl_surface)
-                        .cloned()
-                        .expect("Impossible having a surface on focus not present in the Space"),
-                )
-            });
+    fn new_popup(&mut self, _: PopupSurface, _: PositionerState) {}
 
-        let node_to_update = match focus_window {
-            Some(focus_window) => self.tiling_state.split(focus_window, window),
-            None => {
-                // render full size screen
-                // TODO: in the state should be added something like output geometry
-                // to not fetch it every time
-                let output = self.space.outputs().next();
-                let output_geometry = output
-                    .and_then(|o| {
-                        let geo = self.space.output_geometry(&o)?;
-                        let map = layer_map_for_output(&o);
-                        let zone = map.non_exclusive_zone();
-                        Some(Rectangle::from_loc_and_size(geo.loc + zone.loc, zone.size))
-                    })
-                    .unwrap_or_else(|| Rectangle::from_loc_and_size((0, 0), (800, 800)));
-
-                // Do not send a configure here, the initial configure
-                // of a xdg_surface has to be sent during the commit if
-                // the surface is not already configured
-                // window.toplevel().send_configure();
-
-                self.tiling_state
-                    .insert_head(window, output_geometry)
-                    .unwrap()
-            }
-        };
+    fn client_pong(&mut self, client: ShellClient) {
+        if !self.ping_state.pong(&client) {
+            return;
+        }
+        tracing::debug!("CLIENT RESPONSIVE AGAIN");
 
-        self.tiling_state
-            .update_space(node_to_update, &mut self.space);
+        // The client can close the window by itself now
+        let answered = self.force_close.as_ref().is_some_and(|dialog| {
+            dialog.reason == CloseReason::Unresponsive
+                && dialog.window.toplevel().client() == client
+        });
+        if answered {
+            if let Some(dialog) = self.force_close.take() {
+                dialog.window.toplevel().send_close();
+            }
+            schedule_render(self);
+        }
     }
 
-    fn new_popup(&mut self, _: PopupSurface, _: PositionerState) {}
-
-    // TODO
-    fn move_request(&mut self, _: ToplevelSurface, _: wl_seat::WlSeat, _: Serial) {}
+    // The client asks to be moved, usually because the user
+    // is dragging the client side titlebar
+    fn move_request(&mut self, surface: ToplevelSurface, seat: wl_seat::WlSeat, serial: Serial) {
+        let Some(start_data) = self.client_grab_start_data(&surface, &seat, serial) else {
+            return;
+        };
+        if let Some(window) = self.window_for_surface(surface.wl_surface()) {
+            let button = start_data.button;
+            self.start_move_grab(window, start_data, button, serial);
+        }
+    }
 
-    // TODO
+    // The client asks to be resized, usually because the user
+    // is dragging one of the client side edges
     fn resize_request(
         &mut self,
-        _: ToplevelSurface,
-        _: wl_seat::WlSeat,
-        _: Serial,
-        _: xdg_toplevel::ResizeEdge,
+        surface: ToplevelSurface,
+        seat: wl_seat::WlSeat,
+        serial: Serial,
+        edges: xdg_toplevel::ResizeEdge,
     ) {
+        let Some(start_data) = self.client_grab_start_data(&surface, &seat, serial) else {
+            return;
+        };
+        if let Some(window) = self.window_for_surface(surface.wl_surface()) {
+            let button = start_data.button;
+            self.start_resize_grab(window, start_data, edges, button, serial);
+        }
+    }
+
+    fn maximize_request(&mut self, surface: ToplevelSurface) {
+        if self.maximized.as_ref().map(|w| w.toplevel()) != Some(&surface) {
+            self.toggle_maximize(surface.wl_surface());
+        }
+    }
+
+    fn unmaximize_request(&mut self, surface: ToplevelSurface) {
+        if self.maximized.as_ref().map(|w| w.toplevel()) == Some(&surface) {
+            self.toggle_maximize(surface.wl_surface());
+        }
+    }
+
+    // The window goes fullscreen on the output it's on, the one
+    // asked by the client is not taken into account
+    fn fullscreen_request(&mut self, surface: ToplevelSurface, _output: Option<WlOutput>) {
+        if self.fullscreen.as_ref().map(|w| w.toplevel()) != Some(&surface) {
+            self.toggle_fullscreen(surface.wl_surface());
+        }
+    }
+
+    fn unfullscreen_request(&mut self, surface: ToplevelSurface) {
+        if self.fullscreen.as_ref().map(|w| w.toplevel()) == Some(&surface) {
+            self.toggle_fullscreen(surface.wl_surface());
+        }
     }
 
     // TODO
     fn grab(&mut self, _surface: PopupSurface, _seat: wl_seat::WlSeat, _serial: Serial) {}
 
     fn toplevel_destroyed(&mut self, surface: ToplevelSurface) {
-        let window = self
-            .space
-            .elements()
-            .find(|w| *w.toplevel() == surface)
-            .expect("IMP destroy a non existring surface")
-            .clone();
+        self.decoration_state.remove(surface.wl_surface());
+        marks::window_closed(self, surface.wl_surface());
+        // Never mapped, nothing else knows about it
+        if let Some(index) = self
+            .unmapped
+            .iter()
+            .position(|w| w.toplevel().wl_surface() == surface.wl_surface())
+        {
+            self.unmapped.remove(index);
+            return;
+        }
+        if placement::forget(self, surface.wl_surface()) {
+            return;
+        }
+        let Some(window) = self.window_for_surface(surface.wl_surface()) else {
+            // Windows on hidden workspaces are not mapped in the space
+            let hidden = self
+                .workspaces
+                .windows()
+                .map(|(_, window)| window)
+                .find(|window| window.toplevel() == &surface)
+                .cloned();
+            if let Some(window) = hidden {
+                self.workspaces.remove_window(surface.wl_surface());
+                ipc::workspaces_changed(self);
+                scripting::window_closed(self, &window);
+            } else {
+                println!("IMP destroy a non existring surface");
+            }
+            return;
+        };
+        let focused = self.seat.get_keyboard().unwrap().current_focus().as_ref()
+            == Some(surface.wl_surface());
+        if self.force_close.as_ref().map(|dialog| &dialog.window) == Some(&window) {
+            self.force_close = None;
+        }
         self.space.unmap_elem(&window);
+        self.focus_history.remove(&window);
+        schedule_render(self);
+        ipc::workspaces_changed(self);
+
+        if self.maximized.as_ref() == Some(&window) {
+            self.maximized = None;
+        }
+        if self.fullscreen.as_ref() == Some(&window) {
+            self.fullscreen = None;
+        }
+
+        let absorbed_by = if self.floating_state.remove(surface.wl_surface()).is_none() {
+            self.untile(surface.wl_surface())
+        } else {
+            None
+        };
+
+        // The focus can't stay on a dead surface: it goes to the tiles that took
+        // the space of the window (the last focused one if they are many),
+        // otherwise to the window focused before
+        if focused {
+            let next = absorbed_by
+                .map(|node| self.tiling_state.windows(node))
+                .and_then(|windows| {
+                    self.focus_history
+                        .most_recent(&windows)
+                        .or_else(|| windows.first().cloned())
+                })
+                .or_else(|| self.focus_history.last());
+            self.set_keyboard_focus(next.as_ref());
 
-        // TODO remove this unwrap :sweat_smile:
-        if let Some(node_to_update) = self.tiling_state.destroy(surface.wl_surface()).unwrap() {
-            self.tiling_state
-                .update_space(node_to_update, &mut self.space);
+            // Otherwise the focus would follow the pointer as soon as it moves
+            if let (Some(window), true) = (next, self.config.focus.warp_pointer) {
+                self.warp_pointer_to(&window);
+            }
         }
+        scripting::window_closed(self, &window);
     }
 }
 delegate_xdg_shell!(AIGIState);
 
+// The decoration mode is part of the toplevel state, so it's sent
+// with the next configure (the initial one if the window is new)
+impl XdgDecorationHandler for AIGIState {
+    fn new_decoration(&mut self, toplevel: ToplevelSurface) {
+        let mode = self.preferred_decoration_mode();
+        self.set_decoration_mode(&toplevel, mode);
+    }
+
+    // Clients can refuse the server side decorations,
+    // but they get them only if enabled in the config
+    fn request_mode(&mut self, toplevel: ToplevelSurface, mode: DecorationMode) {
+        let mode = match mode {
+            DecorationMode::ServerSide => self.preferred_decoration_mode(),
+            _ => DecorationMode::ClientSide,
+        };
+        self.set_decoration_mode(&toplevel, mode);
+    }
+
+    fn unset_mode(&mut self, toplevel: ToplevelSurface) {
+        let mode = self.preferred_decoration_mode();
+        self.set_decoration_mode(&toplevel, mode);
+    }
+}
+delegate_xdg_decoration!(AIGIState);
+
+impl XdgActivationHandler for AIGIState {
+    fn activation_state(&mut self) -> &mut XdgActivationState {
+        &mut self.xdg_activation_state
+    }
+
+    // Focus stealing is not allowed: the window is marked
+    // as urgent and the user decides when to look at it
+    fn request_activation(
+        &mut self,
+        _token: XdgActivationToken,
+        _token_data: XdgActivationTokenData,
+        surface: WlSurface,
+    ) {
+        let focused = self.seat.get_keyboard().unwrap().current_focus();
+        if focused.as_ref() != Some(&surface) {
+            self.decoration_state.set_urgent(&surface, true);
+            self.decoration_status_changed();
+        }
+    }
+}
+delegate_xdg_activation!(AIGIState);
+
+impl WlrLayerShellHandler for AIGIState {
+    fn shell_state(&mut self) -> &mut WlrLayerShellState {
+        &mut self.layer_shell_state
+    }
+
+    // Without an output chosen by the client the surface goes on the first
+    // one, the notifications on the configured one. It's configured on its
+    // first commit
+    fn new_layer_surface(
+        &mut self,
+        surface: WlrLayerSurface,
+        output: Option<WlOutput>,
+        _layer: Layer,
+        namespace: String,
+    ) {
+        let output = notifications::output(self, &namespace)
+            .or_else(|| output.as_ref().and_then(Output::from_resource))
+            .or_else(|| self.space.outputs().next().cloned());
+        let Some(output) = output else {
+            println!("No output for the layer surface {namespace}");
+            surface.send_close();
+            return;
+        };
+        tracing::debug!("NEW LAYER SURFACE: {namespace}");
+        let layer = LayerSurface::new(surface, namespace);
+        if notifications::suppress(self, &layer, &output) {
+            return;
+        }
+        if let Err(err) = layer_map_for_output(&output).map_layer(&layer) {
+            println!("Layer surface not mapped: {err}");
+        }
+    }
+
+    fn layer_destroyed(&mut self, surface: WlrLayerSurface) {
+        layer_focus::layer_destroyed(self, surface.wl_surface());
+        notifications::layer_destroyed(self, &surface);
+        let outputs = self.space.outputs().cloned().collect::<Vec<_>>();
+        for output in outputs {
+            let mut map = layer_map_for_output(&output);
+            let layer = map
+                .layers()
+                .find(|layer| layer.layer_surface() == &surface)
+                .cloned();
+            let Some(layer) = layer else {
+                continue;
+            };
+            let zone = map.non_exclusive_zone();
+            map.unmap_layer(&layer);
+            map.arrange();
+            let zone_changed = map.non_exclusive_zone() != zone;
+            drop(map);
+
+            // The windows can take the space of a bar that went away
+            if zone_changed {
+                outputs::output_changed(self, &output);
+            }
+        }
+        schedule_render(self);
+    }
+}
+delegate_layer_shell!(AIGIState);
+
 impl DmabufHandler for AIGIState {
     fn dmabuf_state(&mut self) -> &mut DmabufState {
         &mut self.dmabuf_state
@@ -301,7 +752,7 @@ impl DmabufHandler for AIGIState {
     ) -> Result<(), ImportError> {
         self.backend_data
             .gpu_manager
-            .single_renderer(&self.backend_data.device_data.render_node)
+            .single_renderer(&self.backend_data.render_node)
             .and_then(|mut renderer| renderer.import_dmabuf(&dmabuf, None))
             .map(|_| ())
             .map_err(|_| ImportError::Failed)
@@ -314,6 +765,8 @@ impl AIGIState {
         even_loop_handle: LoopHandle<'static, LoopData>,
         display: &mut Display<Self>,
         mut backend_data: BackendData,
+        config: Config,
+        config_path: Option<PathBuf>,
     ) -> Result<Self, Error> {
         let clock = Clock::new().expect("failed to initialize clock");
 
@@ -481,11 +934,11 @@ impl AIGIState {
         // Extract Renderer from the backend to later use it
         // to extract all the informatin needed to initialize
         // the AigiState
-        let render_node = &backend_data.device_data.render_node;
+        let render_node = &backend_data.render_node;
         let renderer = backend_data
             .gpu_manager
             .single_renderer(render_node)
-            .expect("Impossible get Renderer");
+            .map_err(|err| anyhow::anyhow!("Impossible get Renderer: {err}"))?;
 
         // The compositor for our compositor.
         let compositor_state = CompositorState::new::<AIGIState>(&dh);
@@ -500,11 +953,19 @@ impl AIGIState {
         // Used for desktop applications, defines two types of Wayland surfaces clients can use,
         // "toplevel" (for the main application area) and "popup" (for dialogs/tooltips/etc).
         let xdg_shell_state = XdgShellState::new::<AIGIState>(&dh);
+        // Lets the clients ask for server side decorations (or refuse them)
+        let xdg_decoration_state = XdgDecorationState::new::<AIGIState>(&dh);
+        // Used by clients to ask for the focus, eg. a notification that opens a window
+        let xdg_activation_state = XdgActivationState::new::<AIGIState>(&dh);
+        // Panels, bars, backgrounds and overlays, drawn around the windows
+        let layer_shell_state = WlrLayerShellState::new::<AIGIState>(&dh);
         // A space to map windows on. Keeps track of windows and outputs, can access either with
         // space.elements() and space.outputs().
         let space = Space::<Window>::default();
         // Manage copy/paste and drag-and-drop from inputs.
-        // let data_device_state = DataDeviceState::new::<AIGIState>(&dh);
+        let data_device_state = DataDeviceState::new::<AIGIState>(&dh);
+        // The selection pasted with the middle click
+        let primary_selection_state = PrimarySelectionState::new::<AIGIState>(&dh);
 
         // A seat is a group of input devices like keyboards, pointers, etc. This manages the seat
         // state.
@@ -514,9 +975,13 @@ impl AIGIState {
 
         // Add a keyboard with repeat rate and delay in milliseconds. The repeat is the time to
         // repeat, then delay is how long to wait until the next repeat.
-        seat.add_keyboard(Default::default(), 500, 500)?;
-        // Add pointer to seat.
-        seat.add_pointer();
+        // A keymap that doesn't compile (eg. a typo in the options) falls back
+        // to the default one, like a reload, instead of refusing to start
+        if let Err(err) = seat.add_keyboard(config.keyboard.xkb_config(), 500, 500) {
+            println!("Keymap not used, falling back to the default one: {err:?}");
+            seat.add_keyboard(XkbConfig::default(), 500, 500)?;
+        }
+        // The pointer is added once a pointer device shows up (see devices.rs)
 
         // DO NOT CARE ABOUT egl hardware acceleration
         // because it's the mechanism used by mesa internally before the
@@ -539,34 +1004,1448 @@ impl AIGIState {
         // is part of the rendering part... I can't understand it for now so I will go deeper
         // later... hope the global with the default feedback is enough for now
 
-        let tiling_state = TilingState::init();
+        let mut tiling_state = TilingState::init();
+        tiling_state.set_spiral(config.tiling.spiral);
+        let mut workspaces = Workspaces::init();
+        workspaces.set_spiral(config.tiling.spiral);
+        let permissions = Permissions::new(&config.permissions, &config.permission_helpers);
+        // Sensitive globals, advertised only to the allowed clients
+        screencopy::create_global(&dh, &permissions);
+        content_type::create_global(&dh);
+        pointer_warp::create_global(&dh);
+        toplevel_icon::create_global(&dh);
+        toplevel_drag::create_global(&dh);
+        VirtualKeyboardManagerState::new::<AIGIState, _>(
+            &dh,
+            permissions.filter(Permission::VirtualKeyboard),
+        );
 
         Ok(AIGIState {
             display_handle: dh,
             handle: even_loop_handle,
             space,
+            disabled_outputs: vec![],
             compositor_state,
             xdg_shell_state,
+            xdg_decoration_state,
+            xdg_activation_state,
+            layer_shell_state,
             shm_state,
             output_manager_state,
             seat_state,
-            //data_device_state,
+            data_device_state,
+            primary_selection_state,
             seat,
             pointer_location: (0.0, 0.0).into(),
+            pointer_enter: None,
             cursor_status: CursorImageStatus::Default,
+            chord_state: ChordState::default(),
+            edge_swipe: None,
+            swipe_gesture: None,
+            input_devices: InputDevices::load(),
+            pressed_keys: PressedKeys::default(),
+            game_mode: false,
+            on_battery: crate::power::on_battery(),
+            power_timer_armed: false,
+            idle_state: IdleState::default(),
+            render_state: RenderState::default(),
+            osd: None,
+            text_osd: None,
+            notification_state: NotificationState::default(),
+            marks: Marks::default(),
+            decoration_state: DecorationState::default(),
+            tab_state: TabState::default(),
             tiling_state,
+            floating_state: FloatingState::default(),
+            focus_history: FocusHistory::default(),
+            maximized: None,
+            fullscreen: None,
+            unmapped: vec![],
+            workspaces,
+            ipc_state: IpcState::default(),
+            ext_workspace_state: ExtWorkspaceState::default(),
+            toplevel_drag_state: ToplevelDragState::default(),
+            ping_state: PingState::default(),
+            force_close: None,
+            overview: None,
+            placement: None,
+            layer_focus: None,
+            protocol_log: None,
+            permissions,
+            scripting: None,
+            layouts: LayoutRegistry::default(),
+            status_state: StatusState::default(),
+            layout_restore: saved_layout::load(&config.tiling),
             running: AtomicBool::new(true),
+            relaunch: false,
+            relaunch_asked: None,
+            config_path,
             backend_data,
+            config,
             dmabuf_default_feedback,
             dmabuf_state,
             clock,
         })
     }
 
-    pub fn get_output(&mut self) -> Result<&Output, Box<dyn std::error::Error>> {
+    /// The output rendered by the backend, None if it's not mapped
+    pub fn rendered_output(&self) -> Option<Output> {
+        let name = self.backend_data.output_name();
         self.space
             .outputs()
+            .find(|output| output.name() == name)
+            .cloned()
+    }
+
+    /// Output the active workspace is tiled on: the one of the focused
+    /// window, or the one under the pointer when nothing is focused
+    pub fn active_output(&self) -> Option<Output> {
+        let focused = self
+            .seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.current_focus())
+            .and_then(|wl_surface| self.window_for_surface(&wl_surface))
+            .and_then(|window| self.space.outputs_for_element(&window).into_iter().next());
+        focused
+            .or_else(|| {
+                self.space
+                    .output_under(self.pointer_location)
+                    .next()
+                    .cloned()
+            })
+            .or_else(|| self.rendered_output())
+    }
+
+    /// Read again the config file, the new values are used from now on.
+    /// If the file is invalid the current config is kept
+    pub fn reload_config(&mut self) {
+        let config = match Config::load(self.config_path.clone()) {
+            Ok(config) => config,
+            Err(err) => {
+                println!("Config not reloaded: {err:?}");
+                return;
+            }
+        };
+
+        let cursor_changed = config.cursor != self.config.cursor;
+        let keyboard_changed = config.keyboard != self.config.keyboard;
+        let decorations_changed = config.decorations != self.config.decorations;
+        self.config = config;
+        self.permissions
+            .set_config(&self.config.permissions, &self.config.permission_helpers);
+        crash::set_config(self.config.crash.clone());
+        self.tiling_state.set_spiral(self.config.tiling.spiral);
+        self.workspaces.set_spiral(self.config.tiling.spiral);
+
+        if decorations_changed {
+            self.decoration_state.reload();
+            self.tab_state.reload();
+            let mode = self.preferred_decoration_mode();
+            let windows = self.space.elements().cloned().collect::<Vec<_>>();
+            for window in windows {
+                self.set_decoration_mode(window.toplevel(), mode);
+            }
+            // The size of the decorations could be different
+            if let Some(head) = self.tiling_state.tile_tree_head {
+                self.update_tiles(head);
+            }
+        }
+
+        if keyboard_changed {
+            let keyboard_config = self.config.keyboard.clone();
+            keyboard_config.verify_keymap();
+            keyboard_config.export_env();
+            // Clients get the new keymap right away
+            let keyboard = self.seat.get_keyboard().unwrap();
+            if let Err(err) = keyboard.set_xkb_config(self, keyboard_config.xkb_config()) {
+                println!("Keymap not changed: {err:?}");
+            }
+        }
+
+        if cursor_changed {
+            self.config.cursor.export_env();
+            reload_cursor_theme(self);
+        }
+
+        let outputs = self.space.outputs().cloned().collect::<Vec<_>>();
+        for output in outputs {
+            outputs::apply_settings(self, &output);
+        }
+        schedule_render(self);
+
+        // The idle timeouts could have been enabled
+        idle::arm_timer(self);
+        devices::reconfigure(self);
+        scripting::reload(self);
+    }
+
+    /// Hide the windows of the active workspace and show the ones
+    /// of the workspace at `index`, sliding from one to the other.
+    /// The sticky windows (eg. picture-in-picture) stay where they are
+    pub fn switch_workspace(&mut self, index: usize) {
+        if index == self.workspaces.active() {
+            return;
+        }
+        // The overview shows only the windows of the workspace it was opened on
+        self.overview = None;
+        placement::confirm(self);
+
+        // Maximized and fullscreen windows are restored before being hidden
+        if let Some(window) = self.maximized.clone() {
+            self.toggle_maximize(window.toplevel().wl_surface());
+        }
+        if let Some(window) = self.fullscreen.clone() {
+            self.toggle_fullscreen(window.toplevel().wl_surface());
+        }
+
+        let windows = self
+            .space
+            .elements()
+            .filter(|w| !self.floating_state.is_sticky(w.toplevel().wl_surface()))
+            .map(|w| {
+                (
+                    w.clone(),
+                    self.space.element_location(w).unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<_>>();
+        for (window, _) in &windows {
+            self.space.unmap_elem(window);
+        }
+        let mut sticky = FloatingState::default();
+        self.floating_state.move_sticky(&mut sticky);
+
+        let direction = if index > self.workspaces.active() {
+            1
+        } else {
+            -1
+        };
+        let leaving = Workspace {
+            tiling_state: std::mem::replace(&mut self.tiling_state, TilingState::init()),
+            floating_state: std::mem::take(&mut self.floating_state),
+            focus_history: std::mem::take(&mut self.focus_history),
+            windows: windows.clone(),
+        };
+        let entering = self.workspaces.switch(index, leaving);
+
+        self.tiling_state = entering.tiling_state;
+        self.floating_state = entering.floating_state;
+        sticky.move_sticky(&mut self.floating_state);
+        self.focus_history = entering.focus_history;
+        for (window, location) in entering.windows {
+            self.space.map_element(window, location, false);
+        }
+
+        // Tiles could have changed while the workspace was hidden
+        // (eg. a window was closed), so their geometry is applied again
+        match self.tiling_state.tile_tree_head {
+            Some(head) => self.update_tiles(head),
+            None => self.raise_floating(),
+        }
+
+        // The focus can't stay on a window that is not visible anymore,
+        // it goes to the window focused last time the workspace was shown
+        let last_focused = self.focus_history.last();
+        self.set_keyboard_focus(last_focused.as_ref());
+
+        start_workspace_transition(self, windows, direction);
+        osd::show_workspace(self);
+        ipc::workspaces_changed(self);
+        scripting::workspace_changed(self, index);
+    }
+
+    /// Tile the active workspace with the layout called `name`
+    pub fn set_layout(&mut self, name: &str) -> Result<(), String> {
+        let engine = self.layouts.create(name)?;
+        self.tiling_state.set_engine(engine);
+        if let Some(head) = self.tiling_state.tile_tree_head {
+            // The tree geometries are applied again from the head
+            if let Err(err) = self.tiling_state.update_geometry_node(head, None) {
+                println!("Tiles not resized: {err}");
+            }
+            self.update_tiles(head);
+        }
+        ipc::workspaces_changed(self);
+        Ok(())
+    }
+
+    /// Tile the active workspace with the layout after its current one
+    pub fn cycle_layout(&mut self) {
+        let next = self.layouts.next(self.tiling_state.layout_name());
+        if let Err(err) = self.set_layout(next) {
+            println!("Layout not changed: {err}");
+        }
+    }
+
+    /// Send a window of the active workspace to the hidden workspace at `index`
+    pub fn move_to_workspace(&mut self, window: &Window, index: usize) {
+        if index == self.workspaces.active() || index >= WORKSPACE_COUNT {
+            return;
+        }
+        let wl_surface = window.toplevel().wl_surface().clone();
+        if self.space.element_location(window).is_none() {
+            return;
+        }
+
+        // Maximized and fullscreen windows are restored before being hidden
+        if self.maximized.as_ref() == Some(window) {
+            self.toggle_maximize(&wl_surface);
+        }
+        if self.fullscreen.as_ref() == Some(window) {
+            self.toggle_fullscreen(&wl_surface);
+        }
+        let focused =
+            self.seat.get_keyboard().unwrap().current_focus().as_ref() == Some(&wl_surface);
+
+        self.space.unmap_elem(window);
+        self.focus_history.remove(window);
+        let floating = self.floating_state.remove(&wl_surface);
+        if floating.is_none() {
+            self.untile(&wl_surface);
+        }
+        let area = outputs::tiling_area(self)
+            .unwrap_or_else(|| Rectangle::from_loc_and_size((0, 0), (800, 800)));
+        self.workspaces
+            .insert_window(index, window.clone(), floating, area);
+
+        if focused {
+            let next = self.focus_history.last();
+            self.set_keyboard_focus(next.as_ref());
+        }
+        schedule_render(self);
+        ipc::workspaces_changed(self);
+    }
+
+    /// Give the keyboard focus to the window, or take it from every window
+    pub fn set_keyboard_focus(&mut self, window: Option<&Window>) {
+        let serial = SERIAL_COUNTER.next_serial();
+        let wl_surface = window.map(|window| window.toplevel().wl_surface().clone());
+        // It's focused once the launcher (or whatever holds the keyboard) goes away
+        if layer_focus::defer(self, wl_surface.as_ref()) {
+            return;
+        }
+        // A tab not active is shown first
+        if let Some(tabbed) = wl_surface
+            .as_ref()
+            .and_then(|wl_surface| self.tiling_state.show_tab(wl_surface))
+        {
+            self.update_tiles(tabbed);
+        }
+        self.seat
+            .get_keyboard()
+            .unwrap()
+            .set_focus(self, wl_surface, serial);
+    }
+
+    /// The container of the focused tile goes from split to tabbed,
+    /// to stacked and back to split
+    pub fn cycle_container_mode(&mut self, wl_surface: &WlSurface) {
+        match self.tiling_state.cycle_container_mode(wl_surface) {
+            Ok(Some(container)) => self.update_tiles(container),
+            Ok(None) => (),
+            Err(err) => println!("Container not changed: {err}"),
+        }
+    }
+
+    /// The next (or previous) tab of the tabbed container of the tile is shown and focused
+    pub fn cycle_tab(&mut self, wl_surface: &WlSurface, forward: bool) {
+        match self.tiling_state.cycle_tab(wl_surface, forward) {
+            Ok(Some((tabbed, window))) => {
+                self.update_tiles(tabbed);
+                self.set_keyboard_focus(Some(&window));
+            }
+            Ok(None) => (),
+            Err(err) => println!("Tab not changed: {err}"),
+        }
+    }
+
+    /// Where the clients see the pointer: over a rescaled window (letterboxed
+    /// or shrunk into its tile) the point on the screen is mapped back
+    /// to the unscaled window
+    pub fn client_pointer_location(&self, point: Point<f64, Logical>) -> Point<f64, Logical> {
+        self.space
+            .elements()
+            .find_map(|window| {
+                letterbox(&self.space, window)
+                    .or_else(|| shrink_to_tile(&self.space, &self.tiling_state, window))
+                    .and_then(|rescaled| rescaled.unscale(window, &self.space, point))
+            })
+            .unwrap_or(point)
+    }
+
+    /// Where a point seen by the clients is drawn on the screen,
+    /// the inverse of `client_pointer_location`
+    pub fn screen_pointer_location(&self, point: Point<f64, Logical>) -> Point<f64, Logical> {
+        self.space
+            .elements()
+            .find_map(|window| {
+                letterbox(&self.space, window)
+                    .or_else(|| shrink_to_tile(&self.space, &self.tiling_state, window))
+                    .and_then(|rescaled| rescaled.rescale(window, &self.space, point))
+            })
+            .unwrap_or(point)
+    }
+
+    /// Move the pointer to the center of the window,
+    /// the client under it gets the motion like for a real one
+    pub fn warp_pointer_to(&mut self, window: &Window) {
+        let Some(location) = self.space.element_location(window) else {
+            return;
+        };
+        let size = window.geometry().size;
+        let center =
+            Point::<i32, Logical>::from((location.x + size.w / 2, location.y + size.h / 2))
+                .to_f64();
+        self.warp_pointer(center);
+    }
+
+    /// Move the pointer to a point of the screen,
+    /// the client under it gets the motion like for a real one
+    pub fn warp_pointer(&mut self, location: Point<f64, Logical>) {
+        self.pointer_location = location;
+        toplevel_drag::motion(self);
+        let location = self.client_pointer_location(location);
+
+        let under = self
+            .window_under(location)
+            .and_then(|(window, window_location)| {
+                window
+                    .surface_under(location - window_location.to_f64(), WindowSurfaceType::ALL)
+                    .map(|(s, p)| (s, p + window_location))
+            });
+        if let Some(pointer) = self.seat.get_pointer() {
+            let event = MotionEvent {
+                location,
+                serial: SERIAL_COUNTER.next_serial(),
+                time: Duration::from(self.clock.now()).as_millis() as u32,
+            };
+            pointer_warp::motion(self, under.as_ref(), event.serial);
+            pointer.motion(self, under, &event);
+        }
+        schedule_render(self);
+    }
+
+    /// The window under a point and where it's drawn, like `Space::element_under`
+    /// but without the window moved by a toplevel drag: the drop target is below it
+    pub fn window_under(
+        &self,
+        location: Point<f64, Logical>,
+    ) -> Option<(Window, Point<i32, Logical>)> {
+        let dragged = toplevel_drag::dragged(self);
+        self.space
+            .elements()
+            .rev()
+            .filter(|window| Some(*window) != dragged.as_ref())
+            .find_map(|window| {
+                let bbox = self.space.element_bbox(window)?;
+                let render_location = self.space.element_location(window)? - window.geometry().loc;
+                let under = bbox.to_f64().contains(location)
+                    && window.is_in_input_region(&(location - render_location.to_f64()));
+                under.then(|| (window.clone(), render_location))
+            })
+    }
+
+    /// The surface of a toplevel, found from its xdg_toplevel
+    pub fn toplevel_wl_surface(&self, toplevel: &xdg_toplevel::XdgToplevel) -> Option<WlSurface> {
+        self.xdg_shell_state
+            .toplevel_surfaces()
+            .iter()
+            .find(|surface| surface.xdg_toplevel() == toplevel)
+            .map(|surface| surface.wl_surface().clone())
+    }
+
+    /// Focus the tile next to the focused one in the direction,
+    /// from a floating window the focus doesn't move
+    pub fn focus_direction(&mut self, direction: Direction) {
+        let Some(wl_surface) = self.seat.get_keyboard().unwrap().current_focus() else {
+            return;
+        };
+        let Some(window) = self.tiling_state.focus_in_direction(&wl_surface, direction) else {
+            return;
+        };
+        self.set_keyboard_focus(Some(&window));
+        // Otherwise the focus would follow the pointer as soon as it moves
+        if self.config.focus.warp_pointer {
+            self.warp_pointer_to(&window);
+        }
+    }
+
+    /// Focus the window that had the focus before the current one
+    pub fn focus_previous(&mut self) {
+        let current = self
+            .seat
+            .get_keyboard()
+            .unwrap()
+            .current_focus()
+            .and_then(|wl_surface| self.window_for_surface(&wl_surface));
+        if let Some(previous) = self.focus_history.previous(current.as_ref()) {
+            self.set_keyboard_focus(Some(&previous));
+        }
+    }
+
+    /// Update the space with the geometries of the subtree under the node,
+    /// keeping the floating windows above the tiled ones
+    #[profiling::function]
+    pub fn update_tiles(&mut self, node: Node) {
+        let focus = self.seat.get_keyboard().unwrap().current_focus();
+        let config = &self.config.decorations;
+        let decoration_state = &self.decoration_state;
+        let lone_tile = self.lone_tile();
+        let smart_borders = self.config.tiling.smart_borders && lone_tile;
+        let insets = |window: &Window| {
+            let status = decoration_state.status(window.toplevel().wl_surface(), focus.as_ref());
+            let border = match smart_borders {
+                true => 0,
+                false => decorations::border_width(config, status, false),
+            };
+            decorations::insets(window, config, border)
+        };
+        let gaps = match self.config.tiling.smart_gaps && lone_tile {
+            true => 0,
+            false => self.config.tiling.gaps,
+        };
+        if let Err(err) = self
+            .tiling_state
+            .update_space(node, &mut self.space, &insets, gaps)
+        {
+            println!("Tiles not updated: {err}");
+        }
+        self.raise_floating();
+        // A maximized tile is part of the tree, but it must not
+        // go back in its tile until it's restored
+        if let Some(window) = self.maximized.clone() {
+            self.apply_maximize(&window);
+        }
+        if let Some(window) = self.fullscreen.clone() {
+            self.apply_fullscreen(&window);
+        }
+        schedule_render(self);
+    }
+
+    /// Remove the window from the tiling tree, the remaining tiles take
+    /// its space. A tree out of sync is reported, the session goes on
+    ///
+    /// Returns the node that took the space of the window
+    fn untile(&mut self, wl_surface: &WlSurface) -> Option<Node> {
+        match self.tiling_state.destroy(wl_surface) {
+            Ok(Some(node)) => {
+                self.update_tiles(node);
+                Some(node)
+            }
+            Ok(None) => None,
+            Err(err) => {
+                println!("Tile not removed: {err}");
+                None
+            }
+        }
+    }
+
+    /// Make the window cover the usable area of its output, or restore
+    /// it (in its tile or in its floating geometry) if already maximized
+    pub fn toggle_maximize(&mut self, wl_surface: &WlSurface) {
+        let Some(window) = self.window_for_surface(wl_surface) else {
+            return;
+        };
+
+        if self.maximized.as_ref() == Some(&window) {
+            self.maximized = None;
+            window.toplevel().with_pending_state(|state| {
+                state.states.unset(xdg_toplevel::State::Maximized);
+            });
+
+            if let Some(geometry) = self.floating_state.geometry(wl_surface) {
+                window.toplevel().with_pending_state(|state| {
+                    state.size = Some(geometry.size);
+                });
+                window.toplevel().send_configure();
+                self.space.map_element(window, geometry.loc, false);
+                self.raise_floating();
+                schedule_render(self);
+            } else if let Some(node) = self.tiling_state.tile_node(wl_surface) {
+                self.update_tiles(node);
+            }
+            return;
+        }
+
+        // Only one window at the time can be maximized
+        if let Some(previous) = self.maximized.clone() {
+            self.toggle_maximize(previous.toplevel().wl_surface());
+        }
+
+        self.maximized = Some(window.clone());
+        self.apply_maximize(&window);
+        schedule_render(self);
+    }
+
+    /// Size the maximized window to the output, without the space of the panels
+    pub fn apply_maximize(&mut self, window: &Window) {
+        // It's maximized again when it leaves the fullscreen
+        if self.fullscreen.as_ref() == Some(window) {
+            return;
+        }
+        let Some(location) = self.space.element_location(window) else {
+            return;
+        };
+        let Some(output) = self.space.output_under(location.to_f64()).next().cloned() else {
+            return;
+        };
+        let Some(output_geometry) = self.space.output_geometry(&output) else {
+            return;
+        };
+        // Panels and bars keep their space
+        let zone = layer_map_for_output(&output).non_exclusive_zone();
+        let geometry = Rectangle::from_loc_and_size(output_geometry.loc + zone.loc, zone.size);
+        let geometry = self.decoration_insets(window).shrink(geometry);
+
+        window.toplevel().with_pending_state(|state| {
+            state.states.set(xdg_toplevel::State::Maximized);
+            state.size = Some(geometry.size);
+        });
+        window.toplevel().send_configure();
+        self.space.map_element(window.clone(), geometry.loc, true);
+    }
+
+    /// Make the window cover its whole output, above the panels too,
+    /// or restore it (in its tile or in its floating geometry)
+    pub fn toggle_fullscreen(&mut self, wl_surface: &WlSurface) {
+        let Some(window) = self.window_for_surface(wl_surface) else {
+            return;
+        };
+
+        if self.fullscreen.as_ref() == Some(&window) {
+            self.fullscreen = None;
+            window.toplevel().with_pending_state(|state| {
+                state.states.unset(xdg_toplevel::State::Fullscreen);
+            });
+
+            if self.maximized.as_ref() == Some(&window) {
+                self.apply_maximize(&window);
+            } else if let Some(geometry) = self.floating_state.geometry(wl_surface) {
+                window.toplevel().with_pending_state(|state| {
+                    state.size = Some(geometry.size);
+                });
+                window.toplevel().send_configure();
+                self.space.map_element(window, geometry.loc, false);
+                self.raise_floating();
+            } else if let Some(node) = self.tiling_state.tile_node(wl_surface) {
+                self.update_tiles(node);
+            }
+            schedule_render(self);
+            return;
+        }
+
+        // Only one window at the time can be fullscreen
+        if let Some(previous) = self.fullscreen.clone() {
+            self.toggle_fullscreen(previous.toplevel().wl_surface());
+        }
+
+        self.fullscreen = Some(window.clone());
+        self.apply_fullscreen(&window);
+        schedule_render(self);
+    }
+
+    /// Size the fullscreen window to its output and raise it above everything
+    pub fn apply_fullscreen(&mut self, window: &Window) {
+        let Some(location) = self.space.element_location(window) else {
+            return;
+        };
+        let Some(output) = self.space.output_under(location.to_f64()).next().cloned() else {
+            return;
+        };
+        let Some(geometry) = self.space.output_geometry(&output) else {
+            return;
+        };
+
+        window.toplevel().with_pending_state(|state| {
+            state.states.set(xdg_toplevel::State::Fullscreen);
+            state.size = Some(geometry.size);
+        });
+        window.toplevel().send_configure();
+        self.space.map_element(window.clone(), geometry.loc, true);
+    }
+
+    /// The fullscreen window covers this output (and its top layer),
+    /// the other outputs keep their panels
+    pub fn fullscreen_on(&self, output: &Output) -> bool {
+        self.fullscreen
+            .as_ref()
+            .and_then(|window| self.space.element_location(window))
+            .is_some_and(|location| {
+                self.space
+                    .output_under(location.to_f64())
+                    .any(|under| under == output)
+            })
+    }
+
+    fn preferred_decoration_mode(&self) -> DecorationMode {
+        if self.config.decorations.server_side {
+            DecorationMode::ServerSide
+        } else {
+            DecorationMode::ClientSide
+        }
+    }
+
+    // The new mode changes the size left to the client of tiled and
+    // maximized windows, the others just get a configure (if they had the first one)
+    fn set_decoration_mode(&mut self, toplevel: &ToplevelSurface, mode: DecorationMode) {
+        let changed = toplevel.with_pending_state(|state| {
+            let changed = state.decoration_mode != Some(mode);
+            state.decoration_mode = Some(mode);
+            changed
+        });
+        if !changed {
+            return;
+        }
+
+        let wl_surface = toplevel.wl_surface();
+        let window = self.window_for_surface(wl_surface);
+        let node = window
+            .as_ref()
+            .and_then(|_| self.tiling_state.tile_node(wl_surface));
+        match (window, node) {
+            // Fullscreen windows have no decorations anyway
+            (Some(window), _) if self.fullscreen.as_ref() == Some(&window) => {}
+            (Some(window), _) if self.maximized.as_ref() == Some(&window) => {
+                self.apply_maximize(&window)
+            }
+            (Some(_), Some(node)) => self.update_tiles(node),
+            // Floating and hidden windows keep their size
+            _ if initial_configure_sent(toplevel) => toplevel.send_configure(),
+            _ => {}
+        }
+        schedule_render(self);
+    }
+
+    /// Space taken by the decorations of the window in its current state
+    pub fn decoration_insets(&self, window: &Window) -> Insets {
+        let wl_surface = window.toplevel().wl_surface();
+        let focus = self.seat.get_keyboard().unwrap().current_focus();
+        let status = self.decoration_state.status(wl_surface, focus.as_ref());
+        decorations::insets(
+            window,
+            &self.config.decorations,
+            self.border_width(window, status),
+        )
+    }
+
+    /// Border of the window for its status, a lone tile has none with `smart_borders`
+    pub fn border_width(&self, window: &Window, status: DecorationStatus) -> i32 {
+        let floating = self
+            .floating_state
+            .is_floating(window.toplevel().wl_surface());
+        if !floating && self.config.tiling.smart_borders && self.lone_tile() {
+            return 0;
+        }
+        decorations::border_width(&self.config.decorations, status, floating)
+    }
+
+    /// The only tiled window of the workspace, `smart_borders` and
+    /// `smart_gaps` leave it without border and gaps
+    pub fn lone_tile(&self) -> bool {
+        self.tiling_state.tile_count() == 1
+    }
+
+    /// The border width of the tiles depends on their state, so
+    /// the tiles are updated if the state of a window changed it
+    pub fn decoration_status_changed(&mut self) {
+        let widths = &self.config.decorations.border_width;
+        let same_width = widths.focused == widths.unfocused && widths.focused == widths.urgent;
+        if same_width {
+            schedule_render(self);
+            return;
+        }
+        match self.tiling_state.tile_tree_head {
+            Some(head) => self.update_tiles(head),
+            None => schedule_render(self),
+        }
+    }
+
+    /// Decoration under the point, if it's not covered by another window
+    pub fn decoration_under(&self, point: Point<f64, Logical>) -> Option<(Window, DecorationHit)> {
+        // From the topmost window, the first one that contains
+        // the point (client area or decorations) wins
+        for window in self.space.elements().rev() {
+            let location = self.space.element_location(window)?;
+            let client = Rectangle::from_loc_and_size(location, window.geometry().size);
+            if client.to_f64().contains(point) {
+                return None;
+            }
+            let relative = point - location.to_f64();
+            if let Some(hit) = decorations::hit(window, &self.config.decorations, relative) {
+                return Some((window.clone(), hit));
+            }
+        }
+        None
+    }
+
+    /// Action of a titlebar button, clicked on the window of `wl_surface`
+    pub fn decoration_button(&mut self, wl_surface: &WlSurface, button: DecorationButton) {
+        let Some(window) = self.window_for_surface(wl_surface) else {
+            return;
+        };
+        match button {
+            DecorationButton::Close => force_close::close_window(self, &window),
+            DecorationButton::Maximize => self.toggle_maximize(wl_surface),
+            DecorationButton::Float => self.toggle_floating(wl_surface),
+        }
+    }
+
+    /// Take a tiled window out of the tiling tree, keeping its geometry,
+    /// or put a floating window back in the tree next to the tile below it
+    pub fn toggle_floating(&mut self, wl_surface: &WlSurface) {
+        let Some(window) = self.window_for_surface(wl_surface) else {
+            return;
+        };
+        if self.maximized.as_ref() == Some(&window) {
+            self.toggle_maximize(wl_surface);
+        }
+        let Some(location) = self.space.element_location(&window) else {
+            return;
+        };
+        let geometry = Rectangle::from_loc_and_size(location, window.geometry().size);
+
+        if self.floating_state.remove(wl_surface).is_some() {
+            let center = Point::from((
+                location.x + geometry.size.w / 2,
+                location.y + geometry.size.h / 2,
+            ));
+            let node = match self.tiling_state.tile_near(center) {
+                Some(sibling) => self.tiling_state.split(sibling, window),
+                None => {
+                    let Some(area) = self.output_geometry_at(center.to_f64()) else {
+                        return;
+                    };
+                    self.tiling_state.insert_head(window, area)
+                }
+            };
+            match node {
+                Ok(node) => self.update_tiles(node),
+                // The window stays floating
+                Err(err) => {
+                    println!("Window not tiled: {err}");
+                    self.floating_state.set_geometry(wl_surface, geometry);
+                }
+            }
+            return;
+        }
+
+        self.untile(wl_surface);
+        self.floating_state.set_geometry(wl_surface, geometry);
+        window.toplevel().with_pending_state(|state| {
+            state.bounds = None;
+        });
+        self.space.map_element(window, location, true);
+        self.raise_floating();
+        schedule_render(self);
+    }
+
+    /// Floating windows are always stacked above the tiled ones,
+    /// and the always on top ones above everything else
+    pub fn raise_floating(&mut self) {
+        let (on_top, floating): (Vec<Window>, Vec<Window>) = self
+            .space
+            .elements()
+            .filter(|w| self.floating_state.is_floating(w.toplevel().wl_surface()))
+            .cloned()
+            .partition(|w| {
+                self.floating_state
+                    .is_always_on_top(w.toplevel().wl_surface())
+            });
+
+        for window in floating.iter().chain(on_top.iter()) {
+            self.space.raise_element(window, false);
+        }
+        // Above everything, the always on top ones too
+        if let Some(window) = self.fullscreen.as_ref() {
+            self.space.raise_element(window, false);
+        }
+    }
+
+    // Check if the window looks like a picture-in-picture popup: a client
+    // that says it shows a video is enough, the others are recognized
+    // by title or because they don't want to be bigger than a small size
+    fn is_pip(&self, window: &Window) -> bool {
+        let pip = &self.config.pip;
+        if !pip.enabled {
+            return false;
+        }
+        if content_type::is_video(window.toplevel().wl_surface()) {
+            return true;
+        }
+
+        let (title, max_size) = with_states(window.toplevel().wl_surface(), |states| {
+            let title = states
+                .data_map
+                .get::<XdgToplevelSurfaceData>()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .title
+                .clone();
+            let max_size = states.cached_state.current::<SurfaceCachedState>().max_size;
+            (title, max_size)
+        });
+
+        // a zero dimension means that the size is not constrained
+        let small = max_size.w > 0
+            && max_size.h > 0
+            && max_size.w <= pip.max_size.0
+            && max_size.h <= pip.max_size.1;
+
+        title.as_deref() == Some(pip.title.as_str()) || small
+    }
+
+    // Commit of a layer surface: the first one gets the initial configure,
+    // then the surface is arranged again on every commit because the size,
+    // the anchors or the margins could have changed. Arranging sends a new
+    // configure if the size of the surface changes
+    fn layer_commit(&mut self, surface: &WlSurface) {
+        let Some(output) = self
+            .space
+            .outputs()
+            .find(|output| {
+                layer_map_for_output(output)
+                    .layer_for_surface(surface, WindowSurfaceType::TOPLEVEL)
+                    .is_some()
+            })
+            .cloned()
+        else {
+            return;
+        };
+
+        let initial_configure_sent = with_states(surface, |states| {
+            states
+                .data_map
+                .get::<LayerSurfaceData>()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .initial_configure_sent
+        });
+
+        let mut map = layer_map_for_output(&output);
+        let zone = map.non_exclusive_zone();
+        map.arrange();
+        let zone_changed = map.non_exclusive_zone() != zone;
+        if !initial_configure_sent {
+            if let Some(layer) = map.layer_for_surface(surface, WindowSurfaceType::TOPLEVEL) {
+                layer.layer_surface().send_configure();
+            }
+        }
+        drop(map);
+
+        // A bar appeared or changed its exclusive zone
+        if zone_changed {
+            outputs::output_changed(self, &output);
+        }
+        // Configured and drawn, it can take the keyboard
+        if initial_configure_sent {
+            layer_focus::layer_committed(self, surface);
+        }
+    }
+
+    // First commit with a buffer of a toplevel: it gets its place in the
+    // layout, floating if it's a picture-in-picture window, otherwise
+    // tiled splitting the focused tile.
+    //
+    // Returns false if the window waits for the manual placement
+    fn map_toplevel(&mut self, window: Window) -> bool {
+        if self.is_pip(&window) {
+            self.float_pip(&window);
+            ipc::workspaces_changed(self);
+            return true;
+        }
+        if self.overflow(&window) || self.split_refused(&window) {
+            return true;
+        }
+        if placement::start(self, &window) {
+            return false;
+        }
+        self.tile_window(window);
+        true
+    }
+
+    /// Tiles the active workspace can have, from the config of its output
+    /// (see `active_output`) or from the tiling config. None if there is no cap
+    pub fn max_tiles(&self) -> Option<usize> {
+        let output = self.active_output();
+        let max_tiles = output
+            .as_ref()
+            .and_then(|output| outputs::config(&self.config.outputs, output))
+            .and_then(|config| config.max_tiles)
+            .unwrap_or(self.config.tiling.max_tiles);
+        (max_tiles > 0).then_some(max_tiles)
+    }
+
+    // With the active workspace full the window is tiled on the next empty
+    // one, it stays where it is if every workspace has windows
+    fn overflow(&mut self, window: &Window) -> bool {
+        let Some(max_tiles) = self.max_tiles() else {
+            return false;
+        };
+        if self.tiling_state.tile_info.len() < max_tiles {
+            return false;
+        }
+        if !self.tile_on_next_empty(window) {
+            println!("Every workspace has windows, the tiles go over {max_tiles}");
+            return false;
+        }
+        true
+    }
+
+    // A split that would leave tiles smaller than `min_tile_size` is refused,
+    // the window goes where `refused_split` says
+    fn split_refused(&mut self, window: &Window) -> bool {
+        let (w, h) = self.config.tiling.min_tile_size;
+        let Some(target) = self.split_target() else {
+            return false;
+        };
+        if self
+            .tiling_state
+            .split_fits(target.toplevel().wl_surface(), Size::from((w, h)))
+        {
+            return false;
+        }
+
+        tracing::debug!("SPLIT REFUSED, THE TILES WOULD BE SMALLER THAN {w}x{h}");
+        match self.config.tiling.refused_split {
+            RefusedSplit::NextWorkspace if self.tile_on_next_empty(window) => return true,
+            RefusedSplit::Tabbed => match self.tiling_state.split_tabbed(target, window.clone()) {
+                Ok(tabbed) => {
+                    self.update_tiles(tabbed);
+                    ipc::workspaces_changed(self);
+                    return true;
+                }
+                Err(err) => println!("New window not tabbed: {err}"),
+            },
+            _ => (),
+        }
+        self.float_centered(window);
+        ipc::workspaces_changed(self);
+        true
+    }
+
+    // The window is tiled on the next empty workspace, returns false
+    // if every workspace has windows
+    fn tile_on_next_empty(&mut self, window: &Window) -> bool {
+        let Some(index) = self.workspaces.next_empty() else {
+            return false;
+        };
+
+        tracing::debug!("WINDOW OPENED ON WORKSPACE {index}");
+        let area = outputs::tiling_area(self)
+            .unwrap_or_else(|| Rectangle::from_loc_and_size((0, 0), (800, 800)));
+        self.workspaces
+            .insert_window(index, window.clone(), None, area);
+        ipc::broadcast(
+            self,
+            ipc::Event::WindowOverflowed {
+                title: decorations::title(window),
+                workspace: index,
+            },
+        );
+        ipc::workspaces_changed(self);
+        true
+    }
+
+    /// The tiled window under focus, if the focus is not on
+    /// a tile (eg. a floating window) any tile. New windows split it
+    pub fn split_target(&self) -> Option<Window> {
+        let area = outputs::tiling_area(self).unwrap_or_default();
+        self.seat
+            .get_keyboard()
+            .unwrap()
+            .current_focus()
+            .filter(|wl_surface| self.tiling_state.tile_node(wl_surface).is_some())
+            .and_then(|wl_surface| self.window_for_surface(&wl_surface))
+            .or_else(|| self.tiling_state.tile_near(area.loc))
+    }
+
+    /// Tile a new window splitting the `split_target`,
+    /// or in the whole area if it's the first one
+    pub fn tile_window(&mut self, window: Window) {
+        // render full size screen
+        let output_geometry = outputs::tiling_area(self)
+            .unwrap_or_else(|| Rectangle::from_loc_and_size((0, 0), (800, 800)));
+
+        // A window of the previous session goes back to its tile
+        let node_to_update = match saved_layout::place(self, &window) {
+            Some(result) => result,
+            None => match self.split_target() {
+                Some(focus_window) => self.tiling_state.split(focus_window, window),
+                None => self.tiling_state.insert_head(window, output_geometry),
+            },
+        };
+
+        match node_to_update {
+            Ok(node) => self.update_tiles(node),
+            Err(err) => println!("New window not tiled: {err}"),
+        }
+        ipc::workspaces_changed(self);
+    }
+
+    // A new window floating in the middle of the tiling area, with its own size
+    fn float_centered(&mut self, window: &Window) {
+        let area = outputs::tiling_area(self)
+            .unwrap_or_else(|| Rectangle::from_loc_and_size((0, 0), (800, 800)));
+        let size = window.geometry().size;
+        let location = Point::from((
+            area.loc.x + (area.size.w - size.w) / 2,
+            area.loc.y + (area.size.h - size.h) / 2,
+        ));
+        self.space.map_element(window.clone(), location, true);
+        self.floating_state.set_geometry(
+            window.toplevel().wl_surface(),
+            Rectangle::from_loc_and_size(location, size),
+        );
+        self.raise_floating();
+    }
+
+    // Remove the window from the tiling tree and park it, floating,
+    // always on top and sticky, in the configured corner of the output
+    fn float_pip(&mut self, window: &Window) {
+        let wl_surface = window.toplevel().wl_surface();
+        tracing::debug!("PIP WINDOW DETECTED");
+
+        if self.tiling_state.tile_node(wl_surface).is_some() {
+            self.untile(wl_surface);
+        }
+
+        let Some(output_geometry) = self.output_geometry_at(self.pointer_location) else {
+            return;
+        };
+        let size = Size::from(self.config.pip.size);
+        let location = park(
+            size,
+            output_geometry,
+            self.config.pip.corner,
+            self.config.pip.margin,
+        );
+
+        window.toplevel().with_pending_state(|state| {
+            state.size = Some(size);
+        });
+        self.space.map_element(window.clone(), location, false);
+        self.floating_state
+            .set_geometry(wl_surface, Rectangle::from_loc_and_size(location, size));
+        self.floating_state.set_always_on_top(wl_surface, true);
+        self.floating_state.set_sticky(wl_surface, true);
+        self.raise_floating();
+    }
+
+    /// When game mode is enabled and the focused window is fullscreen
+    /// that window is returned: it is the only thing that needs to be rendered
+    /// and every effect (animations, dimming, ...) is skipped.
+    ///
+    /// Direct scanout and tearing are deferred: the GbmBufferedSurface of
+    /// the backend can only flip to its own buffers, and it always waits for
+    /// the VBlank (see the README)
+    pub fn game_mode_window(&self) -> Option<Window> {
+        if !self.game_mode {
+            return None;
+        }
+
+        let focus = self.seat.get_keyboard()?.current_focus()?;
+        let window = self.window_for_surface(&focus)?;
+        let fullscreen = window
+            .toplevel()
+            .current_state()
+            .states
+            .contains(xdg_toplevel::State::Fullscreen);
+
+        fullscreen.then_some(window)
+    }
+
+    /// Animations are a waste of energy while on battery,
+    /// unless the user wants them anyway
+    pub fn animations_enabled(&self) -> bool {
+        !self.on_battery || self.config.power.battery_animations
+    }
+
+    /// Repaint rate cap, if any: the lowest between the cap of the
+    /// rendered output and the one used on battery.
+    ///
+    /// There is no direct scanout yet (every frame is composited),
+    /// so there is nothing to prefer when running on battery
+    pub fn max_fps(&self) -> Option<u32> {
+        let fps = self.config.power.battery_max_fps;
+        let battery = (self.on_battery && fps > 0).then_some(fps);
+        let output = self.rendered_output();
+        let output_cap = output
+            .as_ref()
+            .and_then(|output| FrameClock::of(output).borrow().max_fps());
+        battery.into_iter().chain(output_cap).min()
+    }
+
+    /// Map a new output in the space, in the position specified in the config.
+    /// Without a config the output is placed to the right of the already mapped ones
+    pub fn map_output(&mut self, output: &Output) {
+        let size = output
+            .current_mode()
+            .map(|mode| {
+                output
+                    .current_transform()
+                    .transform_size(mode.size)
+                    .to_f64()
+                    .to_logical(output.current_scale().fractional_scale())
+                    .to_i32_round()
+            })
+            .unwrap_or_default();
+
+        let output_geometry_by_name = |name: &str| {
+            self.space
+                .outputs()
+                .find(|o| outputs::is_named(o, name))
+                .and_then(|o| self.space.output_geometry(o))
+        };
+
+        outputs::apply_settings(self, output);
+        let config = outputs::config(&self.config.outputs, output);
+        let configured_position = config.and_then(|config| {
+            if let Some(position) = config.position {
+                return Some(Point::from(position));
+            }
+            if let Some(geo) = config.right_of.as_deref().and_then(output_geometry_by_name) {
+                return Some((geo.loc.x + geo.size.w, geo.loc.y).into());
+            }
+            if let Some(geo) = config.left_of.as_deref().and_then(output_geometry_by_name) {
+                return Some((geo.loc.x - size.w, geo.loc.y).into());
+            }
+            None
+        });
+
+        let position = configured_position.unwrap_or_else(|| {
+            self.layout_geometry()
+                .map(|layout| (layout.loc.x + layout.size.w, layout.loc.y).into())
+                .unwrap_or_default()
+        });
+
+        tracing::debug!("OUTPUT {} MAPPED AT {position:?}", output.name());
+        output.change_current_state(None, None, None, Some(position));
+        self.space.map_output(output, position);
+    }
+
+    /// Bounding box of all the outputs mapped in the space,
+    /// in global layout coordinates
+    pub fn layout_geometry(&self) -> Option<Rectangle<i32, Logical>> {
+        self.space
+            .outputs()
+            .filter_map(|output| self.space.output_geometry(output))
+            .reduce(|layout, geometry| layout.merge(geometry))
+    }
+
+    /// If the point is outside every output it's moved
+    /// to the closest point of the closest output
+    pub fn clamp_coords(&self, point: Point<f64, Logical>) -> Point<f64, Logical> {
+        if self.space.output_under(point).next().is_some() {
+            return point;
+        }
+
+        self.space
+            .outputs()
+            .filter_map(|output| self.space.output_geometry(output))
+            .map(|geometry| {
+                let geometry = geometry.to_f64();
+                // The right and bottom edges are excluded from the output
+                let max_x = geometry.loc.x + geometry.size.w - 1.0;
+                let max_y = geometry.loc.y + geometry.size.h - 1.0;
+                Point::<f64, Logical>::from((
+                    point.x.clamp(geometry.loc.x, max_x),
+                    point.y.clamp(geometry.loc.y, max_y),
+                ))
+            })
+            .min_by(|a, b| {
+                let distance = |p: &Point<f64, Logical>| (p.x - point.x).hypot(p.y - point.y);
+                distance(a).total_cmp(&distance(b))
+            })
+            .unwrap_or(point)
+    }
+
+    /// Geometry of the output under the point, or of the first output
+    /// if the point is outside every output
+    pub fn output_geometry_at(
+        &self,
+        point: Point<f64, Logical>,
+    ) -> Option<Rectangle<i32, Logical>> {
+        let output = self
+            .space
+            .output_under(point)
             .next()
-            .ok_or("No output available".into())
+            .or_else(|| self.space.outputs().next())?;
+        self.space.output_geometry(output)
+    }
+
+    /// Move and resize a floating window to a portion of its output,
+    /// tiled windows are left untouched
+    pub fn snap_floating(&mut self, wl_surface: &WlSurface, position: SnapPosition) {
+        let Some(current) = self.floating_state.geometry(wl_surface) else {
+            return;
+        };
+        let Some(window) = self.window_for_surface(wl_surface) else {
+            return;
+        };
+
+        // The window is snapped on the output that contains its center
+        let center = Point::<i32, Logical>::from((
+            current.loc.x + current.size.w / 2,
+            current.loc.y + current.size.h / 2,
+        ));
+        let Some(output_geometry) = self.output_geometry_at(center.to_f64()) else {
+            return;
+        };
+
+        let geometry = position.area(output_geometry);
+        window.toplevel().with_pending_state(|state| {
+            state.size = Some(geometry.size);
+        });
+        window.toplevel().send_configure();
+        self.space.map_element(window, geometry.loc, true);
+        self.floating_state.set_geometry(wl_surface, geometry);
+        schedule_render(self);
+    }
+
+    pub fn window_for_surface(&self, wl_surface: &WlSurface) -> Option<Window> {
+        self.space
+            .elements()
+            .find(|w| w.toplevel().wl_surface() == wl_surface)
+            .cloned()
+            // The tabs not active are not mapped
+            .or_else(|| self.tiling_state.window(wl_surface))
+    }
+
+    /// Start an interactive move of the window, it will follow
+    /// the pointer until `button` is released
+    pub fn start_move_grab(
+        &mut self,
+        window: Window,
+        start_data: PointerGrabStartData<Self>,
+        button: u32,
+        serial: Serial,
+    ) {
+        let Some(initial_window_location) = self.space.element_location(&window) else {
+            return;
+        };
+
+        let grab = MoveSurfaceGrab {
+            start_data,
+            window,
+            initial_window_location,
+            button,
+            drop_target: None,
+        };
+        let Some(pointer) = self.seat.get_pointer() else {
+            return;
+        };
+        pointer.set_grab(self, grab, serial, Focus::Clear);
+    }
+
+    /// Start an interactive resize of the window, if no edges are specified
+    /// they are choosen based on the position of the pointer over the window
+    pub fn start_resize_grab(
+        &mut self,
+        window: Window,
+        start_data: PointerGrabStartData<Self>,
+        edges: xdg_toplevel::ResizeEdge,
+        button: u32,
+        serial: Serial,
+    ) {
+        let Some(window_location) = self.space.element_location(&window) else {
+            return;
+        };
+
+        let edges = match edges {
+            xdg_toplevel::ResizeEdge::None => {
+                edges_from_location(&window, window_location, start_data.location)
+            }
+            edges => edges,
+        };
+        let Some(pointer) = self.seat.get_pointer() else {
+            return;
+        };
+
+        // A tile is resized moving the divisions of the tree, its
+        // neighbours are resized too and nothing overlaps
+        let wl_surface = window.toplevel().wl_surface().clone();
+        if self.tiling_state.tile_node(&wl_surface).is_some() {
+            let dividers = tile_edges(edges)
+                .into_iter()
+                .filter_map(|edge| self.tiling_state.divider(&wl_surface, edge))
+                .collect::<Vec<_>>();
+            // A single tile, or an edge on the border of the output
+            if dividers.is_empty() {
+                return;
+            }
+            window.toplevel().with_pending_state(|state| {
+                state.states.set(xdg_toplevel::State::Resizing);
+            });
+            let grab = TileResizeGrab {
+                start_data,
+                window,
+                dividers,
+                button,
+            };
+            pointer.set_grab(self, grab, serial, Focus::Clear);
+            return;
+        }
+
+        let initial_window_geometry =
+            Rectangle::from_loc_and_size(window_location, window.geometry().size);
+
+        let grab =
+            ResizeSurfaceGrab::new(start_data, window, edges, initial_window_geometry, button);
+        pointer.set_grab(self, grab, serial, Focus::Clear);
+    }
+
+    // A client can start a grab only if the serial refers to a button
+    // press that is still held and the pointer is over one of its surfaces
+    fn client_grab_start_data(
+        &self,
+        surface: &ToplevelSurface,
+        seat: &wl_seat::WlSeat,
+        serial: Serial,
+    ) -> Option<PointerGrabStartData<Self>> {
+        let seat = Seat::<Self>::from_resource(seat)?;
+        let pointer = seat.get_pointer()?;
+
+        if !pointer.has_grab(serial) {
+            return None;
+        }
+
+        let start_data = pointer.grab_start_data()?;
+        let (focus, _) = start_data.focus.as_ref()?;
+        if !focus.id().same_client_as(&surface.wl_surface().id()) {
+            return None;
+        }
+
+        Some(start_data)
+    }
+}
+
+// The first configure of a toplevel must be sent only after its first commit
+fn initial_configure_sent(toplevel: &ToplevelSurface) -> bool {
+    with_states(toplevel.wl_surface(), |states| {
+        states
+            .data_map
+            .get::<XdgToplevelSurfaceData>()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .initial_configure_sent
+    })
+}
+
+// Sides of a tile moved by dragging the edges of its window
+fn tile_edges(edges: xdg_toplevel::ResizeEdge) -> Vec<Direction> {
+    use xdg_toplevel::ResizeEdge;
+    match edges {
+        ResizeEdge::Left => vec![Direction::Left],
+        ResizeEdge::Right => vec![Direction::Right],
+        ResizeEdge::Top => vec![Direction::Up],
+        ResizeEdge::Bottom => vec![Direction::Down],
+        ResizeEdge::TopLeft => vec![Direction::Left, Direction::Up],
+        ResizeEdge::TopRight => vec![Direction::Right, Direction::Up],
+        ResizeEdge::BottomLeft => vec![Direction::Left, Direction::Down],
+        ResizeEdge::BottomRight => vec![Direction::Right, Direction::Down],
+        _ => vec![],
     }
 }
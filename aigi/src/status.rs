@@ -0,0 +1,114 @@
+use serde::Serialize;
+use smithay::reexports::calloop::timer::{TimeoutAction, Timer};
+use std::{io::Write, os::unix::net::UnixStream, time::Duration};
+
+use crate::{decorations, ipc, render, state::AIGIState};
+
+// The focused title changes without any event, so the status is sent
+// this often, and right away when the workspaces change
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Bars connected to the IPC with `status_line`, they get the status
+/// in the swaybar protocol: a header, then an array of blocks every update
+#[derive(Default)]
+pub struct StatusState {
+    bars: Vec<UnixStream>,
+    timer_armed: bool,
+}
+
+// A block of the swaybar protocol, only the fields that are used
+#[derive(Serialize)]
+struct Block {
+    name: &'static str,
+    full_text: String,
+}
+
+/// The bar gets the header of the protocol and the current status
+pub fn add_bar(state: &mut AIGIState, mut bar: UnixStream) {
+    if bar.write_all(b"{\"version\": 1}\n[\n").is_err() {
+        return;
+    }
+    state.status_state.bars.push(bar);
+    update(state);
+}
+
+pub fn arm_timer(state: &mut AIGIState) {
+    if state.status_state.timer_armed {
+        return;
+    }
+    state.status_state.timer_armed = true;
+    state
+        .handle
+        .insert_source(Timer::from_duration(STATUS_INTERVAL), |_, _, loop_data| {
+            let state = &mut loop_data.state;
+            // Without input and commits the title can't change
+            if render::suspend_timer(state) {
+                state.status_state.timer_armed = false;
+                return TimeoutAction::Drop;
+            }
+            update(state);
+            TimeoutAction::ToDuration(STATUS_INTERVAL)
+        })
+        .expect("failed to schedule status line timer");
+}
+
+/// Send the status to every bar, the ones that
+/// closed the connection are forgotten
+pub fn update(state: &mut AIGIState) {
+    if state.status_state.bars.is_empty() {
+        return;
+    }
+
+    let mut line = serde_json::to_vec(&blocks(state)).expect("IMP serialize status");
+    line.extend_from_slice(b",\n");
+    state
+        .status_state
+        .bars
+        .retain_mut(|bar| bar.write_all(&line).is_ok());
+}
+
+fn blocks(state: &AIGIState) -> Vec<Block> {
+    // Workspaces are numbered from 1 (see `auto_rename` for the names),
+    // the empty ones are hidden and the active one is between brackets
+    let workspaces = ipc::workspaces(state)
+        .into_iter()
+        .filter(|workspace| workspace.active || workspace.windows > 0)
+        .map(|workspace| match workspace.active {
+            true => format!("[{}]", workspace.name),
+            false => workspace.name,
+        })
+        .collect::<Vec<_>>();
+
+    let title = state
+        .seat
+        .get_keyboard()
+        .and_then(|keyboard| keyboard.current_focus())
+        .and_then(|wl_surface| state.window_for_surface(&wl_surface))
+        .map(|window| decorations::title(&window))
+        .unwrap_or_default();
+
+    // The configured one, the empty string is the system default
+    let keyboard_layout = match state.config.keyboard.layout.as_str() {
+        "" => std::env::var("XKB_DEFAULT_LAYOUT").unwrap_or_else(|_| "default".into()),
+        layout => layout.to_string(),
+    };
+
+    vec![
+        Block {
+            name: "workspaces",
+            full_text: workspaces.join(" "),
+        },
+        Block {
+            name: "title",
+            full_text: title,
+        },
+        Block {
+            name: "layout",
+            full_text: state.tiling_state.layout_name().to_string(),
+        },
+        Block {
+            name: "keyboard_layout",
+            full_text: keyboard_layout,
+        },
+    ]
+}
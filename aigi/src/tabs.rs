@@ -0,0 +1,178 @@
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            element::{
+                solid::SolidColorBuffer,
+                texture::{TextureBuffer, TextureRenderElement},
+            },
+            multigpu::MultiTexture,
+            ImportAll, ImportMem, Renderer,
+        },
+    },
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    utils::{Logical, Point, Rectangle, Scale, Transform},
+};
+use std::collections::HashMap;
+
+use crate::{
+    config::{Color, DecorationConfig},
+    decorations::{self, DecorationRenderElement},
+    tiling::{ContainerMode, TabStrip, TAB_HEIGHT},
+};
+
+// Space between the edges of a tab and its title
+const TAB_PADDING: i32 = 6;
+// Line between two tabs
+const SEPARATOR: i32 = 1;
+
+// Title already rasterized, recreated only if something changes
+struct TabTitle {
+    text: String,
+    max_width: i32,
+    scale: i32,
+    color: Color,
+    buffer: Option<TextureBuffer<MultiTexture>>,
+}
+
+#[derive(Default)]
+struct Tab {
+    background: SolidColorBuffer,
+    title: Option<TabTitle>,
+}
+
+/// The tabs drawn above the active tile of the tabbed and stacked containers,
+/// with the colors of the titlebars. Buffers are kept between frames so the
+/// damage tracker can tell if a tab really changed
+#[derive(Default)]
+pub struct TabState {
+    tabs: HashMap<WlSurface, Tab>,
+}
+
+impl TabState {
+    /// Forget the rasterized titles (eg. the config or the renderer changed)
+    pub fn reload(&mut self) {
+        self.tabs.clear();
+    }
+
+    /// Elements of the tabs of every strip, relative to the output at
+    /// `output_location`. The active tab has the colors of a focused titlebar
+    pub fn render_elements<R>(
+        &mut self,
+        renderer: &mut R,
+        config: &DecorationConfig,
+        font: Option<&fontdue::Font>,
+        strips: &[TabStrip],
+        output_location: Point<i32, Logical>,
+        scale: Scale<f64>,
+    ) -> Vec<DecorationRenderElement<R>>
+    where
+        R: Renderer<TextureId = MultiTexture> + ImportAll + ImportMem,
+    {
+        // Windows closed, or not in a tabbed container anymore
+        self.tabs.retain(|wl_surface, _| {
+            strips.iter().any(|strip| {
+                strip
+                    .windows
+                    .iter()
+                    .any(|window| window.toplevel().wl_surface() == wl_surface)
+            })
+        });
+
+        let int_scale = scale.x.ceil().max(1.0) as i32;
+        let mut elements = vec![];
+        for strip in strips {
+            let count = strip.windows.len() as i32;
+            let area = Rectangle::from_loc_and_size(
+                strip.geometry.loc - output_location,
+                strip.geometry.size,
+            );
+            for (index, window) in strip.windows.iter().enumerate() {
+                let wl_surface = window.toplevel().wl_surface();
+                let index = index as i32;
+                let geometry = match strip.mode {
+                    ContainerMode::Stacked => {
+                        let height = area.size.h / count.max(1);
+                        Rectangle::from_loc_and_size(
+                            (area.loc.x, area.loc.y + index * height),
+                            (area.size.w, height - SEPARATOR),
+                        )
+                    }
+                    ContainerMode::Split | ContainerMode::Tabbed => {
+                        let x = area.loc.x + area.size.w * index / count;
+                        let right = area.loc.x + area.size.w * (index + 1) / count;
+                        Rectangle::from_loc_and_size(
+                            (x, area.loc.y),
+                            (right - x - SEPARATOR, area.size.h.min(TAB_HEIGHT)),
+                        )
+                    }
+                };
+                let colors = match index as usize == strip.active {
+                    true => &config.focused,
+                    false => &config.unfocused,
+                };
+
+                let tab = self.tabs.entry(wl_surface.clone()).or_default();
+                let text = decorations::title(window);
+                let max_width = geometry.size.w - 2 * TAB_PADDING;
+                let needs_update = tab.title.as_ref().map_or(true, |current| {
+                    current.text != text
+                        || current.max_width != max_width
+                        || current.scale != int_scale
+                        || current.color != colors.text
+                });
+                if needs_update {
+                    let buffer = font.and_then(|font| {
+                        let (pixels, w, h) = decorations::rasterize(
+                            font,
+                            &text,
+                            config.font_size * int_scale as f32,
+                            max_width * int_scale,
+                            colors.text,
+                        )?;
+                        let texture = renderer
+                            .import_memory(&pixels, Fourcc::Abgr8888, (w, h).into(), false)
+                            .map_err(|err| println!("Impossible import tab texture: {err:?}"))
+                            .ok()?;
+                        Some(TextureBuffer::from_texture(
+                            renderer,
+                            texture,
+                            int_scale,
+                            Transform::Normal,
+                            None,
+                        ))
+                    });
+                    tab.title = Some(TabTitle {
+                        text,
+                        max_width,
+                        scale: int_scale,
+                        color: colors.text,
+                        buffer,
+                    });
+                }
+
+                if let Some(buffer) = tab.title.as_ref().and_then(|t| t.buffer.as_ref()) {
+                    let title_height = buffer.logical_size().h;
+                    let loc = geometry.loc
+                        + Point::from((TAB_PADDING, (geometry.size.h - title_height) / 2));
+                    elements.push(DecorationRenderElement::Title(
+                        TextureRenderElement::from_texture_buffer(
+                            loc.to_physical_precise_round(scale).to_f64(),
+                            buffer,
+                            None,
+                            None,
+                            None,
+                        ),
+                    ));
+                }
+                tab.background.update(geometry.size, colors.titlebar.0);
+                elements.push(decorations::solid_element(
+                    &tab.background,
+                    geometry.loc,
+                    scale,
+                ));
+            }
+        }
+        elements
+    }
+}
@@ -0,0 +1,176 @@
+use smithay::{
+    delegate_compositor, delegate_xdg_shell,
+    desktop::Window,
+    reexports::wayland_server::{
+        backend::ClientData,
+        protocol::{wl_seat, wl_surface::WlSurface},
+        Client, Display,
+    },
+    utils::Serial,
+    wayland::{
+        compositor::{CompositorClientState, CompositorHandler, CompositorState},
+        shell::xdg::{
+            PopupSurface, PositionerState, ToplevelSurface, XdgShellHandler, XdgShellState,
+        },
+    },
+};
+use std::{os::unix::net::UnixStream, sync::Arc};
+use wayland_client::{
+    delegate_noop,
+    protocol::{
+        wl_callback::WlCallback,
+        wl_compositor::WlCompositor,
+        wl_registry::{self, WlRegistry},
+        wl_surface::WlSurface as ClientSurface,
+    },
+    Connection, Dispatch, EventQueue, QueueHandle,
+};
+use wayland_protocols::xdg::shell::client::{
+    xdg_surface::XdgSurface, xdg_toplevel::XdgToplevel, xdg_wm_base::XdgWmBase,
+};
+
+/// Real windows for the tests, a smithay Window needs a toplevel created by
+/// a client. The client lives in the same thread and talks to a display with
+/// only the compositor and xdg-shell globals, the two sides are pumped by hand
+pub struct TestWindows {
+    display: Display<Server>,
+    server: Server,
+    connection: Connection,
+    queue: EventQueue<TestClient>,
+    client: TestClient,
+}
+
+impl TestWindows {
+    pub fn new() -> Self {
+        let mut display = Display::<Server>::new().unwrap();
+        let display_handle = display.handle();
+        let server = Server {
+            compositor_state: CompositorState::new::<Server>(&display_handle),
+            xdg_shell_state: XdgShellState::new::<Server>(&display_handle),
+            toplevels: vec![],
+        };
+
+        let (server_side, client_side) = UnixStream::pair().unwrap();
+        display
+            .handle()
+            .insert_client(server_side, Arc::new(ServerClient::default()))
+            .unwrap();
+        let connection = Connection::from_socket(client_side).unwrap();
+        let queue = connection.new_event_queue();
+        connection.display().get_registry(&queue.handle(), ());
+
+        let mut windows = Self {
+            display,
+            server,
+            connection,
+            queue,
+            client: TestClient::default(),
+        };
+        windows.roundtrip();
+        windows
+    }
+
+    /// New toplevels, without a buffer
+    pub fn create(&mut self, count: usize) -> Vec<Window> {
+        let qh = self.queue.handle();
+        let compositor = self.client.compositor.clone().unwrap();
+        let wm_base = self.client.wm_base.clone().unwrap();
+        for _ in 0..count {
+            let surface = compositor.create_surface(&qh, ());
+            let xdg_surface = wm_base.get_xdg_surface(&surface, &qh, ());
+            xdg_surface.get_toplevel(&qh, ());
+            surface.commit();
+        }
+        self.roundtrip();
+        self.server.toplevels.drain(..).map(Window::new).collect()
+    }
+
+    // The requests of the client are handled by the server and its events
+    // read back, the sync callback makes sure there is something to read
+    fn roundtrip(&mut self) {
+        self.connection.display().sync(&self.queue.handle(), ());
+        self.connection.flush().unwrap();
+        self.display.dispatch_clients(&mut self.server).unwrap();
+        self.display.flush_clients().unwrap();
+        if let Some(guard) = self.connection.prepare_read() {
+            guard.read().unwrap();
+        }
+        self.queue.dispatch_pending(&mut self.client).unwrap();
+    }
+}
+
+struct Server {
+    compositor_state: CompositorState,
+    xdg_shell_state: XdgShellState,
+    toplevels: Vec<ToplevelSurface>,
+}
+
+#[derive(Default)]
+struct ServerClient {
+    compositor_state: CompositorClientState,
+}
+
+impl ClientData for ServerClient {}
+
+impl CompositorHandler for Server {
+    fn compositor_state(&mut self) -> &mut CompositorState {
+        &mut self.compositor_state
+    }
+
+    fn client_compositor_state<'a>(&self, client: &'a Client) -> &'a CompositorClientState {
+        &client.get_data::<ServerClient>().unwrap().compositor_state
+    }
+
+    fn commit(&mut self, _surface: &WlSurface) {}
+}
+delegate_compositor!(Server);
+
+impl XdgShellHandler for Server {
+    fn xdg_shell_state(&mut self) -> &mut XdgShellState {
+        &mut self.xdg_shell_state
+    }
+
+    fn new_toplevel(&mut self, surface: ToplevelSurface) {
+        self.toplevels.push(surface);
+    }
+
+    fn new_popup(&mut self, _: PopupSurface, _: PositionerState) {}
+
+    fn grab(&mut self, _: PopupSurface, _: wl_seat::WlSeat, _: Serial) {}
+}
+delegate_xdg_shell!(Server);
+
+#[derive(Default)]
+struct TestClient {
+    compositor: Option<WlCompositor>,
+    wm_base: Option<XdgWmBase>,
+}
+
+impl Dispatch<WlRegistry, ()> for TestClient {
+    fn event(
+        client: &mut Self,
+        registry: &WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_registry::Event::Global {
+            name, interface, ..
+        } = event
+        {
+            match interface.as_str() {
+                "wl_compositor" => client.compositor = Some(registry.bind(name, 1, qh, ())),
+                "xdg_wm_base" => client.wm_base = Some(registry.bind(name, 1, qh, ())),
+                _ => (),
+            }
+        }
+    }
+}
+
+delegate_noop!(TestClient: WlCompositor);
+delegate_noop!(TestClient: ignore WlCallback);
+delegate_noop!(TestClient: ignore ClientSurface);
+delegate_noop!(TestClient: ignore XdgWmBase);
+delegate_noop!(TestClient: ignore XdgSurface);
+delegate_noop!(TestClient: ignore XdgToplevel);
@@ -1,18 +1,80 @@
+use serde::{Deserialize, Serialize};
+use slotmap::{new_key_type, SlotMap};
 use smithay::{
-    backend::egl::ffi::egl::types::__eglMustCastToProperFunctionPointerType,
-    desktop::{space::SpaceElement, Space, Window},
+    desktop::{Space, Window},
     reexports::wayland_server::protocol::wl_surface::WlSurface,
-    utils::{Logical, Point, Rectangle},
-    wayland::shell::xdg::ToplevelSurface,
+    utils::{Logical, Point, Rectangle, Size},
 };
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::{
+    decorations::{self, Insets},
+    layout::{LayoutEngine, TREE_LAYOUT},
+    saved_layout::SavedNode,
+};
+
+// Smallest part of a container that a child can be resized to
+const MIN_RATIO: f32 = 0.1;
+
+/// Height of a tab (of a row, in a stacked container) above the active tile
+pub const TAB_HEIGHT: i32 = 24;
+
+new_key_type! {
+    /// A node of the tiling tree, a Structure or a Tile.
+    /// It's an index in the arena of the TilingState that owns it
+    pub struct Node;
+}
+
+/// The tree and the windows it should contain are out of sync,
+/// the operation is not applied and the tree is left as it was
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TilingError {
+    /// The tree has already a head, the new tile must split another one
+    HeadExists,
+    /// The surface has no tile (eg. the window is floating)
+    UnknownSurface,
+    /// A node points to a node that is not in the tree anymore
+    MissingNode,
+    /// A Tile found where a Structure is expected, or the opposite
+    WrongNodeKind,
+    /// Found by `TilingState::validate`
+    BrokenInvariant(&'static str),
+}
+
+impl fmt::Display for TilingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            TilingError::HeadExists => "the tiling tree has already a head",
+            TilingError::UnknownSurface => "the surface has no tile",
+            TilingError::MissingNode => "a node points to a node not in the tree",
+            TilingError::WrongNodeKind => "unexpected kind of node",
+            TilingError::BrokenInvariant(invariant) => {
+                return write!(f, "broken tiling tree: {invariant}")
+            }
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for TilingError {}
 
 /// This Struct keeps track of all the tiles
 /// in a tree structure
+///
+/// The nodes live in an arena and point to each other through their
+/// index, the tree is never borrowed at runtime and removing a node
+/// can't leave cycles around
 pub struct TilingState {
-    // TEST
     pub tile_tree_head: Option<Node>,
-    pub tile_info: HashMap<WlSurface, Rc<RefCell<Tile>>>,
+    pub tile_info: HashMap<WlSurface, Node>,
+    nodes: SlotMap<Node, NodeData>,
+    // Places the tiles instead of the tree, see `LayoutEngine`
+    engine: Option<Box<dyn LayoutEngine>>,
+    // New tiles split by depth instead of by `next_split`, see `split`
+    spiral: bool,
 }
 
 impl TilingState {
@@ -20,154 +82,739 @@ impl TilingState {
         Self {
             tile_tree_head: None,
             tile_info: HashMap::new(),
+            nodes: SlotMap::with_key(),
+            engine: None,
+            spiral: false,
         }
     }
 
+    pub fn set_spiral(&mut self, spiral: bool) {
+        self.spiral = spiral;
+    }
+
+    /// None goes back to the geometries of the tree
+    pub fn set_engine(&mut self, engine: Option<Box<dyn LayoutEngine>>) {
+        self.engine = engine;
+    }
+
+    pub fn layout_name(&self) -> &'static str {
+        self.engine
+            .as_ref()
+            .map_or(TREE_LAYOUT, |engine| engine.name())
+    }
+
     pub fn insert_head(
         &mut self,
         window: Window,
         geometry: Rectangle<i32, Logical>,
-    ) -> Result<Node, &'static str> {
-        Ok(match self.tile_tree_head {
-            Some(_) => return Err("WOOOOOW head already exists"),
-            None => {
-                let tile = Tile {
-                    next_split: Split::Vertical,
-                    geometry,
-                    container: None,
-                    side: Side::Unique,
-                    window: window.clone(),
-                };
-                let tile = Rc::new(RefCell::new(tile));
-                let node = Node::Tile(Rc::clone(&tile));
-                self.tile_tree_head = Some(Node::clone(&node));
-                self.tile_info
-                    .insert(window.toplevel().wl_surface().clone(), tile);
-                node
-            }
-        })
+    ) -> Result<Node, TilingError> {
+        if self.tile_tree_head.is_some() {
+            return Err(TilingError::HeadExists);
+        }
+
+        let wl_surface = window.toplevel().wl_surface().clone();
+        let node = self.nodes.insert(NodeData {
+            geometry,
+            container: None,
+            kind: NodeKind::Tile {
+                next_split: Split::Vertical,
+                window,
+            },
+        });
+        self.tile_tree_head = Some(node);
+        self.tile_info.insert(wl_surface, node);
+        self.debug_validate();
+        Ok(node)
+    }
+
+    /// The tree fills a new area, eg. because the output changed mode
+    pub fn set_area(&mut self, area: Rectangle<i32, Logical>) -> Result<(), TilingError> {
+        if let Some(head) = self.tile_tree_head {
+            self.update_geometry_node(head, Some(area))?;
+        }
+        self.debug_validate();
+        Ok(())
     }
 
     /// This method is called on a Tile,
     /// from this tile will be created a Stucture Node containing
     /// two children the current Tile and the new tile (both with updated sizes)
-    pub fn split(&mut self, window: Window, new_window: Window) -> Node {
+    ///
+    /// In spiral mode the split alternates with the depth of the tile and the
+    /// new tiles go right, down, left, up and again, without choosing the split
+    pub fn split(&mut self, window: Window, new_window: Window) -> Result<Node, TilingError> {
+        let wl_surface = window.toplevel().wl_surface();
+        match self.spiral_split(wl_surface)? {
+            Some((split, new_first)) => {
+                self.split_tile(wl_surface, new_window, Some(split), new_first)
+            }
+            None => self.split_tile(wl_surface, new_window, None, false),
+        }
+    }
+
+    /// Like `split`, but the new tile is a tab of the container it shares with
+    /// the tile, which becomes tabbed (unless the tile is a tab already).
+    /// The new window is the active tab. Returns the tabbed container
+    pub fn split_tabbed(
+        &mut self,
+        window: Window,
+        new_window: Window,
+    ) -> Result<Node, TilingError> {
+        let wl_surface = new_window.toplevel().wl_surface().clone();
+        self.split(window, new_window)?;
+        let tile = self.tile(&wl_surface)?;
+        let tabbed = match self.tabbed_container(tile) {
+            Some(tabbed) => tabbed,
+            None => {
+                let container = self.node(tile)?.container.ok_or(TilingError::MissingNode)?;
+                match &mut self.node_mut(container)?.kind {
+                    NodeKind::Structure { mode, .. } => *mode = ContainerMode::Tabbed,
+                    NodeKind::Tile { .. } => return Err(TilingError::WrongNodeKind),
+                }
+                container
+            }
+        };
+        self.set_active_tab(tabbed, &wl_surface)?;
+        self.update_geometry_node(tabbed, None)?;
+        self.debug_validate();
+        Ok(tabbed)
+    }
+
+    // Split of the tile in spiral mode and if the new tile goes on the
+    // left (or top), from its depth: the head is split side by side
+    fn spiral_split(&self, wl_surface: &WlSurface) -> Result<Option<(Split, bool)>, TilingError> {
+        if !self.spiral {
+            return Ok(None);
+        }
+        let mut depth = 0;
+        let mut node = self.tile(wl_surface)?;
+        while let Some(container) = self.node(node)?.container {
+            depth += 1;
+            node = container;
+        }
+        let split = match depth % 2 {
+            0 => Split::Horizontal,
+            _ => Split::Vertical,
+        };
+        Ok(Some((split, depth % 4 >= 2)))
+    }
+
+    // The split of the tile is used if `split` is None,
+    // the new tile goes on the left (or top) if `new_first`
+    fn split_tile(
+        &mut self,
+        wl_surface: &WlSurface,
+        new_window: Window,
+        split: Option<Split>,
+        new_first: bool,
+    ) -> Result<Node, TilingError> {
         // Get the Tile that needs to be splited in half
-        let tile_to_split = Rc::clone(
-            self.tile_info
-                .get(window.toplevel().wl_surface())
-                .expect("IMP not having a wl_surface in TileInfo"),
-        );
+        let tile_to_split = self.tile(wl_surface)?;
+        let NodeKind::Tile { next_split, .. } = self.node(tile_to_split)?.kind else {
+            return Err(TilingError::WrongNodeKind);
+        };
+        self.split_node(
+            tile_to_split,
+            new_window,
+            split.unwrap_or(next_split),
+            new_first,
+            0.5,
+        )
+    }
+
+    /// The node (a tile or a whole subtree) takes a side of a new Structure
+    /// in its place, the new window the other one (the left, or top, one if
+    /// `new_first`). Returns the node to be re-laid out
+    pub fn split_node(
+        &mut self,
+        node_to_split: Node,
+        new_window: Window,
+        split: Split,
+        new_first: bool,
+        ratio: f32,
+    ) -> Result<Node, TilingError> {
+        let data = self.node(node_to_split)?;
+        // The new tile splits like the one it comes from
+        let next_split = match data.kind {
+            NodeKind::Tile { next_split, .. } => next_split,
+            NodeKind::Structure { .. } => Split::Vertical,
+        };
+        let geometry = data.geometry;
+        let upper_container = data.container;
+        // The upper container is checked before touching the tree
+        if let Some(upper_container) = upper_container {
+            match self.node(upper_container)?.kind {
+                NodeKind::Structure { left, right, .. }
+                    if left == node_to_split || right == node_to_split => {}
+                _ => return Err(TilingError::WrongNodeKind),
+            }
+        }
 
         // Create new tile
-        let new_tile = Rc::new(RefCell::new(Tile {
-            next_split: tile_to_split.borrow().next_split.clone(),
+        let new_wl_surface = new_window.toplevel().wl_surface().clone();
+        let new_tile = self.nodes.insert(NodeData {
             geometry: Rectangle::default(), // not relevant, to be changed later
             container: None,                // not relevant, to be changed later
-            side: Side::Right,
-            window: new_window,
-        }));
-
-        self.tile_info.insert(
-            new_tile.borrow().window.toplevel().wl_surface().clone(),
-            Rc::clone(&new_tile),
-        );
-
-        // Create structure
-        let structure = Rc::new(RefCell::new(Structure {
-            geometry: tile_to_split.borrow().geometry,
-            container: tile_to_split.borrow().container.clone(),
-            side: tile_to_split.borrow().side.clone(),
-            split: tile_to_split.borrow().next_split.clone(),
-            left: Node::Tile(Rc::clone(&tile_to_split)),
-            right: Node::Tile(Rc::clone(&new_tile)),
-        }));
-
-        match structure.borrow().container.as_ref() {
-            // The upper container must poit to the new struct
-            Some(upper_container) => upper_container.borrow_mut().set_side(
-                structure.borrow().side,
-                &Node::Structure(Rc::clone(&structure)),
-            ),
-            // update head of the tree
-            None => self.tile_tree_head = Some(Node::Structure(Rc::clone(&structure))),
+            kind: NodeKind::Tile {
+                next_split,
+                window: new_window,
+            },
+        });
+        self.tile_info.insert(new_wl_surface.clone(), new_tile);
+
+        // Create structure, in the place of the splitted node
+        let (left, right) = match new_first {
+            true => (new_tile, node_to_split),
+            false => (node_to_split, new_tile),
+        };
+        let structure = self.nodes.insert(NodeData {
+            geometry,
+            container: upper_container,
+            kind: NodeKind::Structure {
+                split,
+                ratio: ratio.clamp(MIN_RATIO, 1.0 - MIN_RATIO),
+                left,
+                right,
+                mode: ContainerMode::Split,
+                active: 0,
+            },
+        });
+        self.replace_child(upper_container, node_to_split, structure)?;
+        self.node_mut(node_to_split)?.container = Some(structure);
+        self.node_mut(new_tile)?.container = Some(structure);
+
+        // A new window in a tabbed container is its active tab,
+        // the whole container is updated (a stacked one gets a new row)
+        let updated = match self.tabbed_container(structure) {
+            Some(tabbed) => {
+                self.set_active_tab(tabbed, &new_wl_surface)?;
+                tabbed
+            }
+            None => structure,
+        };
+        self.update_geometry_node(updated, None)?;
+        self.debug_validate();
+        Ok(updated)
+    }
+
+    /// Window of the tile that contains the point, or of any tile
+    /// if none contains it (None only if there are no tiles)
+    pub fn tile_near(&self, point: Point<i32, Logical>) -> Option<Window> {
+        let tiles = self
+            .tile_info
+            .values()
+            .filter_map(|node| self.nodes.get(*node));
+        tiles
+            .clone()
+            .find(|tile| tile.geometry.contains(point))
+            .or_else(|| tiles.clone().next())
+            .and_then(NodeData::window)
+            .cloned()
+    }
+
+    /// Where a dragged tile would go if dropped on the point: the tile shown
+    /// under it (not the dragged one) and the half of it nearest to the point.
+    /// The half is returned too, to be highlighted
+    pub fn drop_target(
+        &self,
+        point: Point<i32, Logical>,
+        dragged: &WlSurface,
+    ) -> Option<(Window, DropZone, Rectangle<i32, Logical>)> {
+        // Where the tiles are shown, the engine could have placed them
+        // elsewhere and the hidden tabs share the geometry of the active one
+        let (window, geometry) = self
+            .placed_tiles()
+            .into_iter()
+            .filter(|(window, _)| window.toplevel().wl_surface() != dragged)
+            .find(|(_, geometry)| geometry.contains(point))?;
+
+        // The zone of the nearest edge, relative to the size of the tile
+        let x = (point.x - geometry.loc.x) as f64 / geometry.size.w.max(1) as f64;
+        let y = (point.y - geometry.loc.y) as f64 / geometry.size.h.max(1) as f64;
+        let zone = [
+            (x, DropZone::Left),
+            (1.0 - x, DropZone::Right),
+            (y, DropZone::Top),
+            (1.0 - y, DropZone::Bottom),
+        ]
+        .into_iter()
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, zone)| zone)?;
+
+        Some((window, zone, zone.half(geometry)))
+    }
+
+    /// Move the tile of the surface next to the target tile, in the zone
+    /// where it was dropped. The tile is removed from its place, like the
+    /// window was closed, and the target is split in half.
+    /// If the split fails the tile is put back where it was.
+    ///
+    /// Returns the head of the tree, every tile could have moved
+    pub fn move_tile(
+        &mut self,
+        wl_surface: &WlSurface,
+        target: &WlSurface,
+        zone: DropZone,
+    ) -> Result<Node, TilingError> {
+        // Both are checked before touching the tree
+        let tile = self.tile(wl_surface)?;
+        let target_tile = self.tile(target)?;
+        self.node(target_tile)?
+            .window()
+            .ok_or(TilingError::WrongNodeKind)?;
+        if wl_surface == target {
+            return self.tile_tree_head.ok_or(TilingError::MissingNode);
         }
+        let data = self.node(tile)?;
+        let window = data.window().cloned().ok_or(TilingError::WrongNodeKind)?;
 
-        // Update tiles
-        {
-            let mut left_tile = tile_to_split.borrow_mut();
-            left_tile.container = Some(Rc::clone(&structure));
-            left_tile.side = Side::Left;
+        // The sibling of the tile and how they shared their container,
+        // it's still in the tree (in the place of the container) after the destroy
+        let geometry = data.geometry;
+        let old_place = match data.container {
+            Some(container) => {
+                let NodeKind::Structure {
+                    split,
+                    ratio,
+                    left,
+                    right,
+                    ..
+                } = self.node(container)?.kind
+                else {
+                    return Err(TilingError::WrongNodeKind);
+                };
+                Some(match left == tile {
+                    true => (right, split, ratio, true),
+                    false => (left, split, ratio, false),
+                })
+            }
+            None => None,
+        };
+
+        self.destroy(wl_surface)?;
+        if let Err(err) = self.split_in_zone(target, window.clone(), zone) {
+            // The window must not leave the tree
+            match old_place {
+                Some((sibling, split, ratio, first)) => {
+                    self.split_node(sibling, window, split, first, ratio)?
+                }
+                None => self.insert_head(window, geometry)?,
+            };
+            return Err(err);
         }
+        self.tile_tree_head.ok_or(TilingError::MissingNode)
+    }
 
-        new_tile.borrow_mut().container = Some(Rc::clone(&structure));
+    /// Split the target tile, the new window takes the half in the zone
+    pub fn split_in_zone(
+        &mut self,
+        target: &WlSurface,
+        new_window: Window,
+        zone: DropZone,
+    ) -> Result<Node, TilingError> {
+        self.split_tile(target, new_window, Some(zone.split()), zone.first())
+    }
 
-        // call update size on the structure
-        Self::update_geometry_node(Node::Structure(Rc::clone(&structure)), None);
-        Node::Structure(structure)
+    /// Splitting the tile of the surface would leave both halves
+    /// at least `min` big
+    pub fn split_fits(&self, wl_surface: &WlSurface, min: Size<i32, Logical>) -> bool {
+        let Some(node) = self.tile_info.get(wl_surface).copied() else {
+            return false;
+        };
+        // The new tab takes the whole container
+        if self.tabbed_container(node).is_some() {
+            return true;
+        }
+        let Some(tile) = self.nodes.get(node) else {
+            return false;
+        };
+        let NodeKind::Tile { next_split, .. } = tile.kind else {
+            return false;
+        };
+        let split = match self.spiral_split(wl_surface) {
+            Ok(Some((split, _))) => split,
+            _ => next_split,
+        };
+        let (left, right) = split_geometry(tile.geometry, split, 0.5);
+        [left, right]
+            .iter()
+            .all(|half| half.size.w >= min.w && half.size.h >= min.h)
     }
 
-    pub fn set_split(&mut self, wl_surface: &WlSurface, new_split: Split) {
-        self.tile_info
-            .get_mut(wl_surface)
-            .expect("IMP having surface NOT present in tile_info map")
-            .borrow_mut()
-            .next_split = new_split;
+    /// Geometry of the tile of the surface, decorations included
+    pub fn tile_geometry(&self, wl_surface: &WlSurface) -> Option<Rectangle<i32, Logical>> {
+        self.nodes
+            .get(self.tile_node(wl_surface)?)
+            .map(|tile| tile.geometry)
+    }
+
+    /// The tile next to the one of the surface in the direction: among the
+    /// tiles beyond that edge and side by side with it, the nearest one.
+    /// If they are many at the same distance the one that shares the
+    /// longest part of the edge wins
+    pub fn focus_in_direction(
+        &self,
+        wl_surface: &WlSurface,
+        direction: Direction,
+    ) -> Option<Window> {
+        let tiles = self.placed_tiles();
+        let (_, from) = tiles
+            .iter()
+            .find(|(window, _)| window.toplevel().wl_surface() == wl_surface)?;
+        let (left, top) = (from.loc.x, from.loc.y);
+        let (right, bottom) = (left + from.size.w, top + from.size.h);
+
+        tiles
+            .iter()
+            .filter(|(window, _)| window.toplevel().wl_surface() != wl_surface)
+            .filter_map(|(window, geometry)| {
+                let (x, y) = (geometry.loc.x, geometry.loc.y);
+                let (x_end, y_end) = (x + geometry.size.w, y + geometry.size.h);
+                // Distance from the edge, and the part of the edge shared
+                let (distance, shared) = match direction {
+                    Direction::Left => (left - x_end, overlap(top, bottom, y, y_end)),
+                    Direction::Right => (x - right, overlap(top, bottom, y, y_end)),
+                    Direction::Up => (top - y_end, overlap(left, right, x, x_end)),
+                    Direction::Down => (y - bottom, overlap(left, right, x, x_end)),
+                };
+                (distance >= 0 && shared > 0).then_some((distance, -shared, window))
+            })
+            .min_by_key(|(distance, shared, _)| (*distance, *shared))
+            .map(|(_, _, window)| (*window).clone())
+    }
+
+    /// Every tiled window with the geometry it's shown in,
+    /// which comes from the engine if there is one. The tabs
+    /// not active are not shown
+    pub fn placed_tiles(&self) -> Vec<(Window, Rectangle<i32, Logical>)> {
+        let Some(head) = self.tile_tree_head else {
+            return vec![];
+        };
+        let windows = self.windows(head);
+        match (&self.engine, self.nodes.get(head)) {
+            (Some(engine), Some(data)) => {
+                let geometries = engine.arrange(&windows, data.geometry);
+                windows.into_iter().zip(geometries).collect()
+            }
+            _ => windows
+                .into_iter()
+                .filter(|window| !self.is_hidden_tab(window.toplevel().wl_surface()))
+                .filter_map(|window| {
+                    let geometry = self.tile_geometry(window.toplevel().wl_surface())?;
+                    Some((window, geometry))
+                })
+                .collect(),
+        }
+    }
+
+    /// Windows of the tiles in the subtree under the node,
+    /// from the left (or top) one
+    pub fn windows(&self, node: Node) -> Vec<Window> {
+        let Some(data) = self.nodes.get(node) else {
+            return vec![];
+        };
+        match &data.kind {
+            NodeKind::Structure { left, right, .. } => {
+                let mut windows = self.windows(*left);
+                windows.extend(self.windows(*right));
+                windows
+            }
+            NodeKind::Tile { window, .. } => vec![window.clone()],
+        }
+    }
+
+    /// The largest subtree with the tile of the surface
+    /// whose windows are all accepted by `inside`
+    pub fn enclosing(
+        &self,
+        wl_surface: &WlSurface,
+        inside: impl Fn(&Window) -> bool,
+    ) -> Option<Node> {
+        let mut node = *self.tile_info.get(wl_surface)?;
+        while let Some(container) = self.nodes.get(node)?.container {
+            if !self.windows(container).iter().all(&inside) {
+                break;
+            }
+            node = container;
+        }
+        Some(node)
+    }
+
+    /// The tree with the app ids of the windows, None if there are no tiles.
+    /// Tabbed and stacked containers are saved as split ones
+    pub fn save(&self) -> Option<SavedNode> {
+        self.save_node(self.tile_tree_head?)
+    }
+
+    fn save_node(&self, node: Node) -> Option<SavedNode> {
+        Some(match &self.nodes.get(node)?.kind {
+            NodeKind::Tile { window, .. } => SavedNode::Tile {
+                app_id: decorations::app_id(window),
+            },
+            NodeKind::Structure {
+                split,
+                ratio,
+                left,
+                right,
+                ..
+            } => SavedNode::Structure {
+                split: *split,
+                ratio: *ratio,
+                left: Box::new(self.save_node(*left)?),
+                right: Box::new(self.save_node(*right)?),
+            },
+        })
+    }
+
+    /// Get the Tile Node related to the surface, if present
+    pub fn tile_node(&self, wl_surface: &WlSurface) -> Option<Node> {
+        self.tile_info.get(wl_surface).copied()
+    }
+
+    pub fn set_split(
+        &mut self,
+        wl_surface: &WlSurface,
+        new_split: Split,
+    ) -> Result<(), TilingError> {
+        let tile = self.tile(wl_surface)?;
+        match &mut self.node_mut(tile)?.kind {
+            NodeKind::Tile { next_split, .. } => {
+                *next_split = new_split;
+                Ok(())
+            }
+            NodeKind::Structure { .. } => Err(TilingError::WrongNodeKind),
+        }
+    }
+
+    /// Flip the split of the Structure containing the tile, its two
+    /// children are placed again side by side or one above the other.
+    ///
+    /// Returns the Structure whose subtree must be updated in the space,
+    /// None if the tile is alone and there is nothing to flip
+    pub fn toggle_split(&mut self, wl_surface: &WlSurface) -> Result<Option<Node>, TilingError> {
+        let tile = self.tile(wl_surface)?;
+        let Some(container) = self.node(tile)?.container else {
+            return Ok(None);
+        };
+        match &mut self.node_mut(container)?.kind {
+            NodeKind::Structure { split, .. } => *split = split.flipped(),
+            NodeKind::Tile { .. } => return Err(TilingError::WrongNodeKind),
+        }
+        self.update_geometry_node(container, None)?;
+        self.debug_validate();
+        Ok(Some(container))
+    }
+
+    /// The tile of the window grows by `delta` (shrinks if negative) as part
+    /// of its container, the sibling gets the rest. Returns the container to
+    /// be re-laid out, None if the tile is alone
+    pub fn resize_tile(
+        &mut self,
+        wl_surface: &WlSurface,
+        delta: f32,
+    ) -> Result<Option<Node>, TilingError> {
+        let tile = self.tile(wl_surface)?;
+        let Some(container) = self.node(tile)?.container else {
+            return Ok(None);
+        };
+        let NodeKind::Structure { ratio, left, .. } = self.node(container)?.kind else {
+            return Err(TilingError::WrongNodeKind);
+        };
+        let delta = if left == tile { delta } else { -delta };
+        self.set_ratio(container, ratio + delta).map(Some)
+    }
+
+    /// Every Structure divides its space so that the tiles side by side in
+    /// the same direction get equal parts again (eg. after many resizes).
+    /// Returns the head to be re-laid out, None if there are no tiles
+    pub fn balance(&mut self) -> Result<Option<Node>, TilingError> {
+        let Some(head) = self.tile_tree_head else {
+            return Ok(None);
+        };
+        let mut stack = vec![head];
+        while let Some(node) = stack.pop() {
+            let NodeKind::Structure {
+                split, left, right, ..
+            } = self.node(node)?.kind
+            else {
+                continue;
+            };
+            let left_span = self.span(left, split)?;
+            let right_span = self.span(right, split)?;
+            if let NodeKind::Structure { ratio, .. } = &mut self.node_mut(node)?.kind {
+                let equal = left_span as f32 / (left_span + right_span) as f32;
+                *ratio = equal.clamp(MIN_RATIO, 1.0 - MIN_RATIO);
+            }
+            stack.extend([left, right]);
+        }
+        self.update_geometry_node(head, None)?;
+        self.debug_validate();
+        Ok(Some(head))
+    }
+
+    // Tiles one after the other along `along` in the subtree, a subtree
+    // divided the other way (or tabbed) takes the space of one tile
+    fn span(&self, node: Node, along: Split) -> Result<u32, TilingError> {
+        match self.node(node)?.kind {
+            NodeKind::Structure {
+                split,
+                left,
+                right,
+                mode: ContainerMode::Split,
+                ..
+            } if split == along => Ok(self.span(left, along)? + self.span(right, along)?),
+            _ => Ok(1),
+        }
+    }
+
+    /// Structure whose division is the `edge` of the tile of the window,
+    /// the closest one going up the tree. None if the edge is on the
+    /// border of the tree, or if an engine places the tiles
+    pub fn divider(&self, wl_surface: &WlSurface, edge: Direction) -> Option<Divider> {
+        if self.engine.is_some() {
+            return None;
+        }
+        let mut child = *self.tile_info.get(wl_surface)?;
+        while let Some(container) = self.nodes.get(child)?.container {
+            let data = self.nodes.get(container)?;
+            // The tiles of a tabbed container are one above the other
+            if self.tabbed_container(container).is_some() {
+                child = container;
+                continue;
+            }
+            if let NodeKind::Structure {
+                split, ratio, left, ..
+            } = data.kind
+            {
+                let found = match (split, edge) {
+                    (Split::Horizontal, Direction::Right) | (Split::Vertical, Direction::Down) => {
+                        left == child
+                    }
+                    (Split::Horizontal, Direction::Left) | (Split::Vertical, Direction::Up) => {
+                        left != child
+                    }
+                    _ => false,
+                };
+                if found {
+                    return Some(Divider {
+                        node: container,
+                        split,
+                        geometry: data.geometry,
+                        ratio,
+                    });
+                }
+            }
+            child = container;
+        }
+        None
+    }
+
+    /// Part of the Structure taken by its left (or top) child, the whole
+    /// subtree is laid out again. Returns the Structure
+    pub fn set_ratio(&mut self, node: Node, new_ratio: f32) -> Result<Node, TilingError> {
+        match &mut self.node_mut(node)?.kind {
+            // Neither of the two children can disappear
+            NodeKind::Structure { ratio, .. } => {
+                *ratio = new_ratio.clamp(MIN_RATIO, 1.0 - MIN_RATIO)
+            }
+            NodeKind::Tile { .. } => return Err(TilingError::WrongNodeKind),
+        }
+        self.update_geometry_node(node, None)?;
+        self.debug_validate();
+        Ok(node)
     }
 
     /// given a wl surface the sibiling node will assume the geometry of the container
     /// the container will be eliminated and the upper container will point to the remaining Tile
-    pub fn destroy(&mut self, wl_surface: &WlSurface) -> Result<Option<Node>, &'static str> {
-        // get the tile to be destroyed
-        let tile_to_destroy = self
-            .tile_info
-            .remove(wl_surface)
-            .expect("IMP having surface NOT present in tile_info map");
-
-        // Get the sibiling that should cover the all the destroyed space
-        let container = match tile_to_destroy.borrow().container.as_ref() {
-            // The container is a normal Structure
-            Some(c) => Rc::clone(c),
-            // If the container is not present then
-            // the tile is unique, just needed to  remove the head of the Tree
-            None => {
-                println!("REMOVE LAST TILE");
-                self.tile_tree_head = None;
-                return Ok(None);
+    ///
+    /// The tree is checked before being touched, an error leaves it untouched
+    pub fn destroy(&mut self, wl_surface: &WlSurface) -> Result<Option<Node>, TilingError> {
+        // get the tile to be destroyed, and check the nodes that will be touched
+        let tile_to_destroy = self.tile(wl_surface)?;
+        if let Some(container) = self.node(tile_to_destroy)?.container {
+            let NodeKind::Structure { left, right, .. } = self.node(container)?.kind else {
+                return Err(TilingError::WrongNodeKind);
+            };
+            let sibiling = if left == tile_to_destroy { right } else { left };
+            self.node(sibiling)?;
+            if let Some(upper_container) = self.node(container)?.container {
+                self.node(upper_container)?;
+            }
+        }
+
+        // The tabs after the closed one move back by one,
+        // the active one stays the same
+        if let Some(tabbed) = self.tabbed_container(tile_to_destroy) {
+            let index = self
+                .windows(tabbed)
+                .iter()
+                .position(|window| window.toplevel().wl_surface() == wl_surface);
+            if let (Some(index), NodeKind::Structure { active, .. }) =
+                (index, &mut self.node_mut(tabbed)?.kind)
+            {
+                if index < *active {
+                    *active -= 1;
+                }
             }
+        }
+
+        self.tile_info.remove(wl_surface);
+        let tile = self
+            .nodes
+            .remove(tile_to_destroy)
+            .expect("IMP checked above");
+
+        // If the container is not present then
+        // the tile is unique, just needed to  remove the head of the Tree
+        let Some(container_node) = tile.container else {
+            println!("REMOVE LAST TILE");
+            self.tile_tree_head = None;
+            return Ok(None);
         };
-        let mut sibiling = Node::get_sibiling(&container.borrow(), tile_to_destroy.borrow().side);
+        let container = self
+            .nodes
+            .remove(container_node)
+            .expect("IMP checked above");
 
-        // We have two cases now:
-        // + The sibilign is a Tile
-        // + The sibiling is a Structure
+        // Get the sibiling that should cover the all the destroyed space,
+        // it could be a Tile or a Structure
+        let NodeKind::Structure { left, right, .. } = container.kind else {
+            unreachable!("IMP checked above");
+        };
+        let sibiling = if left == tile_to_destroy { right } else { left };
 
-        let upper_container = container.borrow().container.clone();
         // Copy the geometry from the container
-        sibiling.set_geometry(container.borrow().geometry);
-        // Update the container of the tile
-        sibiling.set_container(upper_container.clone());
-        sibiling.set_side(container.borrow().side);
-
-        match upper_container.as_ref() {
-            // the upper container will be the new container of the remaining tile
-            Some(upper_container) => {
-                // Make the upper container pointing to the remaining tile
-                upper_container
-                    .borrow_mut()
-                    .set_side(container.borrow().side, &sibiling);
-            }
-            // If there's no upper container then the tile
-            // will become the head of the tile tree
-            None => {
-                self.tile_tree_head = Some(Node::clone(&sibiling));
+        // and make the upper container point to the sibiling,
+        // if there's no upper container the sibiling becomes the head of the tree
+        self.node_mut(sibiling)?.container = container.container;
+        self.replace_child(container.container, container_node, sibiling)?;
+
+        // A tabbed container going away leaves its tabs to the sibling
+        if let NodeKind::Structure {
+            mode: inherited @ (ContainerMode::Tabbed | ContainerMode::Stacked),
+            active,
+            ..
+        } = container.kind
+        {
+            if let NodeKind::Structure {
+                mode: sibling_mode,
+                active: sibling_active,
+                ..
+            } = &mut self.node_mut(sibiling)?.kind
+            {
+                *sibling_mode = inherited;
+                *sibling_active = active;
             }
-        };
+        }
 
-        if let Node::Structure(_) = sibiling {
-            Self::update_geometry_node(Node::clone(&sibiling), None);
+        self.update_geometry_node(sibiling, Some(container.geometry))?;
+        // A stacked container lost a row
+        let updated = self.tabbed_container(sibiling).unwrap_or(sibiling);
+        if updated != sibiling {
+            self.update_geometry_node(updated, None)?;
         }
-        Ok(Some(Node::clone(&sibiling)))
+        self.debug_validate();
+        Ok(Some(updated))
     }
 
     /// This function will accept a Node and update all the subtree geometry with the new
@@ -175,202 +822,830 @@ impl TilingState {
     ///
     /// if None then every node in the subtree will be reevaluated with the current geometry
     /// in the passed node
-    pub fn update_geometry_node(node: Node, new_geometry: Option<Rectangle<i32, Logical>>) {
-        match node {
-            Node::Structure(structure) => {
-                // if new geometry is specified then they are applied to the
-                // structure before upfate all the subtree geometries
-                if let Some(new_geom) = new_geometry {
-                    structure.borrow_mut().geometry = new_geom;
-                }
+    pub fn update_geometry_node(
+        &mut self,
+        node: Node,
+        new_geometry: Option<Rectangle<i32, Logical>>,
+    ) -> Result<(), TilingError> {
+        // if new geometry is specified then they are applied to the
+        // node before update all the subtree geometries
+        let data = self.node_mut(node)?;
+        if let Some(new_geom) = new_geometry {
+            data.geometry = new_geom;
+        }
 
-                let structure = structure.borrow();
-                // TODO: How can I avoid this two clones?
-                let mut left_node = Node::clone(&structure.left);
-                let mut right_node = Node::clone(&structure.right);
-
-                match structure.split {
-                    Split::Horizontal => {
-                        let new_width = (structure.geometry.size.w as f32 / 2.0).floor() as i32;
-                        let mut left_geom = structure.geometry;
-                        left_geom.size.w = new_width;
-                        left_node.set_geometry(left_geom);
-
-                        let right_geom = Rectangle::from_loc_and_size(
-                            (left_geom.loc.x + new_width, left_geom.loc.y),
-                            left_geom.size,
-                        );
-                        right_node.set_geometry(right_geom);
-                    }
-                    Split::Vertical => {
-                        let new_height = (structure.geometry.size.h as f32 / 2.0).floor() as i32;
-                        let mut left_geom = structure.geometry;
-                        left_geom.size.h = new_height;
-                        left_node.set_geometry(left_geom);
-
-                        let right_geom = Rectangle::from_loc_and_size(
-                            (left_geom.loc.x, left_geom.loc.y + new_height),
-                            left_geom.size,
-                        );
-                        right_node.set_geometry(right_geom);
-                    }
+        let NodeKind::Structure { left, right, .. } = data.kind else {
+            return Ok(());
+        };
+
+        let (left_geom, right_geom) = self.child_geometries(node)?;
+        self.update_geometry_node(left, Some(left_geom))?;
+        self.update_geometry_node(right, Some(right_geom))
+    }
+
+    /// This function should update the space
+    /// of all the subtree under the node
+    /// The geometry of a tile includes the server side decorations
+    /// of its window (see `insets`), the client gets only what remains.
+    /// The tiles are `gaps` apart, and from the edges of the tree
+    pub fn update_space(
+        &self,
+        node: Node,
+        space: &mut Space<Window>,
+        insets: &dyn Fn(&Window) -> Insets,
+        gaps: i32,
+    ) -> Result<(), TilingError> {
+        let Some(head) = self.tile_tree_head else {
+            return Err(TilingError::MissingNode);
+        };
+        let area = self.node(head)?.geometry;
+        let place = |window: &Window, tile, space: &mut Space<Window>| {
+            place_tile(window, with_gaps(tile, area, gaps), space, insets)
+        };
+
+        // An engine places every tile at once, whatever node changed,
+        // in the area of the whole tree
+        if let Some(engine) = &self.engine {
+            let windows = self.windows(head);
+            for (window, geometry) in windows.iter().zip(engine.arrange(&windows, area)) {
+                place(window, geometry, space);
+            }
+            return Ok(());
+        }
+
+        // Only the active tab of a tabbed container is mapped,
+        // so the whole container is placed again
+        let node = self.tabbed_container(node).unwrap_or(node);
+        let mut to_place = vec![(node, None)];
+        while let Some((node, shown)) = to_place.pop() {
+            let data = self.node(node)?;
+            match &data.kind {
+                NodeKind::Structure {
+                    left, right, mode, ..
+                } => {
+                    // The first tabbed container met is the outermost one
+                    let shown = match (shown, mode) {
+                        (None, ContainerMode::Tabbed | ContainerMode::Stacked) => {
+                            self.active_tab(node)
+                        }
+                        (shown, _) => shown,
+                    };
+                    to_place.extend([(*right, shown.clone()), (*left, shown)]);
                 }
+                NodeKind::Tile { window, .. } if shown.as_ref().is_some_and(|s| s != window) => {
+                    space.unmap_elem(window);
+                }
+                NodeKind::Tile { window, .. } => {
+                    println!("TILE: {data:?}");
+                    place(window, data.geometry, space);
+                }
+            }
+        }
+        Ok(())
+    }
 
-                // recursive if left or right sons are Strucutre
-                let recursive_if_structure = |node: Node| match node {
-                    Node::Structure(_) => Self::update_geometry_node(node, None),
-                    _ => (),
-                };
-                recursive_if_structure(left_node);
-                recursive_if_structure(right_node);
+    /// Window of the tile of the surface, mapped or not (eg. a tab not active)
+    pub fn window(&self, wl_surface: &WlSurface) -> Option<Window> {
+        self.nodes
+            .get(self.tile_node(wl_surface)?)
+            .and_then(NodeData::window)
+            .cloned()
+    }
+
+    /// The container of the tile goes from split to tabbed, to stacked and back
+    /// to split, the tile is its active tab. Inside a tabbed container it's the
+    /// tabbed one that changes. Returns the container, None if the tile is alone
+    pub fn cycle_container_mode(
+        &mut self,
+        wl_surface: &WlSurface,
+    ) -> Result<Option<Node>, TilingError> {
+        let tile = self.tile(wl_surface)?;
+        let Some(container) = self.tabbed_container(tile).or(self.node(tile)?.container) else {
+            return Ok(None);
+        };
+        let index = self
+            .windows(container)
+            .iter()
+            .position(|window| window.toplevel().wl_surface() == wl_surface)
+            .unwrap_or(0);
+        match &mut self.node_mut(container)?.kind {
+            NodeKind::Structure { mode, active, .. } => {
+                *mode = mode.next();
+                *active = index;
             }
-            // That's NOT so stupid, when you have only two window
-            // and you destroy on of the two then the last node
-            // remained is a Tile and it should update the sizes here ?
-            Node::Tile(_) => panic!("you stupid?"),
+            NodeKind::Tile { .. } => return Err(TilingError::WrongNodeKind),
         }
+        self.update_geometry_node(container, None)?;
+        self.debug_validate();
+        Ok(Some(container))
     }
 
-    /// This function should update the space
-    /// of all the subtree under the node
-    pub fn update_space(&self, node: Node, space: &mut Space<Window>) {
-        match node {
-            Node::Structure(structure) => {
-                self.update_space(Node::clone(&structure.borrow().left), space);
-                self.update_space(Node::clone(&structure.borrow().right), space);
-            }
-            Node::Tile(tile) => {
-                println!("TILE: {tile:?}");
-                tile.borrow()
-                    .window
-                    .toplevel()
-                    .with_pending_state(|top_level_state| {
-                        top_level_state.bounds = Some(tile.borrow().geometry.size);
-                        top_level_state.size = Some(tile.borrow().geometry.size);
-                        // here could be setted also the decoration mode
+    /// The next (or previous) tab of the tabbed container of the tile becomes the
+    /// active one. Returns the container and the window of the tab, None if the
+    /// tile is not in a tabbed container
+    pub fn cycle_tab(
+        &mut self,
+        wl_surface: &WlSurface,
+        forward: bool,
+    ) -> Result<Option<(Node, Window)>, TilingError> {
+        let tile = self.tile(wl_surface)?;
+        let Some(tabbed) = self.tabbed_container(tile) else {
+            return Ok(None);
+        };
+        let windows = self.windows(tabbed);
+        let NodeKind::Structure { active, .. } = &mut self.node_mut(tabbed)?.kind else {
+            return Err(TilingError::WrongNodeKind);
+        };
+        let count = windows.len();
+        let current = (*active).min(count - 1);
+        *active = match forward {
+            true => (current + 1) % count,
+            false => (current + count - 1) % count,
+        };
+        Ok(Some((tabbed, windows[*active].clone())))
+    }
+
+    /// A tab not active becomes the active one (eg. its window got the focus),
+    /// returns the tabbed container to be placed again
+    pub fn show_tab(&mut self, wl_surface: &WlSurface) -> Option<Node> {
+        if !self.is_hidden_tab(wl_surface) {
+            return None;
+        }
+        let tabbed = self.tabbed_container(self.tile_node(wl_surface)?)?;
+        self.set_active_tab(tabbed, wl_surface).ok()?;
+        Some(tabbed)
+    }
+
+    /// The tabs of the tabbed and stacked containers, to be drawn
+    /// above their active tile. There are none with an engine
+    pub fn tab_strips(&self) -> Vec<TabStrip> {
+        let (None, Some(head)) = (&self.engine, self.tile_tree_head) else {
+            return vec![];
+        };
+        let mut strips = vec![];
+        let mut to_visit = vec![head];
+        while let Some(node) = to_visit.pop() {
+            let Some(data) = self.nodes.get(node) else {
+                continue;
+            };
+            match data.kind {
+                NodeKind::Structure {
+                    mode: mode @ (ContainerMode::Tabbed | ContainerMode::Stacked),
+                    active,
+                    ..
+                } => {
+                    let windows = self.windows(node);
+                    let (geometry, _) = tab_areas(data.geometry, mode, windows.len());
+                    strips.push(TabStrip {
+                        mode,
+                        geometry,
+                        active: active.min(windows.len().saturating_sub(1)),
+                        windows,
                     });
-                // TODO: find a way to avoid sending figure if
-                // the window is just created
-                tile.borrow().window.toplevel().send_configure();
-                // TODO: ACTIVATE???
-                space.map_element(
-                    tile.borrow().window.clone(),
-                    tile.borrow().geometry.loc,
-                    false,
-                );
+                }
+                NodeKind::Structure { left, right, .. } => to_visit.extend([left, right]),
+                NodeKind::Tile { .. } => (),
             }
         }
+        strips
     }
-}
 
-// The derive clone should use the clone of Rc,
-// then I can direcly use Node::clone istead of pattern matching
-// and the Rc::clone the body (maybe)
-#[derive(Clone, Debug)]
-pub enum Node {
-    Structure(Rc<RefCell<Structure>>),
-    Tile(Rc<RefCell<Tile>>),
-}
+    // The outermost tabbed (or stacked) container of the node, the node
+    // itself included. Every tile under it is one of its tabs
+    fn tabbed_container(&self, node: Node) -> Option<Node> {
+        let mut tabbed = None;
+        let mut current = Some(node);
+        while let Some(node) = current {
+            let data = self.nodes.get(node)?;
+            if let NodeKind::Structure {
+                mode: ContainerMode::Tabbed | ContainerMode::Stacked,
+                ..
+            } = data.kind
+            {
+                tabbed = Some(node);
+            }
+            current = data.container;
+        }
+        tabbed
+    }
 
-impl Node {
-    fn set_geometry(&mut self, new_geometry: Rectangle<i32, Logical>) {
-        match self {
-            Node::Structure(s) => s.borrow_mut().geometry = new_geometry,
-            Node::Tile(t) => t.borrow_mut().geometry = new_geometry,
+    // Window of the active tab of the tabbed container
+    fn active_tab(&self, tabbed: Node) -> Option<Window> {
+        let NodeKind::Structure { active, .. } = self.nodes.get(tabbed)?.kind else {
+            return None;
+        };
+        let windows = self.windows(tabbed);
+        windows
+            .get(active.min(windows.len().saturating_sub(1)))
+            .cloned()
+    }
+
+    fn is_hidden_tab(&self, wl_surface: &WlSurface) -> bool {
+        let Some(tabbed) = self
+            .tile_node(wl_surface)
+            .and_then(|node| self.tabbed_container(node))
+        else {
+            return false;
+        };
+        self.active_tab(tabbed)
+            .is_some_and(|window| window.toplevel().wl_surface() != wl_surface)
+    }
+
+    fn set_active_tab(&mut self, tabbed: Node, wl_surface: &WlSurface) -> Result<(), TilingError> {
+        let index = self
+            .windows(tabbed)
+            .iter()
+            .position(|window| window.toplevel().wl_surface() == wl_surface)
+            .ok_or(TilingError::UnknownSurface)?;
+        match &mut self.node_mut(tabbed)?.kind {
+            NodeKind::Structure { active, .. } => *active = index,
+            NodeKind::Tile { .. } => return Err(TilingError::WrongNodeKind),
         }
+        Ok(())
     }
 
-    fn set_container(&mut self, new_container: Option<Rc<RefCell<Structure>>>) {
-        match self {
-            Node::Structure(s) => s.borrow_mut().container = new_container,
-            Node::Tile(t) => t.borrow_mut().container = new_container,
+    // Geometries of the two children of a Structure. In a tabbed container
+    // every tile gets the area under the tabs, whatever the splits under it
+    fn child_geometries(
+        &self,
+        node: Node,
+    ) -> Result<(Rectangle<i32, Logical>, Rectangle<i32, Logical>), TilingError> {
+        let data = self.node(node)?;
+        let NodeKind::Structure {
+            split, ratio, mode, ..
+        } = data.kind
+        else {
+            return Err(TilingError::WrongNodeKind);
+        };
+        let in_tabbed = data
+            .container
+            .and_then(|container| self.tabbed_container(container))
+            .is_some();
+        if in_tabbed {
+            return Ok((data.geometry, data.geometry));
         }
+        if mode != ContainerMode::Split {
+            let (_, content) = tab_areas(data.geometry, mode, self.windows(node).len());
+            return Ok((content, content));
+        }
+        Ok(split_geometry(data.geometry, split, ratio))
     }
 
-    fn set_side(&mut self, new_side: Side) {
-        match self {
-            Node::Structure(s) => s.borrow_mut().side = new_side,
-            Node::Tile(t) => t.borrow_mut().side = new_side,
+    /// Number of tiled windows
+    pub fn tile_count(&self) -> usize {
+        self.tile_info.len()
+    }
+
+    // The container (or the head of the tree, if there is no container)
+    // points to `new` instead of `old`
+    fn replace_child(
+        &mut self,
+        container: Option<Node>,
+        old: Node,
+        new: Node,
+    ) -> Result<(), TilingError> {
+        let Some(container) = container else {
+            self.tile_tree_head = Some(new);
+            return Ok(());
+        };
+        match &mut self.node_mut(container)?.kind {
+            NodeKind::Structure { left, .. } if *left == old => *left = new,
+            NodeKind::Structure { right, .. } if *right == old => *right = new,
+            _ => return Err(TilingError::WrongNodeKind),
+        }
+        Ok(())
+    }
+
+    /// Check the invariants of the tree:
+    /// + containers and children point to each other, the head has no container
+    /// + the children of a Structure cover exactly its geometry
+    /// + every window has exactly one tile, and it's the one in `tile_info`
+    /// + every node in the arena is reachable from the head
+    ///
+    /// The tests apply random sequences of operations and check it after each one
+    pub fn validate(&self) -> Result<(), TilingError> {
+        let check = |holds: bool, invariant: &'static str| {
+            holds
+                .then_some(())
+                .ok_or(TilingError::BrokenInvariant(invariant))
+        };
+
+        let Some(head) = self.tile_tree_head else {
+            return check(
+                self.nodes.is_empty() && self.tile_info.is_empty(),
+                "nodes without a head",
+            );
+        };
+        check(
+            self.node(head)?.container.is_none(),
+            "the head has a container",
+        )?;
+
+        let mut visited = 0;
+        let mut surfaces = HashSet::new();
+        let mut to_visit = vec![head];
+        while let Some(node) = to_visit.pop() {
+            visited += 1;
+            check(visited <= self.nodes.len(), "cycle in the tree")?;
+
+            let data = self.node(node)?;
+            match &data.kind {
+                NodeKind::Structure { left, right, .. } => {
+                    for child in [*left, *right] {
+                        check(
+                            self.node(child)?.container == Some(node),
+                            "child not pointing to its container",
+                        )?;
+                        to_visit.push(child);
+                    }
+                    check(
+                        self.child_geometries(node)?
+                            == (self.node(*left)?.geometry, self.node(*right)?.geometry),
+                        "children not covering their container",
+                    )?;
+                }
+                NodeKind::Tile { window, .. } => {
+                    let wl_surface = window.toplevel().wl_surface();
+                    check(surfaces.insert(wl_surface.clone()), "window with two tiles")?;
+                    check(
+                        self.tile_info.get(wl_surface) == Some(&node),
+                        "tile missing from tile_info",
+                    )?;
+                }
+            }
         }
+
+        check(
+            visited == self.nodes.len(),
+            "nodes not reachable from the head",
+        )?;
+        check(
+            surfaces.len() == self.tile_info.len(),
+            "tile_info pointing to removed tiles",
+        )
     }
 
-    fn get_sibiling(container: &Structure, side: Side) -> Node {
-        match side {
-            Side::Left => container.right.clone(),
-            Side::Right => container.left.clone(),
-            Side::Unique => panic!("WAJKHSAKJDHAd"),
+    // Every mutation is checked in debug builds,
+    // so a broken tree panics where it was broken
+    fn debug_validate(&self) {
+        if cfg!(debug_assertions) {
+            if let Err(err) = self.validate() {
+                panic!("{err}");
+            }
         }
     }
+
+    fn tile(&self, wl_surface: &WlSurface) -> Result<Node, TilingError> {
+        self.tile_info
+            .get(wl_surface)
+            .copied()
+            .ok_or(TilingError::UnknownSurface)
+    }
+
+    fn node(&self, node: Node) -> Result<&NodeData, TilingError> {
+        self.nodes.get(node).ok_or(TilingError::MissingNode)
+    }
+
+    fn node_mut(&mut self, node: Node) -> Result<&mut NodeData, TilingError> {
+        self.nodes.get_mut(node).ok_or(TilingError::MissingNode)
+    }
 }
 
-#[derive(Clone)]
-pub enum Split {
-    Vertical,
-    Horizontal,
+// The window gets the geometry of its tile without the decorations
+fn place_tile(
+    window: &Window,
+    tile: Rectangle<i32, Logical>,
+    space: &mut Space<Window>,
+    insets: &dyn Fn(&Window) -> Insets,
+) {
+    let geometry = insets(window).shrink(tile);
+    window.toplevel().with_pending_state(|top_level_state| {
+        top_level_state.bounds = Some(geometry.size);
+        top_level_state.size = Some(geometry.size);
+    });
+    // TODO: find a way to avoid sending figure if
+    // the window is just created
+    window.toplevel().send_configure();
+    // TODO: ACTIVATE???
+    space.map_element(window.clone(), geometry.loc, false);
+}
+
+// The sides of the tile on the edges of the area give the whole gap,
+// the ones shared with another tile half of it (the other tile gives the rest)
+fn with_gaps(
+    tile: Rectangle<i32, Logical>,
+    area: Rectangle<i32, Logical>,
+    gaps: i32,
+) -> Rectangle<i32, Logical> {
+    if gaps <= 0 {
+        return tile;
+    }
+    let side = |on_edge: bool, first: bool| match (on_edge, first) {
+        (true, _) => gaps,
+        (false, true) => gaps - gaps / 2,
+        (false, false) => gaps / 2,
+    };
+    Insets {
+        top: side(tile.loc.y == area.loc.y, false),
+        bottom: side(tile.loc.y + tile.size.h == area.loc.y + area.size.h, true),
+        left: side(tile.loc.x == area.loc.x, false),
+        right: side(tile.loc.x + tile.size.w == area.loc.x + area.size.w, true),
+    }
+    .shrink(tile)
+}
+
+// Length of the intersection of two segments
+fn overlap(a_start: i32, a_end: i32, b_start: i32, b_end: i32) -> i32 {
+    a_end.min(b_end) - a_start.max(b_start)
 }
 
-#[derive(Clone)]
-pub struct Structure {
+// Geometries of the two children of a Structure, the left (or top) one
+// gets `ratio` of the space rounded down and the right (or bottom) one the rest
+fn split_geometry(
     geometry: Rectangle<i32, Logical>,
-    container: Option<Rc<RefCell<Structure>>>,
-    side: Side,
     split: Split,
-    left: Node,
-    right: Node,
+    ratio: f32,
+) -> (Rectangle<i32, Logical>, Rectangle<i32, Logical>) {
+    let mut left_geom = geometry;
+    let mut right_geom = geometry;
+    match split {
+        Split::Horizontal => {
+            left_geom.size.w = (geometry.size.w as f32 * ratio) as i32;
+            right_geom.loc.x += left_geom.size.w;
+            right_geom.size.w -= left_geom.size.w;
+        }
+        Split::Vertical => {
+            left_geom.size.h = (geometry.size.h as f32 * ratio) as i32;
+            right_geom.loc.y += left_geom.size.h;
+            right_geom.size.h -= left_geom.size.h;
+        }
+    }
+    (left_geom, right_geom)
 }
 
-impl std::fmt::Debug for Structure {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(
-            f,
-            "Strcuture: \n \
-             geometry: \n{:?}\n \
-             left: \n{:?}\n \
-             right: \n{:?}",
-            self.geometry, self.left, self.right
-        )
-    }
+// The strip of the tabs on top of a tabbed container, a row for each tab
+// if it's stacked (at most half of the container), and the area under it
+fn tab_areas(
+    geometry: Rectangle<i32, Logical>,
+    mode: ContainerMode,
+    tabs: usize,
+) -> (Rectangle<i32, Logical>, Rectangle<i32, Logical>) {
+    let rows = match mode {
+        ContainerMode::Stacked => tabs as i32,
+        ContainerMode::Split | ContainerMode::Tabbed => 1,
+    };
+    let height = (rows * TAB_HEIGHT).min(geometry.size.h / 2);
+    let strip = Rectangle::from_loc_and_size(geometry.loc, (geometry.size.w, height));
+    let content = Rectangle::from_loc_and_size(
+        (geometry.loc.x, geometry.loc.y + height),
+        (geometry.size.w, geometry.size.h - height),
+    );
+    (strip, content)
 }
-impl Structure {
-    fn set_side(&mut self, side: Side, node: &Node) {
-        match side {
-            Side::Right => {
-                self.right = Node::clone(node);
-            }
-            Side::Left => {
-                self.left = Node::clone(node);
-            }
-            Side::Unique => {
-                panic!("IMP Structure has only left and right sons")
-            }
-        };
+
+/// Structure found by `TilingState::divider`, as it was when found
+#[derive(Clone, Copy, Debug)]
+pub struct Divider {
+    pub node: Node,
+    pub split: Split,
+    pub geometry: Rectangle<i32, Logical>,
+    pub ratio: f32,
+}
+
+/// How the children of a Structure share its geometry: split between
+/// them, or one at a time under a strip with a tab for each tile
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContainerMode {
+    Split,
+    /// The tabs side by side
+    Tabbed,
+    /// The tabs one above the other
+    Stacked,
+}
+
+impl ContainerMode {
+    fn next(self) -> Self {
+        match self {
+            ContainerMode::Split => ContainerMode::Tabbed,
+            ContainerMode::Tabbed => ContainerMode::Stacked,
+            ContainerMode::Stacked => ContainerMode::Split,
+        }
     }
 }
 
-#[derive(Clone, Copy)]
-enum Side {
+/// Tabs of a tabbed container, see `TilingState::tab_strips`
+pub struct TabStrip {
+    pub mode: ContainerMode,
+    /// Where the tabs are drawn, above the active tile
+    pub geometry: Rectangle<i32, Logical>,
+    /// A tab for each window, from the left (or top) one
+    pub windows: Vec<Window>,
+    pub active: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Split {
+    Vertical,
+    Horizontal,
+}
+
+/// Direction of the arrow keys, eg. to move the focus between the tiles
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
     Left,
     Right,
-    Unique,
+    Up,
+    Down,
 }
 
-#[derive(Clone)]
-pub struct Tile {
-    next_split: Split,
+/// Half of a tile where a dragged tile is dropped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropZone {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl DropZone {
+    /// Half of the geometry in the zone
+    pub fn half(self, geometry: Rectangle<i32, Logical>) -> Rectangle<i32, Logical> {
+        let (left, right) = split_geometry(geometry, self.split(), 0.5);
+        if self.first() {
+            left
+        } else {
+            right
+        }
+    }
+
+    fn split(self) -> Split {
+        match self {
+            DropZone::Left | DropZone::Right => Split::Horizontal,
+            DropZone::Top | DropZone::Bottom => Split::Vertical,
+        }
+    }
+
+    // The dropped tile is the left (or top) child
+    fn first(self) -> bool {
+        matches!(self, DropZone::Left | DropZone::Top)
+    }
+}
+
+impl Split {
+    fn flipped(self) -> Self {
+        match self {
+            Split::Vertical => Split::Horizontal,
+            Split::Horizontal => Split::Vertical,
+        }
+    }
+}
+
+struct NodeData {
     geometry: Rectangle<i32, Logical>,
-    // The container of a Tile can ONLY be a structure
-    container: Option<Rc<RefCell<Structure>>>,
-    side: Side,
-    window: Window,
+    // The container of a node can ONLY be a structure,
+    // None for the head of the tree
+    container: Option<Node>,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    Structure {
+        split: Split,
+        // Part of the container taken by the left (or top) child
+        ratio: f32,
+        left: Node,
+        right: Node,
+        mode: ContainerMode,
+        // Index of the tab shown among the windows of the subtree,
+        // only used if the container is tabbed
+        active: usize,
+    },
+    Tile {
+        next_split: Split,
+        window: Window,
+    },
 }
 
-impl std::fmt::Debug for Tile {
+impl NodeData {
+    fn window(&self) -> Option<&Window> {
+        match &self.kind {
+            NodeKind::Tile { window, .. } => Some(window),
+            NodeKind::Structure { .. } => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for NodeData {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(
-            f,
-            "Tile: geometry: {:?}, container_is_none: {}",
-            self.geometry,
-            self.container.is_none()
-        )
+        match self.kind {
+            NodeKind::Structure {
+                split, ratio, mode, ..
+            } => write!(
+                f,
+                "Structure: geometry: {:?}, split: {split:?}, ratio: {ratio}, mode: {mode:?}",
+                self.geometry
+            ),
+            NodeKind::Tile { .. } => write!(
+                f,
+                "Tile: geometry: {:?}, container_is_none: {}",
+                self.geometry,
+                self.container.is_none()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_windows::TestWindows;
+    use proptest::{collection::vec, prelude::*, test_runner::TestRunner};
+
+    // Windows the random operations choose from
+    const POOL: usize = 6;
+
+    fn area() -> Rectangle<i32, Logical> {
+        Rectangle::from_loc_and_size((0, 0), (1920, 1080))
+    }
+
+    // The first window is the head, each other one splits the previous one
+    fn tree(windows: &[Window]) -> TilingState {
+        let mut tiling = TilingState::init();
+        tiling.insert_head(windows[0].clone(), area()).unwrap();
+        for pair in windows.windows(2) {
+            tiling.split(pair[0].clone(), pair[1].clone()).unwrap();
+        }
+        tiling
+    }
+
+    fn broken(tiling: &TilingState) -> bool {
+        matches!(tiling.validate(), Err(TilingError::BrokenInvariant(_)))
+    }
+
+    #[test]
+    fn validate_accepts_a_built_tree() {
+        let windows = TestWindows::new().create(4);
+        let mut tiling = tree(&windows);
+        assert_eq!(tiling.validate(), Ok(()));
+        tiling.destroy(windows[1].toplevel().wl_surface()).unwrap();
+        assert_eq!(tiling.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_reports_a_corrupted_tree() {
+        let windows = TestWindows::new().create(3);
+        let wl_surface = windows[2].toplevel().wl_surface();
+
+        let mut tiling = tree(&windows);
+        tiling.tile_info.remove(wl_surface);
+        assert!(broken(&tiling));
+
+        let mut tiling = tree(&windows);
+        let tile = tiling.tile_info[wl_surface];
+        tiling.nodes[tile].container = None;
+        assert!(broken(&tiling));
+
+        let mut tiling = tree(&windows);
+        let tile = tiling.tile_info[wl_surface];
+        tiling.nodes[tile].geometry.size.w += 10;
+        assert!(broken(&tiling));
+
+        let mut tiling = tree(&windows);
+        let head = tiling.tile_tree_head.unwrap();
+        tiling.nodes[head].container = Some(head);
+        assert!(broken(&tiling));
+
+        let mut tiling = tree(&windows);
+        tiling.nodes.insert(NodeData {
+            geometry: area(),
+            container: None,
+            kind: NodeKind::Tile {
+                next_split: Split::Vertical,
+                window: windows[0].clone(),
+            },
+        });
+        assert!(broken(&tiling));
+    }
+
+    #[test]
+    fn split_tabbed_adds_the_active_tab() {
+        let windows = TestWindows::new().create(3);
+        let mut tiling = tree(&windows[..1]);
+
+        let tabbed = tiling
+            .split_tabbed(windows[0].clone(), windows[1].clone())
+            .unwrap();
+        assert_eq!(tiling.active_tab(tabbed).as_ref(), Some(&windows[1]));
+        // A tab of the container, not a new tabbed container inside it
+        let again = tiling
+            .split_tabbed(windows[1].clone(), windows[2].clone())
+            .unwrap();
+        assert_eq!(again, tabbed);
+        assert_eq!(tiling.windows(tabbed).len(), 3);
+        assert_eq!(tiling.active_tab(tabbed).as_ref(), Some(&windows[2]));
+        assert_eq!(tiling.validate(), Ok(()));
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    enum Operation {
+        Split {
+            tile: usize,
+            new: usize,
+        },
+        Destroy(usize),
+        Move {
+            tile: usize,
+            target: usize,
+            zone: DropZone,
+        },
+        Resize {
+            tile: usize,
+            delta: f32,
+        },
+        ToggleSplit(usize),
+        CycleMode(usize),
+        SetArea {
+            width: i32,
+            height: i32,
+        },
+        Balance,
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        let window = || 0..POOL;
+        let zone = prop_oneof![
+            Just(DropZone::Left),
+            Just(DropZone::Right),
+            Just(DropZone::Top),
+            Just(DropZone::Bottom),
+        ];
+        prop_oneof![
+            3 => (window(), window()).prop_map(|(tile, new)| Operation::Split { tile, new }),
+            2 => window().prop_map(Operation::Destroy),
+            2 => (window(), window(), zone)
+                .prop_map(|(tile, target, zone)| Operation::Move { tile, target, zone }),
+            (window(), -0.5f32..0.5).prop_map(|(tile, delta)| Operation::Resize { tile, delta }),
+            window().prop_map(Operation::ToggleSplit),
+            window().prop_map(Operation::CycleMode),
+            (1..4000, 1..4000).prop_map(|(width, height)| Operation::SetArea { width, height }),
+            Just(Operation::Balance),
+        ]
+    }
+
+    // Like the compositor, a window is split in only if it has no tile and
+    // the head is inserted only in an empty tree. The errors are ignored,
+    // the tree must stay valid anyway
+    fn apply(tiling: &mut TilingState, windows: &[Window], operation: Operation) {
+        let surface = |index: usize| windows[index].toplevel().wl_surface();
+        match operation {
+            Operation::Split { tile, new } => {
+                if tiling.tile_info.contains_key(surface(new)) {
+                    return;
+                }
+                if tiling.tile_tree_head.is_none() {
+                    let _ = tiling.insert_head(windows[new].clone(), area());
+                } else {
+                    let _ = tiling.split(windows[tile].clone(), windows[new].clone());
+                }
+            }
+            Operation::Destroy(tile) => {
+                let _ = tiling.destroy(surface(tile));
+            }
+            Operation::Move { tile, target, zone } => {
+                let _ = tiling.move_tile(surface(tile), surface(target), zone);
+            }
+            Operation::Resize { tile, delta } => {
+                let _ = tiling.resize_tile(surface(tile), delta);
+            }
+            Operation::ToggleSplit(tile) => {
+                let _ = tiling.toggle_split(surface(tile));
+            }
+            Operation::CycleMode(tile) => {
+                let _ = tiling.cycle_container_mode(surface(tile));
+            }
+            Operation::SetArea { width, height } => {
+                let _ = tiling.set_area(Rectangle::from_loc_and_size((0, 0), (width, height)));
+            }
+            Operation::Balance => {
+                let _ = tiling.balance();
+            }
+        }
+    }
+
+    #[test]
+    fn random_operations_keep_the_tree_valid() {
+        let windows = TestWindows::new().create(POOL);
+        let mut runner = TestRunner::default();
+        runner
+            .run(&vec(operation(), 1..64), |operations| {
+                let mut tiling = TilingState::init();
+                for operation in operations {
+                    apply(&mut tiling, &windows, operation);
+                    prop_assert_eq!(tiling.validate(), Ok(()), "after {:?}", operation);
+                }
+                Ok(())
+            })
+            .unwrap();
     }
 }
@@ -0,0 +1,210 @@
+use smithay::{
+    desktop::Window,
+    reexports::wayland_server::{
+        protocol::{wl_data_source::WlDataSource, wl_surface::WlSurface},
+        Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+    },
+    utils::{Logical, Point},
+};
+use std::sync::Mutex;
+
+use crate::{
+    grabs::{drop_window, update_drop_target},
+    protocols::toplevel_drag::{
+        xdg_toplevel_drag_manager_v1::{self, XdgToplevelDragManagerV1},
+        xdg_toplevel_drag_v1::{self, XdgToplevelDragV1},
+    },
+    render::schedule_render,
+    state::AIGIState,
+    tiling::DropZone,
+};
+
+const VERSION: u32 = 1;
+
+/// The drags that can move a toplevel, and the one in progress
+#[derive(Default)]
+pub struct ToplevelDragState {
+    drags: Vec<XdgToplevelDragV1>,
+    active: Option<XdgToplevelDragV1>,
+    // A tiled window is dropped next to the tile under the pointer
+    drop_target: Option<(Window, DropZone)>,
+}
+
+/// Data source of a toplevel drag, and the toplevel moved with it
+pub struct ToplevelDrag {
+    source: WlDataSource,
+    attached: Mutex<Option<Attached>>,
+}
+
+struct Attached {
+    surface: WlSurface,
+    // Where the pointer is, relative to the geometry of the window
+    offset: Point<i32, Logical>,
+}
+
+/// xdg-toplevel-drag, a window follows the pointer during a drag and drop
+/// (eg. a tab detached from Chromium)
+pub fn create_global(display: &DisplayHandle) {
+    display.create_global::<AIGIState, XdgToplevelDragManagerV1, _>(VERSION, ());
+}
+
+/// A drag and drop started, called from `ClientDndGrabHandler::started`
+pub fn started(state: &mut AIGIState, source: Option<WlDataSource>) {
+    let drags = &mut state.toplevel_drag_state.drags;
+    drags.retain(|drag| drag.is_alive());
+    state.toplevel_drag_state.active = source.and_then(|source| {
+        drags
+            .iter()
+            .find(|drag| drag_data(drag).map_or(false, |data| data.source == source))
+            .cloned()
+    });
+    motion(state);
+}
+
+/// The window moved by the drag in progress, if it's mapped
+pub fn dragged(state: &AIGIState) -> Option<Window> {
+    let drag = state.toplevel_drag_state.active.as_ref()?;
+    let attached = drag_data(drag)?.attached.lock().unwrap();
+    state.window_for_surface(&attached.as_ref()?.surface)
+}
+
+/// The pointer moved, the window moves with it like in a move grab
+pub fn motion(state: &mut AIGIState) {
+    let Some(window) = dragged(state) else {
+        return;
+    };
+    let Some(offset) = state
+        .toplevel_drag_state
+        .active
+        .as_ref()
+        .and_then(drag_data)
+        .and_then(|data| data.attached.lock().unwrap().as_ref().map(|a| a.offset))
+    else {
+        return;
+    };
+    let location = state.client_pointer_location(state.pointer_location);
+    if !state
+        .floating_state
+        .is_floating(window.toplevel().wl_surface())
+    {
+        state.toplevel_drag_state.drop_target = update_drop_target(state, &window, location);
+    }
+    state
+        .space
+        .map_element(window, location.to_i32_round() - offset, true);
+    schedule_render(state);
+}
+
+/// The drag ended, called from `ClientDndGrabHandler::dropped`: the window
+/// is put where it was dropped, like at the end of a move grab
+pub fn dropped(state: &mut AIGIState) {
+    let window = dragged(state);
+    state.toplevel_drag_state.active = None;
+    let target = state.toplevel_drag_state.drop_target.take();
+    if let Some(window) = window {
+        drop_window(state, &window, target);
+    }
+}
+
+fn drag_data(drag: &XdgToplevelDragV1) -> Option<&ToplevelDrag> {
+    drag.data::<ToplevelDrag>()
+}
+
+impl GlobalDispatch<XdgToplevelDragManagerV1, ()> for AIGIState {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        manager: New<XdgToplevelDragManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(manager, ());
+    }
+}
+
+impl Dispatch<XdgToplevelDragManagerV1, ()> for AIGIState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        manager: &XdgToplevelDragManagerV1,
+        request: xdg_toplevel_drag_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            xdg_toplevel_drag_manager_v1::Request::GetXdgToplevelDrag { id, data_source } => {
+                let drags = &mut state.toplevel_drag_state.drags;
+                drags.retain(|drag| drag.is_alive());
+                let used = drags
+                    .iter()
+                    .any(|drag| drag_data(drag).map_or(false, |data| data.source == data_source));
+                if used {
+                    manager.post_error(
+                        xdg_toplevel_drag_manager_v1::Error::InvalidSource,
+                        "the data source already has a toplevel drag",
+                    );
+                    return;
+                }
+                let drag = data_init.init(
+                    id,
+                    ToplevelDrag {
+                        source: data_source,
+                        attached: Mutex::new(None),
+                    },
+                );
+                drags.push(drag);
+            }
+            xdg_toplevel_drag_manager_v1::Request::Destroy => {}
+        }
+    }
+}
+
+impl Dispatch<XdgToplevelDragV1, ToplevelDrag> for AIGIState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        drag: &XdgToplevelDragV1,
+        request: xdg_toplevel_drag_v1::Request,
+        data: &ToplevelDrag,
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            xdg_toplevel_drag_v1::Request::Attach {
+                toplevel,
+                x_offset,
+                y_offset,
+            } => {
+                let mut attached = data.attached.lock().unwrap();
+                if attached.as_ref().map_or(false, |a| a.surface.is_alive()) {
+                    drag.post_error(
+                        xdg_toplevel_drag_v1::Error::ToplevelAttached,
+                        "a toplevel is already attached",
+                    );
+                    return;
+                }
+                let Some(surface) = state.toplevel_wl_surface(&toplevel) else {
+                    return;
+                };
+                *attached = Some(Attached {
+                    surface,
+                    offset: (x_offset, y_offset).into(),
+                });
+                drop(attached);
+                if state.toplevel_drag_state.active.as_ref() == Some(drag) {
+                    motion(state);
+                }
+            }
+            xdg_toplevel_drag_v1::Request::Destroy => {
+                if state.toplevel_drag_state.active.as_ref() == Some(drag) {
+                    drag.post_error(
+                        xdg_toplevel_drag_v1::Error::OngoingDrag,
+                        "the drag has not ended",
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use smithay::{
+    reexports::{
+        wayland_protocols::xdg::shell::server::xdg_toplevel::XdgToplevel,
+        wayland_server::{
+            protocol::{wl_buffer::WlBuffer, wl_shm, wl_surface::WlSurface},
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+        },
+    },
+    wayland::{compositor::with_states, shm},
+};
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    decorations,
+    protocols::toplevel_icon::{
+        xdg_toplevel_icon_manager_v1::{self, XdgToplevelIconManagerV1},
+        xdg_toplevel_icon_v1::{self, XdgToplevelIconV1},
+    },
+    state::AIGIState,
+};
+
+const VERSION: u32 = 1;
+
+/// Icon set by the client on a toplevel, the pixels are copied
+/// from its buffers: they can be destroyed with the icon object
+pub struct ToplevelIcon {
+    /// Name in the icon theme, not looked up: only the buffers are drawn
+    pub name: Option<String>,
+    buffers: Vec<IconBuffer>,
+}
+
+/// Square image of an icon, ARGB pixels without padding
+pub struct IconBuffer {
+    pub size: i32,
+    scale: i32,
+    pub pixels: Vec<u8>,
+}
+
+impl ToplevelIcon {
+    /// The smallest buffer with at least `size` pixels of side,
+    /// or the biggest one if none is enough
+    pub fn buffer(&self, size: i32) -> Option<&IconBuffer> {
+        let big_enough = self.buffers.iter().filter(|buffer| buffer.size >= size);
+        big_enough
+            .min_by_key(|buffer| buffer.size)
+            .or_else(|| self.buffers.iter().max_by_key(|buffer| buffer.size))
+    }
+}
+
+/// What an icon object collected, until it's set on a toplevel
+#[derive(Default)]
+pub struct IconBuilder {
+    name: Option<String>,
+    buffers: Vec<IconBuffer>,
+    // Built by the first set_icon, the object can't be changed after it
+    immutable: bool,
+    icon: Option<Arc<ToplevelIcon>>,
+}
+
+/// Icon of a toplevel, double buffered like the rest of its state:
+/// `pending` is applied by the next commit (see `commit`)
+#[derive(Default)]
+struct ToplevelIconData {
+    pending: Option<Option<Arc<ToplevelIcon>>>,
+    current: Option<Arc<ToplevelIcon>>,
+}
+
+/// xdg-toplevel-icon, the clients give an icon to their windows,
+/// drawn in the titlebar
+pub fn create_global(display: &DisplayHandle) {
+    display.create_global::<AIGIState, XdgToplevelIconManagerV1, _>(VERSION, ());
+}
+
+/// Apply the pending icon, called on every commit of the surface
+pub fn commit(surface: &WlSurface) {
+    with_states(surface, |states| {
+        if let Some(data) = states.data_map.get::<Mutex<ToplevelIconData>>() {
+            let mut data = data.lock().unwrap();
+            if let Some(icon) = data.pending.take() {
+                data.current = icon;
+            }
+        }
+    });
+}
+
+/// The icon of a toplevel, None if the client didn't set one
+pub fn icon(surface: &WlSurface) -> Option<Arc<ToplevelIcon>> {
+    with_states(surface, |states| {
+        states
+            .data_map
+            .get::<Mutex<ToplevelIconData>>()
+            .and_then(|data| data.lock().unwrap().current.clone())
+    })
+}
+
+// The pixels of a square shm buffer, None if it's not one. Only the formats
+// that can be drawn are kept, the others are ignored without an error
+fn copy_buffer(buffer: &WlBuffer) -> Result<Option<(i32, Vec<u8>)>, ()> {
+    shm::with_buffer_contents(buffer, |ptr, len, info| {
+        if info.width != info.height || info.width <= 0 {
+            return Err(());
+        }
+        if !matches!(
+            info.format,
+            wl_shm::Format::Argb8888 | wl_shm::Format::Xrgb8888
+        ) {
+            return Ok(None);
+        }
+        // SAFETY: the pool is mapped for `len` bytes while the closure runs
+        let contents = unsafe { std::slice::from_raw_parts(ptr, len) };
+        let row = info.width as usize * 4;
+        // The alpha of XRGB is undefined, the texture is always ARGB
+        let opaque = info.format == wl_shm::Format::Xrgb8888;
+        let mut pixels = Vec::with_capacity(row * info.height as usize);
+        for y in 0..info.height as usize {
+            let start = info.offset as usize + y * info.stride as usize;
+            let line = contents.get(start..start + row).ok_or(())?;
+            pixels.extend(line.chunks_exact(4).flat_map(|pixel| {
+                let alpha = if opaque { 0xff } else { pixel[3] };
+                [pixel[0], pixel[1], pixel[2], alpha]
+            }));
+        }
+        Ok(Some((info.width, pixels)))
+    })
+    .unwrap_or(Err(()))
+}
+
+impl GlobalDispatch<XdgToplevelIconManagerV1, ()> for AIGIState {
+    fn bind(
+        state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        manager: New<XdgToplevelIconManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let manager = data_init.init(manager, ());
+        // The size drawn in the titlebar, if there is one
+        let size = decorations::icon_size(&state.config.decorations);
+        if size > 0 {
+            manager.icon_size(size);
+        }
+        manager.done();
+    }
+}
+
+impl Dispatch<XdgToplevelIconManagerV1, ()> for AIGIState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _manager: &XdgToplevelIconManagerV1,
+        request: xdg_toplevel_icon_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            xdg_toplevel_icon_manager_v1::Request::CreateIcon { id } => {
+                data_init.init(id, Mutex::new(IconBuilder::default()));
+            }
+            xdg_toplevel_icon_manager_v1::Request::SetIcon { toplevel, icon } => {
+                set_icon(state, &toplevel, icon.as_ref());
+            }
+            xdg_toplevel_icon_manager_v1::Request::Destroy => {}
+        }
+    }
+}
+
+fn set_icon(state: &mut AIGIState, toplevel: &XdgToplevel, icon: Option<&XdgToplevelIconV1>) {
+    let Some(surface) = state.toplevel_wl_surface(toplevel) else {
+        return;
+    };
+    let icon = icon.and_then(|icon| {
+        let mut builder = icon.data::<Mutex<IconBuilder>>()?.lock().unwrap();
+        // An empty icon resets the toplevel to the default one
+        if !builder.immutable && (builder.name.is_some() || !builder.buffers.is_empty()) {
+            builder.icon = Some(Arc::new(ToplevelIcon {
+                name: builder.name.take(),
+                buffers: std::mem::take(&mut builder.buffers),
+            }));
+        }
+        builder.immutable = true;
+        builder.icon.clone()
+    });
+    with_states(&surface, |states| {
+        states
+            .data_map
+            .insert_if_missing_threadsafe(|| Mutex::new(ToplevelIconData::default()));
+        let mut data = states
+            .data_map
+            .get::<Mutex<ToplevelIconData>>()
+            .unwrap()
+            .lock()
+            .unwrap();
+        data.pending = Some(icon);
+    });
+}
+
+impl Dispatch<XdgToplevelIconV1, Mutex<IconBuilder>> for AIGIState {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        icon: &XdgToplevelIconV1,
+        request: xdg_toplevel_icon_v1::Request,
+        builder: &Mutex<IconBuilder>,
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let mut builder = builder.lock().unwrap();
+        let immutable = builder.immutable;
+        let post_immutable = || {
+            icon.post_error(
+                xdg_toplevel_icon_v1::Error::Immutable,
+                "the icon was already set on a toplevel",
+            )
+        };
+        match request {
+            xdg_toplevel_icon_v1::Request::SetName { icon_name } => {
+                if immutable {
+                    return post_immutable();
+                }
+                builder.name = Some(icon_name);
+            }
+            xdg_toplevel_icon_v1::Request::AddBuffer { buffer, scale } => {
+                if immutable {
+                    return post_immutable();
+                }
+                let Ok(copied) = copy_buffer(&buffer) else {
+                    icon.post_error(
+                        xdg_toplevel_icon_v1::Error::InvalidBuffer,
+                        "the buffer is not a square shm buffer",
+                    );
+                    return;
+                };
+                let Some((size, pixels)) = copied else {
+                    return;
+                };
+                // The last buffer of a size and scale replaces the previous one
+                builder
+                    .buffers
+                    .retain(|buffer| buffer.size != size || buffer.scale != scale);
+                builder.buffers.push(IconBuffer {
+                    size,
+                    scale,
+                    pixels,
+                });
+            }
+            xdg_toplevel_icon_v1::Request::Destroy => {}
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use smithay::reexports::calloop::timer::{TimeoutAction, Timer};
+use std::time::{Duration, Instant};
+
+use crate::{backend::BackendSurface, render, state::AIGIState};
+
+// How often the pending frame is checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// A queued frame whose VBlank didn't arrive within this time is lost,
+// it's many refresh intervals even for the slowest monitors
+const VBLANK_TIMEOUT: Duration = Duration::from_secs(2);
+// Frames that can fail to render in a row before recovering
+const MAX_RENDER_ERRORS: u32 = 3;
+
+/// Detects an output that stopped showing frames: the rendering keeps
+/// failing or the VBlank of the queued frame never arrives. The output is
+/// then reinitialized, only the DRM surface and the renderer are touched,
+/// so the clients keep running and don't notice anything
+#[derive(Default)]
+pub struct Watchdog {
+    // When the pending frame was queued, cleared by its VBlank
+    queued: Option<Instant>,
+    render_errors: u32,
+    // Recoveries since the last frame shown, the first one resets only
+    // the buffers of the surface, the next ones the renderer too
+    recoveries: u32,
+    timer_armed: bool,
+}
+
+impl Watchdog {
+    pub fn frame_queued(&mut self) {
+        self.queued = Some(Instant::now());
+        self.render_errors = 0;
+    }
+
+    pub fn frame_shown(&mut self) {
+        self.queued = None;
+        self.recoveries = 0;
+    }
+
+    /// Returns true if the output has to be recovered
+    pub fn render_failed(&mut self) -> bool {
+        self.render_errors += 1;
+        self.render_errors >= MAX_RENDER_ERRORS
+    }
+
+    fn vblank_overdue(&self) -> Option<Duration> {
+        self.queued
+            .map(|queued| queued.elapsed())
+            .filter(|elapsed| *elapsed > VBLANK_TIMEOUT)
+    }
+}
+
+pub fn arm_timer(state: &mut AIGIState) {
+    if state.render_state.watchdog.timer_armed {
+        return;
+    }
+    state.render_state.watchdog.timer_armed = true;
+    state
+        .handle
+        .insert_source(Timer::from_duration(CHECK_INTERVAL), |_, _, loop_data| {
+            let state = &mut loop_data.state;
+            // No frame pending, there is nothing to check
+            if render::suspend_timer(state) {
+                state.render_state.watchdog.timer_armed = false;
+                return TimeoutAction::Drop;
+            }
+            check(state);
+            TimeoutAction::ToDuration(CHECK_INTERVAL)
+        })
+        .expect("failed to schedule render watchdog");
+}
+
+fn check(state: &mut AIGIState) {
+    // A frame queued right before turning off the output is never shown
+    if state.idle_state.display_off() {
+        return;
+    }
+    if let Some(elapsed) = state.render_state.watchdog.vblank_overdue() {
+        recover(
+            state,
+            &format!(
+                "no VBlank for the frame queued {}ms ago",
+                elapsed.as_millis()
+            ),
+        );
+    }
+}
+
+/// The output is stuck, the reason is only logged
+pub fn recover(state: &mut AIGIState, reason: &str) {
+    let watchdog = &mut state.render_state.watchdog;
+    watchdog.queued = None;
+    watchdog.render_errors = 0;
+    watchdog.recoveries += 1;
+    let recoveries = watchdog.recoveries;
+
+    // The buffers queued or being scanned out are dropped,
+    // the next frame is rendered from scratch in new ones
+    match &mut state.backend_data.surface {
+        BackendSurface::Drm { device_data, .. } => {
+            let mode = device_data.gbm_surface.pending_mode();
+            println!(
+                "RENDER WATCHDOG {}: {reason}, recovery {recoveries} (mode {}x{}@{}, crtc {:?})",
+                device_data.connector_name,
+                mode.size().0,
+                mode.size().1,
+                mode.vrefresh(),
+                device_data.crtc,
+            );
+            device_data.gbm_surface.reset_buffers();
+        }
+        BackendSurface::Winit(winit_data) => {
+            println!("RENDER WATCHDOG winit: {reason}, recovery {recoveries}");
+            winit_data.reset_buffer();
+        }
+    }
+
+    // Textures imported by the old renderer can't be used by the new one
+    if recoveries > 1 {
+        let render_node = state.backend_data.render_node;
+        let gbm = state.backend_data.gbm.clone();
+        let gpus = state.backend_data.gpu_manager.as_mut();
+        gpus.remove_node(&render_node);
+        match gpus.add_node(render_node, gbm) {
+            Ok(()) => {
+                state.decoration_state.reload();
+                state.tab_state.reload();
+                if let Some(dialog) = state.force_close.as_mut() {
+                    dialog.forget_texture();
+                }
+                if let Some(osd) = state.text_osd.as_mut() {
+                    osd.forget_texture();
+                }
+                println!("RENDER WATCHDOG: renderer created again");
+                if let Err(err) = render::warm_up(&mut state.backend_data) {
+                    println!("Renderer not warmed up: {err}");
+                }
+            }
+            Err(err) => println!("RENDER WATCHDOG: renderer not created: {err:?}"),
+        }
+    }
+
+    render::reset(state);
+}
@@ -0,0 +1,197 @@
+use smithay::{
+    desktop::Window,
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    utils::{Logical, Point, Rectangle},
+};
+
+use crate::{
+    floating::FloatingState, focus::FocusHistory, layout::TREE_LAYOUT, tiling::TilingState,
+};
+
+// Number of workspaces, they all exist from the start even if empty
+pub const WORKSPACE_COUNT: usize = 9;
+
+/// Everything that belongs to a workspace that is not shown,
+/// its windows are unmapped from the Space until it becomes active again
+pub struct Workspace {
+    pub tiling_state: TilingState,
+    pub floating_state: FloatingState,
+    pub focus_history: FocusHistory,
+    pub windows: Vec<(Window, Point<i32, Logical>)>,
+}
+
+impl Workspace {
+    pub fn empty() -> Self {
+        Self {
+            tiling_state: TilingState::init(),
+            floating_state: FloatingState::default(),
+            focus_history: FocusHistory::default(),
+            windows: vec![],
+        }
+    }
+}
+
+/// The active workspace lives directly in the AIGIState (space,
+/// tiling_state and floating_state), so only the hidden ones are stored here
+pub struct Workspaces {
+    active: usize,
+    // The slot of the active workspace is always None
+    hidden: Vec<Option<Workspace>>,
+}
+
+impl Workspaces {
+    pub fn init() -> Self {
+        let mut hidden = (0..WORKSPACE_COUNT)
+            .map(|_| Some(Workspace::empty()))
+            .collect::<Vec<_>>();
+        hidden[0] = None;
+        Self { active: 0, hidden }
+    }
+
+    /// The active workspace is set apart, see `TilingState::set_spiral`
+    pub fn set_spiral(&mut self, spiral: bool) {
+        for workspace in self.hidden.iter_mut().flatten() {
+            workspace.tiling_state.set_spiral(spiral);
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// Number of windows in a hidden workspace
+    pub fn window_count(&self, index: usize) -> usize {
+        self.hidden
+            .get(index)
+            .and_then(Option::as_ref)
+            .map_or(0, |workspace| workspace.windows.len())
+    }
+
+    /// Layout of a hidden workspace, see `LayoutEngine`
+    pub fn layout_name(&self, index: usize) -> &'static str {
+        self.hidden
+            .get(index)
+            .and_then(Option::as_ref)
+            .map_or(TREE_LAYOUT, |workspace| {
+                workspace.tiling_state.layout_name()
+            })
+    }
+
+    /// Windows of the hidden workspaces, with the index of their workspace
+    pub fn windows(&self) -> impl Iterator<Item = (usize, &Window)> {
+        self.hidden
+            .iter()
+            .enumerate()
+            .flat_map(|(index, workspace)| {
+                workspace
+                    .iter()
+                    .flat_map(|workspace| workspace.windows.iter())
+                    .map(move |(window, _)| (index, window))
+            })
+    }
+
+    /// The first hidden workspace without windows after the active
+    /// one, starting again from the first after the last one
+    pub fn next_empty(&self) -> Option<usize> {
+        (1..WORKSPACE_COUNT)
+            .map(|offset| (self.active + offset) % WORKSPACE_COUNT)
+            .find(|index| {
+                self.hidden[*index]
+                    .as_ref()
+                    .is_some_and(|workspace| workspace.windows.is_empty())
+            })
+    }
+
+    /// Index of the workspace `offset` positions away from the active one,
+    /// None if it would be before the first or after the last one
+    pub fn relative(&self, offset: isize) -> Option<usize> {
+        self.active
+            .checked_add_signed(offset)
+            .filter(|index| *index < WORKSPACE_COUNT)
+    }
+
+    /// Store the state of the workspace that is being left
+    /// and return the one of the new active workspace
+    pub fn switch(&mut self, index: usize, leaving: Workspace) -> Workspace {
+        let entering = self.hidden[index]
+            .take()
+            .expect("IMP the active workspace is not hidden");
+        self.hidden[self.active] = Some(leaving);
+        self.active = index;
+        entering
+    }
+
+    /// The tiles of the hidden workspaces will fill the new
+    /// area once shown, see `TilingState::set_area`
+    pub fn set_tiling_area(&mut self, area: Rectangle<i32, Logical>) {
+        for workspace in self.hidden.iter_mut().flatten() {
+            if let Err(err) = workspace.tiling_state.set_area(area) {
+                println!("Tiles not resized: {err}");
+            }
+        }
+    }
+
+    /// Put a window in a hidden workspace, a floating window keeps its
+    /// geometry and a tiled one splits a tile (or fills `area` if there are
+    /// no tiles). Its tile gets the right size when the workspace is shown
+    pub fn insert_window(
+        &mut self,
+        index: usize,
+        window: Window,
+        floating: Option<Rectangle<i32, Logical>>,
+        area: Rectangle<i32, Logical>,
+    ) {
+        let Some(workspace) = self.hidden.get_mut(index).and_then(Option::as_mut) else {
+            return;
+        };
+
+        let wl_surface = window.toplevel().wl_surface().clone();
+        let location = match floating {
+            Some(geometry) => {
+                workspace.floating_state.set_geometry(&wl_surface, geometry);
+                geometry.loc
+            }
+            None => {
+                let result = match workspace.tiling_state.tile_near(area.loc) {
+                    Some(tile) => workspace.tiling_state.split(tile, window.clone()),
+                    None => workspace.tiling_state.insert_head(window.clone(), area),
+                };
+                if let Err(err) = result {
+                    println!("Window not tiled in workspace {index}: {err}");
+                }
+                area.loc
+            }
+        };
+        workspace.windows.push((window, location));
+    }
+
+    /// Remove a window that lives on a hidden workspace,
+    /// returns false if no hidden workspace contains it
+    pub fn remove_window(&mut self, wl_surface: &WlSurface) -> bool {
+        let Some(workspace) = self.hidden.iter_mut().flatten().find(|workspace| {
+            workspace
+                .windows
+                .iter()
+                .any(|(window, _)| window.toplevel().wl_surface() == wl_surface)
+        }) else {
+            return false;
+        };
+
+        workspace.windows.retain(|(window, _)| {
+            let removed = window.toplevel().wl_surface() == wl_surface;
+            if removed {
+                workspace.focus_history.remove(window);
+            }
+            !removed
+        });
+
+        // The geometries of the remaining tiles are updated in the tree,
+        // the Space will be updated once the workspace is shown
+        if workspace.floating_state.remove(wl_surface).is_none() {
+            if let Err(err) = workspace.tiling_state.destroy(wl_surface) {
+                println!("Tile not removed: {err}");
+            }
+        }
+        true
+    }
+}
@@ -38,7 +38,7 @@ use smithay::{
             Client, Display, DisplayHandle,
         },
     },
-    utils::{Scale, Transform, SERIAL_COUNTER},
+    utils::{Rectangle, Scale, Transform, SERIAL_COUNTER},
     wayland::{
         buffer::BufferHandler,
         compositor::{with_states, CompositorClientState, CompositorHandler, CompositorState},
@@ -175,6 +175,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                             None,
                         );
                         layer_map_for_output(&output).arrange();
+
+                        // The tiles have to fill the new size,
+                        // without the space reserved by the layer surfaces
+                        if let Some(output_geometry) = state.space.output_geometry(&output) {
+                            let zone = layer_map_for_output(&output).non_exclusive_zone();
+                            state.tiling_state.set_area(Rectangle::from_loc_and_size(
+                                output_geometry.loc + zone.loc,
+                                zone.size,
+                            ));
+                        }
+                        if let Some(head) = state.tiling_state.tile_tree_head.clone() {
+                            state.tiling_state.update_space(head, &mut state.space);
+                        }
                     }
                     WinitEvent::Input(event) => {
                         match event {
@@ -172,6 +172,16 @@ impl TilingState {
         Ok(Some(Node::clone(&sibiling)))
     }
 
+    /// The area covered by all the tiles changed (eg. the window of
+    /// the winit backend was resized), every tile is resized to fit it
+    pub fn set_area(&self, area: Rectangle<i32, Logical>) {
+        match self.tile_tree_head.clone() {
+            Some(mut head @ Node::Tile(_)) => head.set_geometry(area),
+            Some(head) => Self::update_geometry_node(head, Some(area)),
+            None => (),
+        }
+    }
+
     /// This function will accept a Node and update all the subtree geometry with the new
     /// geometry specified, nothing will be changed except the field geometry
     ///